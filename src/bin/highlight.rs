@@ -0,0 +1,221 @@
+//! Highlights text from stdin or a file and writes the result to stdout, for eyeballing output.
+//!
+//! ```text
+//! highlight [--ranges 0-5,6-11 | --pattern <substring> | --regex <re>]
+//!           [--output html|ansi|markdown|segments-json] [--lenient] [FILE]
+//! ```
+//!
+//! Exit codes: 0 on success, 1 if the ranges are rejected, 2 on bad arguments, 3 on IO errors.
+
+use std::{
+    env, fs,
+    io::{self, Read, Write},
+    process::ExitCode,
+};
+
+use highlight_ranges_kata::{
+    AnsiStyle, BoundsPolicy, HighlightRange, Highlighter, MarkdownDelimiter, SkippedRange,
+    highlight_segments, highlight_text_ansi, highlight_text_escaped, highlight_text_markdown,
+    parse_ranges, ranges_for_regex, ranges_for_substring,
+};
+
+const EXIT_INVALID: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_IO: u8 = 3;
+
+const USAGE: &str = "usage: highlight [--ranges 0-5,6-11 | --pattern <substring> | --regex <re>] \
+                     [--output html|ansi|markdown|segments-json] [--lenient] [FILE]";
+
+enum Source {
+    Ranges(Vec<HighlightRange>),
+    Pattern(String),
+    Regex(regex::Regex),
+}
+
+#[derive(Clone, Copy)]
+enum Output {
+    Html,
+    Ansi,
+    Markdown,
+    SegmentsJson,
+}
+
+struct Args {
+    source: Source,
+    output: Output,
+    lenient: bool,
+    path: Option<String>,
+}
+
+enum Failure {
+    Usage(String),
+    Invalid(String),
+    Io(String),
+}
+
+impl Failure {
+    fn exit_code(&self) -> u8 {
+        match self {
+            Failure::Invalid(_) => EXIT_INVALID,
+            Failure::Usage(_) => EXIT_USAGE,
+            Failure::Io(_) => EXIT_IO,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            match &failure {
+                Failure::Usage(message) => eprintln!("highlight: {message}\n{USAGE}"),
+                Failure::Invalid(message) | Failure::Io(message) => {
+                    eprintln!("highlight: {message}")
+                }
+            }
+            ExitCode::from(failure.exit_code())
+        }
+    }
+}
+
+fn run() -> Result<(), Failure> {
+    let args = parse_args(env::args().skip(1))?;
+    let input = read_input(args.path.as_deref())?;
+
+    let highlights = match &args.source {
+        Source::Ranges(ranges) => ranges.clone(),
+        Source::Pattern(needle) => ranges_for_substring(&input, needle),
+        Source::Regex(re) => ranges_for_regex(&input, re),
+    };
+    let highlights = if args.lenient {
+        lenient_ranges(&input, &highlights)
+    } else {
+        highlights
+    };
+
+    let rendered = render(&input, highlights, args.output)?;
+    let mut stdout = io::stdout().lock();
+    stdout
+        .write_all(rendered.as_bytes())
+        .and_then(|()| stdout.flush())
+        .map_err(|e| Failure::Io(format!("can't write to stdout: {e}")))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, Failure> {
+    let mut source = None;
+    let mut output = Output::Html;
+    let mut lenient = false;
+    let mut path = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| {
+            args.next()
+                .ok_or_else(|| Failure::Usage(format!("{flag} needs a value")))
+        };
+        let next_source = match arg.as_str() {
+            "--ranges" => {
+                let ranges = parse_ranges(&value("--ranges")?)
+                    .map_err(|e| Failure::Usage(format!("invalid --ranges: {e}")))?;
+                Some(Source::Ranges(ranges))
+            }
+            "--pattern" => Some(Source::Pattern(value("--pattern")?)),
+            "--regex" => {
+                let re = regex::Regex::new(&value("--regex")?)
+                    .map_err(|e| Failure::Usage(format!("invalid --regex: {e}")))?;
+                Some(Source::Regex(re))
+            }
+            "--output" => {
+                output = match value("--output")?.as_str() {
+                    "html" => Output::Html,
+                    "ansi" => Output::Ansi,
+                    "markdown" => Output::Markdown,
+                    "segments-json" => Output::SegmentsJson,
+                    other => return Err(Failure::Usage(format!("unknown output {other:?}"))),
+                };
+                None
+            }
+            "--lenient" => {
+                lenient = true;
+                None
+            }
+            flag if flag.starts_with("--") => {
+                return Err(Failure::Usage(format!("unknown flag {flag}")));
+            }
+            _ if path.is_none() => {
+                path = Some(arg);
+                None
+            }
+            _ => return Err(Failure::Usage(String::from("more than one input file"))),
+        };
+
+        if let Some(next_source) = next_source {
+            if source.is_some() {
+                return Err(Failure::Usage(String::from(
+                    "--ranges, --pattern and --regex are mutually exclusive",
+                )));
+            }
+            source = Some(next_source);
+        }
+    }
+
+    let source = source.ok_or_else(|| {
+        Failure::Usage(String::from(
+            "one of --ranges, --pattern or --regex is required",
+        ))
+    })?;
+    Ok(Args {
+        source,
+        output,
+        lenient,
+        path,
+    })
+}
+
+fn read_input(path: Option<&str>) -> Result<String, Failure> {
+    match path {
+        Some(path) => {
+            fs::read_to_string(path).map_err(|e| Failure::Io(format!("can't read {path}: {e}")))
+        }
+        None => {
+            let mut input = String::new();
+            io::stdin()
+                .read_to_string(&mut input)
+                .map_err(|e| Failure::Io(format!("can't read stdin: {e}")))?;
+            Ok(input)
+        }
+    }
+}
+
+/// The ranges [`Highlighter::highlight_lossy`] would render with [`BoundsPolicy::Clamp`],
+/// reporting each skipped range on stderr
+fn lenient_ranges(input: &str, highlights: &[HighlightRange]) -> Vec<HighlightRange> {
+    let clamping = Highlighter::builder()
+        .bounds_policy(BoundsPolicy::Clamp)
+        .build()
+        .expect("clamping alone is a valid configuration");
+
+    let (accepted, skipped) = clamping.partition_lossy(input, highlights);
+    for SkippedRange { range, reason } in skipped {
+        eprintln!("highlight: skipped range {range}: {reason}");
+    }
+
+    accepted
+}
+
+fn render(input: &str, highlights: Vec<HighlightRange>, output: Output) -> Result<String, Failure> {
+    let invalid = |e: highlight_ranges_kata::HighlightingError| Failure::Invalid(e.to_string());
+
+    match output {
+        Output::Html => highlight_text_escaped(input, highlights).map_err(invalid),
+        Output::Ansi => highlight_text_ansi(input, highlights, AnsiStyle::Bold).map_err(invalid),
+        Output::Markdown => {
+            highlight_text_markdown(input, highlights, MarkdownDelimiter::Strong).map_err(invalid)
+        }
+        Output::SegmentsJson => {
+            let segments = highlight_segments(input, highlights).map_err(invalid)?;
+            let mut json = serde_json::to_string(&segments).expect("segments serialize to JSON");
+            json.push('\n');
+            Ok(json)
+        }
+    }
+}
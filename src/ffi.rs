@@ -0,0 +1,162 @@
+use super::*;
+use std::ffi::{CStr, CString, c_char};
+
+pub const HR_OK: i32 = 0;
+/// `text` or `out` is null, or `ranges` is null while `range_count` isn't zero
+pub const HR_ERR_NULL_POINTER: i32 = -1;
+/// `text` isn't valid UTF-8
+pub const HR_ERR_INVALID_UTF8: i32 = -2;
+/// see [`HighlightingError::OverlappingRanges`]
+pub const HR_ERR_OVERLAPPING_RANGES: i32 = 1;
+/// see [`HighlightingError::RangesOutOfBounds`]
+pub const HR_ERR_OUT_OF_BOUNDS: i32 = 2;
+/// see [`HighlightingError::NotACharBoundary`]
+pub const HR_ERR_NOT_A_CHAR_BOUNDARY: i32 = 3;
+/// any other [`HighlightingError`], which byte ranges don't produce
+pub const HR_ERR_OTHER: i32 = 99;
+
+fn error_code(error: &HighlightingError) -> i32 {
+    match error {
+        HighlightingError::OverlappingRanges { .. } => HR_ERR_OVERLAPPING_RANGES,
+        HighlightingError::RangesOutOfBounds { .. }
+        | HighlightingError::CharRangeOutOfBounds { .. } => HR_ERR_OUT_OF_BOUNDS,
+        HighlightingError::NotACharBoundary(_) => HR_ERR_NOT_A_CHAR_BOUNDARY,
+        _ => HR_ERR_OTHER,
+    }
+}
+
+/// Highlights the NUL-terminated UTF-8 `text` like [`highlight_text`], `ranges` holds
+/// `range_count` pairs of byte offsets (`lower, upper, lower, upper, ...`). On success the
+/// result is stored in `*out` and has to be released with [`hr_free_string`]; on failure
+/// `*out` is set to null (unless `out` itself is null) and an `HR_ERR_*` code is returned.
+///
+/// # Safety
+///
+/// `text` must be null or point to a NUL-terminated string, `ranges` must be null or point
+/// to `2 * range_count` readable `u32`s, and `out` must be null or valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hr_highlight(
+    text: *const c_char,
+    ranges: *const u32,
+    range_count: usize,
+    out: *mut *mut c_char,
+) -> i32 {
+    if out.is_null() {
+        return HR_ERR_NULL_POINTER;
+    }
+    // SAFETY: `out` is non-null and valid for writes per the contract
+    unsafe { *out = core::ptr::null_mut() };
+    if text.is_null() || (ranges.is_null() && range_count != 0) {
+        return HR_ERR_NULL_POINTER;
+    }
+
+    // SAFETY: `text` is non-null and NUL-terminated per the contract
+    let Ok(text) = unsafe { CStr::from_ptr(text) }.to_str() else {
+        return HR_ERR_INVALID_UTF8;
+    };
+    let bounds: &[u32] = if range_count == 0 {
+        &[]
+    } else {
+        let Some(len) = range_count.checked_mul(2) else {
+            return HR_ERR_OUT_OF_BOUNDS;
+        };
+        // SAFETY: `ranges` is non-null and holds `2 * range_count` values per the contract
+        unsafe { core::slice::from_raw_parts(ranges, len) }
+    };
+    let highlights = bounds
+        .chunks_exact(2)
+        .map(|pair| HighlightRange::new(pair[0] as usize, pair[1] as usize))
+        .collect::<Vec<_>>();
+
+    match Highlighter::default().highlight(text, &highlights) {
+        Ok(highlighted) => {
+            let highlighted =
+                CString::new(highlighted).expect("neither the input nor the tags contain NUL");
+            // SAFETY: checked for null above
+            unsafe { *out = highlighted.into_raw() };
+            HR_OK
+        }
+        Err(e) => error_code(&e),
+    }
+}
+
+/// Releases a string returned by [`hr_highlight`], null is ignored
+///
+/// # Safety
+///
+/// `s` must be null or a pointer stored by [`hr_highlight`] that hasn't been freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn hr_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        // SAFETY: `s` came from `CString::into_raw` per the contract
+        drop(unsafe { CString::from_raw(s) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_highlight_through_c_interface() {
+        use std::ffi::{CStr, CString};
+
+        let text = CString::new("Hello world").unwrap();
+        let ranges = [0u32, 5, 6, 11];
+        let mut out = core::ptr::null_mut();
+
+        let code = unsafe { hr_highlight(text.as_ptr(), ranges.as_ptr(), 2, &mut out) };
+
+        assert_eq!(HR_OK, code);
+        assert_eq!(
+            "<em>Hello</em> <em>world</em>",
+            unsafe { CStr::from_ptr(out) }.to_str().unwrap()
+        );
+        unsafe { hr_free_string(out) };
+
+        // no ranges at all, the range pointer may be null then
+        let mut out = core::ptr::null_mut();
+        let code = unsafe { hr_highlight(text.as_ptr(), core::ptr::null(), 0, &mut out) };
+        assert_eq!(HR_OK, code);
+        assert_eq!(
+            "Hello world",
+            unsafe { CStr::from_ptr(out) }.to_str().unwrap()
+        );
+        unsafe { hr_free_string(out) };
+        unsafe { hr_free_string(core::ptr::null_mut()) };
+    }
+
+    #[test]
+    fn should_report_error_codes_through_c_interface() {
+        use std::ffi::CString;
+
+        let call = |text: &[u8], ranges: &[u32]| {
+            let text = CString::new(text).unwrap();
+            let mut out = core::ptr::dangling_mut();
+            let code =
+                unsafe { hr_highlight(text.as_ptr(), ranges.as_ptr(), ranges.len() / 2, &mut out) };
+            assert!(out.is_null());
+            code
+        };
+
+        assert_eq!(
+            HR_ERR_OVERLAPPING_RANGES,
+            call(b"Hello world", &[0, 5, 3, 8])
+        );
+        assert_eq!(HR_ERR_OUT_OF_BOUNDS, call(b"Hello", &[0, 50]));
+        assert_eq!(HR_ERR_NOT_A_CHAR_BOUNDARY, call("öl".as_bytes(), &[1, 3]));
+        assert_eq!(HR_ERR_INVALID_UTF8, call(b"\xff\xfe", &[]));
+
+        let text = CString::new("Hello").unwrap();
+        let mut out = core::ptr::null_mut();
+        assert_eq!(HR_ERR_NULL_POINTER, unsafe {
+            hr_highlight(core::ptr::null(), core::ptr::null(), 0, &mut out)
+        });
+        assert_eq!(HR_ERR_NULL_POINTER, unsafe {
+            hr_highlight(text.as_ptr(), core::ptr::null(), 1, &mut out)
+        });
+        assert_eq!(HR_ERR_NULL_POINTER, unsafe {
+            hr_highlight(text.as_ptr(), core::ptr::null(), 0, core::ptr::null_mut())
+        });
+    }
+}
@@ -1,167 +1,80 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![allow(unused, dead_code)]
 
-use std::{fmt::format, usize};
+extern crate alloc;
 
-#[derive(Debug, PartialEq, Eq)]
-enum HighlightingBoundry {
-    Start, // <em>
-    End,   // </em>
-}
-
-#[derive(Debug, PartialEq, Eq)]
-enum HighlightingError {
-    OverlappingRanges,
-    RangesOutOfBounds,
-}
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-struct HighlightRange {
-    /// inclusive
-    lower: u32,
-    /// exclusive
-    upper: u32,
-}
-
-impl HighlightRange {
-    /// lower = inclusive, upper = exclusive, swaps upper and lower if necessary
-    fn new(lower: u32, upper: u32) -> Self {
-        if lower < upper {
-            HighlightRange { lower, upper }
-        } else {
-            HighlightRange {
-                lower: upper,
-                upper: lower,
-            }
-        }
-    }
-}
-
-fn validate_ranges(
-    input_len: usize,
-    highlights: &Vec<HighlightRange>,
-) -> Result<(), HighlightingError> {
-    for h in highlights {
-        if h.upper as usize > input_len || h.lower as usize >= input_len {
-            return Err(HighlightingError::RangesOutOfBounds);
-        }
-    }
-
-    let mut sorted = highlights.clone();
-    sorted.sort_by_key(|r| r.lower);
-
-    for i in 1..sorted.len() {
-        if sorted[i].lower < sorted[i - 1].upper {
-            return Err(HighlightingError::OverlappingRanges);
-        }
-    }
+pub mod prelude;
+pub mod range;
+pub mod render;
+pub mod search;
+pub mod validate;
 
-    Ok(())
-}
+pub use range::*;
+pub use render::*;
+pub use search::*;
+pub use validate::*;
 
-#[derive(Debug, Default)]
-struct FoldState<'a> {
-    out: String,       // mutable output
-    offset: usize,     // mutable offset
-    original: &'a str, // original input text
-}
+/// C interface, build with `--features ffi` and `--crate-type cdylib` to link it into C or C++
+#[cfg(feature = "ffi")]
+pub mod ffi;
 
-impl<'a> From<&'a str> for FoldState<'a> {
-    fn from(value: &'a str) -> Self {
-        FoldState {
-            out: String::new(),
-            offset: 0,
-            original: value,
-        }
-    }
-}
+/// Browser bindings behind the `wasm` feature, offsets are UTF-16 code units like JavaScript
+/// string indices and ranges are passed as flat `[lower, upper, lower, upper, ...]` arrays
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
-fn highlight_text(
-    input: &str,
-    highlights: Vec<HighlightRange>,
-) -> Result<String, HighlightingError> {
-    validate_ranges(input.len(), &highlights)?;
-
-    let mut highlights = highlights
-        .iter()
-        .flat_map(|hr| {
-            [
-                (hr.lower as usize, HighlightingBoundry::Start),
-                (hr.upper as usize, HighlightingBoundry::End),
-            ]
-        })
-        .collect::<Vec<(usize, HighlightingBoundry)>>();
-
-    highlights.sort_by_key(|(idx, _)| *idx);
-
-    let out = highlights.iter().fold(
-        FoldState::from(input),
-        |mut state, (next_high_pos, next_high_kind)| {
-            let next: &str = &state.original[state.offset..*next_high_pos];
-            state.out += next;
-            state.out += match next_high_kind {
-                HighlightingBoundry::Start => "<em>",
-                HighlightingBoundry::End => "</em>",
-            };
-            state.offset = *next_high_pos;
-            state
-        },
-    );
-
-    Ok(out.out + &input[out.offset..])
-}
+/// Range generators and invariant checks for fuzzing code that produces ranges, behind the
+/// `testing` feature; `proptest` adds them as proptest strategies
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
+/// Helpers shared by the unit tests of several modules
 #[cfg(test)]
-mod tests {
-    use super::*;
+mod test_support {
 
-    #[test]
-    fn should_swap_false_upper_and_lower() {
-        assert_eq!(HighlightRange::new(0, 5), HighlightRange::new(5, 0));
-    }
+    use core::fmt;
 
-    #[test]
-    fn should_hightlight() {
-        let actual = highlight_text("Hello world", vec![HighlightRange::new(0, 5)]).unwrap();
+    /// counts the allocations of the current thread, so tests running in parallel don't interfere
+    struct CountingAllocator;
 
-        assert_eq!("<em>Hello</em> world", actual);
+    std::thread_local! {
+        static ALLOCATIONS: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
     }
 
-    #[test]
-    fn should_hightlight_multiple_ranges() {
-        let actual = highlight_text(
-            "Hello world",
-            vec![HighlightRange::new(0, 5), HighlightRange::new(6, 11)],
-        )
-        .unwrap();
+    unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            ALLOCATIONS.with(|count| count.set(count.get() + 1));
+            unsafe { std::alloc::System.alloc(layout) }
+        }
 
-        assert_eq!("<em>Hello</em> <em>world</em>", actual);
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            unsafe { std::alloc::System.dealloc(ptr, layout) }
+        }
     }
 
-    #[test]
-    fn should_hightlight_multiple_ranges_when_ranges_touch() {
-        let actual = highlight_text(
-            "Hello world",
-            vec![HighlightRange::new(0, 5), HighlightRange::new(5, 11)],
-        )
-        .unwrap();
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
 
-        assert_eq!("<em>Hello</em><em> world</em>", actual);
+    pub(crate) fn count_allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+        let before = ALLOCATIONS.with(|count| count.get());
+        let result = f();
+        (result, ALLOCATIONS.with(|count| count.get()) - before)
     }
 
-    #[test]
-    fn should_return_err_invalid_ranges() {
-        let actual = highlight_text(
-            "Hello world",
-            vec![HighlightRange::new(0, 5), HighlightRange::new(4, 11)],
-        );
-
-        assert_eq!(Err(HighlightingError::OverlappingRanges), actual);
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub(crate) enum MatchKind {
+        Synonym,
+        Stemmed,
+        Exact,
     }
 
-    #[test]
-    fn should_return_err_out_of_bounds() {
-        let actual = highlight_text("Hello world", vec![HighlightRange::new(0, 50)]);
-
-        assert_eq!(Err(HighlightingError::RangesOutOfBounds), actual);
+    impl fmt::Display for MatchKind {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(match self {
+                MatchKind::Synonym => "synonym",
+                MatchKind::Stemmed => "stemmed",
+                MatchKind::Exact => "exact",
+            })
+        }
     }
 }
@@ -0,0 +1,9 @@
+//! The types and functions most callers need, `use highlight_ranges_kata::prelude::*`
+
+pub use crate::range::{HighlightRange, LabeledRange, merge_ranges, parse_ranges};
+pub use crate::render::{
+    Highlighter, HighlighterBuilder, Render, Segment, TagStyle, ValidatedHighlights,
+    highlight_segments, highlight_text, highlight_text_escaped, validate,
+};
+pub use crate::search::{CompiledQuery, MatchOptions, ranges_for_keywords, ranges_for_substring};
+pub use crate::validate::{BoundsPolicy, HighlightingError, OverlapPolicy};
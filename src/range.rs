@@ -0,0 +1,4656 @@
+//! [`HighlightRange`] itself and everything that builds, parses, converts or combines ranges
+//! without rendering them
+
+use alloc::{
+    borrow::{Borrow, Cow},
+    collections::{BTreeMap, BTreeSet},
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{
+    cmp::{Ordering, Reverse},
+    convert::Infallible,
+    fmt,
+    ops::{Range, RangeInclusive},
+    str::FromStr,
+};
+
+use crate::{render::*, validate::*};
+
+/// A [`HighlightRange`] rendered with `label` as its CSS class, see [`highlight_text_labeled`].
+/// The label can also be an own type, e.g. an enum of match kinds, see
+/// [`highlight_text_labeled_by`] and [`resolve_labeled_overlaps_ord`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabeledRange<L = String> {
+    pub range: HighlightRange,
+    pub label: L,
+}
+
+impl LabeledRange {
+    pub fn new(range: HighlightRange, label: impl Into<String>) -> Self {
+        LabeledRange {
+            range,
+            label: label.into(),
+        }
+    }
+}
+
+impl<L> LabeledRange<L> {
+    /// Same as [`LabeledRange::new`] for labels that aren't strings
+    pub fn with_label(range: HighlightRange, label: L) -> Self {
+        LabeledRange { range, label }
+    }
+}
+
+/// Ranges are ordered by `lower`, then `upper`; validation and rendering rely on that order to
+/// find overlaps and to place zero-length ranges in front of a range starting at the same offset.
+///
+/// Offset arithmetic never wraps, whatever the build profile: every function computing offsets
+/// either saturates at `0` and `usize::MAX` or returns an error, as its doc says.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "HighlightRangeRepr"))]
+pub struct HighlightRange {
+    /// inclusive
+    pub(crate) lower: usize,
+    /// exclusive
+    pub(crate) upper: usize,
+}
+
+impl HighlightRange {
+    /// lower = inclusive, upper = exclusive, swaps upper and lower if necessary; usable in const
+    /// context, e.g. for static tables checked with [`validate_ranges_const`]
+    pub const fn new(lower: usize, upper: usize) -> Self {
+        if lower < upper {
+            HighlightRange { lower, upper }
+        } else {
+            HighlightRange {
+                lower: upper,
+                upper: lower,
+            }
+        }
+    }
+
+    /// inclusive
+    pub const fn lower(&self) -> usize {
+        self.lower
+    }
+
+    /// exclusive
+    pub const fn upper(&self) -> usize {
+        self.upper
+    }
+
+    /// Same as [`HighlightRange::new`], but reversed bounds are rejected instead of swapped
+    pub const fn try_new(lower: usize, upper: usize) -> Result<Self, RangeConstructionError> {
+        if lower > upper {
+            return Err(RangeConstructionError::Reversed { lower, upper });
+        }
+        Ok(HighlightRange { lower, upper })
+    }
+
+    /// Same as [`HighlightRange::try_new`], but zero-length ranges are rejected as well
+    pub const fn try_new_non_empty(
+        lower: usize,
+        upper: usize,
+    ) -> Result<Self, RangeConstructionError> {
+        if lower == upper {
+            return Err(RangeConstructionError::Empty { at: lower });
+        }
+        HighlightRange::try_new(lower, upper)
+    }
+
+    /// `lower..=upper_inclusive` in the half-open representation, fails if `upper_inclusive` is
+    /// `usize::MAX` or smaller than `lower`; see [`Bounds`]
+    pub const fn new_inclusive(
+        lower: usize,
+        upper_inclusive: usize,
+    ) -> Result<Self, RangeConstructionError> {
+        let Some(upper) = upper_inclusive.checked_add(1) else {
+            return Err(RangeConstructionError::EndOverflow);
+        };
+        if lower > upper_inclusive {
+            return Err(RangeConstructionError::Reversed {
+                lower,
+                upper: upper_inclusive,
+            });
+        }
+        Ok(HighlightRange { lower, upper })
+    }
+
+    /// `(lower, upper_inclusive)`, the inverse of [`HighlightRange::new_inclusive`]; `None` for
+    /// zero-length ranges, which have no inclusive form
+    pub const fn to_inclusive(&self) -> Option<(usize, usize)> {
+        if self.is_empty() {
+            return None;
+        }
+        Some((self.lower, self.upper - 1))
+    }
+
+    /// `start..start + len`, for APIs reporting `(offset, length)` pairs; the end saturates at
+    /// `usize::MAX`, see [`HighlightRange::try_from_start_len`] to reject that instead
+    pub const fn from_start_len(start: usize, len: usize) -> Self {
+        HighlightRange::new(start, start.saturating_add(len))
+    }
+
+    /// Same as [`HighlightRange::from_start_len`], but fails with
+    /// [`RangeConstructionError::EndOverflow`] if the end is past `usize::MAX`
+    pub const fn try_from_start_len(
+        start: usize,
+        len: usize,
+    ) -> Result<Self, RangeConstructionError> {
+        match start.checked_add(len) {
+            Some(upper) => Ok(HighlightRange {
+                lower: start,
+                upper,
+            }),
+            None => Err(RangeConstructionError::EndOverflow),
+        }
+    }
+
+    pub const fn len(&self) -> usize {
+        self.upper - self.lower
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.lower == self.upper
+    }
+
+    /// Grows the range by `by` on both sides, saturating at `0` and `usize::MAX`; the result
+    /// might reach past the input, see [`clamp_ranges`]
+    pub const fn expand(&self, by: usize) -> HighlightRange {
+        HighlightRange::new(self.lower.saturating_sub(by), self.upper.saturating_add(by))
+    }
+
+    /// Narrows the range by `by` on both sides, a range shorter than `2 * by` collapses to an
+    /// empty one at its middle
+    pub const fn shrink(&self, by: usize) -> HighlightRange {
+        let len = self.upper - self.lower;
+        if by.saturating_mul(2) >= len {
+            let middle = self.lower + len / 2;
+            return HighlightRange::new(middle, middle);
+        }
+        HighlightRange::new(self.lower + by, self.upper - by)
+    }
+}
+
+/// Accepts `{"lower": 0, "upper": 5}` as well as the compact `[0, 5]`, both go through
+/// [`HighlightRange::new`] so reversed bounds are swapped
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum HighlightRangeRepr {
+    Object { lower: usize, upper: usize },
+    Tuple(usize, usize),
+}
+
+#[cfg(feature = "serde")]
+impl From<HighlightRangeRepr> for HighlightRange {
+    fn from(value: HighlightRangeRepr) -> Self {
+        match value {
+            HighlightRangeRepr::Object { lower, upper } => HighlightRange::new(lower, upper),
+            HighlightRangeRepr::Tuple(lower, upper) => HighlightRange::new(lower, upper),
+        }
+    }
+}
+
+impl From<Range<usize>> for HighlightRange {
+    fn from(value: Range<usize>) -> Self {
+        HighlightRange::new(value.start, value.end)
+    }
+}
+
+/// Why [`HighlightRange::try_new`] rejected its bounds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeConstructionError {
+    /// `lower` is greater than `upper`
+    Reversed { lower: usize, upper: usize },
+    /// `lower` and `upper` are both `at`, see [`HighlightRange::try_new_non_empty`]
+    Empty { at: usize },
+    /// the upper bound would be past `usize::MAX`: an inclusive upper bound of `usize::MAX` has no
+    /// exclusive counterpart, and a start and length may add up to more
+    EndOverflow,
+}
+
+impl fmt::Display for RangeConstructionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RangeConstructionError::Reversed { lower, upper } => {
+                write!(f, "lower bound {lower} is greater than upper bound {upper}")
+            }
+            RangeConstructionError::Empty { at } => write!(f, "range {at}..{at} is empty"),
+            RangeConstructionError::EndOverflow => f.write_str("upper bound overflows usize"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RangeConstructionError {}
+
+impl TryFrom<(usize, usize)> for HighlightRange {
+    type Error = RangeConstructionError;
+
+    /// see [`HighlightRange::try_new`]
+    fn try_from((lower, upper): (usize, usize)) -> Result<Self, Self::Error> {
+        HighlightRange::try_new(lower, upper)
+    }
+}
+
+impl From<&HighlightRange> for HighlightRange {
+    fn from(value: &HighlightRange) -> Self {
+        value.clone()
+    }
+}
+
+impl From<RangeInclusive<usize>> for HighlightRange {
+    /// the end saturates at `usize::MAX` like [`HighlightRange::from_start_len`], see
+    /// [`HighlightRange::new_inclusive`] to reject that instead
+    fn from(value: RangeInclusive<usize>) -> Self {
+        let (start, end) = value.into_inner();
+        HighlightRange::new(start, end.saturating_add(1))
+    }
+}
+
+impl fmt::Display for HighlightRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.lower, self.upper)
+    }
+}
+
+/// Why a token couldn't be parsed into a [`HighlightRange`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeParseErrorKind {
+    /// neither `..` nor `-` between the bounds
+    MissingSeparator,
+    /// the contained bound isn't an unsigned integer
+    InvalidNumber(String),
+    /// lower is greater than upper, unlike [`HighlightRange::new`] the parser doesn't swap them
+    ReversedBounds,
+    /// an inclusive upper bound of `usize::MAX`, see [`Bounds::Inclusive`]
+    EndOverflow,
+}
+
+/// Whether the upper bound of a range in text form is part of the range, see
+/// [`HighlightRange::parse_with`]; ranges are always half-open once parsed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Bounds {
+    /// `0-5` is `0..5`
+    #[default]
+    Exclusive,
+    /// `0-4` is `0..5`
+    Inclusive,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeParseError {
+    /// the token that failed to parse
+    pub token: String,
+    pub kind: RangeParseErrorKind,
+}
+
+impl fmt::Display for RangeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid range `{}`: ", self.token)?;
+        match &self.kind {
+            RangeParseErrorKind::MissingSeparator => f.write_str("expected `a..b` or `a-b`"),
+            RangeParseErrorKind::InvalidNumber(bound) => write!(f, "`{bound}` is not a number"),
+            RangeParseErrorKind::ReversedBounds => f.write_str("lower is greater than upper"),
+            RangeParseErrorKind::EndOverflow => f.write_str("inclusive upper bound is too large"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for RangeParseError {}
+
+impl FromStr for HighlightRange {
+    type Err = RangeParseError;
+
+    /// accepts `a..b` as well as `a-b`, with an exclusive upper bound
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        HighlightRange::parse_with(s, Bounds::Exclusive)
+    }
+}
+
+impl HighlightRange {
+    /// Same as [`FromStr`], but `bounds` decides whether the upper bound is inclusive
+    pub fn parse_with(s: &str, bounds: Bounds) -> Result<Self, RangeParseError> {
+        let error = |kind| RangeParseError {
+            token: s.to_string(),
+            kind,
+        };
+
+        let (lower, upper) = s
+            .split_once("..")
+            .or_else(|| s.split_once('-'))
+            .ok_or_else(|| error(RangeParseErrorKind::MissingSeparator))?;
+        let parse = |bound: &str| {
+            bound
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| error(RangeParseErrorKind::InvalidNumber(bound.to_string())))
+        };
+        let (lower, upper) = (parse(lower)?, parse(upper)?);
+
+        match bounds {
+            Bounds::Exclusive => HighlightRange::try_new(lower, upper),
+            Bounds::Inclusive => HighlightRange::new_inclusive(lower, upper),
+        }
+        .map_err(|e| {
+            error(match e {
+                RangeConstructionError::EndOverflow => RangeParseErrorKind::EndOverflow,
+                _ => RangeParseErrorKind::ReversedBounds,
+            })
+        })
+    }
+}
+
+/// Parses a comma- and/or whitespace-separated list like `"0-5,6-11 20..24"`
+pub fn parse_ranges(s: &str) -> Result<Vec<HighlightRange>, RangeParseError> {
+    parse_ranges_with(s, Bounds::Exclusive)
+}
+
+/// Same as [`parse_ranges`], but `bounds` decides whether the upper bounds are inclusive
+pub fn parse_ranges_with(s: &str, bounds: Bounds) -> Result<Vec<HighlightRange>, RangeParseError> {
+    s.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|token| !token.is_empty())
+        .map(|token| HighlightRange::parse_with(token, bounds))
+        .collect()
+}
+
+/// Sorts by the [`Ord`] of [`HighlightRange`]: by `lower`, then `upper`
+pub fn sort_ranges(highlights: &mut [HighlightRange]) {
+    highlights.sort();
+}
+
+/// Sorts the ranges and removes exact duplicates
+pub fn dedup_ranges(highlights: &mut Vec<HighlightRange>) {
+    sort_ranges(highlights);
+    highlights.dedup();
+}
+
+/// Removes exact duplicates and ranges that lie entirely within another range, keeping the
+/// remaining ranges in their original order. Of two identical ranges the first one is kept, so
+/// only genuine partial overlaps are left for validation to reject. Zero-length ranges are only
+/// removed as exact duplicates.
+pub fn dedup_contained_ranges(highlights: &mut Vec<HighlightRange>) {
+    dedup_contained_by(highlights, |h| h);
+}
+
+/// Same as [`dedup_contained_ranges`] for values carrying a range, e.g. [`LabeledRange`] or
+/// [`AnnotatedRange`]; the payload of the first occurrence wins
+pub fn dedup_contained_by<T>(items: &mut Vec<T>, range: impl Fn(&T) -> &HighlightRange) {
+    // longest first among equal lower bounds, so containing ranges come before their contents
+    let mut order = (0..items.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| {
+        let h = range(&items[i]);
+        (h.lower, Reverse(h.upper), i)
+    });
+
+    let mut keep = vec![true; items.len()];
+    let mut previous: Option<&HighlightRange> = None;
+    let mut reach = None;
+    for i in order {
+        let h = range(&items[i]);
+        let duplicate = previous == Some(h);
+        let contained = !h.is_empty() && reach.is_some_and(|upper| h.upper <= upper);
+        if duplicate || contained {
+            keep[i] = false;
+        } else if !h.is_empty() {
+            reach = Some(reach.map_or(h.upper, |upper: usize| upper.max(h.upper)));
+        }
+        previous = Some(h);
+    }
+
+    let mut keep = keep.into_iter();
+    items.retain(|_| keep.next().unwrap_or(true));
+}
+
+/// Moves every range by `delta` bytes, e.g. after prepending a prefix to the text they were
+/// computed for. Fails without touching any range if one of them would move below zero or
+/// past `usize::MAX`.
+pub fn shift_ranges(
+    highlights: &mut [HighlightRange],
+    delta: isize,
+) -> Result<(), HighlightingError> {
+    let shift = |offset: usize| offset.checked_add_signed(delta);
+    if let Some(range) = highlights
+        .iter()
+        .find(|h| shift(h.lower).is_none() || shift(h.upper).is_none())
+    {
+        return Err(HighlightingError::ShiftOutOfRange {
+            range: range.clone(),
+            delta,
+        });
+    }
+
+    for h in highlights {
+        h.lower = shift(h.lower).expect("checked above");
+        h.upper = shift(h.upper).expect("checked above");
+    }
+    Ok(())
+}
+
+/// Part of a concatenation whose ranges don't fit it, see [`concat_with_ranges`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConcatError {
+    /// index into the parts
+    pub part: usize,
+    /// measured against the part on its own
+    pub error: HighlightingError,
+}
+
+impl fmt::Display for ConcatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "part {}: {}", self.part, self.error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ConcatError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Joins the parts with `separator`, e.g. fields with `" — "`, and moves the ranges of every part
+/// into the joined text. The ranges of each part are checked against that part first, in bounds
+/// and on char boundaries; overlaps are left to the highlighting.
+pub fn concat_with_ranges(
+    parts: &[(&str, &[HighlightRange])],
+    separator: &str,
+) -> Result<(String, Vec<HighlightRange>), ConcatError> {
+    concat_parts(parts, separator, false)
+}
+
+/// Same as [`concat_with_ranges`], but a separator between a part ending in a highlight and a
+/// part starting with one is highlighted as well, by a range of its own touching both; merge
+/// them with [`merge_ranges`] to render one highlight
+pub fn concat_with_ranges_bridged(
+    parts: &[(&str, &[HighlightRange])],
+    separator: &str,
+) -> Result<(String, Vec<HighlightRange>), ConcatError> {
+    concat_parts(parts, separator, true)
+}
+
+fn concat_parts(
+    parts: &[(&str, &[HighlightRange])],
+    separator: &str,
+    bridge: bool,
+) -> Result<(String, Vec<HighlightRange>), ConcatError> {
+    for (part, (text, highlights)) in parts.iter().enumerate() {
+        validate_bounds(text, highlights).map_err(|error| ConcatError { part, error })?;
+    }
+
+    let len = parts.iter().map(|(text, _)| text.len()).sum::<usize>()
+        + separator.len() * parts.len().saturating_sub(1);
+    let mut joined = String::with_capacity(len);
+    let mut ranges = Vec::with_capacity(parts.iter().map(|(_, h)| h.len()).sum());
+    let mut ends_highlighted = false;
+    for (i, (text, highlights)) in parts.iter().enumerate() {
+        if i > 0 {
+            let starts_highlighted = highlights.iter().any(|h| h.lower == 0 && !h.is_empty());
+            if bridge && ends_highlighted && starts_highlighted {
+                ranges.push(HighlightRange::new(
+                    joined.len(),
+                    joined.len() + separator.len(),
+                ));
+            }
+            joined.push_str(separator);
+        }
+
+        let offset = joined.len();
+        ranges.extend(
+            highlights
+                .iter()
+                .map(|h| HighlightRange::new(h.lower + offset, h.upper + offset)),
+        );
+        joined.push_str(text);
+        ends_highlighted = highlights
+            .iter()
+            .any(|h| h.upper == text.len() && !h.is_empty());
+    }
+
+    Ok((joined, ranges))
+}
+
+/// `deleted` bytes at `at` replaced with `inserted` new ones, see [`transform_ranges`]; offsets
+/// computed from an edit saturate at `usize::MAX`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextEdit {
+    pub at: usize,
+    pub deleted: usize,
+    pub inserted: usize,
+}
+
+impl TextEdit {
+    pub fn insert(at: usize, len: usize) -> Self {
+        TextEdit {
+            at,
+            deleted: 0,
+            inserted: len,
+        }
+    }
+
+    pub fn delete(at: usize, len: usize) -> Self {
+        TextEdit {
+            at,
+            deleted: len,
+            inserted: 0,
+        }
+    }
+
+    /// Offset of the text at `offset` after the edit, offsets inside the deleted text move to
+    /// `at`
+    fn map_offset(&self, offset: usize) -> usize {
+        match offset {
+            o if o <= self.at => o,
+            o if self.at.checked_add(self.deleted).is_none_or(|end| o < end) => self.at,
+            o => (o - self.deleted).saturating_add(self.inserted),
+        }
+    }
+}
+
+/// Moves ranges computed before `edit` onto the edited text: ranges after the edit shift, ranges
+/// containing it grow or shrink and ranges partially covered by the deletion are clipped.
+/// Non-empty ranges inside the deleted text are removed. Text inserted at the start or end of
+/// a range is left out of it; zero-length ranges stay in front of text inserted at them.
+pub fn transform_ranges(highlights: &mut Vec<HighlightRange>, edit: &TextEdit) {
+    let deleted = edit.at..edit.at.saturating_add(edit.deleted);
+    highlights.retain_mut(|h| {
+        if !h.is_empty() && deleted.start <= h.lower && h.upper <= deleted.end {
+            return false;
+        }
+
+        if h.is_empty() {
+            let offset = edit.map_offset(h.lower);
+            *h = HighlightRange::new(offset, offset);
+            return true;
+        }
+
+        // text inserted at the start goes in front of the range
+        h.lower = match h.lower == edit.at || deleted.contains(&h.lower) {
+            true => edit.at.saturating_add(edit.inserted),
+            false => edit.map_offset(h.lower),
+        };
+        h.upper = edit.map_offset(h.upper);
+        true
+    });
+}
+
+/// Same as [`transform_ranges`] for edits that were applied one after another, each with
+/// offsets into the text as left by the ones before it
+pub fn transform_ranges_batch<'e>(
+    highlights: &mut Vec<HighlightRange>,
+    edits: impl IntoIterator<Item = &'e TextEdit>,
+) {
+    for edit in edits {
+        transform_ranges(highlights, edit);
+    }
+}
+
+/// Chars [`split_long_ranges`] looks back from a cut for whitespace to cut after instead
+const SPLIT_LOOK_BACK: usize = 16;
+
+/// Splits every range longer than `max_len` chars into touching ranges of at most `max_len`
+/// chars, which are rendered as separate highlights, e.g. so no highlight of a minified blob
+/// gets too long to measure. A cut is moved back to just after whitespace if there is some
+/// among the last `max_len / 4` (at most 16) chars, so words are only cut when they have to
+/// be. The pieces replace their range in place; ranges have to be in bounds and on char
+/// boundaries, and `max_len` can't be 0.
+pub fn split_long_ranges(
+    ranges: Vec<HighlightRange>,
+    max_len: usize,
+    input: &str,
+) -> Result<Vec<HighlightRange>, HighlightingError> {
+    if max_len == 0 {
+        return Err(HighlightingError::ZeroMaxLen);
+    }
+    validate_bounds(input, &ranges)?;
+
+    let look_back = (max_len / 4).min(SPLIT_LOOK_BACK);
+    let mut split = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        let mut lower = range.lower;
+        loop {
+            let rest = &input[lower..range.upper];
+            let Some((cut, _)) = rest.char_indices().nth(max_len) else {
+                split.push(HighlightRange::new(lower, range.upper));
+                break;
+            };
+            let len = rest[..cut]
+                .char_indices()
+                .rev()
+                .take(look_back)
+                .find(|(_, c)| c.is_whitespace())
+                .map_or(cut, |(i, c)| i + c.len_utf8());
+            split.push(HighlightRange::new(lower, lower + len));
+            lower += len;
+        }
+    }
+
+    Ok(split)
+}
+
+/// The ranges clipped to `bounds`, ranges that end up empty (including zero-length ones) are
+/// dropped; unlike [`clip_ranges`], the offsets aren't shifted
+pub fn clamp_ranges(highlights: &[HighlightRange], bounds: Range<usize>) -> Vec<HighlightRange> {
+    highlights
+        .iter()
+        .filter(|h| h.lower < h.upper && h.lower < bounds.end && h.upper > bounds.start)
+        .map(|h| HighlightRange::new(h.lower.max(bounds.start), h.upper.min(bounds.end)))
+        .collect()
+}
+
+/// Canonical form of `highlights`: sorted, with every overlapping or touching pair coalesced into
+/// one range and zero-length ranges dropped. Covers exactly the same bytes as the input and never
+/// fails; used by [`OverlapPolicy::Merge`].
+pub fn merge_ranges(mut highlights: Vec<HighlightRange>) -> Vec<HighlightRange> {
+    highlights.retain(|r| r.lower != r.upper);
+    sort_ranges(&mut highlights);
+
+    let mut merged: Vec<HighlightRange> = Vec::with_capacity(highlights.len());
+    for range in highlights {
+        match merged.last_mut() {
+            Some(last) if range.lower <= last.upper => last.upper = last.upper.max(range.upper),
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+/// Same as [`merge_ranges`], but ranges separated by a gap of at most `max_gap` bytes are joined
+/// as well, highlighting the gap; `max_gap = 0` only joins touching ranges like [`merge_ranges`]
+pub fn merge_ranges_with_gap(
+    highlights: Vec<HighlightRange>,
+    max_gap: usize,
+) -> Vec<HighlightRange> {
+    bridge_gaps(merge_ranges(highlights), max_gap, |_| true)
+}
+
+/// Same as [`merge_ranges_with_gap`], but a gap is only bridged if `pred` accepts the text of
+/// `input` it covers, e.g. `|gap| gap.trim().is_empty()`; gaps that don't fall on char
+/// boundaries of `input` are never bridged
+pub fn merge_ranges_with_gap_if(
+    input: &str,
+    highlights: Vec<HighlightRange>,
+    max_gap: usize,
+    pred: impl Fn(&str) -> bool,
+) -> Vec<HighlightRange> {
+    bridge_gaps(merge_ranges(highlights), max_gap, |gap| {
+        input.get(gap.lower..gap.upper).is_some_and(&pred)
+    })
+}
+
+/// What [`join_phrase_ranges_with`] accepts between two ranges of a phrase besides spaces and
+/// tabs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhraseJoinOptions<'j> {
+    /// chars joining the words of a phrase, by default hyphens and apostrophes
+    pub joiners: &'j [char],
+    /// whether a phrase continues across line breaks
+    pub across_lines: bool,
+}
+
+impl Default for PhraseJoinOptions<'_> {
+    fn default() -> Self {
+        PhraseJoinOptions {
+            joiners: &['-', '\'', '’'],
+            across_lines: false,
+        }
+    }
+}
+
+/// Joins ranges of the tokens of a phrase, e.g. the hits for "New", "York" and "City", into a
+/// single range over the phrase with the default [`PhraseJoinOptions`]: a gap is bridged if it's
+/// only whitespace within a line, hyphens and apostrophes, whatever its length. Ranges separated
+/// by other words or punctuation are merged like [`merge_ranges`] only.
+pub fn join_phrase_ranges(input: &str, ranges: Vec<HighlightRange>) -> Vec<HighlightRange> {
+    join_phrase_ranges_with(input, ranges, PhraseJoinOptions::default())
+}
+
+/// Same as [`join_phrase_ranges`], but configured by `options`
+pub fn join_phrase_ranges_with(
+    input: &str,
+    ranges: Vec<HighlightRange>,
+    options: PhraseJoinOptions<'_>,
+) -> Vec<HighlightRange> {
+    merge_ranges_with_gap_if(input, ranges, usize::MAX, |gap| {
+        gap.chars().all(|c| match c {
+            '\n' | '\r' => options.across_lines,
+            c => c.is_whitespace() || options.joiners.contains(&c),
+        })
+    })
+}
+
+/// Joins neighbours of merged, sorted `highlights` whose gap is at most `max_gap` bytes and
+/// passes `bridge`
+fn bridge_gaps(
+    highlights: Vec<HighlightRange>,
+    max_gap: usize,
+    bridge: impl Fn(&HighlightRange) -> bool,
+) -> Vec<HighlightRange> {
+    let mut bridged: Vec<HighlightRange> = Vec::with_capacity(highlights.len());
+    for range in highlights {
+        match bridged.last_mut() {
+            Some(last)
+                if range.lower - last.upper <= max_gap
+                    && bridge(&HighlightRange::new(last.upper, range.lower)) =>
+            {
+                last.upper = range.upper
+            }
+            _ => bridged.push(range),
+        }
+    }
+
+    bridged
+}
+
+/// Bytes covered by `a` or `b`, in the canonical form of [`merge_ranges`]
+pub fn union_ranges(a: &[HighlightRange], b: &[HighlightRange]) -> Vec<HighlightRange> {
+    merge_ranges(a.iter().chain(b).cloned().collect())
+}
+
+/// Bytes covered by both `a` and `b`, in the canonical form of [`merge_ranges`]; ranges that
+/// only touch don't share a byte and produce nothing
+pub fn intersect_ranges(a: &[HighlightRange], b: &[HighlightRange]) -> Vec<HighlightRange> {
+    let (a, b) = (merge_ranges(a.to_vec()), merge_ranges(b.to_vec()));
+
+    let mut intersection = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while let (Some(x), Some(y)) = (a.get(i), b.get(j)) {
+        let (lower, upper) = (x.lower.max(y.lower), x.upper.min(y.upper));
+        if lower < upper {
+            intersection.push(HighlightRange::new(lower, upper));
+        }
+        // the range ending first can't intersect anything after the other one
+        if x.upper < y.upper {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    intersection
+}
+
+/// Bytes covered by `a` but not by `b`, in the canonical form of [`merge_ranges`]
+pub fn subtract_ranges(a: &[HighlightRange], b: &[HighlightRange]) -> Vec<HighlightRange> {
+    let b = merge_ranges(b.to_vec());
+
+    let mut difference = Vec::new();
+    let mut j = 0;
+    for x in merge_ranges(a.to_vec()) {
+        let mut lower = x.lower;
+        // skip the subtrahends that end before this range, they can't affect later ones either
+        while b.get(j).is_some_and(|y| y.upper <= lower) {
+            j += 1;
+        }
+        for y in b[j..].iter().take_while(|y| y.lower < x.upper) {
+            if lower < y.lower {
+                difference.push(HighlightRange::new(lower, y.lower));
+            }
+            lower = lower.max(y.upper);
+        }
+        if lower < x.upper {
+            difference.push(HighlightRange::new(lower, x.upper));
+        }
+    }
+
+    difference
+}
+
+/// Removes the regions covered by `exclusions` from every highlight: a highlight straddling an
+/// exclusion is split into the pieces outside of it, pieces that end up empty are dropped. Unlike
+/// [`subtract_ranges`] the highlights aren't merged, they keep their order and the exclusions
+/// may overlap each other. A zero-length range is dropped if it lies strictly inside an
+/// exclusion.
+pub fn subtract_exclusions(
+    highlights: &[HighlightRange],
+    exclusions: &[HighlightRange],
+) -> Vec<HighlightRange> {
+    cut_exclusions(highlights, &merge_ranges(exclusions.to_vec()))
+}
+
+/// [`subtract_exclusions`] with exclusions in the canonical form of [`merge_ranges`]
+pub(crate) fn cut_exclusions(
+    highlights: &[HighlightRange],
+    exclusions: &[HighlightRange],
+) -> Vec<HighlightRange> {
+    let mut pieces = Vec::with_capacity(highlights.len());
+    for h in highlights {
+        // merged exclusions are sorted by `upper` as well, so the ones ending before `h` are a
+        // prefix
+        let start = exclusions.partition_point(|e| e.upper <= h.lower);
+        let exclusions = &exclusions[start..];
+
+        if h.is_empty() {
+            if exclusions.first().is_none_or(|e| e.lower >= h.lower) {
+                pieces.push(h.clone());
+            }
+            continue;
+        }
+
+        let mut lower = h.lower;
+        for e in exclusions.iter().take_while(|e| e.lower < h.upper) {
+            if lower < e.lower {
+                pieces.push(HighlightRange::new(lower, e.lower));
+            }
+            lower = lower.max(e.upper);
+        }
+        if lower < h.upper {
+            pieces.push(HighlightRange::new(lower, h.upper));
+        }
+    }
+
+    pieces
+}
+
+/// Gaps between the ranges within `0..input_len`, including the leading and trailing one; the
+/// complement of `highlights`, in the canonical form of [`merge_ranges`]
+pub fn invert_ranges(input_len: usize, highlights: &[HighlightRange]) -> Vec<HighlightRange> {
+    subtract_ranges(&[HighlightRange::new(0, input_len)], highlights)
+}
+
+/// Whether byte `pos` lies in one of the sorted, non-overlapping `highlights` (e.g. validated
+/// ones or [`HighlightSet::as_slice`]); `lower` is inclusive and `upper` exclusive, as rendered
+pub fn is_highlighted(highlights: &[HighlightRange], pos: usize) -> bool {
+    range_covering(highlights, pos).is_some()
+}
+
+/// The range containing byte `pos`, see [`is_highlighted`]
+pub fn range_covering(highlights: &[HighlightRange], pos: usize) -> Option<&HighlightRange> {
+    // non-overlapping and sorted by lower, so the uppers are sorted as well
+    let idx = highlights.partition_point(|r| r.upper <= pos);
+    highlights.get(idx).filter(|r| r.lower <= pos)
+}
+
+/// The sorted, non-overlapping `highlights` sharing at least one byte position with `window`,
+/// see [`is_highlighted`]
+pub fn ranges_in(highlights: &[HighlightRange], window: Range<usize>) -> &[HighlightRange] {
+    if window.is_empty() {
+        return &[];
+    }
+    let start = highlights.partition_point(|r| r.upper <= window.start);
+    let end = highlights.partition_point(|r| r.lower < window.end);
+    // `max` only matters for unsorted input, where the result is unspecified but mustn't panic
+    &highlights[start..end.max(start)]
+}
+
+/// Which units of an input are highlighted, as alternating runs of highlighted and plain units
+/// that together cover the whole input; see [`highlight_mask`]. With the `serde` feature it is
+/// serialized as `{"unit": "Bytes", "first_highlighted": false, "runs": [4, 3, 9]}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "RunLengthMaskRepr"))]
+pub struct RunLengthMask {
+    unit: IndexKind,
+    /// whether the first run is highlighted, the following ones alternate
+    first_highlighted: bool,
+    /// lengths of the runs in `unit`, none of them zero
+    runs: Vec<usize>,
+}
+
+/// Serialized form of [`RunLengthMask`], checked for empty runs when deserializing
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct RunLengthMaskRepr {
+    unit: IndexKind,
+    first_highlighted: bool,
+    runs: Vec<usize>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RunLengthMaskRepr> for RunLengthMask {
+    type Error = &'static str;
+
+    fn try_from(value: RunLengthMaskRepr) -> Result<Self, Self::Error> {
+        if value.runs.contains(&0) {
+            return Err("runs of a mask can't be empty");
+        }
+        Ok(RunLengthMask {
+            unit: value.unit,
+            first_highlighted: value.first_highlighted,
+            runs: value.runs,
+        })
+    }
+}
+
+impl RunLengthMask {
+    pub fn unit(&self) -> IndexKind {
+        self.unit
+    }
+
+    /// The `(len, highlighted)` runs in input order
+    pub fn iter(&self) -> impl Iterator<Item = (usize, bool)> + '_ {
+        let highlighted = (0..).map(move |i| self.first_highlighted == (i % 2 == 0));
+        self.runs.iter().copied().zip(highlighted)
+    }
+
+    /// Length of the input in [`RunLengthMask::unit`]
+    pub fn len(&self) -> usize {
+        self.runs.iter().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    /// Number of highlighted units
+    pub fn covered_len(&self) -> usize {
+        self.iter()
+            .filter(|&(_, highlighted)| highlighted)
+            .map(|(len, _)| len)
+            .sum()
+    }
+
+    /// The highlighted runs as ranges in [`RunLengthMask::unit`], in the canonical form of
+    /// [`merge_ranges`]
+    pub fn to_ranges(&self) -> Vec<HighlightRange> {
+        let mut ranges = Vec::with_capacity(self.runs.len().div_ceil(2));
+        let mut offset = 0;
+        for (len, highlighted) in self.iter() {
+            if highlighted {
+                ranges.push(HighlightRange::new(offset, offset + len));
+            }
+            offset += len;
+        }
+
+        ranges
+    }
+}
+
+/// Which units of `input` the `ranges` highlight, without any markup; offsets and run lengths
+/// are counted in `unit`. The ranges are validated like with [`validate_ranges_as`] and then
+/// merged like with [`merge_ranges`], so touching ranges share a run and zero-length ones vanish.
+pub fn highlight_mask(
+    input: &str,
+    ranges: &[HighlightRange],
+    unit: IndexKind,
+) -> Result<RunLengthMask, HighlightingError> {
+    validate_ranges_as(input, ranges, unit)?;
+
+    let input_len = match unit {
+        IndexKind::Bytes => input.len(),
+        IndexKind::Chars => input.chars().count(),
+    };
+    let merged = merge_ranges(ranges.to_vec());
+    let first_highlighted = merged.first().is_some_and(|r| r.lower == 0);
+
+    let mut runs = Vec::with_capacity(merged.len() * 2 + 1);
+    let mut offset = 0;
+    for range in merged {
+        if range.lower > offset {
+            runs.push(range.lower - offset);
+        }
+        runs.push(range.len());
+        offset = range.upper;
+    }
+    if input_len > offset {
+        runs.push(input_len - offset);
+    }
+
+    Ok(RunLengthMask {
+        unit,
+        first_highlighted,
+        runs,
+    })
+}
+
+/// Coverage of a document, see [`highlight_stats`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HighlightStats {
+    pub highlighted_bytes: usize,
+    pub highlighted_chars: usize,
+    pub total_chars: usize,
+    /// number of highlights after [`merge_ranges`]
+    pub span_count: usize,
+    /// merged span with the most chars, the first one if several are equally long
+    pub longest_span: Option<HighlightRange>,
+}
+
+impl HighlightStats {
+    /// Fraction of chars that are highlighted, `0.0` for an empty input
+    pub fn coverage(&self) -> f64 {
+        if self.total_chars == 0 {
+            0.0
+        } else {
+            self.highlighted_chars as f64 / self.total_chars as f64
+        }
+    }
+}
+
+/// Counts what would be highlighted with [`OverlapPolicy::Merge`]; overlapping ranges are fine,
+/// but every range has to be in bounds and on char boundaries
+pub fn highlight_stats(
+    input: &str,
+    highlights: &[HighlightRange],
+) -> Result<HighlightStats, HighlightingError> {
+    validate_bounds(input, highlights)?;
+
+    let mut stats = HighlightStats {
+        highlighted_bytes: 0,
+        highlighted_chars: 0,
+        total_chars: input.chars().count(),
+        span_count: 0,
+        longest_span: None,
+    };
+    let mut longest_chars = 0;
+    for span in merge_ranges(highlights.to_vec()) {
+        let chars = input[span.lower..span.upper].chars().count();
+        stats.highlighted_bytes += span.upper - span.lower;
+        stats.highlighted_chars += chars;
+        stats.span_count += 1;
+        if chars > longest_chars {
+            longest_chars = chars;
+            stats.longest_span = Some(span);
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Sorted, disjoint and non-touching ranges, kept that way on every [`HighlightSet::insert`] so
+/// ranges can be collected incrementally without re-validating the whole list
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HighlightSet {
+    ranges: Vec<HighlightRange>,
+}
+
+impl HighlightSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `range`, merging it with every range it overlaps or touches; zero-length ranges
+    /// don't cover any byte and are ignored
+    pub fn insert(&mut self, range: impl Into<HighlightRange>) {
+        let range = range.into();
+        if range.lower == range.upper {
+            return;
+        }
+
+        let start = self.ranges.partition_point(|r| r.upper < range.lower);
+        let end = self.ranges.partition_point(|r| r.lower <= range.upper);
+        let merged = self.ranges[start..end].iter().fold(range, |acc, r| {
+            HighlightRange::new(acc.lower.min(r.lower), acc.upper.max(r.upper))
+        });
+
+        self.ranges.splice(start..end, [merged]);
+    }
+
+    /// Un-highlights every byte covered by `range`, splitting ranges that only partly overlap it;
+    /// returns whether anything was removed
+    pub fn remove(&mut self, range: impl Into<HighlightRange>) -> bool {
+        let range = range.into();
+        let start = self.ranges.partition_point(|r| r.upper <= range.lower);
+        let end = self.ranges.partition_point(|r| r.lower < range.upper);
+        if range.lower == range.upper || start >= end {
+            return false;
+        }
+
+        let first = &self.ranges[start];
+        let last = &self.ranges[end - 1];
+        let left =
+            (first.lower < range.lower).then(|| HighlightRange::new(first.lower, range.lower));
+        let right =
+            (last.upper > range.upper).then(|| HighlightRange::new(range.upper, last.upper));
+
+        self.ranges
+            .splice(start..end, left.into_iter().chain(right));
+        true
+    }
+
+    pub fn iter(&self) -> core::slice::Iter<'_, HighlightRange> {
+        self.ranges.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[HighlightRange] {
+        &self.ranges
+    }
+
+    /// See [`is_highlighted`]
+    pub fn is_highlighted(&self, pos: usize) -> bool {
+        is_highlighted(&self.ranges, pos)
+    }
+
+    /// See [`range_covering`]
+    pub fn range_covering(&self, pos: usize) -> Option<&HighlightRange> {
+        range_covering(&self.ranges, pos)
+    }
+
+    /// See [`ranges_in`]
+    pub fn ranges_in(&self, window: Range<usize>) -> &[HighlightRange] {
+        ranges_in(&self.ranges, window)
+    }
+
+    /// Same output as [`highlight_text`], but only bounds and char boundaries are checked since
+    /// the set can't contain overlaps
+    pub fn render(&self, input: &str) -> Result<String, HighlightingError> {
+        Ok(PreparedHighlights::from_sorted(input, &self.ranges)?.render())
+    }
+}
+
+impl<R: Into<HighlightRange>> FromIterator<R> for HighlightSet {
+    fn from_iter<I: IntoIterator<Item = R>>(iter: I) -> Self {
+        let mut set = HighlightSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<R: Into<HighlightRange>> Extend<R> for HighlightSet {
+    fn extend<I: IntoIterator<Item = R>>(&mut self, iter: I) {
+        for range in iter {
+            self.insert(range);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a HighlightSet {
+    type Item = &'a HighlightRange;
+    type IntoIter = core::slice::Iter<'a, HighlightRange>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl From<HighlightSet> for Vec<HighlightRange> {
+    fn from(set: HighlightSet) -> Self {
+        set.ranges
+    }
+}
+
+/// A [`HighlightRange`] carrying arbitrary data (an entity id, a tooltip, ...) that is handed back
+/// with the segments it produced, see [`highlight_segments_annotated`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnotatedRange<T> {
+    pub range: HighlightRange,
+    pub payload: T,
+}
+
+impl<T> AnnotatedRange<T> {
+    pub fn new(range: HighlightRange, payload: T) -> Self {
+        AnnotatedRange { range, payload }
+    }
+}
+
+/// Range with `(open, close)` tags that replace the highlighter's for it, see
+/// [`Highlighter::highlight_tagged`]
+pub type TaggedRange = AnnotatedRange<Option<(String, String)>>;
+
+/// One step of turning a source text into a target text, lengths are in bytes, see
+/// [`OffsetMap`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditOp {
+    /// the next bytes are copied unchanged
+    Keep(usize),
+    /// the next `from_len` bytes are replaced with `to_len` other ones
+    Replace { from_len: usize, to_len: usize },
+    /// the next bytes are dropped
+    Delete(usize),
+    /// bytes appear in the target that aren't in the source
+    Insert(usize),
+}
+
+impl EditOp {
+    fn lens(self) -> (usize, usize) {
+        match self {
+            EditOp::Keep(n) => (n, n),
+            EditOp::Replace { from_len, to_len } => (from_len, to_len),
+            EditOp::Delete(n) => (n, 0),
+            EditOp::Insert(n) => (0, n),
+        }
+    }
+}
+
+/// Translates ranges over a source text to a target text derived from it by a sequence of
+/// [`EditOp`]s, e.g. to map ranges found in a normalized text back onto the displayed one.
+/// Lengths and offsets saturate at `usize::MAX`, which no real text reaches.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OffsetMap {
+    /// `(source offset, target offset, op)`, without ops that don't change anything
+    ops: Vec<(usize, usize, EditOp)>,
+    source_len: usize,
+    target_len: usize,
+}
+
+impl OffsetMap {
+    pub fn new(ops: impl IntoIterator<Item = EditOp>) -> Self {
+        let mut map = OffsetMap::default();
+        for op in ops {
+            let (from_len, to_len) = op.lens();
+            if from_len == 0 && to_len == 0 {
+                continue;
+            }
+            map.ops.push((map.source_len, map.target_len, op));
+            map.source_len = map.source_len.saturating_add(from_len);
+            map.target_len = map.target_len.saturating_add(to_len);
+        }
+
+        map
+    }
+
+    pub fn source_len(&self) -> usize {
+        self.source_len
+    }
+
+    pub fn target_len(&self) -> usize {
+        self.target_len
+    }
+
+    /// The range of the target produced from the source bytes in `range`. A range covering part
+    /// of a replacement covers all of it, a range partially inside deleted text is clipped and
+    /// inserted text at either end of a range is left out. `None` if `range` lies entirely in
+    /// deleted text or reaches past the source; zero-length ranges stay zero-length.
+    pub fn map_range(&self, range: HighlightRange) -> Option<HighlightRange> {
+        if range.upper > self.source_len {
+            return None;
+        }
+
+        let lower = self.map_lower(range.lower);
+        if range.lower == range.upper {
+            return Some(HighlightRange::new(lower, lower));
+        }
+        let upper = self.map_upper(range.upper);
+        (lower < upper).then(|| HighlightRange::new(lower, upper))
+    }
+
+    /// The map from the target back to the source
+    pub fn inverse(&self) -> OffsetMap {
+        OffsetMap::new(self.ops.iter().map(|&(_, _, op)| match op {
+            EditOp::Keep(n) => EditOp::Keep(n),
+            EditOp::Replace { from_len, to_len } => EditOp::Replace {
+                from_len: to_len,
+                to_len: from_len,
+            },
+            EditOp::Delete(n) => EditOp::Insert(n),
+            EditOp::Insert(n) => EditOp::Delete(n),
+        }))
+    }
+
+    /// Target offset of the first byte produced from source bytes at or after `offset`
+    fn map_lower(&self, offset: usize) -> usize {
+        // the first op consuming the byte at `offset`, inserts there are skipped
+        let idx = self
+            .ops
+            .partition_point(|&(source, _, op)| source.saturating_add(op.lens().0) <= offset);
+        match self.ops.get(idx) {
+            Some(&(source, target, EditOp::Keep(_))) => target.saturating_add(offset - source),
+            Some(&(_, target, _)) => target,
+            None => self.target_len,
+        }
+    }
+
+    /// Target offset after the last byte produced from source bytes before `offset`
+    fn map_upper(&self, offset: usize) -> usize {
+        // the last op consuming bytes before `offset`, inserts there come after it
+        let Some(idx) = self
+            .ops
+            .partition_point(|&(source, _, _)| source < offset)
+            .checked_sub(1)
+        else {
+            return 0;
+        };
+        match self.ops[idx] {
+            (source, target, EditOp::Keep(_)) => target.saturating_add(offset - source),
+            (_, target, op) => target.saturating_add(op.lens().1),
+        }
+    }
+}
+
+/// NFC-normalized form of `input` and the [`OffsetMap`] from `input` to it. The map is built
+/// while normalizing: `input` is normalized piece by piece, split before every starter that
+/// doesn't compose with the text in front of it, and every piece that changes becomes one
+/// [`EditOp::Replace`].
+#[cfg(feature = "unicode-normalization")]
+pub fn nfc_with_offset_map(input: &str) -> (String, OffsetMap) {
+    use unicode_normalization::{
+        UnicodeNormalization,
+        char::{canonical_combining_class, compose, decompose_canonical},
+    };
+
+    let mut normalized = String::with_capacity(input.len());
+    let mut ops = Vec::new();
+    let mut flush = |piece: &str, normalized_piece: &str| {
+        if piece == normalized_piece {
+            ops.push(EditOp::Keep(piece.len()));
+        } else {
+            ops.push(EditOp::Replace {
+                from_len: piece.len(),
+                to_len: normalized_piece.len(),
+            });
+        }
+        normalized.push_str(normalized_piece);
+    };
+
+    let mut piece_start = 0;
+    for (i, c) in input.char_indices() {
+        if i == piece_start || canonical_combining_class(c) != 0 {
+            continue;
+        }
+
+        let normalized_piece = input[piece_start..i].nfc().collect::<String>();
+        let mut first = None;
+        decompose_canonical(c, |d| {
+            first.get_or_insert(d);
+        });
+        let composes = normalized_piece
+            .chars()
+            .last()
+            .zip(first)
+            .is_some_and(|(last, first)| compose(last, first).is_some());
+        if !composes {
+            flush(&input[piece_start..i], &normalized_piece);
+            piece_start = i;
+        }
+    }
+    let rest = &input[piece_start..];
+    flush(rest, &rest.nfc().collect::<String>());
+
+    (normalized, OffsetMap::new(ops))
+}
+
+/// NFC-normalized form of `input` and `highlights` moved from `input` onto it, see
+/// [`OffsetMap::map_range`]: a range covering part of a composed char covers all of it. Ranges
+/// reaching past `input` are dropped.
+#[cfg(feature = "unicode-normalization")]
+pub fn normalize_and_remap(
+    input: &str,
+    highlights: &[HighlightRange],
+) -> (String, Vec<HighlightRange>) {
+    let (normalized, map) = nfc_with_offset_map(input);
+    let remapped = highlights
+        .iter()
+        .filter_map(|h| map.map_range(h.clone()))
+        .collect();
+    (normalized, remapped)
+}
+
+/// The reverse of [`normalize_and_remap`]: moves `highlights`, computed on the NFC-normalized
+/// form of `original`, onto `original` itself
+#[cfg(feature = "unicode-normalization")]
+pub fn remap_from_nfc(original: &str, highlights: &[HighlightRange]) -> Vec<HighlightRange> {
+    let map = nfc_with_offset_map(original).1.inverse();
+    highlights
+        .iter()
+        .filter_map(|h| map.map_range(h.clone()))
+        .collect()
+}
+
+/// Which chars [`strip_for_display`] removes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CharPolicy {
+    /// format chars that don't render on their own: the soft hyphen, zero-width spaces and
+    /// joiners, bidi controls, word joiners and the byte order mark
+    pub invisible_formatting: bool,
+    /// U+0000 to U+001F, except for `\t`, `\n` and `\r`
+    pub c0_controls: bool,
+    pub custom: BTreeSet<char>,
+    /// put in place of every stripped char instead of dropping it
+    pub replacement: Option<char>,
+}
+
+impl CharPolicy {
+    pub fn strips(&self, c: char) -> bool {
+        let invisible_formatting = matches!(
+            c,
+            '\u{ad}'
+                | '\u{61c}'
+                | '\u{180e}'
+                | '\u{200b}'..='\u{200f}'
+                | '\u{202a}'..='\u{202e}'
+                | '\u{2060}'..='\u{2064}'
+                | '\u{2066}'..='\u{206f}'
+                | '\u{feff}'
+        );
+        let c0_control = c.is_ascii_control() && c != '\u{7f}' && !matches!(c, '\t' | '\n' | '\r');
+
+        (self.invisible_formatting && invisible_formatting)
+            || (self.c0_controls && c0_control)
+            || self.custom.contains(&c)
+    }
+}
+
+/// `input` without the chars `strip` removes, or with them replaced by
+/// [`CharPolicy::replacement`], and the [`OffsetMap`] from `input` to it. Ranges moved with
+/// [`OffsetMap::map_range`] shrink by the stripped chars they cover and shift by the ones in
+/// front of them.
+pub fn strip_for_display(input: &str, strip: &CharPolicy) -> (String, OffsetMap) {
+    let mut stripped = String::with_capacity(input.len());
+    let mut ops = Vec::new();
+    let mut kept_from = 0;
+    for (i, c) in input.char_indices().filter(|&(_, c)| strip.strips(c)) {
+        stripped.push_str(&input[kept_from..i]);
+        ops.push(EditOp::Keep(i - kept_from));
+        match strip.replacement {
+            Some(replacement) => {
+                stripped.push(replacement);
+                ops.push(EditOp::Replace {
+                    from_len: c.len_utf8(),
+                    to_len: replacement.len_utf8(),
+                });
+            }
+            None => ops.push(EditOp::Delete(c.len_utf8())),
+        }
+        kept_from = i + c.len_utf8();
+    }
+    stripped.push_str(&input[kept_from..]);
+    ops.push(EditOp::Keep(input.len() - kept_from));
+
+    (stripped, OffsetMap::new(ops))
+}
+
+/// A range given either in absolute byte offsets or relative to the end of the input, for when
+/// the length of the text isn't known yet, see [`resolve_ranges`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnchoredRange {
+    Absolute(HighlightRange),
+    /// the `len` units ending `end_gap` units before the end of the input
+    FromEnd {
+        len: usize,
+        end_gap: usize,
+        kind: IndexKind,
+    },
+}
+
+impl AnchoredRange {
+    /// The last `len` bytes before the final `end_gap` ones, `from_end(12, 0)` is the last 12
+    /// bytes
+    pub fn from_end(len: usize, end_gap: usize) -> Self {
+        AnchoredRange::FromEnd {
+            len,
+            end_gap,
+            kind: IndexKind::Bytes,
+        }
+    }
+
+    /// Same as [`AnchoredRange::from_end`], but counted in chars, so the range never splits one
+    pub fn from_end_chars(len: usize, end_gap: usize) -> Self {
+        AnchoredRange::FromEnd {
+            len,
+            end_gap,
+            kind: IndexKind::Chars,
+        }
+    }
+
+    /// The absolute byte range within `input`. Absolute ranges are returned unchanged and
+    /// validated later like any other range; a range from the end that would start before the
+    /// start of `input` fails with [`HighlightingError::AnchoredRangeUnderflow`].
+    pub fn resolve(&self, input: &str) -> Result<HighlightRange, HighlightingError> {
+        self.resolve_with(input, BoundsPolicy::Strict)
+    }
+
+    /// Same as [`AnchoredRange::resolve`], but with [`BoundsPolicy::Clamp`] a range from the end
+    /// that doesn't fit is cut off at the start of `input` instead
+    pub fn resolve_with(
+        &self,
+        input: &str,
+        policy: BoundsPolicy,
+    ) -> Result<HighlightRange, HighlightingError> {
+        let (len, end_gap, kind) = match *self {
+            AnchoredRange::Absolute(ref range) => return Ok(range.clone()),
+            AnchoredRange::FromEnd { len, end_gap, kind } => (len, end_gap, kind),
+        };
+
+        let input_len = match kind {
+            IndexKind::Bytes => input.len(),
+            IndexKind::Chars => input.chars().count(),
+        };
+        let upper = input_len.saturating_sub(end_gap);
+        let lower = match (upper.checked_sub(len), policy) {
+            (Some(lower), _) if end_gap <= input_len => lower,
+            (_, BoundsPolicy::Clamp) => upper.saturating_sub(len),
+            (_, BoundsPolicy::Strict) => {
+                return Err(HighlightingError::AnchoredRangeUnderflow {
+                    len,
+                    end_gap,
+                    input_len,
+                    kind,
+                });
+            }
+        };
+
+        let range = HighlightRange::new(lower, upper);
+        match kind {
+            IndexKind::Bytes => Ok(range),
+            IndexKind::Chars => char_range_to_byte_range(input, &range),
+        }
+    }
+}
+
+impl From<HighlightRange> for AnchoredRange {
+    fn from(range: HighlightRange) -> Self {
+        AnchoredRange::Absolute(range)
+    }
+}
+
+impl From<Range<usize>> for AnchoredRange {
+    fn from(range: Range<usize>) -> Self {
+        AnchoredRange::Absolute(range.into())
+    }
+}
+
+/// Resolves a mix of absolute and anchored ranges against `input`, see
+/// [`AnchoredRange::resolve_with`]
+pub fn resolve_ranges<R: Into<AnchoredRange>>(
+    input: &str,
+    highlights: impl IntoIterator<Item = R>,
+    policy: BoundsPolicy,
+) -> Result<Vec<HighlightRange>, HighlightingError> {
+    highlights
+        .into_iter()
+        .map(|h| h.into().resolve_with(input, policy))
+        .collect()
+}
+
+/// An item of [`IntoRanges`]: anything borrowing a [`HighlightRange`], like the range itself or
+/// a reference to it, or a `Range<usize>` or `RangeInclusive<usize>`, also by reference
+pub trait RangeItem {
+    fn into_range(self) -> HighlightRange;
+}
+
+impl<T: Borrow<HighlightRange>> RangeItem for T {
+    fn into_range(self) -> HighlightRange {
+        self.borrow().clone()
+    }
+}
+
+impl RangeItem for Range<usize> {
+    fn into_range(self) -> HighlightRange {
+        self.into()
+    }
+}
+
+impl RangeItem for &Range<usize> {
+    fn into_range(self) -> HighlightRange {
+        self.clone().into()
+    }
+}
+
+impl RangeItem for RangeInclusive<usize> {
+    fn into_range(self) -> HighlightRange {
+        self.into()
+    }
+}
+
+impl RangeItem for &RangeInclusive<usize> {
+    fn into_range(self) -> HighlightRange {
+        self.clone().into()
+    }
+}
+
+/// Ranges accepted by the free functions: anything iterable over [`RangeItem`]s, e.g. a `Vec`,
+/// slice, array or set of ranges, a [`HighlightSet`], an `Option` or any iterator. Owned
+/// `Vec<HighlightRange>`s and [`HighlightSet`]s are used without copying, anything else is
+/// collected once; a type that isn't iterable can implement it to lend its ranges instead.
+pub trait IntoRanges<'a> {
+    fn into_ranges(self) -> Cow<'a, [HighlightRange]>;
+}
+
+impl<'a, I> IntoRanges<'a> for I
+where
+    I: IntoIterator,
+    I::Item: RangeItem,
+{
+    fn into_ranges(self) -> Cow<'a, [HighlightRange]> {
+        // collecting the ranges of a `Vec<HighlightRange>` reuses its buffer
+        Cow::Owned(self.into_iter().map(RangeItem::into_range).collect())
+    }
+}
+
+impl<'a> IntoRanges<'a> for HighlightSet {
+    fn into_ranges(self) -> Cow<'a, [HighlightRange]> {
+        Cow::Owned(self.ranges)
+    }
+}
+
+/// The ranges of any [`IntoRanges`]
+pub(crate) fn collect_ranges<'a>(highlights: impl IntoRanges<'a>) -> Cow<'a, [HighlightRange]> {
+    highlights.into_ranges()
+}
+
+/// Same as [`merge_ranges`], but only ranges with the same label are merged with each other. The
+/// result is sorted by range, then label, and has no zero-length ranges; ranges with different
+/// labels that still overlap afterwards are rejected with
+/// [`HighlightingError::OverlappingRanges`], use [`resolve_labeled_overlaps`] to settle them.
+pub fn merge_labeled_ranges<L: Ord + Clone>(
+    highlights: Vec<LabeledRange<L>>,
+) -> Result<Vec<LabeledRange<L>>, HighlightingError> {
+    let mut by_label: BTreeMap<L, Vec<HighlightRange>> = BTreeMap::new();
+    for h in highlights {
+        by_label.entry(h.label).or_default().push(h.range);
+    }
+
+    let mut merged = by_label
+        .into_iter()
+        .flat_map(|(label, ranges)| {
+            merge_ranges(ranges)
+                .into_iter()
+                .map(move |range| LabeledRange::with_label(range, label.clone()))
+        })
+        .collect::<Vec<_>>();
+    merged.sort_by(|a, b| a.range.cmp(&b.range).then_with(|| a.label.cmp(&b.label)));
+
+    // sorted by lower, so a range overlaps an earlier one iff it starts before the furthest end
+    let mut furthest: Option<&HighlightRange> = None;
+    for h in &merged {
+        match furthest {
+            Some(last) if h.range.lower < last.upper => {
+                return Err(HighlightingError::OverlappingRanges {
+                    first: last.clone(),
+                    second: h.range.clone(),
+                });
+            }
+            Some(last) if h.range.upper <= last.upper => {}
+            _ => furthest = Some(&h.range),
+        }
+    }
+
+    Ok(merged)
+}
+
+/// Which end of a range a [`Boundary`] is; a zero-length range has a single `Empty` one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum BoundaryKind {
+    End,
+    Start,
+    Empty,
+}
+
+/// Offset where a range starts or ends. Boundaries are ordered by offset and, at the same offset,
+/// so that the ranges nest: ends come before starts and close the innermost range first, starts
+/// open the longest range first and zero-length ranges go innermost. Remaining ties are broken by
+/// label and then by index of the range (ends in reverse), so the order never depends on the
+/// order the ranges were passed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Boundary<'l> {
+    pub(crate) offset: usize,
+    pub(crate) kind: BoundaryKind,
+    /// offset of the other end of the range
+    pub(crate) other: usize,
+    pub(crate) label: Option<&'l str>,
+    pub(crate) index: usize,
+}
+
+impl<'l> Boundary<'l> {
+    /// The start of `range`, which is its only boundary if it's zero-length
+    pub(crate) fn start(range: &HighlightRange, label: Option<&'l str>, index: usize) -> Self {
+        let kind = if range.is_empty() {
+            BoundaryKind::Empty
+        } else {
+            BoundaryKind::Start
+        };
+        Boundary {
+            offset: range.lower,
+            kind,
+            other: range.upper,
+            label,
+            index,
+        }
+    }
+
+    pub(crate) fn of(
+        range: &HighlightRange,
+        label: Option<&'l str>,
+        index: usize,
+    ) -> impl Iterator<Item = Self> {
+        let start = Boundary::start(range, label, index);
+        let end = (start.kind == BoundaryKind::Start).then_some(Boundary {
+            offset: range.upper,
+            kind: BoundaryKind::End,
+            other: range.lower,
+            label,
+            index,
+        });
+        core::iter::once(start).chain(end)
+    }
+}
+
+impl Ord for Boundary<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let nesting = |b: &Self| (b.label, b.index);
+        self.offset
+            .cmp(&other.offset)
+            .then(self.kind.cmp(&other.kind))
+            .then_with(|| match self.kind {
+                // the range that started last is the innermost
+                BoundaryKind::End => {
+                    (other.other, nesting(other)).cmp(&(self.other, nesting(self)))
+                }
+                BoundaryKind::Start => (Reverse(self.other), nesting(self))
+                    .cmp(&(Reverse(other.other), nesting(other))),
+                BoundaryKind::Empty => nesting(self).cmp(&nesting(other)),
+            })
+    }
+}
+
+impl PartialOrd for Boundary<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Splits overlapping labeled ranges into flat, disjoint regions, each carrying the set of labels
+/// active over it, e.g. `person` 0..6 and `match` 3..9 give 0..3 `{person}`, 3..6
+/// `{match, person}` and 6..9 `{match}`. Regions are in document order, touching regions with
+/// the same labels are joined and zero-length ranges are dropped.
+pub fn flatten_labeled_ranges(
+    highlights: &[LabeledRange],
+) -> Vec<(HighlightRange, BTreeSet<String>)> {
+    let mut boundaries = highlights
+        .iter()
+        .enumerate()
+        .filter(|(_, h)| !h.range.is_empty())
+        .flat_map(|(i, h)| Boundary::of(&h.range, Some(h.label.as_str()), i))
+        .collect::<Vec<_>>();
+    boundaries.sort_unstable();
+
+    // the same label may be active several times over
+    let mut active: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut flattened: Vec<(HighlightRange, BTreeSet<String>)> = Vec::new();
+    let mut boundaries = boundaries.into_iter().peekable();
+    while let Some(boundary) = boundaries.next() {
+        let (offset, label) = (boundary.offset, boundary.label.unwrap_or_default());
+        if boundary.kind == BoundaryKind::Start {
+            *active.entry(label).or_default() += 1;
+        } else if let Some(count) = active.get_mut(label) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(label);
+            }
+        }
+
+        // emit a region once all boundaries at `offset` are processed
+        let Some(next) = boundaries.peek().map(|b| b.offset) else {
+            break;
+        };
+        if next == offset || active.is_empty() {
+            continue;
+        }
+
+        let region = HighlightRange::new(offset, next);
+        match flattened.last_mut() {
+            Some((last, labels))
+                if last.upper == offset
+                    && labels.iter().map(String::as_str).eq(active.keys().copied()) =>
+            {
+                last.upper = next
+            }
+            _ => {
+                let labels = active.keys().map(|label| label.to_string()).collect();
+                flattened.push((region, labels));
+            }
+        }
+    }
+
+    flattened
+}
+
+/// Same as [`flatten_labeled_ranges`] for plain ranges: splits overlapping ranges at every start
+/// and end into disjoint regions, each carrying the sorted indices of the input ranges covering
+/// it, e.g. 0..7 and 5..11 give 0..5 `[0]`, 5..7 `[0, 1]` and 7..11 `[1]`. Regions with more
+/// than one index are where the input overlapped; zero-length ranges are dropped.
+pub fn flatten_overlaps(ranges: Vec<HighlightRange>) -> Vec<(HighlightRange, Vec<usize>)> {
+    let mut boundaries = ranges
+        .iter()
+        .enumerate()
+        .filter(|(_, range)| !range.is_empty())
+        .flat_map(|(i, range)| Boundary::of(range, None, i))
+        .collect::<Vec<_>>();
+    boundaries.sort_unstable();
+
+    let mut active = BTreeSet::new();
+    let mut flattened = Vec::new();
+    let mut boundaries = boundaries.into_iter().peekable();
+    while let Some(boundary) = boundaries.next() {
+        if boundary.kind == BoundaryKind::Start {
+            active.insert(boundary.index);
+        } else {
+            active.remove(&boundary.index);
+        }
+
+        // emit a region once all boundaries at its start are processed
+        let Some(next) = boundaries.peek().map(|b| b.offset) else {
+            break;
+        };
+        if next != boundary.offset && !active.is_empty() {
+            let region = HighlightRange::new(boundary.offset, next);
+            flattened.push((region, active.iter().copied().collect()));
+        }
+    }
+
+    flattened
+}
+
+/// Widens every range that starts or ends inside a word to that whole word, then merges the ranges
+/// with [`merge_ranges`]. Words are runs of alphanumeric chars, see
+/// [`expand_to_word_boundaries_by`]. Ranges are clamped to `input` and widened to char boundaries
+/// first, so the result is always valid.
+pub fn expand_to_word_boundaries<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+) -> Vec<HighlightRange> {
+    expand_to_word_boundaries_by(input, highlights, char::is_alphanumeric)
+}
+
+/// Same as [`expand_to_word_boundaries`], but words are runs of chars for which `is_word_char`
+/// returns `true`
+pub fn expand_to_word_boundaries_by<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    is_word_char: impl Fn(char) -> bool,
+) -> Vec<HighlightRange> {
+    let highlights = collect_ranges(highlights);
+    let expanded = highlights
+        .iter()
+        .map(|h| {
+            let mut upper = input.ceil_char_boundary(h.upper.min(input.len()));
+            let mut lower = input.floor_char_boundary(h.lower.min(upper));
+            if inside_word(input, lower, &is_word_char) {
+                lower -= word_len_before(input, lower, &is_word_char);
+            }
+            if inside_word(input, upper, &is_word_char) {
+                upper += word_len_after(input, upper, &is_word_char);
+            }
+            HighlightRange::new(lower, upper)
+        })
+        .collect();
+
+    merge_ranges(expanded)
+}
+
+/// Counterpart of [`expand_to_word_boundaries`]: narrows every range that starts or ends inside a
+/// word so it leaves that word out, dropping ranges that don't contain a whole word afterwards.
+/// Ranges are clamped to `input` and narrowed to char boundaries first; the remaining ones keep
+/// their order.
+pub fn shrink_to_word_boundaries<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+) -> Vec<HighlightRange> {
+    shrink_to_word_boundaries_by(input, highlights, char::is_alphanumeric)
+}
+
+/// Same as [`shrink_to_word_boundaries`], but words are runs of chars for which `is_word_char`
+/// returns `true`
+pub fn shrink_to_word_boundaries_by<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    is_word_char: impl Fn(char) -> bool,
+) -> Vec<HighlightRange> {
+    let highlights = collect_ranges(highlights);
+    highlights
+        .iter()
+        .filter_map(|h| {
+            let mut upper = input.floor_char_boundary(h.upper.min(input.len()));
+            let mut lower = input.ceil_char_boundary(h.lower.min(upper));
+            if inside_word(input, lower, &is_word_char) {
+                lower += word_len_after(input, lower, &is_word_char);
+            }
+            if inside_word(input, upper, &is_word_char) {
+                upper -= word_len_before(input, upper, &is_word_char);
+            }
+            let has_word = lower < upper && input[lower..upper].chars().any(&is_word_char);
+            has_word.then(|| HighlightRange::new(lower, upper))
+        })
+        .collect()
+}
+
+/// Whether the chars on both sides of `offset` are word chars
+pub(crate) fn inside_word(input: &str, offset: usize, is_word_char: impl Fn(char) -> bool) -> bool {
+    let before = input[..offset].chars().next_back();
+    let after = input[offset..].chars().next();
+    before.is_some_and(&is_word_char) && after.is_some_and(&is_word_char)
+}
+
+/// Length in bytes of the word chars directly in front of `offset`
+pub(crate) fn word_len_before(
+    input: &str,
+    offset: usize,
+    is_word_char: impl Fn(char) -> bool,
+) -> usize {
+    input[..offset]
+        .chars()
+        .rev()
+        .take_while(|&c| is_word_char(c))
+        .map(char::len_utf8)
+        .sum()
+}
+
+/// Length in bytes of the word chars directly after `offset`
+pub(crate) fn word_len_after(
+    input: &str,
+    offset: usize,
+    is_word_char: impl Fn(char) -> bool,
+) -> usize {
+    input[offset..]
+        .chars()
+        .take_while(|&c| is_word_char(c))
+        .map(char::len_utf8)
+        .sum()
+}
+
+/// How [`sentence_spans_with`] tells sentence ends from other periods
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentenceRules {
+    /// words ending in a period that don't end a sentence, e.g. `e.g.`, compared
+    /// case-sensitively after stripping leading punctuation; the default has a few common English
+    /// ones
+    pub abbreviations: BTreeSet<String>,
+}
+
+impl Default for SentenceRules {
+    fn default() -> Self {
+        let abbreviations = [
+            "e.g.", "i.e.", "etc.", "vs.", "cf.", "Mr.", "Mrs.", "Ms.", "Dr.",
+        ];
+        SentenceRules {
+            abbreviations: abbreviations.into_iter().map(String::from).collect(),
+        }
+    }
+}
+
+/// Same as [`sentence_spans_with`] with the default [`SentenceRules`]
+pub fn sentence_spans(input: &str) -> Vec<HighlightRange> {
+    sentence_spans_with(input, &SentenceRules::default())
+}
+
+/// The sentences of `input` in order, without the whitespace around them. A sentence ends after
+/// a run of `.`, `!` or `?` that is followed by whitespace and an uppercase letter, unless the
+/// word before it is one of the [`SentenceRules::abbreviations`], or after a run of `。`, `！`
+/// or `？`; the last one ends with the input.
+pub fn sentence_spans_with(input: &str, rules: &SentenceRules) -> Vec<HighlightRange> {
+    let is_terminator = |c: char| matches!(c, '.' | '!' | '?' | '。' | '！' | '？');
+
+    let mut spans = Vec::new();
+    let mut start = None;
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let lower = match start {
+            Some(lower) => lower,
+            None if c.is_whitespace() => continue,
+            None => *start.insert(i),
+        };
+        // a run of terminators like `?!` or `...` ends the sentence at its last char
+        if !is_terminator(c) || chars.peek().is_some_and(|&(_, next)| is_terminator(next)) {
+            continue;
+        }
+
+        let upper = i + c.len_utf8();
+        let ends = match c {
+            '。' | '！' | '？' => true,
+            _ => {
+                let rest = &input[upper..];
+                let next_sentence = rest.trim_start();
+                let word = input[lower..upper]
+                    .rsplit(char::is_whitespace)
+                    .next()
+                    .unwrap_or_default()
+                    .trim_start_matches(|c: char| !c.is_alphanumeric());
+                next_sentence.len() < rest.len()
+                    && next_sentence.chars().next().is_some_and(char::is_uppercase)
+                    && !(c == '.' && rules.abbreviations.contains(word))
+            }
+        };
+        if ends {
+            spans.push(HighlightRange::new(lower, upper));
+            start = None;
+        }
+    }
+    if let Some(lower) = start {
+        spans.push(HighlightRange::new(lower, input.trim_end().len()));
+    }
+
+    spans
+}
+
+/// Same as [`expand_to_sentences_with`] with the default [`SentenceRules`]
+pub fn expand_to_sentences<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+) -> Vec<HighlightRange> {
+    expand_to_sentences_with(input, highlights, &SentenceRules::default())
+}
+
+/// Widens every range to the sentences it touches, see [`sentence_spans_with`], and merges the
+/// ones ending up in the same sentence; a zero-length range counts as touching the sentence it
+/// is in. Ranges are clamped to `input`, ranges covering only whitespace between sentences are
+/// dropped.
+pub fn expand_to_sentences_with<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    rules: &SentenceRules,
+) -> Vec<HighlightRange> {
+    let sentences = sentence_spans_with(input, rules);
+    let expanded = collect_ranges(highlights)
+        .iter()
+        .filter_map(|h| {
+            let lower = h.lower.min(input.len());
+            let upper = h.upper.min(input.len()).max(lower + 1);
+            let first = sentences.partition_point(|s| s.upper <= lower);
+            let touched = &sentences[first..];
+            let last = touched.iter().take_while(|s| s.lower < upper).last()?;
+            Some(HighlightRange::new(touched[0].lower, last.upper))
+        })
+        .collect();
+
+    merge_ranges(expanded)
+}
+
+/// The ranges sharing at least one byte with `window`, clipped to it and shifted to be relative
+/// to `window.lower()`, i.e. valid for `&input[window]`
+pub fn clip_ranges(highlights: &[HighlightRange], window: &HighlightRange) -> Vec<HighlightRange> {
+    highlights
+        .iter()
+        .filter(|h| h.lower < window.upper && h.upper > window.lower)
+        .map(|h| {
+            HighlightRange::new(
+                h.lower.max(window.lower) - window.lower,
+                h.upper.min(window.upper) - window.lower,
+            )
+        })
+        .collect()
+}
+
+/// Widens every range outward to the nearest extended grapheme cluster boundaries, so a range
+/// never splits a base letter from its combining marks, a ZWJ emoji sequence or a flag. Ranges
+/// that only overlap because of the snapping are merged, ranges that overlapped before stay
+/// overlapping for validation to reject, ranges that only touch are kept apart and the result
+/// is sorted. A zero-length range moves to the start of the cluster it's in; ranges reaching past
+/// the end of `input` are left for validation to reject.
+#[cfg(feature = "unicode-segmentation")]
+pub fn snap_to_graphemes(input: &str, highlights: &[HighlightRange]) -> Vec<HighlightRange> {
+    use unicode_segmentation::UnicodeSegmentation;
+
+    let boundaries = input
+        .grapheme_indices(true)
+        .map(|(offset, _)| offset)
+        .chain([input.len()])
+        .collect::<Vec<_>>();
+    let snap_down = |offset: usize| boundaries[boundaries.partition_point(|&b| b <= offset) - 1];
+    let snap_up = |offset: usize| boundaries[boundaries.partition_point(|&b| b < offset)];
+
+    let snapped = highlights
+        .iter()
+        .map(|h| match h.upper <= input.len() {
+            true if h.is_empty() => HighlightRange::new(snap_down(h.lower), snap_down(h.lower)),
+            true => HighlightRange::new(snap_down(h.lower), snap_up(h.upper)),
+            false => h.clone(),
+        })
+        .collect::<Vec<_>>();
+    merge_snapped(highlights, snapped)
+}
+
+/// Widens every range outward to the edges of the HTML entities and tags its boundaries fall
+/// inside of, so it passes [`ValidationMode::HtmlAware`]; a zero-length range moves to the start
+/// of the markup it's in. Merged, sorted and with out-of-bounds ranges left alone like
+/// `snap_to_graphemes`.
+pub fn snap_out_of_markup(input: &str, highlights: &[HighlightRange]) -> Vec<HighlightRange> {
+    let spans = markup_spans(input);
+    let snap_down = |offset| markup_around(&spans, offset).map_or(offset, |(span, _)| span.lower);
+    let snap_up = |offset| markup_around(&spans, offset).map_or(offset, |(span, _)| span.upper);
+
+    let snapped = highlights
+        .iter()
+        .map(|h| match h.upper <= input.len() {
+            true if h.is_empty() => HighlightRange::new(snap_down(h.lower), snap_down(h.lower)),
+            true => HighlightRange::new(snap_down(h.lower), snap_up(h.upper)),
+            false => h.clone(),
+        })
+        .collect::<Vec<_>>();
+    merge_snapped(highlights, snapped)
+}
+
+/// Sorts the ranges `snapped` from `original` and merges the ones that only overlap because of
+/// the snapping, touching ones are kept apart. Originals that already overlapped stay
+/// overlapping, so validation still rejects them.
+fn merge_snapped(original: &[HighlightRange], snapped: Vec<HighlightRange>) -> Vec<HighlightRange> {
+    let mut pairs = original.iter().zip(snapped).collect::<Vec<_>>();
+    // snapping is monotonic, so sorting by the originals sorts the snapped lowers as well
+    pairs.sort_by_key(|(original, _)| *original);
+
+    let mut merged: Vec<HighlightRange> = Vec::with_capacity(pairs.len());
+    // of the originals ordered before, the furthest end; an original starting before it overlaps
+    let mut furthest = 0;
+    for (original, range) in pairs {
+        let overlapped = original.lower < furthest;
+        furthest = furthest.max(original.upper);
+        match merged.last_mut() {
+            Some(last) if range.lower < last.upper && !overlapped => {
+                last.upper = last.upper.max(range.upper)
+            }
+            _ => merged.push(range),
+        }
+    }
+
+    sort_ranges(&mut merged);
+    merged
+}
+
+/// How [`word_ranges_to_byte_ranges_with`] splits the input into words
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WordSplit {
+    /// words are separated by whitespace, punctuation belongs to the word it's attached to
+    #[default]
+    Whitespace,
+    /// words as defined by Unicode word segmentation (UAX #29), without punctuation
+    #[cfg(feature = "unicode-segmentation")]
+    UnicodeWords,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WordRangeOptions {
+    pub split: WordSplit,
+    /// whether a range of several words becomes a single range including the separators between
+    /// them, instead of one range per word
+    pub include_separators: bool,
+}
+
+/// Converts ranges of word indices, e.g. `1..3` for the second and third word, into byte ranges
+/// with the default [`WordRangeOptions`]: one range per word, split on whitespace
+pub fn word_ranges_to_byte_ranges(
+    input: &str,
+    word_ranges: &[HighlightRange],
+) -> Result<Vec<HighlightRange>, HighlightingError> {
+    word_ranges_to_byte_ranges_with(input, word_ranges, WordRangeOptions::default())
+}
+
+/// Same as [`word_ranges_to_byte_ranges`], but configured by `options`
+pub fn word_ranges_to_byte_ranges_with(
+    input: &str,
+    word_ranges: &[HighlightRange],
+    options: WordRangeOptions,
+) -> Result<Vec<HighlightRange>, HighlightingError> {
+    let words = match options.split {
+        WordSplit::Whitespace => input
+            .split_whitespace()
+            .map(|word| {
+                // `word` is a subslice of `input`
+                let lower = word.as_ptr().addr() - input.as_ptr().addr();
+                HighlightRange::new(lower, lower + word.len())
+            })
+            .collect::<Vec<_>>(),
+        #[cfg(feature = "unicode-segmentation")]
+        WordSplit::UnicodeWords => {
+            use unicode_segmentation::UnicodeSegmentation;
+
+            input
+                .unicode_word_indices()
+                .map(|(lower, word)| HighlightRange::new(lower, lower + word.len()))
+                .collect()
+        }
+    };
+
+    let mut byte_ranges = Vec::with_capacity(word_ranges.len());
+    for range in word_ranges {
+        let Some(covered) = words.get(range.lower..range.upper) else {
+            return Err(HighlightingError::WordRangeOutOfBounds {
+                range: range.clone(),
+                word_count: words.len(),
+            });
+        };
+        match (covered.first(), covered.last()) {
+            (Some(first), Some(last)) if options.include_separators => {
+                byte_ranges.push(HighlightRange::new(first.lower, last.upper));
+            }
+            _ => byte_ranges.extend_from_slice(covered),
+        }
+    }
+
+    Ok(byte_ranges)
+}
+
+/// Span between two 1-based `(line, column)` positions, with `end` pointing right after the last
+/// char of the span; columns count chars, see [`line_col_to_byte_ranges`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineColRange {
+    pub start: (u32, u32),
+    pub end: (u32, u32),
+}
+
+/// Converts line/column spans into byte ranges. Lines end at `\n` or `\r\n`, neither of which
+/// counts as part of the line, so the last valid column of a line is its char count plus one. An
+/// input ending with a line break has an extra empty last line.
+pub fn line_col_to_byte_ranges(
+    input: &str,
+    spans: &[LineColRange],
+) -> Result<Vec<HighlightRange>, HighlightingError> {
+    let line_starts = core::iter::once(0)
+        .chain(input.match_indices('\n').map(|(offset, _)| offset + 1))
+        .collect::<Vec<_>>();
+
+    let byte_offset = |(line, column): (u32, u32)| {
+        let line_idx = (line as usize)
+            .checked_sub(1)
+            .filter(|&idx| idx < line_starts.len())
+            .ok_or(HighlightingError::LineOutOfBounds {
+                line,
+                line_count: line_starts.len(),
+            })?;
+        let start = line_starts[line_idx];
+        let end = line_starts
+            .get(line_idx + 1)
+            .map_or(input.len(), |next| next - 1);
+        let content = &input[start..end];
+        let content = content.strip_suffix('\r').unwrap_or(content);
+
+        let mut char_offsets = content
+            .char_indices()
+            .map(|(offset, _)| offset)
+            .chain(core::iter::once(content.len()));
+        (column as usize)
+            .checked_sub(1)
+            .and_then(|idx| char_offsets.nth(idx))
+            .map(|offset| start + offset)
+            .ok_or_else(|| HighlightingError::ColumnOutOfBounds {
+                line,
+                column,
+                line_len: content.chars().count(),
+            })
+    };
+
+    spans
+        .iter()
+        .map(|span| {
+            Ok(HighlightRange::new(
+                byte_offset(span.start)?,
+                byte_offset(span.end)?,
+            ))
+        })
+        .collect()
+}
+
+/// One line of an input and the ranges on it, see [`split_ranges_by_lines`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineHighlights<'a> {
+    /// the line without its `\n` or `\r\n`
+    pub text: &'a str,
+    /// byte offset of the line within the input
+    pub offset: usize,
+    /// 1-based, like in [`LineColRange`]
+    pub line: u32,
+    /// the ranges sharing a byte with `text`, or zero-length ones within it, clipped to `text` and
+    /// relative to `offset`, in `(lower, upper)` order
+    pub ranges: Vec<HighlightRange>,
+}
+
+/// Splits `input` into lines like [`line_col_to_byte_ranges`] does and gives each line the ranges
+/// on it, e.g. for a viewer rendering line by line; a range spanning several lines shows up
+/// clipped on each of them. The ranges aren't validated, whatever lies beyond the input or on a
+/// line break is left out.
+pub fn split_ranges_by_lines<'a>(
+    input: &'a str,
+    highlights: &[HighlightRange],
+) -> Vec<LineHighlights<'a>> {
+    let mut sorted = highlights.to_vec();
+    sort_ranges(&mut sorted);
+    let mut pending = sorted.into_iter().peekable();
+    // ranges starting at or before the end of the current line that might reach into it
+    let mut active = Vec::new();
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for (idx, line) in input.split('\n').enumerate() {
+        let text = line.strip_suffix('\r').unwrap_or(line);
+        let end = offset + text.len();
+        while let Some(h) = pending.next_if(|h| h.lower <= end) {
+            active.push(h);
+        }
+
+        let ranges = active
+            .iter()
+            .filter_map(|h| {
+                let (lower, upper) = (h.lower.max(offset), h.upper.min(end));
+                let on_line = match h.is_empty() {
+                    true => offset <= h.lower,
+                    false => lower < upper,
+                };
+                on_line.then(|| HighlightRange::new(lower - offset, upper - offset))
+            })
+            .collect();
+        active.retain(|h| h.upper > end);
+
+        lines.push(LineHighlights {
+            text,
+            offset,
+            line: idx as u32 + 1,
+            ranges,
+        });
+        offset += line.len() + 1;
+    }
+
+    lines
+}
+
+/// Why [`parse_highlighted`] failed, offsets are bytes into its input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MarkerParseError {
+    /// close marker without an open one
+    UnexpectedClose(usize),
+    /// open marker that is never closed
+    Unclosed(usize),
+    /// open marker inside a highlight, nesting isn't supported
+    Nested(usize),
+}
+
+impl fmt::Display for MarkerParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MarkerParseError::UnexpectedClose(offset) => {
+                write!(f, "close marker at {offset} without an open marker")
+            }
+            MarkerParseError::Unclosed(offset) => {
+                write!(f, "open marker at {offset} is never closed")
+            }
+            MarkerParseError::Nested(offset) => {
+                write!(
+                    f,
+                    "open marker at {offset} is nested inside another highlight"
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MarkerParseError {}
+
+/// Inverse of [`highlight_text_with_tags`]: removes the `open` and `close` markers and returns
+/// the plain text with the byte ranges (into it) that were highlighted. Empty highlights such as
+/// `<em></em>` are dropped, like the renderer drops zero-length ranges.
+pub fn parse_highlighted(
+    input: &str,
+    open: &str,
+    close: &str,
+) -> Result<(String, Vec<HighlightRange>), MarkerParseError> {
+    let mut ranges = Vec::new();
+    let mut opened: Option<(usize, usize)> = None;
+    let text = scan_markers(input, open, close, |is_open, offset, text_len| {
+        match (is_open, opened.take()) {
+            (true, None) => opened = Some((offset, text_len)),
+            (true, Some(_)) => return Err(MarkerParseError::Nested(offset)),
+            (false, None) => return Err(MarkerParseError::UnexpectedClose(offset)),
+            (false, Some((_, lower))) if lower == text_len => {}
+            (false, Some((_, lower))) => ranges.push(HighlightRange::new(lower, text_len)),
+        }
+        Ok(())
+    })?;
+
+    match opened {
+        Some((offset, _)) => Err(MarkerParseError::Unclosed(offset)),
+        None => Ok((text, ranges)),
+    }
+}
+
+/// Removes every `<em>` and `</em>` from `input`, balanced or not
+pub fn strip_highlights(input: &str) -> String {
+    match scan_markers(input, "<em>", "</em>", |_, _, _| Ok::<(), Infallible>(())) {
+        Ok(text) => text,
+    }
+}
+
+/// Plain text and byte ranges of a text with its highlights marked by `open` and `close`, e.g.
+/// `"[Hello] [world]"` gives `"Hello world"` with 0..5 and 6..11. A backslash in front of a
+/// marker or another backslash makes it literal text. Unlike [`parse_highlighted`], empty
+/// highlights like `[]` are kept; see [`to_marked_with`] for the inverse.
+pub fn from_marked(
+    input: &str,
+    open: char,
+    close: char,
+) -> Result<(String, Vec<HighlightRange>), MarkerParseError> {
+    let mut text = String::with_capacity(input.len());
+    let mut highlights = Vec::new();
+    // (offset in `input`, offset in `text`) of the open marker of the current highlight
+    let mut opened: Option<(usize, usize)> = None;
+    let mut chars = input.char_indices();
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            '\\' => match chars.clone().next() {
+                Some((_, next)) if next == open || next == close || next == '\\' => {
+                    text.push(next);
+                    chars.next();
+                }
+                _ => text.push(c),
+            },
+            c if c == open => match opened {
+                Some(_) => return Err(MarkerParseError::Nested(offset)),
+                None => opened = Some((offset, text.len())),
+            },
+            c if c == close => match opened.take() {
+                Some((_, lower)) => highlights.push(HighlightRange::new(lower, text.len())),
+                None => return Err(MarkerParseError::UnexpectedClose(offset)),
+            },
+            c => text.push(c),
+        }
+    }
+
+    match opened {
+        Some((offset, _)) => Err(MarkerParseError::Unclosed(offset)),
+        None => Ok((text, highlights)),
+    }
+}
+
+/// Same as [`from_marked`] with the highlights marked by `[` and `]`
+pub fn from_brackets(input: &str) -> Result<(String, Vec<HighlightRange>), MarkerParseError> {
+    from_marked(input, '[', ']')
+}
+
+/// Copies `input` without the markers and calls `on_marker(is_open, offset, text_len)` for each
+/// of them, with its offset in `input` and the length of the text copied so far. Where both
+/// markers match, the longer one wins; empty markers never match.
+fn scan_markers<E>(
+    input: &str,
+    open: &str,
+    close: &str,
+    mut on_marker: impl FnMut(bool, usize, usize) -> Result<(), E>,
+) -> Result<String, E> {
+    let mut markers = [(open, true), (close, false)];
+    markers.sort_by_key(|(marker, _)| Reverse(marker.len()));
+
+    let mut text = String::with_capacity(input.len());
+    let mut pos = 0;
+    while let Some(c) = input[pos..].chars().next() {
+        let rest = &input[pos..];
+        match markers
+            .iter()
+            .find(|(marker, _)| !marker.is_empty() && rest.starts_with(marker))
+        {
+            Some(&(marker, is_open)) => {
+                on_marker(is_open, pos, text.len())?;
+                pos += marker.len();
+            }
+            None => {
+                text.push(c);
+                pos += c.len_utf8();
+            }
+        }
+    }
+
+    Ok(text)
+}
+
+/// Converts a range of char indices into the equivalent range of byte offsets into `input`
+pub fn char_range_to_byte_range(
+    input: &str,
+    range: &HighlightRange,
+) -> Result<HighlightRange, HighlightingError> {
+    char_ranges_to_byte_ranges(input, core::slice::from_ref(range)).map(|mut r| r.remove(0))
+}
+
+/// Converts all ranges in a single pass over `input.char_indices()`
+pub(crate) fn char_ranges_to_byte_ranges(
+    input: &str,
+    ranges: &[HighlightRange],
+) -> Result<Vec<HighlightRange>, HighlightingError> {
+    let unit_offsets = input
+        .char_indices()
+        .map(|(byte, _)| byte)
+        .chain(core::iter::once(input.len()))
+        .enumerate();
+
+    unit_ranges_to_byte_ranges(
+        ranges,
+        unit_offsets,
+        HighlightingError::NotACharBoundary,
+        |range, char_count| HighlightingError::CharRangeOutOfBounds { range, char_count },
+    )
+}
+
+/// Converts all ranges in a single pass over the UTF-16 encoding of `input`
+pub(crate) fn utf16_ranges_to_byte_ranges(
+    input: &str,
+    ranges: &[HighlightRange],
+) -> Result<Vec<HighlightRange>, HighlightingError> {
+    let unit_offsets = input
+        .char_indices()
+        .map(Some)
+        .chain(core::iter::once(None))
+        .scan(0, |utf16_idx, next| {
+            let current = *utf16_idx;
+            match next {
+                Some((byte, c)) => {
+                    *utf16_idx += c.len_utf16();
+                    Some((current, byte))
+                }
+                None => Some((current, input.len())),
+            }
+        });
+
+    unit_ranges_to_byte_ranges(
+        ranges,
+        unit_offsets,
+        HighlightingError::SplitsSurrogatePair,
+        |range, input_len| HighlightingError::RangesOutOfBounds { range, input_len },
+    )
+}
+
+/// Maps the lower/upper of each range from some unit (chars, UTF-16 code units, ...) to bytes.
+///
+/// `unit_offsets` yields `(unit index, byte offset)` in ascending order for every char start plus
+/// the end of the input; an endpoint that is skipped over lands inside a char and is reported
+/// via `split_err`, an endpoint past the end is out of bounds and reported via `bounds_err` with
+/// the length in units.
+fn unit_ranges_to_byte_ranges(
+    ranges: &[HighlightRange],
+    unit_offsets: impl Iterator<Item = (usize, usize)>,
+    split_err: fn(usize) -> HighlightingError,
+    bounds_err: fn(HighlightRange, usize) -> HighlightingError,
+) -> Result<Vec<HighlightRange>, HighlightingError> {
+    // (unit index, index into `ranges`, is upper bound)
+    let mut endpoints = ranges
+        .iter()
+        .enumerate()
+        .flat_map(|(i, r)| [(r.lower, i, false), (r.upper, i, true)])
+        .collect::<Vec<(usize, usize, bool)>>();
+    endpoints.sort_unstable();
+
+    let mut converted = ranges.to_vec();
+    let mut endpoints = endpoints.into_iter().peekable();
+    let mut unit_len = 0;
+
+    for (unit_idx, byte) in unit_offsets {
+        unit_len = unit_idx;
+        if let Some((skipped, _, _)) = endpoints.next_if(|(u, _, _)| *u < unit_idx) {
+            return Err(split_err(skipped));
+        }
+        while let Some((_, i, is_upper)) = endpoints.next_if(|(u, _, _)| *u == unit_idx) {
+            if is_upper {
+                converted[i].upper = byte;
+            } else {
+                converted[i].lower = byte;
+            }
+        }
+    }
+
+    match endpoints.next() {
+        Some((_, i, _)) => Err(bounds_err(ranges[i].clone(), unit_len)),
+        None => Ok(converted),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn should_swap_false_upper_and_lower() {
+        assert_eq!(HighlightRange::new(0, 5), HighlightRange::new(5, 0));
+    }
+
+    #[test]
+    fn should_convert_char_range_to_byte_range() {
+        assert_eq!(
+            Ok(HighlightRange::new(0, 5)),
+            char_range_to_byte_range("über cool", &HighlightRange::new(0, 4))
+        );
+        assert_eq!(
+            Ok(HighlightRange::new(6, 10)),
+            char_range_to_byte_range("hé€😀 wörld", &HighlightRange::new(3, 4))
+        );
+        assert_eq!(
+            Ok(HighlightRange::new(11, 17)),
+            char_range_to_byte_range("hé€😀 wörld", &HighlightRange::new(5, 10))
+        );
+    }
+
+    #[test]
+    fn should_merge_contained_duplicate_and_chained_ranges() {
+        assert_eq!(
+            vec![HighlightRange::new(0, 11)],
+            merge_ranges(vec![HighlightRange::new(0, 11), HighlightRange::new(2, 4)])
+        );
+        assert_eq!(
+            vec![HighlightRange::new(2, 4)],
+            merge_ranges(vec![HighlightRange::new(2, 4), HighlightRange::new(2, 4)])
+        );
+        assert_eq!(
+            vec![HighlightRange::new(0, 9)],
+            merge_ranges(vec![
+                HighlightRange::new(6, 9),
+                HighlightRange::new(0, 3),
+                HighlightRange::new(2, 5),
+                HighlightRange::new(4, 7),
+            ])
+        );
+    }
+
+    #[test]
+    fn should_produce_valid_ranges_after_merge() {
+        let merged = merge_ranges(vec![
+            HighlightRange::new(0, 3),
+            HighlightRange::new(2, 5),
+            HighlightRange::new(7, 9),
+            HighlightRange::new(8, 11),
+            HighlightRange::new(8, 9),
+        ]);
+
+        assert_eq!(
+            vec![HighlightRange::new(0, 5), HighlightRange::new(7, 11)],
+            merged
+        );
+        assert_eq!(Ok(()), validate_ranges("Hello world", &merged));
+    }
+
+    #[test]
+    fn should_convert_std_ranges() {
+        assert_eq!(HighlightRange::new(0, 5), HighlightRange::from(0..5));
+        assert_eq!(HighlightRange::new(0, 5), HighlightRange::from(0..=4));
+        assert_eq!(
+            HighlightRange::new(usize::MAX - 1, usize::MAX),
+            HighlightRange::from(usize::MAX - 1..=usize::MAX - 1)
+        );
+    }
+
+    #[test]
+    fn should_saturate_inclusive_ranges_ending_at_usize_max() {
+        assert_eq!(
+            HighlightRange::new(0, usize::MAX),
+            HighlightRange::from(0..=usize::MAX)
+        );
+        assert_eq!(
+            HighlightRange::new(usize::MAX, usize::MAX),
+            HighlightRange::from(usize::MAX..=usize::MAX)
+        );
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(0, usize::MAX),
+                input_len: 5
+            }),
+            highlight_text("Hello", [0..=usize::MAX])
+        );
+    }
+
+    #[test]
+    fn should_parse_highlight_range() {
+        assert_eq!(Ok(HighlightRange::new(0, 5)), "0..5".parse());
+        assert_eq!(Ok(HighlightRange::new(6, 11)), "6-11".parse());
+        assert_eq!(Ok(HighlightRange::new(3, 3)), "3..3".parse());
+    }
+
+    #[test]
+    fn should_round_trip_highlight_range_through_display() {
+        let range = HighlightRange::new(20, 24);
+
+        assert_eq!("20..24", range.to_string());
+        assert_eq!(Ok(range.clone()), range.to_string().parse());
+    }
+
+    #[test]
+    fn should_return_err_invalid_highlight_range() {
+        let cases = [
+            ("05", RangeParseErrorKind::MissingSeparator),
+            (
+                "a..5",
+                RangeParseErrorKind::InvalidNumber(String::from("a")),
+            ),
+            ("0-", RangeParseErrorKind::InvalidNumber(String::new())),
+            ("5..0", RangeParseErrorKind::ReversedBounds),
+        ];
+
+        for (token, kind) in cases {
+            assert_eq!(
+                Err(RangeParseError {
+                    token: token.to_string(),
+                    kind,
+                }),
+                token.parse::<HighlightRange>()
+            );
+        }
+    }
+
+    #[test]
+    fn should_parse_range_list() {
+        assert_eq!(
+            Ok(vec![
+                HighlightRange::new(0, 5),
+                HighlightRange::new(6, 11),
+                HighlightRange::new(20, 24),
+            ]),
+            parse_ranges("0-5,6-11, 20..24")
+        );
+        assert_eq!(Ok(vec![]), parse_ranges(""));
+    }
+
+    #[test]
+    fn should_return_err_with_failing_token_of_range_list() {
+        let actual = parse_ranges("0-5 6-x 20..24").unwrap_err();
+
+        assert_eq!("6-x", actual.token);
+        assert_eq!(
+            "invalid range `6-x`: `x` is not a number",
+            actual.to_string()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn should_round_trip_ranges_through_json_objects() {
+        let ranges = vec![HighlightRange::new(0, 5), HighlightRange::new(6, 11)];
+
+        let json = serde_json::to_string(&ranges).unwrap();
+
+        assert_eq!(r#"[{"lower":0,"upper":5},{"lower":6,"upper":11}]"#, json);
+        assert_eq!(
+            ranges,
+            serde_json::from_str::<Vec<HighlightRange>>(&json).unwrap()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn should_round_trip_ranges_through_json_tuples() {
+        let ranges = serde_json::from_str::<Vec<HighlightRange>>("[[0, 5], [6, 11]]").unwrap();
+
+        assert_eq!(
+            vec![HighlightRange::new(0, 5), HighlightRange::new(6, 11)],
+            ranges
+        );
+        assert_eq!(
+            ranges,
+            serde_json::from_str::<Vec<HighlightRange>>(&serde_json::to_string(&ranges).unwrap())
+                .unwrap()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn should_swap_reversed_bounds_when_deserializing() {
+        assert_eq!(
+            HighlightRange::new(0, 5),
+            serde_json::from_str(r#"{"lower": 5, "upper": 0}"#).unwrap()
+        );
+        assert_eq!(
+            HighlightRange::new(0, 5),
+            serde_json::from_str("[5, 0]").unwrap()
+        );
+    }
+
+    #[test]
+    fn should_order_by_lower_then_upper() {
+        let mut ranges = vec![
+            HighlightRange::new(3, 9),
+            HighlightRange::new(0, 5),
+            HighlightRange::new(3, 4),
+            HighlightRange::new(0, 2),
+        ];
+
+        sort_ranges(&mut ranges);
+
+        assert_eq!(
+            vec![
+                HighlightRange::new(0, 2),
+                HighlightRange::new(0, 5),
+                HighlightRange::new(3, 4),
+                HighlightRange::new(3, 9),
+            ],
+            ranges
+        );
+        assert!(HighlightRange::new(3, 4) < HighlightRange::new(3, 9));
+    }
+
+    #[test]
+    fn should_dedup_exact_duplicates() {
+        let mut ranges = vec![
+            HighlightRange::new(6, 11),
+            HighlightRange::new(0, 5),
+            HighlightRange::new(6, 11),
+            HighlightRange::new(0, 4),
+            HighlightRange::new(0, 5),
+        ];
+
+        dedup_ranges(&mut ranges);
+
+        assert_eq!(
+            vec![
+                HighlightRange::new(0, 4),
+                HighlightRange::new(0, 5),
+                HighlightRange::new(6, 11),
+            ],
+            ranges
+        );
+    }
+
+    #[test]
+    fn should_hash_equal_ranges_equally() {
+        let set = [
+            HighlightRange::new(0, 5),
+            HighlightRange::new(5, 0),
+            HighlightRange::new(6, 11),
+        ]
+        .into_iter()
+        .collect::<std::collections::HashSet<_>>();
+
+        assert_eq!(2, set.len());
+    }
+
+    #[test]
+    fn should_merge_on_insert_into_highlight_set() {
+        let mut set = HighlightSet::new();
+        set.insert(0..2);
+        set.insert(4..6);
+        set.insert(8..10);
+        set.insert(10..11);
+        assert_eq!(3, set.len());
+
+        // bridges the first two and touches the third
+        set.insert(1..8);
+
+        assert_eq!(vec![HighlightRange::new(0, 11)], Vec::from(set));
+    }
+
+    #[test]
+    fn should_ignore_empty_and_contained_ranges_in_highlight_set() {
+        let mut set = [0..5, 1..3, 7..7].into_iter().collect::<HighlightSet>();
+        set.insert(5..5);
+
+        assert_eq!(&[HighlightRange::new(0, 5)], set.as_slice());
+    }
+
+    #[test]
+    fn should_split_on_remove_from_highlight_set() {
+        let mut set = [0..5, 6..11].into_iter().collect::<HighlightSet>();
+
+        assert!(set.remove(2..3));
+        assert!(set.remove(4..8));
+        assert!(!set.remove(4..6));
+
+        assert_eq!(
+            vec![
+                &HighlightRange::new(0, 2),
+                &HighlightRange::new(3, 4),
+                &HighlightRange::new(8, 11),
+            ],
+            set.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn should_hightlight_highlight_set() {
+        let set = [6..11, 0..3, 2..5].into_iter().collect::<HighlightSet>();
+
+        assert_eq!(
+            Ok("<em>hello</em> <em>world</em>".to_string()),
+            set.render("hello world")
+        );
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(6, 11),
+                input_len: 5
+            }),
+            set.render("hello")
+        );
+    }
+
+    #[test]
+    fn should_drop_empty_ranges_when_merging() {
+        assert_eq!(
+            vec![HighlightRange::new(0, 5)],
+            merge_ranges(vec![
+                HighlightRange::new(3, 3),
+                HighlightRange::new(0, 5),
+                HighlightRange::new(9, 9),
+            ])
+        );
+        assert_eq!(Vec::<HighlightRange>::new(), merge_ranges(vec![]));
+    }
+
+    #[test]
+    fn should_cover_same_positions_after_merge() {
+        // small linear congruential generator, so the cases are random-ish but reproducible
+        let mut seed = 0x2545_f491_u64;
+        let mut next = |bound: u64| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            ((seed >> 33) % bound) as usize
+        };
+
+        for _ in 0..500 {
+            let ranges = (0..next(8))
+                .map(|_| HighlightRange::new(next(30), next(30)))
+                .collect::<Vec<_>>();
+            let covered = |ranges: &[HighlightRange], pos: usize| {
+                ranges.iter().any(|r| r.lower() <= pos && pos < r.upper())
+            };
+
+            let merged = merge_ranges(ranges.clone());
+
+            for pos in 0..30 {
+                assert_eq!(covered(&ranges, pos), covered(&merged, pos), "{ranges:?}");
+            }
+            assert_canonical(&merged);
+        }
+    }
+
+    #[test]
+    fn should_union_ranges() {
+        let a = [HighlightRange::new(0, 3), HighlightRange::new(8, 10)];
+        let b = [HighlightRange::new(3, 5), HighlightRange::new(9, 9)];
+
+        assert_eq!(
+            vec![HighlightRange::new(0, 5), HighlightRange::new(8, 10)],
+            union_ranges(&a, &b)
+        );
+        assert_eq!(a.to_vec(), union_ranges(&a, &[]));
+    }
+
+    #[test]
+    fn should_intersect_ranges() {
+        let a = [HighlightRange::new(0, 11)];
+        let b = [
+            HighlightRange::new(6, 8),
+            HighlightRange::new(1, 3),
+            HighlightRange::new(2, 4),
+            HighlightRange::new(10, 14),
+        ];
+
+        assert_eq!(
+            vec![
+                HighlightRange::new(1, 4),
+                HighlightRange::new(6, 8),
+                HighlightRange::new(10, 11),
+            ],
+            intersect_ranges(&a, &b)
+        );
+        // touching ranges share no byte
+        assert_eq!(
+            Vec::<HighlightRange>::new(),
+            intersect_ranges(&[HighlightRange::new(0, 5)], &[HighlightRange::new(5, 8)])
+        );
+        assert_eq!(Vec::<HighlightRange>::new(), intersect_ranges(&a, &[]));
+    }
+
+    #[test]
+    fn should_subtract_ranges() {
+        let a = [HighlightRange::new(0, 11), HighlightRange::new(13, 15)];
+        let b = [
+            HighlightRange::new(2, 4),
+            HighlightRange::new(5, 6),
+            HighlightRange::new(10, 14),
+        ];
+
+        assert_eq!(
+            vec![
+                HighlightRange::new(0, 2),
+                HighlightRange::new(4, 5),
+                HighlightRange::new(6, 10),
+                HighlightRange::new(14, 15),
+            ],
+            subtract_ranges(&a, &b)
+        );
+        // touching ranges share no byte, so nothing is removed
+        assert_eq!(
+            vec![HighlightRange::new(0, 5)],
+            subtract_ranges(&[HighlightRange::new(0, 5)], &[HighlightRange::new(5, 8)])
+        );
+        assert_eq!(Vec::<HighlightRange>::new(), subtract_ranges(&[], &a));
+        assert_eq!(
+            Vec::<HighlightRange>::new(),
+            subtract_ranges(&a, &[HighlightRange::new(0, 20)])
+        );
+    }
+
+    #[test]
+    fn should_invert_ranges() {
+        let ranges = [
+            HighlightRange::new(6, 8),
+            HighlightRange::new(2, 4),
+            HighlightRange::new(4, 5),
+        ];
+
+        assert_eq!(
+            vec![
+                HighlightRange::new(0, 2),
+                HighlightRange::new(5, 6),
+                HighlightRange::new(8, 11),
+            ],
+            invert_ranges(11, &ranges)
+        );
+        assert_eq!(
+            Vec::<HighlightRange>::new(),
+            invert_ranges(11, &[HighlightRange::new(0, 11)])
+        );
+        assert_eq!(vec![HighlightRange::new(0, 11)], invert_ranges(11, &[]));
+        assert_eq!(Vec::<HighlightRange>::new(), invert_ranges(0, &[]));
+    }
+
+    #[test]
+    fn should_find_range_covering_position() {
+        let ranges = [HighlightRange::new(0, 5), HighlightRange::new(6, 11)];
+
+        assert_eq!(Some(&HighlightRange::new(0, 5)), range_covering(&ranges, 0));
+        assert_eq!(Some(&HighlightRange::new(0, 5)), range_covering(&ranges, 4));
+        assert_eq!(None, range_covering(&ranges, 5));
+        assert_eq!(
+            Some(&HighlightRange::new(6, 11)),
+            range_covering(&ranges, 6)
+        );
+        assert_eq!(None, range_covering(&ranges, 11));
+        assert!(is_highlighted(&ranges, 10));
+        assert!(!is_highlighted(&[], 0));
+    }
+
+    #[test]
+    fn should_find_ranges_in_window() {
+        let set = [0..2, 4..6, 8..10].into_iter().collect::<HighlightSet>();
+
+        assert_eq!(
+            &[HighlightRange::new(0, 2), HighlightRange::new(4, 6)],
+            set.ranges_in(1..5)
+        );
+        // windows ending at a lower bound or starting at an upper bound share no byte with it
+        assert_eq!(&[HighlightRange::new(4, 6)], set.ranges_in(2..8));
+        assert!(set.ranges_in(6..8).is_empty());
+        assert!(set.ranges_in(5..5).is_empty());
+        assert!(set.is_highlighted(9));
+    }
+
+    #[test]
+    fn should_compute_highlight_stats() {
+        // "ö" and "ü" are two bytes each
+        let input = "hö wörld üx";
+        let ranges = [
+            HighlightRange::new(0, 3),
+            HighlightRange::new(1, 3),
+            HighlightRange::new(4, 11),
+            HighlightRange::new(13, 14),
+        ];
+
+        let stats = highlight_stats(input, &ranges).unwrap();
+
+        assert_eq!(
+            HighlightStats {
+                highlighted_bytes: 11,
+                highlighted_chars: 9,
+                total_chars: 11,
+                span_count: 3,
+                longest_span: Some(HighlightRange::new(4, 11)),
+            },
+            stats
+        );
+        assert_eq!(9.0 / 11.0, stats.coverage());
+    }
+
+    #[test]
+    fn should_reject_invalid_ranges_in_highlight_stats() {
+        assert_eq!(
+            Err(HighlightingError::NotACharBoundary(2)),
+            highlight_stats("hö", &[HighlightRange::new(0, 2)])
+        );
+        assert_eq!(0.0, highlight_stats("", &[]).unwrap().coverage());
+    }
+
+    #[test]
+    fn should_encode_highlights_as_alternating_runs() {
+        let input = "touching and separate ranges";
+        let ranges = [
+            HighlightRange::new(13, 21),
+            HighlightRange::new(0, 5),
+            HighlightRange::new(5, 8),
+            HighlightRange::new(10, 10),
+        ];
+
+        let mask = highlight_mask(input, &ranges, IndexKind::Bytes).unwrap();
+
+        assert_eq!(
+            vec![(8, true), (5, false), (8, true), (7, false)],
+            mask.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(input.len(), mask.len());
+        assert_eq!(16, mask.covered_len());
+        assert_eq!(
+            vec![HighlightRange::new(0, 8), HighlightRange::new(13, 21)],
+            mask.to_ranges()
+        );
+
+        let mask = highlight_mask("plain", &[], IndexKind::Bytes).unwrap();
+        assert_eq!(vec![(5, false)], mask.iter().collect::<Vec<_>>());
+        assert!(
+            highlight_mask("", &[], IndexKind::Bytes)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn should_count_mask_runs_in_chars() {
+        // "größe" is 5 chars but 7 bytes
+        let input = "die größe";
+        let mask = highlight_mask(input, &[HighlightRange::new(4, 9)], IndexKind::Chars).unwrap();
+
+        assert_eq!(IndexKind::Chars, mask.unit());
+        assert_eq!(vec![(4, false), (5, true)], mask.iter().collect::<Vec<_>>());
+        assert_eq!(input.chars().count(), mask.len());
+        assert_eq!(
+            vec![(4, false), (7, true)],
+            highlight_mask(input, &[HighlightRange::new(4, 11)], IndexKind::Bytes)
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Err(HighlightingError::NotACharBoundary(7)),
+            highlight_mask(input, &[HighlightRange::new(4, 7)], IndexKind::Bytes)
+        );
+        assert!(highlight_mask(input, &[HighlightRange::new(4, 10)], IndexKind::Chars).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn should_serialize_run_length_masks_compactly() {
+        let mask =
+            highlight_mask("abcdef", &[HighlightRange::new(2, 4)], IndexKind::Bytes).unwrap();
+
+        let json = serde_json::to_string(&mask).unwrap();
+
+        assert_eq!(
+            r#"{"unit":"Bytes","first_highlighted":false,"runs":[2,2,2]}"#,
+            json
+        );
+        assert_eq!(mask, serde_json::from_str(&json).unwrap());
+        assert!(
+            serde_json::from_str::<RunLengthMask>(
+                r#"{"unit":"Chars","first_highlighted":true,"runs":[2,0,2]}"#
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn should_expand_ranges_to_word_boundaries() {
+        let input = "an international, überregional event";
+
+        let expanded = expand_to_word_boundaries(
+            input,
+            vec![
+                HighlightRange::new(5, 13),
+                HighlightRange::new(8, 18),
+                HighlightRange::new(21, 23),
+                HighlightRange::new(33, 50),
+            ],
+        );
+
+        // the ranges around ", " touch after widening and are merged
+        assert_eq!(
+            vec![HighlightRange::new(3, 31), HighlightRange::new(32, 37)],
+            expanded
+        );
+        assert_eq!(Ok(()), validate_ranges(input, &expanded));
+    }
+
+    #[test]
+    fn should_shrink_ranges_to_word_boundaries() {
+        let input = "an international event";
+
+        assert_eq!(
+            vec![HighlightRange::new(16, 22), HighlightRange::new(0, 3)],
+            shrink_to_word_boundaries(
+                input,
+                vec![
+                    HighlightRange::new(5, 30),
+                    HighlightRange::new(6, 9),
+                    HighlightRange::new(0, 4),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn should_use_custom_word_chars_for_word_boundaries() {
+        let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+
+        assert_eq!(
+            vec![HighlightRange::new(3, 13)],
+            expand_to_word_boundaries_by(
+                "fn snake_case()",
+                vec![HighlightRange::new(9, 10)],
+                is_word_char
+            )
+        );
+        assert_eq!(
+            vec![HighlightRange::new(9, 13)],
+            expand_to_word_boundaries("fn snake_case()", vec![HighlightRange::new(9, 10)])
+        );
+    }
+
+    #[test]
+    fn should_convert_word_ranges_to_byte_ranges() {
+        let input = "the  quick brown, fox";
+
+        assert_eq!(
+            Ok(vec![
+                HighlightRange::new(5, 10),
+                HighlightRange::new(11, 17),
+            ]),
+            word_ranges_to_byte_ranges(input, &[HighlightRange::new(1, 3)])
+        );
+        let options = WordRangeOptions {
+            include_separators: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            Ok(vec![
+                HighlightRange::new(0, 10),
+                HighlightRange::new(18, 21)
+            ]),
+            word_ranges_to_byte_ranges_with(
+                input,
+                &[HighlightRange::new(0, 2), HighlightRange::new(3, 4)],
+                options
+            )
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "unicode-segmentation")]
+    fn should_convert_unicode_word_ranges_to_byte_ranges() {
+        let options = WordRangeOptions {
+            split: WordSplit::UnicodeWords,
+            include_separators: false,
+        };
+
+        assert_eq!(
+            Ok(vec![HighlightRange::new(0, 5), HighlightRange::new(7, 10)]),
+            word_ranges_to_byte_ranges_with(
+                "Hello, big world",
+                &[HighlightRange::new(0, 2)],
+                options
+            )
+        );
+    }
+
+    #[test]
+    fn should_convert_line_col_spans_to_byte_ranges() {
+        // CRLF line endings, multi-byte chars and no trailing newline
+        let input = "fn main() {\r\n    let ö = 1;\r\n}";
+        let spans = [
+            LineColRange {
+                start: (1, 4),
+                end: (1, 8),
+            },
+            LineColRange {
+                start: (2, 9),
+                end: (2, 15),
+            },
+            LineColRange {
+                start: (3, 1),
+                end: (3, 2),
+            },
+        ];
+
+        let actual = line_col_to_byte_ranges(input, &spans).unwrap();
+
+        assert_eq!(
+            vec!["main", "ö = 1;", "}"],
+            actual
+                .iter()
+                .map(|r| &input[r.lower()..r.upper()])
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn should_reject_line_col_spans_out_of_bounds() {
+        let input = "ab\r\ncd\n";
+        let span = |start, end| [LineColRange { start, end }];
+
+        assert_eq!(
+            Err(HighlightingError::LineOutOfBounds {
+                line: 4,
+                line_count: 3
+            }),
+            line_col_to_byte_ranges(input, &span((1, 1), (4, 1)))
+        );
+        assert_eq!(
+            Err(HighlightingError::ColumnOutOfBounds {
+                line: 1,
+                column: 4,
+                line_len: 2
+            }),
+            line_col_to_byte_ranges(input, &span((1, 4), (2, 1)))
+        );
+        // line 3 is the empty line after the trailing newline
+        assert_eq!(
+            Ok(vec![HighlightRange::new(2, 7)]),
+            line_col_to_byte_ranges(input, &span((1, 3), (3, 1)))
+        );
+        assert!(line_col_to_byte_ranges(input, &span((0, 1), (1, 1))).is_err());
+    }
+
+    // "first line", "second", the empty third line and "last one" start at 0, 12, 19 and 20
+    const LOG: &str = "first line\r\nsecond\n\nlast one";
+
+    fn log_ranges() -> [HighlightRange; 5] {
+        [(6, 15), (0, 5), (17, 22), (25, 28), (24, 24)].map(|(l, u)| HighlightRange::new(l, u))
+    }
+
+    #[test]
+    fn should_split_ranges_by_lines() {
+        let line = |text, offset, line, ranges: &[(usize, usize)]| LineHighlights {
+            text,
+            offset,
+            line,
+            ranges: ranges
+                .iter()
+                .map(|&(lower, upper)| HighlightRange::new(lower, upper))
+                .collect(),
+        };
+
+        assert_eq!(
+            vec![
+                line("first line", 0, 1, &[(0, 5), (6, 10)]),
+                line("second", 12, 2, &[(0, 3), (5, 6)]),
+                line("", 19, 3, &[]),
+                line("last one", 20, 4, &[(0, 2), (4, 4), (5, 8)]),
+            ],
+            split_ranges_by_lines(LOG, &log_ranges())
+        );
+        // ranges only covering a line break or past the end are left out
+        assert_eq!(
+            vec![line("ab", 0, 1, &[]), line("", 4, 2, &[])],
+            split_ranges_by_lines(
+                "ab\r\n",
+                &[HighlightRange::new(2, 4), HighlightRange::new(5, 9)]
+            )
+        );
+    }
+
+    #[test]
+    fn should_render_lines_like_the_slices_of_the_whole_render() {
+        let whole = Highlighter::builder()
+            .split_at_newlines(true)
+            .build()
+            .unwrap()
+            .highlight(LOG, &log_ranges())
+            .unwrap();
+        let rendered_lines = whole
+            .split('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line));
+
+        let lines = split_ranges_by_lines(LOG, &log_ranges());
+
+        assert_eq!(4, lines.len());
+        for (line, rendered) in lines.into_iter().zip(rendered_lines) {
+            assert_eq!(
+                Ok(rendered.to_string()),
+                highlight_text(line.text, line.ranges),
+                "line {}",
+                line.line
+            );
+        }
+    }
+
+    #[test]
+    fn should_clip_ranges_to_window() {
+        let ranges = [
+            HighlightRange::new(0, 3),
+            HighlightRange::new(4, 9),
+            HighlightRange::new(10, 12),
+        ];
+
+        assert_eq!(
+            vec![HighlightRange::new(0, 1), HighlightRange::new(2, 5)],
+            clip_ranges(&ranges, &HighlightRange::new(2, 7))
+        );
+        assert_eq!(
+            Vec::<HighlightRange>::new(),
+            clip_ranges(&ranges, &HighlightRange::new(9, 10))
+        );
+    }
+
+    #[test]
+    fn should_parse_highlighted_text() {
+        assert_eq!(
+            Ok((
+                "Hello wörld".to_string(),
+                vec![HighlightRange::new(0, 5), HighlightRange::new(6, 12)]
+            )),
+            parse_highlighted("<em>Hello</em> <em>wörld</em><em></em>", "<em>", "</em>")
+        );
+        assert_eq!(
+            Ok(("a [b] c".to_string(), vec![HighlightRange::new(2, 5)])),
+            parse_highlighted("a <<[b]>> c", "<<", ">>")
+        );
+        assert_eq!("Hello world", strip_highlights("<em>Hello</em> world</em>"));
+    }
+
+    #[test]
+    fn should_reject_unbalanced_or_nested_markers() {
+        let parse = |input| parse_highlighted(input, "<em>", "</em>");
+
+        assert_eq!(Err(MarkerParseError::UnexpectedClose(1)), parse("a</em>"));
+        assert_eq!(Err(MarkerParseError::Unclosed(1)), parse("a<em>b"));
+        assert_eq!(
+            Err(MarkerParseError::Nested(5)),
+            parse("<em>a<em>b</em></em>")
+        );
+    }
+
+    #[test]
+    fn should_shift_ranges_by_delta() {
+        let mut ranges = vec![HighlightRange::new(0, 5), HighlightRange::new(6, 11)];
+
+        shift_ranges(&mut ranges, 8).unwrap();
+        assert_eq!(
+            vec![HighlightRange::new(8, 13), HighlightRange::new(14, 19)],
+            ranges
+        );
+        shift_ranges(&mut ranges, -8).unwrap();
+        assert_eq!(
+            vec![HighlightRange::new(0, 5), HighlightRange::new(6, 11)],
+            ranges
+        );
+        assert_eq!(
+            Ok("prefix: <em>Hello</em>".to_string()),
+            highlight_text("prefix: Hello", {
+                let mut ranges = vec![HighlightRange::new(0, 5)];
+                shift_ranges(&mut ranges, "prefix: ".len() as isize).unwrap();
+                ranges
+            })
+        );
+    }
+
+    #[test]
+    fn should_reject_shifts_leaving_usize_without_changing_ranges() {
+        let mut ranges = vec![HighlightRange::new(6, 11), HighlightRange::new(2, 4)];
+
+        assert_eq!(
+            Err(HighlightingError::ShiftOutOfRange {
+                range: HighlightRange::new(2, 4),
+                delta: -3,
+            }),
+            shift_ranges(&mut ranges, -3)
+        );
+        assert_eq!(
+            vec![HighlightRange::new(6, 11), HighlightRange::new(2, 4)],
+            ranges
+        );
+
+        let mut at_the_end = vec![HighlightRange::new(0, usize::MAX)];
+        assert_eq!(
+            Err(HighlightingError::ShiftOutOfRange {
+                range: HighlightRange::new(0, usize::MAX),
+                delta: 1,
+            }),
+            shift_ranges(&mut at_the_end, 1)
+        );
+        // moving to exactly zero is fine
+        shift_ranges(&mut ranges, -2).unwrap();
+        assert_eq!(HighlightRange::new(0, 2), ranges[1]);
+    }
+
+    #[test]
+    fn should_clamp_ranges_into_bounds() {
+        assert_eq!(
+            vec![HighlightRange::new(2, 5), HighlightRange::new(6, 8)],
+            clamp_ranges(
+                &[
+                    HighlightRange::new(0, 5),
+                    HighlightRange::new(6, 11),
+                    HighlightRange::new(8, 11),
+                    HighlightRange::new(3, 3),
+                    HighlightRange::new(0, 1),
+                ],
+                2..8
+            )
+        );
+        assert_eq!(
+            Vec::<HighlightRange>::new(),
+            clamp_ranges(&[HighlightRange::new(0, 5)], 5..5)
+        );
+    }
+
+    #[test]
+    fn should_expand_and_shrink_saturating() {
+        let range = HighlightRange::new(3, 9);
+
+        assert_eq!(HighlightRange::new(1, 11), range.expand(2));
+        assert_eq!(HighlightRange::new(0, 14), range.expand(5));
+        assert_eq!(
+            HighlightRange::new(0, usize::MAX),
+            HighlightRange::new(1, usize::MAX - 1).expand(5)
+        );
+
+        assert_eq!(HighlightRange::new(5, 7), range.shrink(2));
+        assert_eq!(HighlightRange::new(6, 6), range.shrink(3));
+        assert_eq!(HighlightRange::new(6, 6), range.shrink(usize::MAX));
+        assert_eq!(
+            HighlightRange::new(4, 4),
+            HighlightRange::new(3, 6).shrink(2)
+        );
+        assert_eq!(range, range.shrink(0));
+    }
+
+    #[test]
+    fn should_map_ranges_through_collapsed_whitespace() {
+        let source = "  Hello   World ";
+        let target = "Hello World";
+        let map = OffsetMap::new([
+            EditOp::Delete(2),
+            EditOp::Keep(5),
+            EditOp::Replace {
+                from_len: 3,
+                to_len: 1,
+            },
+            EditOp::Keep(5),
+            EditOp::Delete(1),
+        ]);
+        assert_eq!(
+            (source.len(), target.len()),
+            (map.source_len(), map.target_len())
+        );
+
+        let mapped = |lower, upper| {
+            map.map_range(HighlightRange::new(lower, upper))
+                .map(|r| &target[r.lower()..r.upper()])
+        };
+        assert_eq!(Some("World"), mapped(10, 15));
+        assert_eq!(Some("o W"), mapped(6, 11));
+        // partially deleted ranges are clipped, entirely deleted ones vanish
+        assert_eq!(Some("He"), mapped(0, 4));
+        assert_eq!(Some("World"), mapped(10, 16));
+        assert_eq!(None, mapped(0, 2));
+        assert_eq!(None, mapped(15, 16));
+        // touching a replaced run covers all of it
+        assert_eq!(Some(" "), mapped(8, 9));
+        assert_eq!(Some("o "), mapped(6, 8));
+        assert_eq!(None, mapped(10, 17));
+    }
+
+    #[test]
+    fn should_leave_inserted_text_out_of_mapped_ranges() {
+        let target = "prefix: Hello, world";
+        let map = OffsetMap::new([
+            EditOp::Insert(8),
+            EditOp::Keep(5),
+            EditOp::Insert(1),
+            EditOp::Keep(6),
+            EditOp::Keep(0),
+        ]);
+        let mapped = |lower, upper| {
+            map.map_range(HighlightRange::new(lower, upper))
+                .map(|r| &target[r.lower()..r.upper()])
+        };
+
+        assert_eq!(Some("Hello"), mapped(0, 5));
+        assert_eq!(Some(" world"), mapped(5, 11));
+        assert_eq!(Some("Hello, world"), mapped(0, 11));
+        assert_eq!(
+            Some(HighlightRange::new(8, 8)),
+            map.map_range(HighlightRange::new(0, 0))
+        );
+        assert_eq!(
+            Some(HighlightRange::new(20, 20)),
+            map.map_range(HighlightRange::new(11, 11))
+        );
+    }
+
+    #[test]
+    fn should_reject_reversed_bounds_in_try_new() {
+        assert_eq!(Ok(HighlightRange::new(0, 5)), HighlightRange::try_new(0, 5));
+        assert_eq!(Ok(HighlightRange::new(3, 3)), HighlightRange::try_new(3, 3));
+        assert_eq!(
+            Err(RangeConstructionError::Reversed { lower: 5, upper: 0 }),
+            HighlightRange::try_new(5, 0)
+        );
+        assert_eq!(
+            Err(RangeConstructionError::Reversed { lower: 5, upper: 0 }),
+            HighlightRange::try_from((5, 0))
+        );
+        assert_eq!(
+            "lower bound 5 is greater than upper bound 0",
+            HighlightRange::try_new(5, 0).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn should_reject_empty_ranges_in_try_new_non_empty() {
+        assert_eq!(
+            Err(RangeConstructionError::Empty { at: 3 }),
+            HighlightRange::try_new_non_empty(3, 3)
+        );
+        assert_eq!(
+            Err(RangeConstructionError::Reversed { lower: 5, upper: 0 }),
+            HighlightRange::try_new_non_empty(5, 0)
+        );
+        assert_eq!(
+            "range 3..3 is empty",
+            RangeConstructionError::Empty { at: 3 }.to_string()
+        );
+    }
+
+    #[test]
+    fn should_build_range_from_start_and_len() {
+        let range = HighlightRange::from_start_len(6, 5);
+
+        assert_eq!(HighlightRange::new(6, 11), range);
+        assert_eq!(5, range.len());
+        assert!(!range.is_empty());
+        assert!(HighlightRange::from_start_len(4, 0).is_empty());
+    }
+
+    #[test]
+    fn should_saturate_or_fail_instead_of_wrapping_at_usize_max() {
+        const MAX: usize = usize::MAX;
+
+        // constructors
+        assert_eq!(
+            HighlightRange::new(MAX, MAX),
+            HighlightRange::from_start_len(MAX, 1)
+        );
+        assert_eq!(
+            HighlightRange::new(1, MAX),
+            HighlightRange::from_start_len(1, MAX)
+        );
+        assert_eq!(
+            Err(RangeConstructionError::EndOverflow),
+            HighlightRange::try_from_start_len(MAX, 1)
+        );
+        assert_eq!(
+            Ok(HighlightRange::new(MAX - 1, MAX)),
+            HighlightRange::try_from_start_len(MAX - 1, 1)
+        );
+        assert_eq!(
+            Err(RangeConstructionError::EndOverflow),
+            HighlightRange::new_inclusive(MAX, MAX)
+        );
+        assert_eq!(
+            Some((MAX - 1, MAX - 1)),
+            HighlightRange::new(MAX - 1, MAX).to_inclusive()
+        );
+        assert_eq!(None, HighlightRange::new(MAX, MAX).to_inclusive());
+
+        // arithmetic on a range
+        assert_eq!(
+            HighlightRange::new(0, MAX),
+            HighlightRange::new(0, MAX).expand(MAX)
+        );
+        assert_eq!(MAX, HighlightRange::new(0, MAX).len());
+        assert_eq!(
+            HighlightRange::new(MAX / 2, MAX / 2),
+            HighlightRange::new(0, MAX).shrink(MAX)
+        );
+        assert_eq!(
+            HighlightRange::new(MAX / 2, MAX - MAX / 2),
+            HighlightRange::new(0, MAX).shrink(MAX / 2)
+        );
+        let mut ranges = vec![HighlightRange::new(0, MAX)];
+        assert!(shift_ranges(&mut ranges, isize::MIN).is_err());
+        assert!(shift_ranges(&mut ranges, 1).is_err());
+        assert_eq!(vec![HighlightRange::new(0, MAX)], ranges);
+
+        // edits and offset maps
+        let mut ranges = vec![HighlightRange::new(2, 8), HighlightRange::new(MAX - 1, MAX)];
+        transform_ranges(&mut ranges, &TextEdit::insert(4, MAX));
+        assert_eq!(
+            vec![HighlightRange::new(2, MAX), HighlightRange::new(MAX, MAX)],
+            ranges
+        );
+        let mut ranges = vec![HighlightRange::new(2, 8), HighlightRange::new(MAX, MAX)];
+        transform_ranges(&mut ranges, &TextEdit::delete(4, MAX));
+        assert_eq!(
+            vec![HighlightRange::new(2, 4), HighlightRange::new(4, 4)],
+            ranges
+        );
+        let map = OffsetMap::new([EditOp::Keep(MAX), EditOp::Insert(MAX), EditOp::Keep(1)]);
+        assert_eq!((MAX, MAX), (map.source_len(), map.target_len()));
+        assert_eq!(
+            Some(HighlightRange::new(MAX - 1, MAX)),
+            map.map_range(HighlightRange::new(MAX - 1, MAX))
+        );
+
+        // combining ranges
+        assert_eq!(
+            vec![HighlightRange::new(0, MAX)],
+            merge_ranges_with_gap(
+                vec![HighlightRange::new(0, 1), HighlightRange::new(MAX - 1, MAX)],
+                MAX
+            )
+        );
+        assert_eq!(
+            vec![HighlightRange::new(1, MAX - 1)],
+            invert_ranges(
+                MAX,
+                &[HighlightRange::new(0, 1), HighlightRange::new(MAX - 1, MAX)]
+            )
+        );
+        assert_eq!(
+            Ok(HighlightRange::new(0, 5)),
+            AnchoredRange::from_end(MAX, 0).resolve_with("Hello", BoundsPolicy::Clamp)
+        );
+        assert_eq!(
+            Ok(HighlightRange::new(0, 0)),
+            AnchoredRange::from_end(MAX, MAX).resolve_with("Hello", BoundsPolicy::Clamp)
+        );
+    }
+
+    #[test]
+    fn should_parse_inclusive_ranges() {
+        assert_eq!(
+            Ok(vec![HighlightRange::new(0, 5), HighlightRange::new(6, 11)]),
+            parse_ranges_with("0-4, 6..10", Bounds::Inclusive)
+        );
+        assert_eq!(
+            Ok(vec![HighlightRange::new(0, 4)]),
+            parse_ranges_with("0-4", Bounds::Exclusive)
+        );
+        assert_eq!(
+            Ok(HighlightRange::new(7, 8)),
+            HighlightRange::parse_with("7-7", Bounds::Inclusive)
+        );
+        assert_eq!(
+            Err(RangeParseErrorKind::EndOverflow),
+            HighlightRange::parse_with(&format!("0-{}", usize::MAX), Bounds::Inclusive)
+                .map_err(|e| e.kind)
+        );
+        assert_eq!(
+            Err(RangeParseErrorKind::ReversedBounds),
+            HighlightRange::parse_with("5-4", Bounds::Inclusive).map_err(|e| e.kind)
+        );
+    }
+
+    #[test]
+    fn should_drop_contained_ranges_keeping_order() {
+        let mut ranges = vec![
+            HighlightRange::new(10, 13),
+            HighlightRange::new(0, 4),
+            HighlightRange::new(10, 16),
+            HighlightRange::new(2, 2),
+            HighlightRange::new(2, 2),
+            HighlightRange::new(12, 16),
+        ];
+
+        dedup_contained_ranges(&mut ranges);
+
+        assert_eq!(
+            vec![
+                HighlightRange::new(0, 4),
+                HighlightRange::new(10, 16),
+                HighlightRange::new(2, 2),
+            ],
+            ranges
+        );
+    }
+
+    #[test]
+    fn should_keep_first_label_of_duplicate_ranges() {
+        let mut labeled = vec![
+            LabeledRange::new(HighlightRange::new(0, 4), "stem"),
+            LabeledRange::new(HighlightRange::new(0, 4), "exact"),
+            LabeledRange::new(HighlightRange::new(1, 3), "ngram"),
+        ];
+
+        dedup_contained_by(&mut labeled, |l| &l.range);
+
+        assert_eq!(
+            vec![LabeledRange::new(HighlightRange::new(0, 4), "stem")],
+            labeled
+        );
+    }
+
+    #[test]
+    fn should_merge_ranges_separated_by_small_gaps() {
+        let ranges = vec![
+            HighlightRange::new(4, 7),
+            HighlightRange::new(13, 16),
+            HighlightRange::new(0, 3),
+            HighlightRange::new(8, 11),
+        ];
+
+        assert_eq!(
+            vec![HighlightRange::new(0, 11), HighlightRange::new(13, 16)],
+            merge_ranges_with_gap(ranges.clone(), 1)
+        );
+        assert_eq!(
+            vec![HighlightRange::new(0, 16)],
+            merge_ranges_with_gap(ranges.clone(), 2)
+        );
+        assert_eq!(
+            merge_ranges(ranges.clone()),
+            merge_ranges_with_gap(ranges, 0)
+        );
+    }
+
+    #[test]
+    fn should_only_bridge_gaps_matching_the_predicate() {
+        let input = "New York, New-Jersey";
+        let ranges = vec![
+            HighlightRange::new(0, 3),
+            HighlightRange::new(4, 8),
+            HighlightRange::new(10, 13),
+            HighlightRange::new(14, 20),
+        ];
+
+        assert_eq!(
+            vec![
+                HighlightRange::new(0, 8),
+                HighlightRange::new(10, 13),
+                HighlightRange::new(14, 20),
+            ],
+            merge_ranges_with_gap_if(input, ranges, 1, |gap| gap.trim().is_empty())
+        );
+    }
+
+    #[test]
+    fn should_join_the_tokens_of_a_phrase() {
+        let input = "in New York  City, Rock-n'-Roll and New\nJersey";
+        let tokens = vec![
+            HighlightRange::new(3, 6),
+            HighlightRange::new(7, 11),
+            HighlightRange::new(13, 17),
+            HighlightRange::new(19, 23),
+            HighlightRange::new(24, 25),
+            HighlightRange::new(27, 31),
+            HighlightRange::new(36, 39),
+            HighlightRange::new(40, 46),
+        ];
+
+        assert_eq!(
+            vec![
+                HighlightRange::new(3, 17),
+                HighlightRange::new(19, 31),
+                HighlightRange::new(36, 39),
+                HighlightRange::new(40, 46),
+            ],
+            join_phrase_ranges(input, tokens.clone())
+        );
+
+        let across_lines = PhraseJoinOptions {
+            joiners: &[','],
+            across_lines: true,
+        };
+        assert_eq!(
+            vec![
+                HighlightRange::new(3, 23),
+                HighlightRange::new(24, 25),
+                HighlightRange::new(27, 31),
+                HighlightRange::new(36, 46),
+            ],
+            join_phrase_ranges_with(input, tokens, across_lines)
+        );
+        // a word between two ranges always separates them
+        assert_eq!(
+            vec![HighlightRange::new(3, 6), HighlightRange::new(13, 17)],
+            join_phrase_ranges(
+                input,
+                vec![HighlightRange::new(13, 17), HighlightRange::new(3, 6)]
+            )
+        );
+    }
+
+    #[test]
+    fn should_cut_exclusions_out_of_highlights() {
+        let exclusions = vec![
+            HighlightRange::new(10, 20),
+            HighlightRange::new(15, 25),
+            HighlightRange::new(40, 45),
+        ];
+        let ranges = vec![
+            // fully inside
+            HighlightRange::new(12, 18),
+            // straddling the start
+            HighlightRange::new(5, 12),
+            // straddling the end
+            HighlightRange::new(22, 30),
+            // containing an entire exclusion
+            HighlightRange::new(35, 50),
+            HighlightRange::new(16, 16),
+            HighlightRange::new(25, 25),
+        ];
+
+        assert_eq!(
+            vec![
+                HighlightRange::new(5, 10),
+                HighlightRange::new(25, 30),
+                HighlightRange::new(35, 40),
+                HighlightRange::new(45, 50),
+                HighlightRange::new(25, 25),
+            ],
+            subtract_exclusions(&ranges, &exclusions)
+        );
+    }
+
+    #[test]
+    fn should_flatten_overlapping_labeled_ranges() {
+        let labeled = vec![
+            LabeledRange::new(HighlightRange::new(0, 6), "person"),
+            LabeledRange::new(HighlightRange::new(3, 9), "match"),
+            LabeledRange::new(HighlightRange::new(9, 12), "match"),
+            LabeledRange::new(HighlightRange::new(10, 11), "match"),
+            LabeledRange::new(HighlightRange::new(14, 14), "marker"),
+        ];
+        let labels = |labels: &[&str]| {
+            labels
+                .iter()
+                .map(|label| label.to_string())
+                .collect::<BTreeSet<_>>()
+        };
+
+        assert_eq!(
+            vec![
+                (HighlightRange::new(0, 3), labels(&["person"])),
+                (HighlightRange::new(3, 6), labels(&["match", "person"])),
+                (HighlightRange::new(6, 12), labels(&["match"])),
+            ],
+            flatten_labeled_ranges(&labeled)
+        );
+    }
+
+    #[test]
+    fn should_flatten_ranges_with_coinciding_boundaries() {
+        let labeled = vec![
+            LabeledRange::new(HighlightRange::new(0, 4), "a"),
+            LabeledRange::new(HighlightRange::new(0, 4), "b"),
+            LabeledRange::new(HighlightRange::new(4, 8), "a"),
+            LabeledRange::new(HighlightRange::new(2, 8), "c"),
+        ];
+        let flattened = flatten_labeled_ranges(&labeled)
+            .into_iter()
+            .map(|(range, labels)| (range, labels.into_iter().collect::<Vec<_>>().join(" ")))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                (HighlightRange::new(0, 2), "a b".to_string()),
+                (HighlightRange::new(2, 4), "a b c".to_string()),
+                (HighlightRange::new(4, 8), "a c".to_string()),
+            ],
+            flattened
+        );
+    }
+
+    #[cfg(feature = "unicode-segmentation")]
+    #[test]
+    fn should_snap_to_grapheme_clusters() {
+        // "e" followed by a combining acute accent, 1 + 2 bytes
+        let accent = "caf\u{65}\u{301} ok";
+        assert_eq!(
+            vec![HighlightRange::new(0, 6)],
+            snap_to_graphemes(accent, &[HighlightRange::new(0, 4)])
+        );
+
+        // family emoji: three people joined by two ZWJs, 4 + 3 + 4 + 3 + 4 bytes
+        let family = "a\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}b";
+        assert_eq!(
+            vec![HighlightRange::new(1, 19), HighlightRange::new(19, 20)],
+            snap_to_graphemes(
+                family,
+                &[HighlightRange::new(5, 8), HighlightRange::new(19, 20)]
+            )
+        );
+
+        // German flag: two regional indicators, split down the middle
+        let flag = "\u{1F1E9}\u{1F1EA}!";
+        assert_eq!(
+            vec![HighlightRange::new(0, 8), HighlightRange::new(8, 8)],
+            snap_to_graphemes(
+                flag,
+                &[HighlightRange::new(4, 8), HighlightRange::new(8, 8)]
+            )
+        );
+    }
+
+    #[test]
+    fn should_snap_out_of_html_markup() {
+        // the tag is 0..15, the entities 20..25 and 32..36 and the closing tag 46..50
+        let html = r#"<p class="a>b">Fish &amp; chips &lt; 3 < 4 & 5</p>"#;
+        let ranges = [
+            HighlightRange::new(22, 31),
+            HighlightRange::new(10, 19),
+            HighlightRange::new(33, 33),
+            HighlightRange::new(39, 48),
+        ];
+
+        let snapped = snap_out_of_markup(html, &ranges);
+
+        assert_eq!(
+            vec![
+                HighlightRange::new(0, 19),
+                HighlightRange::new(20, 31),
+                HighlightRange::new(32, 32),
+                HighlightRange::new(39, 50),
+            ],
+            snapped
+        );
+        assert_eq!(
+            Ok(()),
+            validate_ranges_in(html, &snapped, ValidationMode::HtmlAware)
+        );
+
+        // ranges overlapping before the snapping aren't merged into one
+        assert_eq!(
+            vec![HighlightRange::new(20, 26), HighlightRange::new(20, 31)],
+            snap_out_of_markup(
+                html,
+                &[HighlightRange::new(20, 26), HighlightRange::new(22, 31)]
+            )
+        );
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn should_remap_ranges_into_nfc() {
+        // "e" + combining acute composes into the 2-byte "é"
+        let decomposed = "Cafe\u{301}!";
+
+        let (normalized, remapped) = normalize_and_remap(
+            decomposed,
+            &[HighlightRange::new(0, 6), HighlightRange::new(3, 4)],
+        );
+
+        assert_eq!("Caf\u{e9}!", normalized);
+        assert_eq!(
+            vec![HighlightRange::new(0, 5), HighlightRange::new(3, 5)],
+            remapped
+        );
+        assert_eq!(
+            vec![HighlightRange::new(0, 6), HighlightRange::new(6, 7)],
+            remap_from_nfc(
+                decomposed,
+                &[HighlightRange::new(0, 5), HighlightRange::new(5, 6)]
+            )
+        );
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn should_normalize_like_nfc_of_the_whole_input() {
+        use unicode_normalization::UnicodeNormalization;
+
+        // conjoining Hangul jamo and reordered combining marks
+        let input = "\u{1100}\u{1161}\u{11A8} a\u{323}\u{307}x o\u{307}\u{323}\u{e9}";
+
+        let (normalized, map) = nfc_with_offset_map(input);
+
+        assert_eq!(input.nfc().collect::<String>(), normalized);
+        assert_eq!(input.len(), map.source_len());
+        assert_eq!(normalized.len(), map.target_len());
+        assert_eq!(
+            Some(HighlightRange::new(0, 3)),
+            map.map_range(HighlightRange::new(3, 6))
+        );
+    }
+
+    #[test]
+    fn should_resolve_ranges_anchored_to_the_end() {
+        let input = "Invoice total: 12,50 €";
+
+        assert_eq!(
+            Ok(HighlightRange::new(19, 24)),
+            AnchoredRange::from_end(5, 0).resolve(input)
+        );
+        assert_eq!(
+            Ok(HighlightRange::new(15, 24)),
+            AnchoredRange::from_end_chars(7, 0).resolve(input)
+        );
+        assert_eq!(
+            Ok(HighlightRange::new(15, 20)),
+            AnchoredRange::from_end_chars(5, 2).resolve(input)
+        );
+        assert_eq!(
+            Ok(HighlightRange::new(0, 2)),
+            AnchoredRange::from(0..2).resolve(input)
+        );
+    }
+
+    #[test]
+    fn should_reject_or_clamp_anchored_ranges_that_underflow() {
+        let error = AnchoredRange::from_end_chars(5, 1)
+            .resolve("héllo")
+            .unwrap_err();
+        assert_eq!(
+            HighlightingError::AnchoredRangeUnderflow {
+                len: 5,
+                end_gap: 1,
+                input_len: 5,
+                kind: IndexKind::Chars,
+            },
+            error
+        );
+        assert_eq!(
+            "the last 5 chars before the final 1 don't fit into input of 5 chars",
+            error.to_string()
+        );
+        assert_eq!(
+            Ok(HighlightRange::new(0, 5)),
+            AnchoredRange::from_end_chars(5, 1).resolve_with("héllo", BoundsPolicy::Clamp)
+        );
+        assert_eq!(
+            Ok(HighlightRange::new(0, 0)),
+            AnchoredRange::from_end(2, 9).resolve_with("héllo", BoundsPolicy::Clamp)
+        );
+    }
+
+    #[test]
+    fn should_merge_labeled_ranges_only_within_a_label() {
+        let merged = merge_labeled_ranges(vec![
+            LabeledRange::new(HighlightRange::new(6, 10), "location"),
+            LabeledRange::new(HighlightRange::new(3, 6), "person"),
+            LabeledRange::new(HighlightRange::new(0, 4), "person"),
+            LabeledRange::new(HighlightRange::new(12, 12), "person"),
+            LabeledRange::new(HighlightRange::new(10, 12), "location"),
+        ]);
+
+        assert_eq!(
+            Ok(vec![
+                LabeledRange::new(HighlightRange::new(0, 6), "person"),
+                LabeledRange::new(HighlightRange::new(6, 12), "location"),
+            ]),
+            merged
+        );
+    }
+
+    #[test]
+    fn should_reject_labeled_ranges_still_overlapping_after_merging() {
+        let merged = merge_labeled_ranges(vec![
+            LabeledRange::new(HighlightRange::new(0, 3), "person"),
+            LabeledRange::new(HighlightRange::new(3, 6), "person"),
+            LabeledRange::new(HighlightRange::new(5, 8), "location"),
+        ]);
+
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 6),
+                second: HighlightRange::new(5, 8)
+            }),
+            merged
+        );
+    }
+
+    #[test]
+    fn should_shrink_highlights_around_stripped_soft_hyphens() {
+        let policy = CharPolicy {
+            invisible_formatting: true,
+            ..Default::default()
+        };
+        let input = "high\u{ad}light it";
+        let (stripped, map) = strip_for_display(input, &policy);
+
+        assert_eq!("highlight it", stripped);
+        assert_eq!(
+            Some(HighlightRange::new(0, 9)),
+            map.map_range(HighlightRange::new(0, 11))
+        );
+        assert_eq!(
+            Ok("<em>highlight</em> it".to_string()),
+            highlight_text(&stripped, map.map_range(HighlightRange::new(0, 11)))
+        );
+    }
+
+    #[test]
+    fn should_shift_highlights_after_stripped_chars() {
+        let policy = CharPolicy {
+            invisible_formatting: true,
+            c0_controls: true,
+            ..Default::default()
+        };
+        let input = "\u{ad}Hello\u{ad} \u{7}\u{200d}world";
+        let (stripped, map) = strip_for_display(input, &policy);
+
+        assert_eq!("Hello world", stripped);
+        assert_eq!(
+            vec![HighlightRange::new(0, 5), HighlightRange::new(6, 11)],
+            [HighlightRange::new(2, 7), HighlightRange::new(14, 19)]
+                .into_iter()
+                .filter_map(|h| map.map_range(h))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn should_replace_custom_chars_for_display() {
+        let policy = CharPolicy {
+            custom: BTreeSet::from(['\t', '_']),
+            replacement: Some(' '),
+            ..Default::default()
+        };
+        let (stripped, map) = strip_for_display("a\tb_c\n", &policy);
+
+        assert_eq!("a b c\n", stripped);
+        assert_eq!(
+            Some(HighlightRange::new(1, 4)),
+            map.map_range(HighlightRange::new(1, 4))
+        );
+    }
+
+    #[test]
+    fn should_parse_bracket_marked_text() {
+        assert_eq!(
+            Ok((
+                "Hello world".to_string(),
+                vec![HighlightRange::new(0, 5), HighlightRange::new(6, 11)]
+            )),
+            from_brackets("[Hello] [world]")
+        );
+        assert_eq!(
+            Ok((
+                "a[1] = \\ö".to_string(),
+                vec![HighlightRange::new(1, 4), HighlightRange::new(7, 10)]
+            )),
+            from_brackets(r"a[\[1\]] = [\\ö]")
+        );
+        assert_eq!(
+            Ok((
+                "x <y> ok".to_string(),
+                vec![HighlightRange::new(2, 5), HighlightRange::new(8, 8)]
+            )),
+            from_marked("x <\\<y\\>> ok<>", '<', '>')
+        );
+    }
+
+    #[test]
+    fn should_reject_unbalanced_or_nested_brackets() {
+        assert_eq!(
+            Err(MarkerParseError::UnexpectedClose(5)),
+            from_brackets("Hello] world")
+        );
+        assert_eq!(
+            Err(MarkerParseError::Unclosed(6)),
+            from_brackets("Hello [world")
+        );
+        assert_eq!(Err(MarkerParseError::Nested(3)), from_brackets("[He[llo]]"));
+    }
+
+    #[test]
+    fn should_roundtrip_marked_text() {
+        let marked = r"[Hello] \[not\] [\\world]";
+        let (text, ranges) = from_brackets(marked).unwrap();
+
+        assert_eq!(r"Hello [not] \world", text);
+        assert_eq!(Ok(marked.to_string()), to_marked(&text, &ranges));
+        assert_eq!(
+            Ok("<Hello> [not] <\\\\world>".to_string()),
+            to_marked_with(&text, &ranges, '<', '>')
+        );
+    }
+
+    #[test]
+    fn should_transform_a_range_for_an_edit_at_every_position() {
+        let transform = |edit: TextEdit| {
+            let mut ranges = vec![HighlightRange::new(4, 8)];
+            transform_ranges(&mut ranges, &edit);
+            ranges
+        };
+
+        // before the range
+        assert_eq!(
+            vec![HighlightRange::new(5, 9)],
+            transform(TextEdit::insert(1, 1))
+        );
+        assert_eq!(
+            vec![HighlightRange::new(2, 6)],
+            transform(TextEdit::delete(1, 2))
+        );
+        // touching its start
+        assert_eq!(
+            vec![HighlightRange::new(6, 10)],
+            transform(TextEdit::insert(4, 2))
+        );
+        assert_eq!(
+            vec![HighlightRange::new(2, 6)],
+            transform(TextEdit::delete(2, 2))
+        );
+        // overlapping its start
+        assert_eq!(
+            vec![HighlightRange::new(2, 4)],
+            transform(TextEdit::delete(2, 4))
+        );
+        assert_eq!(
+            vec![HighlightRange::new(5, 7)],
+            transform(TextEdit {
+                at: 2,
+                deleted: 4,
+                inserted: 3
+            })
+        );
+        // inside
+        assert_eq!(
+            vec![HighlightRange::new(4, 11)],
+            transform(TextEdit::insert(6, 3))
+        );
+        assert_eq!(
+            vec![HighlightRange::new(4, 6)],
+            transform(TextEdit::delete(5, 2))
+        );
+        // covering it
+        assert_eq!(
+            Vec::<HighlightRange>::new(),
+            transform(TextEdit::delete(4, 4))
+        );
+        assert_eq!(
+            Vec::<HighlightRange>::new(),
+            transform(TextEdit::delete(3, 7))
+        );
+        // overlapping its end
+        assert_eq!(
+            vec![HighlightRange::new(4, 6)],
+            transform(TextEdit::delete(6, 4))
+        );
+        assert_eq!(
+            vec![HighlightRange::new(4, 6)],
+            transform(TextEdit {
+                at: 6,
+                deleted: 4,
+                inserted: 5
+            })
+        );
+        // touching its end
+        assert_eq!(
+            vec![HighlightRange::new(4, 8)],
+            transform(TextEdit::insert(8, 2))
+        );
+        assert_eq!(
+            vec![HighlightRange::new(4, 8)],
+            transform(TextEdit::delete(8, 2))
+        );
+        // after it
+        assert_eq!(
+            vec![HighlightRange::new(4, 8)],
+            transform(TextEdit::insert(9, 2))
+        );
+    }
+
+    #[test]
+    fn should_keep_zero_length_ranges_in_front_of_insertions() {
+        let mut ranges = vec![HighlightRange::new(4, 4), HighlightRange::new(6, 6)];
+
+        transform_ranges(&mut ranges, &TextEdit::insert(4, 2));
+        assert_eq!(
+            vec![HighlightRange::new(4, 4), HighlightRange::new(8, 8)],
+            ranges
+        );
+
+        transform_ranges(&mut ranges, &TextEdit::delete(3, 6));
+        assert_eq!(
+            vec![HighlightRange::new(3, 3), HighlightRange::new(3, 3)],
+            ranges
+        );
+    }
+
+    #[test]
+    fn should_transform_ranges_across_a_burst_of_edits() {
+        // "Hello world" -> "Hello, world" -> "Hello, big world" -> "Hi, big world"
+        let mut ranges = vec![HighlightRange::new(0, 5), HighlightRange::new(6, 11)];
+        transform_ranges_batch(
+            &mut ranges,
+            &[
+                TextEdit::insert(5, 1),
+                TextEdit::insert(7, 4),
+                TextEdit {
+                    at: 1,
+                    deleted: 4,
+                    inserted: 1,
+                },
+            ],
+        );
+
+        assert_eq!(
+            Ok("<em>Hi</em>, big <em>world</em>".to_string()),
+            highlight_text("Hi, big world", ranges)
+        );
+    }
+
+    #[test]
+    fn should_split_sentences() {
+        let input = "Hello there. This is e.g. a test! Is it? yes it is. Done";
+
+        assert_eq!(
+            vec![
+                HighlightRange::new(0, 12),
+                HighlightRange::new(13, 33),
+                HighlightRange::new(34, 51),
+                HighlightRange::new(52, 56),
+            ],
+            sentence_spans(input)
+        );
+        assert_eq!(
+            vec![HighlightRange::new(2, 9), HighlightRange::new(10, 16)],
+            sentence_spans("  Wait... What?!  ")
+        );
+        assert_eq!(Vec::<HighlightRange>::new(), sentence_spans(" \n "));
+    }
+
+    #[test]
+    fn should_split_sentences_at_cjk_punctuation() {
+        assert_eq!(
+            vec![
+                HighlightRange::new(0, 18),
+                HighlightRange::new(18, 39),
+                HighlightRange::new(40, 42),
+            ],
+            sentence_spans("今日は晴れ。明日は雨、風！ OK")
+        );
+    }
+
+    #[test]
+    fn should_not_split_sentences_after_custom_abbreviations() {
+        let input = "It weighs approx. Two tons.";
+        let mut rules = SentenceRules::default();
+        assert_eq!(2, sentence_spans_with(input, &rules).len());
+
+        rules.abbreviations.insert("approx.".to_string());
+        assert_eq!(
+            vec![HighlightRange::new(0, 27)],
+            sentence_spans_with(input, &rules)
+        );
+    }
+
+    #[test]
+    fn should_expand_ranges_to_sentences() {
+        let input = "Hello there. This is e.g. a test! Is it? yes it is. Done";
+        let ranges = [
+            HighlightRange::new(6, 8),
+            HighlightRange::new(0, 5),
+            HighlightRange::new(28, 32),
+            HighlightRange::new(12, 13),
+            HighlightRange::new(45, 45),
+            HighlightRange::new(50, 53),
+        ];
+
+        assert_eq!(
+            vec![
+                HighlightRange::new(0, 12),
+                HighlightRange::new(13, 33),
+                HighlightRange::new(34, 56),
+            ],
+            expand_to_sentences(input, ranges)
+        );
+        assert_eq!(
+            vec![HighlightRange::new(34, 56)],
+            expand_to_sentences(input, [HighlightRange::new(40, 100)])
+        );
+    }
+
+    #[test]
+    fn should_concat_parts_and_shift_their_ranges() {
+        let title = [HighlightRange::new(0, 4)];
+        let author = [HighlightRange::new(4, 7)];
+        let (joined, ranges) = concat_with_ranges(
+            &[
+                ("Rust in Action", &title),
+                ("Tim McNamara", &author),
+                ("2021", &[]),
+            ],
+            " — ",
+        )
+        .unwrap();
+
+        assert_eq!("Rust in Action — Tim McNamara — 2021", joined);
+        assert_eq!(
+            vec![HighlightRange::new(0, 4), HighlightRange::new(23, 26)],
+            ranges
+        );
+        assert_eq!(
+            Ok("<em>Rust</em> in Action — Tim <em>McN</em>amara — 2021".to_string()),
+            highlight_text(&joined, ranges)
+        );
+    }
+
+    #[test]
+    fn should_name_the_part_with_invalid_ranges() {
+        assert_eq!(
+            Err(ConcatError {
+                part: 1,
+                error: HighlightingError::RangesOutOfBounds {
+                    range: HighlightRange::new(2, 9),
+                    input_len: 3
+                }
+            }),
+            concat_with_ranges(
+                &[
+                    ("first", &[HighlightRange::new(0, 5)]),
+                    ("abc", &[HighlightRange::new(2, 9)])
+                ],
+                ", "
+            )
+        );
+    }
+
+    #[test]
+    fn should_highlight_separators_between_highlighted_part_edges() {
+        let (joined, ranges) = concat_with_ranges_bridged(
+            &[
+                ("ab", &[HighlightRange::new(1, 2)]),
+                ("cd", &[HighlightRange::new(0, 1)]),
+                ("ef", &[HighlightRange::new(1, 2)]),
+            ],
+            "-",
+        )
+        .unwrap();
+
+        assert_eq!(
+            Ok("a<em>b-c</em>d-e<em>f</em>".to_string()),
+            highlight_text_with_policy(&joined, ranges, OverlapPolicy::Merge)
+        );
+    }
+
+    #[test]
+    fn should_split_overlapping_ranges_at_their_intersections() {
+        assert_eq!(
+            vec![
+                (HighlightRange::new(0, 5), vec![0]),
+                (HighlightRange::new(5, 7), vec![0, 1]),
+                (HighlightRange::new(7, 11), vec![1]),
+            ],
+            flatten_overlaps(vec![HighlightRange::new(0, 7), HighlightRange::new(5, 11)])
+        );
+        // nested, duplicated, touching, disjoint and zero-length ranges
+        assert_eq!(
+            vec![
+                (HighlightRange::new(0, 2), vec![1]),
+                (HighlightRange::new(2, 4), vec![0, 1, 3]),
+                (HighlightRange::new(4, 6), vec![1]),
+                (HighlightRange::new(6, 8), vec![2]),
+                (HighlightRange::new(10, 12), vec![4]),
+            ],
+            flatten_overlaps(vec![
+                HighlightRange::new(2, 4),
+                HighlightRange::new(0, 6),
+                HighlightRange::new(6, 8),
+                HighlightRange::new(2, 4),
+                HighlightRange::new(10, 12),
+                HighlightRange::new(3, 3),
+            ])
+        );
+    }
+
+    #[test]
+    fn should_render_flattened_overlaps_with_their_multiplicity() {
+        let flattened =
+            flatten_overlaps(vec![HighlightRange::new(0, 7), HighlightRange::new(5, 11)]);
+        let labeled = flattened
+            .into_iter()
+            .map(|(range, covering)| {
+                let label = if covering.len() > 1 {
+                    "multi"
+                } else {
+                    "single"
+                };
+                LabeledRange::new(range, label)
+            })
+            .collect();
+
+        assert_eq!(
+            Ok(r#"<em class="single">annot</em><em class="multi">at</em><em class="single">ions</em>."#
+                .to_string()),
+            highlight_text_labeled("annotations.", labeled)
+        );
+    }
+
+    #[test]
+    fn should_split_ranges_longer_than_max_len() {
+        let input = "abcdefghij";
+
+        // exactly at the limit
+        assert_eq!(
+            Ok(vec![HighlightRange::new(0, 8)]),
+            split_long_ranges(vec![HighlightRange::new(0, 8)], 8, input)
+        );
+        // one past it
+        assert_eq!(
+            Ok(vec![HighlightRange::new(0, 8), HighlightRange::new(8, 9)]),
+            split_long_ranges(vec![HighlightRange::new(0, 9)], 8, input)
+        );
+        assert_eq!(
+            Ok("<em>abcdefgh</em><em>i</em>j".to_string()),
+            highlight_text(
+                input,
+                split_long_ranges(vec![HighlightRange::new(0, 9)], 8, input).unwrap()
+            )
+        );
+        assert_eq!(
+            Err(HighlightingError::ZeroMaxLen),
+            split_long_ranges(vec![HighlightRange::new(0, 9)], 0, input)
+        );
+    }
+
+    #[test]
+    fn should_split_long_ranges_on_char_boundaries() {
+        // `é`, `€` and `ë` take 2, 3 and 2 bytes
+        let input = "aé€bcdë";
+        assert_eq!(
+            Ok(vec![
+                HighlightRange::new(0, 3),
+                HighlightRange::new(3, 7),
+                HighlightRange::new(7, 9),
+                HighlightRange::new(9, 11),
+            ]),
+            split_long_ranges(vec![HighlightRange::new(0, 11)], 2, input)
+        );
+        assert_eq!(
+            Ok(vec![
+                HighlightRange::new(0, 6),
+                HighlightRange::new(6, 9),
+                HighlightRange::new(9, 11),
+            ]),
+            split_long_ranges(vec![HighlightRange::new(0, 11)], 3, input)
+        );
+    }
+
+    #[test]
+    fn should_prefer_cutting_long_ranges_after_whitespace() {
+        let input = "lorem ipsum dolor sit";
+        assert_eq!(
+            Ok(vec![
+                HighlightRange::new(0, 12),
+                HighlightRange::new(12, 21)
+            ]),
+            split_long_ranges(vec![HighlightRange::new(0, 21)], 13, input)
+        );
+        // no whitespace within the look-back window
+        assert_eq!(
+            Ok(vec![HighlightRange::new(0, 4), HighlightRange::new(4, 5)]),
+            split_long_ranges(vec![HighlightRange::new(0, 5)], 4, input)
+        );
+    }
+}
@@ -0,0 +1,10995 @@
+//! Turning ranges into output: [`Highlighter`] with its tag styles and escaping, the [`Render`]
+//! implementations, segments, events and the `highlight_*` convenience functions
+
+use alloc::{
+    borrow::Cow,
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::{convert::Infallible, fmt, ops::Range, str::FromStr};
+#[cfg(feature = "std")]
+use std::{collections::HashMap, io};
+
+use crate::{range::*, validate::*};
+
+/// Contiguous piece of the input, see [`highlight_segments`]; with the `serde` feature it
+/// serializes as `{"text": …, "range": {"lower": …, "upper": …}, "highlighted": …}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Segment<'a> {
+    /// borrowed from the input
+    pub text: &'a str,
+    /// byte range of `text` within the input
+    pub range: HighlightRange,
+    pub highlighted: bool,
+}
+
+/// [`Segment`] paired with the payload of the range it was produced from, `None` for text
+/// between highlights
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnotatedSegment<'a, T> {
+    pub segment: Segment<'a>,
+    pub payload: Option<&'a T>,
+}
+
+/// Validated ranges over one input, sorted once so that the whole input or any window of it can
+/// be rendered repeatedly without validating or sorting again
+#[derive(Debug, Clone)]
+pub struct PreparedHighlights<'i> {
+    pub(crate) input: &'i str,
+    /// sorted by range, each paired with its index in the slice the ranges were prepared from;
+    /// shared, so clones are cheap
+    pub(crate) sorted: Arc<[(usize, HighlightRange)]>,
+}
+
+impl<'i> PreparedHighlights<'i> {
+    pub fn new(input: &'i str, highlights: &[HighlightRange]) -> Result<Self, HighlightingError> {
+        let mut sorted = Vec::with_capacity(highlights.len());
+        PreparedHighlights::sort_checked(input, highlights, &mut sorted)?;
+
+        Ok(PreparedHighlights {
+            input,
+            sorted: sorted.into(),
+        })
+    }
+
+    /// Validates `highlights` and replaces the contents of `sorted` with them, sorted by range and
+    /// paired with their indices
+    fn sort_checked(
+        input: &str,
+        highlights: &[HighlightRange],
+        sorted: &mut Vec<(usize, HighlightRange)>,
+    ) -> Result<(), HighlightingError> {
+        validate_bounds(input, highlights)?;
+
+        sorted.clear();
+        sorted.extend(highlights.iter().cloned().enumerate());
+        // ties are broken by index, so zero-length ranges sit between the ranges they touch and
+        // keep their input order without the buffer of a stable sort
+        sorted.sort_unstable_by(|(i, a), (j, b)| a.cmp(b).then(i.cmp(j)));
+        if sorted_ranges_overlap(sorted.iter().map(|(_, range)| range)) {
+            // only the error path pays for finding the same pair `validate_ranges` reports
+            check_overlaps(highlights)?;
+        }
+
+        Ok(())
+    }
+
+    /// Skips the overlap check and sorting, `highlights` must already be sorted and disjoint
+    pub(crate) fn from_sorted(
+        input: &'i str,
+        highlights: &[HighlightRange],
+    ) -> Result<Self, HighlightingError> {
+        validate_bounds(input, highlights)?;
+
+        let sorted = highlights.iter().cloned().enumerate().collect();
+        Ok(PreparedHighlights { input, sorted })
+    }
+
+    /// Same as [`PreparedHighlights::from_sorted`] for ranges that are already in bounds
+    pub(crate) fn from_validated(input: &'i str, highlights: Vec<HighlightRange>) -> Self {
+        let sorted = highlights.into_iter().enumerate().collect();
+        PreparedHighlights { input, sorted }
+    }
+
+    pub fn input(&self) -> &'i str {
+        self.input
+    }
+
+    /// The prepared ranges in `(lower, upper)` order
+    pub fn ranges(&self) -> impl Iterator<Item = &HighlightRange> {
+        self.sorted.iter().map(|(_, range)| range)
+    }
+
+    /// Same output as [`highlight_text`]
+    pub fn render(&self) -> String {
+        let highlighter = Highlighter::default();
+        highlighter.render_with(self, 0..self.input.len(), |_| {
+            Cow::Borrowed(&highlighter.open_tag)
+        })
+    }
+
+    /// Renders only `input[window]`, with the highlights clipped to it; `window` has to be in
+    /// bounds and on char boundaries like any other range
+    pub fn render_window(&self, window: Range<usize>) -> Result<String, HighlightingError> {
+        let checked = HighlightRange::from(window);
+        validate_bounds(self.input, core::slice::from_ref(&checked))?;
+
+        let highlighter = Highlighter::default();
+        Ok(
+            highlighter.render_with(self, checked.lower..checked.upper, |_| {
+                Cow::Borrowed(&highlighter.open_tag)
+            }),
+        )
+    }
+
+    /// Highlights the bytes of `added` and then unhighlights the bytes of `removed`, e.g. for an
+    /// annotation UI editing one range at a time. Added ranges are merged with the ranges they
+    /// overlap or touch, removed ones cut the ranges they cover; ranges away from the delta are
+    /// left alone. The ranges are renumbered in document order. Returns the region of the input
+    /// whose output changed, which covers every range that changed, merged or split; render it
+    /// with [`PreparedHighlights::render_region`] and replace the old output for it.
+    pub fn apply_delta(
+        &mut self,
+        added: &[HighlightRange],
+        removed: &[HighlightRange],
+    ) -> Result<AffectedRegion, HighlightingError> {
+        validate_bounds(self.input, added)?;
+        validate_bounds(self.input, removed)?;
+
+        let delta = merge_ranges(added.iter().chain(removed).cloned().collect());
+        let (Some(first), Some(last)) = (delta.first(), delta.last()) else {
+            return Ok(AffectedRegion {
+                range: HighlightRange::new(0, 0),
+            });
+        };
+
+        // only the ranges between the ends of the delta can change: those overlapping or touching
+        // it, and zero-length ones inside it, are replaced
+        let start = self.sorted.partition_point(|(_, r)| r.upper < first.lower);
+        let end = self.sorted.partition_point(|(_, r)| r.lower <= last.upper);
+        let old = self.sorted[start..end.max(start)]
+            .iter()
+            .map(|(_, r)| r.clone())
+            .collect::<Vec<_>>();
+        let involved = |r: &HighlightRange| {
+            let i = delta.partition_point(|d| d.upper < r.lower);
+            delta.get(i).is_some_and(|d| d.lower <= r.upper)
+        };
+        let (replaced, mut window): (Vec<_>, Vec<_>) = old.iter().cloned().partition(involved);
+        window.extend(subtract_ranges(&union_ranges(&replaced, added), removed));
+        sort_ranges(&mut window);
+
+        let prefix = old.iter().zip(&window).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(window[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let changed = old[prefix..old.len() - suffix]
+            .iter()
+            .chain(&window[prefix..window.len() - suffix]);
+        let range = changed
+            .cloned()
+            .reduce(|a, b| HighlightRange::new(a.lower.min(b.lower), a.upper.max(b.upper)))
+            .unwrap_or(HighlightRange::new(0, 0));
+
+        // the window is spliced in between the untouched ranges, renumbered in one pass
+        let before = self.sorted[..start].iter().map(|(_, r)| r.clone());
+        let after = self.sorted[end.max(start)..].iter().map(|(_, r)| r.clone());
+        self.sorted = before.chain(window).chain(after).enumerate().collect();
+        Ok(AffectedRegion { range })
+    }
+
+    /// The output for `region` of the input, the part of [`PreparedHighlights::render`] to
+    /// replace after [`PreparedHighlights::apply_delta`] returned it
+    pub fn render_region(&self, region: &AffectedRegion) -> String {
+        self.render_window(region.range.lower..region.range.upper)
+            .expect("regions are in bounds and on char boundaries of the input they came from")
+    }
+
+    /// Ranges that might show up in `window`: those sharing a byte with it, zero-length ones
+    /// within it, and at most one range touching each of its ends from outside
+    fn in_window(&self, window: &Range<usize>) -> &[(usize, HighlightRange)] {
+        // disjoint and sorted by lower, so the uppers are sorted as well
+        let start = self.sorted.partition_point(|(_, r)| r.upper < window.start);
+        let end = self.sorted.partition_point(|(_, r)| r.lower <= window.end);
+        &self.sorted[start..end.max(start)]
+    }
+
+    /// Calls `f` with the segments covering `input[window]`, see [`walk_segments`]
+    pub(crate) fn walk<E>(
+        &self,
+        window: Range<usize>,
+        keep_empty: bool,
+        f: impl FnMut(Segment<'i>, Option<usize>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let sorted = self.in_window(&window).iter().map(|(i, r)| (*i, r));
+        walk_segments(self.input, window, sorted, keep_empty, f)
+    }
+
+    /// Hands the whole input to `renderer`, see [`render_segments`]
+    pub(crate) fn render_through<'l, R: Render>(
+        &self,
+        keep_empty: bool,
+        label: impl Fn(usize) -> Option<&'l str>,
+        renderer: R,
+    ) -> R::Output {
+        let sorted = self.sorted.iter().map(|(i, r)| (*i, r));
+        let window = 0..self.input.len();
+        render_segments(self.input, window, sorted, keep_empty, label, renderer)
+    }
+}
+
+/// Part of the input whose output [`PreparedHighlights::apply_delta`] changed; the output for the
+/// text before and after it is the same as before the delta
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AffectedRegion {
+    /// spans every range that was changed, merged or split; empty if the output didn't change
+    pub range: HighlightRange,
+}
+
+impl AffectedRegion {
+    pub fn is_empty(&self) -> bool {
+        self.range.is_empty()
+    }
+}
+
+/// Input and ranges checked once by [`validate`], then rendered to any number of formats without
+/// validating or sorting them again. Clones share the ranges, and it is `Send`, so the formats can
+/// be rendered on different threads.
+#[derive(Debug, Clone)]
+pub struct ValidatedHighlights<'a> {
+    prepared: PreparedHighlights<'a>,
+}
+
+/// Checks `highlights` against `input` like [`highlight_text`] does, see [`ValidatedHighlights`]
+pub fn validate<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+) -> Result<ValidatedHighlights<'_>, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    let prepared = PreparedHighlights::new(input, &highlights)?;
+    Ok(ValidatedHighlights { prepared })
+}
+
+impl<'a> ValidatedHighlights<'a> {
+    pub fn input(&self) -> &'a str {
+        self.prepared.input
+    }
+
+    /// The validated ranges in `(lower, upper)` order
+    pub fn ranges(&self) -> impl Iterator<Item = &HighlightRange> {
+        self.prepared.ranges()
+    }
+
+    /// Same output as [`highlight_text`]
+    pub fn to_html(&self) -> String {
+        self.prepared.render()
+    }
+
+    /// Same output as [`highlight_text_escaped`]
+    pub fn to_html_escaped(&self) -> String {
+        self.render(HtmlRenderer::escaped())
+    }
+
+    /// Same output as [`highlight_text_ansi`]
+    pub fn to_ansi(&self, style: AnsiStyle) -> String {
+        // validated ranges never overlap, so merging only joins the touching ones
+        let merged = merge_ranges(self.prepared.ranges().cloned().collect());
+        let prepared = PreparedHighlights::from_validated(self.prepared.input, merged);
+        prepared.render_through(false, |_| None, AnsiRenderer::new(style))
+    }
+
+    /// Same output as [`highlight_segments`]
+    pub fn to_segments(&self) -> Vec<Segment<'a>> {
+        let mut segments = Vec::with_capacity(self.prepared.sorted.len() * 2 + 1);
+        let walked = self
+            .prepared
+            .walk(0..self.prepared.input.len(), false, |segment, _| {
+                segments.push(segment);
+                Ok::<(), Infallible>(())
+            });
+
+        match walked {
+            Ok(()) => segments,
+        }
+    }
+
+    /// Same events as [`highlight_events`]
+    pub fn to_events(&self) -> HighlightEvents<'a> {
+        HighlightEvents {
+            prepared: self.prepared.clone(),
+            next: 0,
+            offset: 0,
+            open: None,
+        }
+    }
+
+    /// Same output as [`highlight_with_renderer`]
+    pub fn render<R: Render>(&self, renderer: R) -> R::Output {
+        self.prepared.render_through(false, |_| None, renderer)
+    }
+
+    /// Same as [`split_at_ranges`]
+    pub fn split(&self) -> SplitResult<'a> {
+        let (highlighted, gaps) = self
+            .to_segments()
+            .into_iter()
+            .partition(|segment| segment.highlighted);
+        SplitResult { highlighted, gaps }
+    }
+}
+
+/// `input` split at the boundaries of the highlights, see [`split_at_ranges`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitResult<'a> {
+    /// the highlighted pieces in document order
+    pub highlighted: Vec<Segment<'a>>,
+    /// the text between them in document order, never empty
+    pub gaps: Vec<Segment<'a>>,
+}
+
+impl<'a> SplitResult<'a> {
+    /// The highlighted pieces and gaps merged back into document order; their texts concatenated
+    /// are the input
+    pub fn interleaved(&self) -> impl Iterator<Item = &Segment<'a>> {
+        let mut highlighted = self.highlighted.iter().peekable();
+        let mut gaps = self.gaps.iter().peekable();
+        core::iter::from_fn(move || match (highlighted.peek(), gaps.peek()) {
+            (Some(h), Some(g)) if g.range.lower < h.range.lower => gaps.next(),
+            (Some(_), _) => highlighted.next(),
+            (None, _) => gaps.next(),
+        })
+    }
+}
+
+/// Splits `input` into its highlighted pieces and the gaps between them instead of rendering it,
+/// e.g. to send them to different processors. The ranges are validated like in
+/// [`highlight_text`] and the pieces are the same as the [`highlight_segments`]: zero-length
+/// ranges are dropped and touching ranges leave no empty gap between them.
+pub fn split_at_ranges<'a, 'r>(
+    input: &'a str,
+    ranges: impl IntoRanges<'r>,
+) -> Result<SplitResult<'a>, HighlightingError> {
+    Ok(validate(input, ranges)?.split())
+}
+
+/// Calls `f` with contiguous segments covering `input[window]`, each paired with the index of
+/// the range it was produced from (`None` for text between highlights). `sorted` must yield
+/// validated, non-overlapping `(index, range)` pairs ordered by `(lower, upper)`; ranges are
+/// clipped to `window`.
+///
+/// Segments are non-empty, except for zero-length ranges when `keep_empty` is set.
+fn walk_segments<'i, 'r, E>(
+    input: &'i str,
+    window: Range<usize>,
+    sorted: impl Iterator<Item = (usize, &'r HighlightRange)>,
+    keep_empty: bool,
+    mut f: impl FnMut(Segment<'i>, Option<usize>) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut emit = |lower: usize, upper: usize, range_idx: Option<usize>| {
+        let segment = Segment {
+            text: &input[lower..upper],
+            range: HighlightRange::new(lower, upper),
+            highlighted: range_idx.is_some(),
+        };
+        f(segment, range_idx)
+    };
+
+    let mut offset = window.start;
+    for (i, range) in sorted {
+        let (lower, upper) = (range.lower.max(window.start), range.upper.min(window.end));
+        let is_marker = range.lower == range.upper;
+        // ranges touching the window from outside are clipped down to nothing
+        if lower >= upper && !(is_marker && keep_empty) {
+            continue;
+        }
+        if offset < lower {
+            emit(offset, lower, None)?;
+        }
+        emit(lower, upper, Some(i))?;
+        offset = upper;
+    }
+    if offset < window.end {
+        emit(offset, window.end, None)?;
+    }
+
+    Ok(())
+}
+
+/// Highlighted `input` that is written straight into the formatter by its [`fmt::Display`] impl,
+/// producing the same output as [`highlight_text`] without building a `String`
+#[derive(Debug, Clone)]
+pub struct HighlightedText<'a> {
+    input: &'a str,
+    /// validated and sorted by `(lower, upper)`, only copied if the caller's ranges weren't sorted
+    highlights: Cow<'a, [HighlightRange]>,
+}
+
+impl<'a> HighlightedText<'a> {
+    pub fn new(
+        input: &'a str,
+        highlights: &'a [HighlightRange],
+    ) -> Result<Self, HighlightingError> {
+        validate_bounds(input, highlights)?;
+
+        let sorted = if highlights.is_sorted() {
+            Cow::Borrowed(highlights)
+        } else {
+            let mut sorted = highlights.to_vec();
+            sort_ranges(&mut sorted);
+            Cow::Owned(sorted)
+        };
+        if sorted_ranges_overlap(sorted.iter()) {
+            check_overlaps(highlights)?;
+        }
+        let highlights = sorted;
+
+        Ok(HighlightedText { input, highlights })
+    }
+}
+
+impl fmt::Display for HighlightedText<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sorted = self.highlights.iter().enumerate();
+        let window = 0..self.input.len();
+        let markup = Markup::default();
+        let tags = |_| (markup.open_tag.clone(), markup.close_tag.clone());
+        let writer = TagWriter::new(Markup::default(), f, tags);
+        render_segments(self.input, window, sorted, false, |_| None, writer)
+    }
+}
+
+/// Which part of the input [`Highlighter::highlight_both`] renders
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BothOptions {
+    /// byte range of the input to render like [`highlight_window`], the whole input if `None`
+    pub window: Option<HighlightRange>,
+    /// render only the first chars of the window followed by `…`, like
+    /// [`Highlighter::highlight_truncated`]
+    pub max_visible_chars: Option<usize>,
+}
+
+/// Output of [`Highlighter::highlight_both`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BothOutputs<'i> {
+    pub highlighted: String,
+    /// `highlighted` without its tags, borrowed from the input unless it's cut or escaped
+    pub plain: Cow<'i, str>,
+}
+
+/// Output of [`Highlighter::highlight_outcome`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HighlightOutcome {
+    pub text: String,
+    /// highlights left out because of [`HighlighterBuilder::max_highlights`]
+    pub dropped: usize,
+    /// `id`s of the rendered highlights in document order, empty unless
+    /// [`HighlighterBuilder::anchor_ids`] is set
+    pub ids: Vec<String>,
+    /// ranges rendered as part of their enclosing highlight because of
+    /// [`HighlighterBuilder::max_nesting_depth`]
+    pub flattened: usize,
+    /// the highlights covered the whole input, `text` follows [`HighlighterBuilder::full_cover`]
+    pub full_cover: bool,
+}
+
+/// How a [`Highlighter`] renders highlights covering the whole input, see
+/// [`HighlighterBuilder::full_cover`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum FullCoverPolicy {
+    /// wrap the highlights like any others
+    #[default]
+    Wrap,
+    /// render the text without highlights, [`HighlightOutcome::full_cover`] tells it apart
+    Skip,
+    /// wrap the whole text in these open and close tags instead
+    Tag(String, String),
+}
+
+/// What [`HighlighterBuilder::max_coverage`] does when the highlights cover too much of the text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CoverageAction {
+    /// render the text without any highlights
+    #[default]
+    Skip,
+    /// drop the lowest-scored ranges until the coverage is within the limit; unscored ranges
+    /// score equally and are dropped from the last in document order. Ranges the overlap policy
+    /// merged are dropped together, scoring as the best-scored of them
+    TrimLowestScored,
+}
+
+/// Decision of the [`HighlighterBuilder::max_coverage`] gate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverageDecision {
+    /// the highlights were within the limit, or there is no limit
+    Kept,
+    /// the text was rendered without highlights
+    Skipped,
+    /// `dropped` input ranges were left out
+    Trimmed { dropped: usize },
+}
+
+/// Output of [`Highlighter::highlight_gated`] and [`Highlighter::highlight_scored`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageOutcome {
+    pub text: String,
+    pub decision: CoverageDecision,
+    /// fraction of chars the given ranges would have highlighted, after merging but before
+    /// [`HighlighterBuilder::max_highlights`]
+    pub original_coverage: f64,
+    /// fraction of chars highlighted in `text`
+    pub coverage: f64,
+}
+
+/// What became of a range passed to [`Highlighter::highlight_placed`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlacementStatus {
+    /// rendered as a highlight of its own, exactly as given
+    Rendered,
+    /// rendered in full as part of a larger highlight, see [`Placement::output`]
+    Merged,
+    /// only partially rendered, e.g. clamped to the input or cut by an exclusion
+    Clipped,
+    /// not rendered at all, e.g. a zero-length range or one beyond
+    /// [`HighlighterBuilder::max_highlights`]
+    Dropped,
+}
+
+/// Where a range passed to [`Highlighter::highlight_placed`] ended up in the output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    /// index of the range in the input
+    pub index: usize,
+    /// byte range of the output from the first to the last byte of highlighted content, i.e.
+    /// without the tags around it; ranges merged into one highlight all get the output of that
+    /// highlight. `None` if the range was dropped.
+    pub output: Option<HighlightRange>,
+    pub status: PlacementStatus,
+}
+
+/// Output of [`Highlighter::highlight_placed`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HighlightResult {
+    pub text: String,
+    /// one for every input range, in input order
+    pub placements: Vec<Placement>,
+}
+
+/// Reusable highlighting configuration, see [`Highlighter::builder`]
+#[derive(Debug, Clone)]
+pub struct Highlighter {
+    /// inserted in front of every highlight
+    open_tag: Arc<str>,
+    /// inserted after every highlight
+    close_tag: Arc<str>,
+    overlap_policy: OverlapPolicy,
+    bounds_policy: BoundsPolicy,
+    /// render touching ranges as one highlight
+    coalesce_adjacent: bool,
+    /// render zero-length ranges as empty tag pairs (e.g. caret markers) instead of dropping them
+    allow_empty_markers: bool,
+    /// escaping applied to the text between the inserted tags
+    escaping: Escaping,
+    /// close highlights before every line break and reopen them after it
+    split_at_newlines: bool,
+    /// drop duplicate and contained ranges before validating, see [`dedup_contained_ranges`]
+    dedup_contained: bool,
+    /// render only the first highlights in document order, counted after merging
+    max_highlights: Option<usize>,
+    /// reject more ranges than this before looking at them
+    max_ranges: Option<usize>,
+    /// reject inputs whose output would be larger than this before rendering them
+    max_output_bytes: Option<usize>,
+    /// fraction of chars that may be highlighted and what happens beyond it
+    max_coverage: Option<(f32, CoverageAction)>,
+    /// levels of nested tags rendered with [`OverlapPolicy::Nest`]
+    max_nesting_depth: usize,
+    /// regions that are never highlighted, in the canonical form of [`merge_ranges`]
+    exclusions: Arc<[HighlightRange]>,
+    /// widen ranges to grapheme cluster boundaries, see [`snap_to_graphemes`]
+    #[cfg(feature = "unicode-segmentation")]
+    snap_to_graphemes: bool,
+    /// give the n-th highlight an `id="{prefix}{n}"` attribute
+    anchor_id_prefix: Option<Arc<str>>,
+    /// wrap the text of every highlight in [`FIRST_STRONG_ISOLATE`] and [`POP_DIRECTIONAL_ISOLATE`]
+    bidi_isolate: bool,
+    /// add the originating ranges to every open tag, see [`HighlighterBuilder::debug_annotations`]
+    debug_annotations: bool,
+    /// what highlights covering the whole input are rendered as
+    full_cover: FullCoverPolicy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escaping {
+    None,
+    Html,
+    /// backslash-escapes `\`, `*`, `_` and, if `equals` is set, `=`
+    Markdown {
+        equals: bool,
+    },
+    /// escapes the LaTeX special characters `# $ % & _ { } ~ ^ \`
+    Latex,
+    /// puts a zero-width space after every `[` so it can't start a BBCode tag
+    BbCode,
+}
+
+impl Escaping {
+    fn write<W: fmt::Write>(self, out: &mut W, text: &str) -> fmt::Result {
+        match self {
+            Escaping::None => out.write_str(text),
+            Escaping::Html => write_html_escaped(out, text),
+            Escaping::Markdown { equals } => write_markdown_escaped(out, text, equals),
+            Escaping::Latex => write_latex_escaped(out, text),
+            Escaping::BbCode => write_bbcode_escaped(out, text),
+        }
+    }
+}
+
+/// How highlights and the text between them are written, the part of a [`Highlighter`] that
+/// [`HtmlRenderer`] and the other renderers share; `<em>` without escaping by default
+#[derive(Debug, Clone)]
+struct Markup<'h> {
+    open_tag: Cow<'h, str>,
+    close_tag: Cow<'h, str>,
+    escaping: Escaping,
+    /// see [`HighlighterBuilder::split_at_newlines`]
+    split_at_newlines: bool,
+    /// see [`HighlighterBuilder::bidi_isolate`]
+    bidi_isolate: bool,
+}
+
+impl Default for Markup<'_> {
+    fn default() -> Self {
+        Markup {
+            open_tag: Cow::Borrowed("<em>"),
+            close_tag: Cow::Borrowed("</em>"),
+            escaping: Escaping::None,
+            split_at_newlines: false,
+            bidi_isolate: false,
+        }
+    }
+}
+
+impl Markup<'_> {
+    fn write_text<W: fmt::Write>(&self, out: &mut W, text: &str) -> fmt::Result {
+        self.escaping.write(out, text)
+    }
+
+    /// Writes `text` between `open_tag` and `close_tag`, or every line of it with
+    /// [`HighlighterBuilder::split_at_newlines`]
+    fn write_highlight<W: fmt::Write>(
+        &self,
+        out: &mut W,
+        open_tag: &str,
+        close_tag: &str,
+        text: &str,
+    ) -> fmt::Result {
+        if self.split_at_newlines && !text.is_empty() {
+            return self.write_lines_highlighted(out, open_tag, close_tag, text);
+        }
+
+        out.write_str(open_tag)?;
+        self.write_highlighted_text(out, text)?;
+        out.write_str(close_tag)
+    }
+
+    /// Highlights every line of `text` on its own, lines that are empty apart from their break
+    /// get no tags
+    fn write_lines_highlighted<W: fmt::Write>(
+        &self,
+        out: &mut W,
+        open_tag: &str,
+        close_tag: &str,
+        text: &str,
+    ) -> fmt::Result {
+        for line in text.split_inclusive('\n') {
+            let content = line
+                .strip_suffix('\n')
+                .map_or(line, |l| l.strip_suffix('\r').unwrap_or(l));
+            if !content.is_empty() {
+                out.write_str(open_tag)?;
+                self.write_highlighted_text(out, content)?;
+                out.write_str(close_tag)?;
+            }
+            out.write_str(&line[content.len()..])?;
+        }
+
+        Ok(())
+    }
+
+    /// Same as [`Markup::write_text`], in bidi isolates if configured
+    fn write_highlighted_text<W: fmt::Write>(&self, out: &mut W, text: &str) -> fmt::Result {
+        if !self.bidi_isolate || text.is_empty() {
+            return self.write_text(out, text);
+        }
+
+        out.write_char(FIRST_STRONG_ISOLATE)?;
+        self.write_text(out, text)?;
+        out.write_char(POP_DIRECTIONAL_ISOLATE)
+    }
+}
+
+impl Default for Highlighter {
+    fn default() -> Self {
+        Highlighter {
+            open_tag: Arc::from("<em>"),
+            close_tag: Arc::from("</em>"),
+            overlap_policy: OverlapPolicy::Error,
+            bounds_policy: BoundsPolicy::Strict,
+            coalesce_adjacent: false,
+            allow_empty_markers: false,
+            escaping: Escaping::None,
+            split_at_newlines: false,
+            dedup_contained: false,
+            max_highlights: None,
+            max_ranges: None,
+            max_output_bytes: None,
+            max_coverage: None,
+            max_nesting_depth: usize::MAX,
+            exclusions: Arc::from(Vec::new()),
+            #[cfg(feature = "unicode-segmentation")]
+            snap_to_graphemes: false,
+            anchor_id_prefix: None,
+            bidi_isolate: false,
+            debug_annotations: false,
+            full_cover: FullCoverPolicy::Wrap,
+        }
+    }
+}
+
+/// Predefined pair of HTML tags a highlight is wrapped in, see [`HighlighterBuilder::tag_style`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TagStyle {
+    /// `<em>…</em>`
+    #[default]
+    Em,
+    /// `<strong>…</strong>`
+    Strong,
+    /// `<mark>…</mark>`
+    Mark,
+    /// `<span class="…">…</span>`, the class is HTML-escaped
+    SpanClass(String),
+    /// used verbatim
+    Custom { open: String, close: String },
+}
+
+impl TagStyle {
+    pub fn open_tag(&self) -> Cow<'_, str> {
+        match self {
+            TagStyle::Em => Cow::Borrowed("<em>"),
+            TagStyle::Strong => Cow::Borrowed("<strong>"),
+            TagStyle::Mark => Cow::Borrowed("<mark>"),
+            TagStyle::SpanClass(class) => {
+                let mut tag = String::with_capacity(class.len() + "<span class=\"\">".len());
+                tag.push_str("<span class=\"");
+                write_html_escaped(&mut tag, class).expect("writing into a String can't fail");
+                tag.push_str("\">");
+                Cow::Owned(tag)
+            }
+            TagStyle::Custom { open, .. } => Cow::Borrowed(open),
+        }
+    }
+
+    pub fn close_tag(&self) -> &str {
+        match self {
+            TagStyle::Em => "</em>",
+            TagStyle::Strong => "</strong>",
+            TagStyle::Mark => "</mark>",
+            TagStyle::SpanClass(_) => "</span>",
+            TagStyle::Custom { close, .. } => close,
+        }
+    }
+}
+
+/// Delimiter a highlight is wrapped in by [`highlight_text_markdown`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MarkdownDelimiter {
+    /// `**strong**`
+    #[default]
+    Strong,
+    /// `*emphasis*`
+    Emphasis,
+    /// `__strong__`
+    Underscore,
+    /// `==mark==` (highlight extension)
+    Mark,
+}
+
+impl MarkdownDelimiter {
+    fn as_str(self) -> &'static str {
+        match self {
+            MarkdownDelimiter::Strong => "**",
+            MarkdownDelimiter::Emphasis => "*",
+            MarkdownDelimiter::Underscore => "__",
+            MarkdownDelimiter::Mark => "==",
+        }
+    }
+}
+
+/// U+2068, starts a bidi isolate taking its direction from its first strong char, see
+/// [`HighlighterBuilder::bidi_isolate`]
+pub const FIRST_STRONG_ISOLATE: char = '\u{2068}';
+
+/// U+2069, ends a bidi isolate
+pub const POP_DIRECTIONAL_ISOLATE: char = '\u{2069}';
+
+/// SGR reset, closes every [`AnsiStyle`]
+const ANSI_RESET: &str = "\x1b[0m";
+
+/// The 16 named terminal colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnsiColor {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    BrightBlack,
+    BrightRed,
+    BrightGreen,
+    BrightYellow,
+    BrightBlue,
+    BrightMagenta,
+    BrightCyan,
+    BrightWhite,
+}
+
+impl AnsiColor {
+    /// SGR foreground code, 30-37 for normal and 90-97 for bright colors
+    fn foreground_code(self) -> u8 {
+        match self {
+            AnsiColor::Black => 30,
+            AnsiColor::Red => 31,
+            AnsiColor::Green => 32,
+            AnsiColor::Yellow => 33,
+            AnsiColor::Blue => 34,
+            AnsiColor::Magenta => 35,
+            AnsiColor::Cyan => 36,
+            AnsiColor::White => 37,
+            AnsiColor::BrightBlack => 90,
+            AnsiColor::BrightRed => 91,
+            AnsiColor::BrightGreen => 92,
+            AnsiColor::BrightYellow => 93,
+            AnsiColor::BrightBlue => 94,
+            AnsiColor::BrightMagenta => 95,
+            AnsiColor::BrightCyan => 96,
+            AnsiColor::BrightWhite => 97,
+        }
+    }
+}
+
+/// Terminal style a highlight is rendered with by [`highlight_text_ansi`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnsiStyle {
+    Bold,
+    /// named 16-color foreground
+    Color(AnsiColor),
+    /// 256-color palette foreground
+    Color256(u8),
+    /// truecolor foreground
+    Rgb(u8, u8, u8),
+}
+
+impl AnsiStyle {
+    /// SGR sequence that switches the style on
+    fn escape_sequence(self) -> String {
+        match self {
+            AnsiStyle::Bold => String::from("\x1b[1m"),
+            AnsiStyle::Color(color) => format!("\x1b[{}m", color.foreground_code()),
+            AnsiStyle::Color256(n) => format!("\x1b[38;5;{n}m"),
+            AnsiStyle::Rgb(r, g, b) => format!("\x1b[38;2;{r};{g};{b}m"),
+        }
+    }
+}
+
+/// Error of the streaming APIs like [`highlight_text_to`], which can fail on the sink as well
+#[derive(Debug)]
+pub enum HighlightError {
+    Invalid(HighlightingError),
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    Fmt(fmt::Error),
+}
+
+impl fmt::Display for HighlightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HighlightError::Invalid(e) => write!(f, "invalid highlights: {e}"),
+            #[cfg(feature = "std")]
+            HighlightError::Io(e) => write!(f, "failed to write highlights: {e}"),
+            HighlightError::Fmt(e) => write!(f, "failed to format highlights: {e}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for HighlightError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HighlightError::Invalid(e) => Some(e),
+            HighlightError::Io(e) => Some(e),
+            HighlightError::Fmt(e) => Some(e),
+        }
+    }
+}
+
+impl From<HighlightingError> for HighlightError {
+    fn from(value: HighlightingError) -> Self {
+        HighlightError::Invalid(value)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for HighlightError {
+    fn from(value: io::Error) -> Self {
+        HighlightError::Io(value)
+    }
+}
+
+impl From<fmt::Error> for HighlightError {
+    fn from(value: fmt::Error) -> Self {
+        HighlightError::Fmt(value)
+    }
+}
+
+/// Combination of [`HighlighterBuilder`] options that can't be satisfied together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HighlighterConfigError {
+    /// merging or coalescing would swallow zero-length ranges into their neighbours
+    EmptyMarkersWithMerging,
+    /// [`HighlighterBuilder::max_coverage`] isn't a fraction from 0 to 1
+    MaxCoverageOutOfRange,
+}
+
+impl fmt::Display for HighlighterConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HighlighterConfigError::EmptyMarkersWithMerging => {
+                f.write_str("empty markers can't be combined with merging or coalescing of ranges")
+            }
+            HighlighterConfigError::MaxCoverageOutOfRange => {
+                f.write_str("the maximum coverage must be a fraction from 0 to 1")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for HighlighterConfigError {}
+
+#[derive(Debug, Clone, Default)]
+pub struct HighlighterBuilder {
+    highlighter: Highlighter,
+}
+
+impl HighlighterBuilder {
+    pub fn open_tag(mut self, tag: &str) -> Self {
+        self.highlighter.open_tag = Arc::from(tag);
+        self
+    }
+
+    pub fn close_tag(mut self, tag: &str) -> Self {
+        self.highlighter.close_tag = Arc::from(tag);
+        self
+    }
+
+    /// shorthand for setting both [`HighlighterBuilder::open_tag`] and
+    /// [`HighlighterBuilder::close_tag`]
+    pub fn tag_style(mut self, style: TagStyle) -> Self {
+        self.highlighter.open_tag = Arc::from(style.open_tag());
+        self.highlighter.close_tag = Arc::from(style.close_tag());
+        self
+    }
+
+    /// render highlights as BBCode `[tag]…[/tag]`, with `[` in the text neutralized by a
+    /// zero-width space so user content can't inject formatting
+    pub fn bbcode(mut self, tag: &str) -> Self {
+        self.highlighter.open_tag = Arc::from(format!("[{tag}]"));
+        self.highlighter.close_tag = Arc::from(format!("[/{tag}]"));
+        self.highlighter.escaping = Escaping::BbCode;
+        self
+    }
+
+    /// render highlights with ANSI SGR codes instead of HTML tags
+    pub fn ansi_style(mut self, style: AnsiStyle) -> Self {
+        self.highlighter.open_tag = Arc::from(style.escape_sequence());
+        self.highlighter.close_tag = Arc::from(ANSI_RESET);
+        self
+    }
+
+    pub fn overlap_policy(mut self, policy: OverlapPolicy) -> Self {
+        self.highlighter.overlap_policy = policy;
+        self
+    }
+
+    pub fn bounds_policy(mut self, policy: BoundsPolicy) -> Self {
+        self.highlighter.bounds_policy = policy;
+        self
+    }
+
+    /// shorthand for [`OverlapPolicy::Merge`] (`true`) or [`OverlapPolicy::Error`] (`false`)
+    pub fn merge_overlaps(self, merge: bool) -> Self {
+        self.overlap_policy(if merge {
+            OverlapPolicy::Merge
+        } else {
+            OverlapPolicy::Error
+        })
+    }
+
+    pub fn coalesce_adjacent(mut self, coalesce: bool) -> Self {
+        self.highlighter.coalesce_adjacent = coalesce;
+        self
+    }
+
+    pub fn allow_empty_markers(mut self, allow: bool) -> Self {
+        self.highlighter.allow_empty_markers = allow;
+        self
+    }
+
+    /// wrap the text of every highlight in [`FIRST_STRONG_ISOLATE`] and
+    /// [`POP_DIRECTIONAL_ISOLATE`] inside the tags, so the bidi algorithm can't move RTL text
+    /// (e.g. an Arabic word in an English sentence) across plain-text markers; the isolates don't
+    /// count towards [`Highlighter::highlight_truncated`]. Doesn't apply to [`OverlapPolicy::Nest`]
+    pub fn bidi_isolate(mut self, isolate: bool) -> Self {
+        self.highlighter.bidi_isolate = isolate;
+        self
+    }
+
+    /// render a highlight that spans line breaks as one pair of tags per line, with the breaks
+    /// (`\n` or `\r\n`) between them; doesn't apply to [`OverlapPolicy::Nest`]
+    pub fn split_at_newlines(mut self, split: bool) -> Self {
+        self.highlighter.split_at_newlines = split;
+        self
+    }
+
+    /// remove duplicate and fully contained ranges before the overlap check, so only partial
+    /// overlaps are rejected, see [`dedup_contained_ranges`]; doesn't apply to
+    /// [`OverlapPolicy::Nest`]
+    pub fn dedup_contained(mut self, dedup: bool) -> Self {
+        self.highlighter.dedup_contained = dedup;
+        self
+    }
+
+    /// render only the first `max` highlights in document order and drop the rest, counted like
+    /// [`take_first_k`] so touching or merged highlights count once and empty markers not at all;
+    /// `0` renders no highlights at all. See [`Highlighter::highlight_outcome`] for how many were
+    /// dropped; doesn't apply to [`OverlapPolicy::Nest`]
+    pub fn max_highlights(mut self, max: usize) -> Self {
+        self.highlighter.max_highlights = Some(max);
+        self
+    }
+
+    /// reject more than `max` ranges with [`HighlightingError::TooManyRanges`] before they are
+    /// validated or sorted, e.g. for ranges coming from untrusted callers. Unlike
+    /// [`HighlighterBuilder::max_highlights`] nothing is rendered, and every range counts.
+    pub fn max_ranges(mut self, max: usize) -> Self {
+        self.highlighter.max_ranges = Some(max);
+        self
+    }
+
+    /// reject with [`HighlightingError::OutputTooLarge`] before rendering if the output would be
+    /// larger than `max` bytes. The estimate is the length of the input plus an open and a close
+    /// tag for every highlight that is rendered, counted after merging and the other options;
+    /// escaping, attributes and tags reopened at line breaks or by [`OverlapPolicy::Nest`] aren't
+    /// counted, so the output can still end up somewhat larger.
+    pub fn max_output_bytes(mut self, max: usize) -> Self {
+        self.highlighter.max_output_bytes = Some(max);
+        self
+    }
+
+    /// highlight at most the fraction `max` (from 0 to 1) of the chars, counted after merging and
+    /// the other options but before [`HighlighterBuilder::max_highlights`]; beyond it `action`
+    /// either skips highlighting or trims ranges, in every way of rendering. E.g. a stop-word
+    /// query matching nearly everything is better not highlighted at all. See
+    /// [`Highlighter::highlight_gated`] for the decision and the coverage.
+    pub fn max_coverage(mut self, max: f32, action: CoverageAction) -> Self {
+        self.highlighter.max_coverage = Some((max, action));
+        self
+    }
+
+    /// render highlights that together cover every byte of the input as `policy` says, e.g.
+    /// without markup when the whole card is styled instead. Detected after merging and the other
+    /// options, so touching highlights add up, and honored in every way of rendering; with
+    /// [`OverlapPolicy::Nest`] the union of the ranges has to cover the input.
+    pub fn full_cover(mut self, policy: FullCoverPolicy) -> Self {
+        self.highlighter.full_cover = policy;
+        self
+    }
+
+    /// with [`OverlapPolicy::Nest`], open at most `max` levels of tags at any position (`0` is
+    /// treated like `1`): a range that would nest deeper is rendered as part of the enclosing
+    /// highlight instead, and opened once it is shallow enough again. See
+    /// [`Highlighter::highlight_outcome`] for how many ranges were flattened.
+    pub fn max_nesting_depth(mut self, max: usize) -> Self {
+        self.highlighter.max_nesting_depth = max;
+        self
+    }
+
+    /// add `id="{prefix}0"`, `id="{prefix}1"`, ... to the open tags in document order, e.g. for
+    /// jump-to-match navigation. Numbered after merging and [`HighlighterBuilder::max_highlights`],
+    /// so every id is visible; see [`Highlighter::highlight_outcome`] for the list of ids. The
+    /// attribute is only added to tags ending in `>`, and highlights split by
+    /// [`HighlighterBuilder::split_at_newlines`] repeat their id. Doesn't apply to
+    /// [`OverlapPolicy::Nest`].
+    pub fn anchor_ids(mut self, prefix: impl Into<String>) -> Self {
+        self.highlighter.anchor_id_prefix = Some(Arc::from(prefix.into()));
+        self
+    }
+
+    /// for debugging the output: give every open tag a `data-range="{lower}..{upper}"` attribute
+    /// with its range after normalization, and `data-src-index="{i}"` with the index of the input
+    /// range it came from, or `data-merged="{i},{j},..."` if several were merged into it. Added
+    /// like [`HighlighterBuilder::anchor_ids`], after them; doesn't apply to
+    /// [`OverlapPolicy::Nest`].
+    pub fn debug_annotations(mut self, annotate: bool) -> Self {
+        self.highlighter.debug_annotations = annotate;
+        self
+    }
+
+    /// never highlight inside these regions, e.g. verbatim code blocks; highlights are cut like
+    /// in [`subtract_exclusions`] before anything else is done with them
+    pub fn exclusions<'r>(mut self, exclusions: impl IntoRanges<'r>) -> Self {
+        self.highlighter.exclusions =
+            Arc::from(merge_ranges(collect_ranges(exclusions).into_owned()));
+        self
+    }
+
+    /// widen ranges to grapheme cluster boundaries before rendering, see [`snap_to_graphemes`]
+    #[cfg(feature = "unicode-segmentation")]
+    pub fn snap_to_graphemes(mut self, snap: bool) -> Self {
+        self.highlighter.snap_to_graphemes = snap;
+        self
+    }
+
+    pub fn escape_html(mut self, escape: bool) -> Self {
+        self.highlighter.escaping = if escape {
+            Escaping::Html
+        } else {
+            Escaping::None
+        };
+        self
+    }
+
+    pub fn build(self) -> Result<Highlighter, HighlighterConfigError> {
+        let h = &self.highlighter;
+        let merging = h.coalesce_adjacent || h.overlap_policy == OverlapPolicy::Merge;
+        if h.allow_empty_markers && merging {
+            return Err(HighlighterConfigError::EmptyMarkersWithMerging);
+        }
+        // also rejects NaN
+        if h.max_coverage
+            .is_some_and(|(max, _)| !(0.0..=1.0).contains(&max))
+        {
+            return Err(HighlighterConfigError::MaxCoverageOutOfRange);
+        }
+
+        Ok(self.highlighter)
+    }
+}
+
+/// Bumped whenever the bytes rendered for the same input, ranges and [`Highlighter`] change on
+/// purpose, which changes every [`output_fingerprint`] with it
+pub const OUTPUT_VERSION: u32 = 1;
+
+/// What this build of the crate supports, see [`capabilities`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// version of the crate, e.g. `"0.1.0"`
+    pub version: &'static str,
+    /// [`OUTPUT_VERSION`] of this build
+    pub output_version: u32,
+    /// cargo features compiled in, e.g. `"serde"` or `"regex"`
+    pub features: Vec<&'static str>,
+    pub overlap_policies: Vec<OverlapPolicy>,
+    pub bounds_policies: Vec<BoundsPolicy>,
+    /// built-in output formats, e.g. `"html"` or `"ansi"`
+    pub renderers: Vec<&'static str>,
+}
+
+impl Capabilities {
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.contains(&feature)
+    }
+}
+
+/// Describes the features, policies and renderers of this build, for services running
+/// different versions of the crate side by side
+pub fn capabilities() -> Capabilities {
+    let features = [
+        ("std", cfg!(feature = "std")),
+        ("cli", cfg!(feature = "cli")),
+        ("ffi", cfg!(feature = "ffi")),
+        ("proptest", cfg!(feature = "proptest")),
+        ("rayon", cfg!(feature = "rayon")),
+        ("regex", cfg!(feature = "regex")),
+        ("serde", cfg!(feature = "serde")),
+        ("testing", cfg!(feature = "testing")),
+        (
+            "unicode-normalization",
+            cfg!(feature = "unicode-normalization"),
+        ),
+        (
+            "unicode-segmentation",
+            cfg!(feature = "unicode-segmentation"),
+        ),
+        ("wasm", cfg!(feature = "wasm")),
+    ];
+
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        output_version: OUTPUT_VERSION,
+        features: features
+            .into_iter()
+            .filter_map(|(name, enabled)| enabled.then_some(name))
+            .collect(),
+        overlap_policies: vec![
+            OverlapPolicy::Error,
+            OverlapPolicy::Merge,
+            OverlapPolicy::Nest,
+        ],
+        bounds_policies: vec![BoundsPolicy::Strict, BoundsPolicy::Clamp],
+        renderers: vec![
+            "html", "ansi", "markdown", "bbcode", "latex", "pango", "caret", "template",
+        ],
+    }
+}
+
+/// Hash of everything in `config` that influences the rendered bytes, and of [`OUTPUT_VERSION`],
+/// for caches keyed on the output. Equal configs have the same fingerprint on every platform and
+/// build of a version; it changes when an option changes, when the crate gains an option and
+/// when `OUTPUT_VERSION` is bumped, so a cache is invalidated rather than served stale output.
+pub fn output_fingerprint(config: &Highlighter) -> u64 {
+    // destructured so that a new option can't be left out
+    let Highlighter {
+        open_tag,
+        close_tag,
+        overlap_policy,
+        bounds_policy,
+        coalesce_adjacent,
+        allow_empty_markers,
+        escaping,
+        split_at_newlines,
+        dedup_contained,
+        max_highlights,
+        max_coverage,
+        max_nesting_depth,
+        exclusions,
+        // limits only reject inputs, they never change the bytes rendered for the others
+        max_ranges: _,
+        max_output_bytes: _,
+        #[cfg(feature = "unicode-segmentation")]
+        snap_to_graphemes,
+        anchor_id_prefix,
+        bidi_isolate,
+        debug_annotations,
+        full_cover,
+    } = config;
+    #[cfg(not(feature = "unicode-segmentation"))]
+    let snap_to_graphemes = &false;
+
+    let mut hash = Fnv1a::default();
+    hash.u64(u64::from(OUTPUT_VERSION));
+    hash.str(open_tag);
+    hash.str(close_tag);
+    hash.u64(match overlap_policy {
+        OverlapPolicy::Error => 0,
+        OverlapPolicy::Merge => 1,
+        OverlapPolicy::Nest => 2,
+    });
+    hash.u64(match bounds_policy {
+        BoundsPolicy::Strict => 0,
+        BoundsPolicy::Clamp => 1,
+    });
+    for flag in [
+        coalesce_adjacent,
+        allow_empty_markers,
+        split_at_newlines,
+        dedup_contained,
+        snap_to_graphemes,
+        bidi_isolate,
+        debug_annotations,
+    ] {
+        hash.u64(u64::from(*flag));
+    }
+    hash.u64(match escaping {
+        Escaping::None => 0,
+        Escaping::Html => 1,
+        Escaping::Markdown { equals: false } => 2,
+        Escaping::Markdown { equals: true } => 3,
+        Escaping::Latex => 4,
+        Escaping::BbCode => 5,
+    });
+    match max_highlights {
+        None => hash.u64(0),
+        Some(max) => hash.u64s([1, *max as u64]),
+    }
+    match max_coverage {
+        None => hash.u64(0),
+        Some((max, CoverageAction::Skip)) => hash.u64s([1, u64::from(max.to_bits())]),
+        Some((max, CoverageAction::TrimLowestScored)) => hash.u64s([2, u64::from(max.to_bits())]),
+    }
+    hash.u64(*max_nesting_depth as u64);
+    hash.u64(exclusions.len() as u64);
+    for exclusion in exclusions.iter() {
+        hash.u64s([exclusion.lower as u64, exclusion.upper as u64]);
+    }
+    match anchor_id_prefix {
+        None => hash.u64(0),
+        Some(prefix) => {
+            hash.u64(1);
+            hash.str(prefix);
+        }
+    }
+    match full_cover {
+        FullCoverPolicy::Wrap => hash.u64(0),
+        FullCoverPolicy::Skip => hash.u64(1),
+        FullCoverPolicy::Tag(open, close) => {
+            hash.u64(2);
+            hash.str(open);
+            hash.str(close);
+        }
+    }
+
+    hash.0
+}
+
+/// 64-bit FNV-1a, fixed by its definition unlike the hashers of `std`, which may change between
+/// Rust releases
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv1a {
+    fn bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn u64(&mut self, n: u64) {
+        self.bytes(&n.to_le_bytes());
+    }
+
+    fn u64s<const N: usize>(&mut self, ns: [u64; N]) {
+        ns.into_iter().for_each(|n| self.u64(n));
+    }
+
+    /// length-prefixed, so `("ab", "c")` and `("a", "bc")` differ
+    fn str(&mut self, s: &str) {
+        self.u64(s.len() as u64);
+        self.bytes(s.as_bytes());
+    }
+}
+
+/// Buffers reused by [`Highlighter::highlight_into`] across calls; cheap to create, it only
+/// allocates once it is used
+#[derive(Debug, Clone, Default)]
+pub struct HighlightScratch {
+    sorted: Vec<(usize, HighlightRange)>,
+}
+
+impl Highlighter {
+    pub fn builder() -> HighlighterBuilder {
+        HighlighterBuilder::default()
+    }
+
+    pub fn highlight(
+        &self,
+        input: &str,
+        highlights: &[HighlightRange],
+    ) -> Result<String, HighlightingError> {
+        let mut out = String::new();
+        self.highlight_into(
+            input,
+            highlights,
+            &mut HighlightScratch::default(),
+            &mut out,
+        )?;
+        Ok(out)
+    }
+
+    /// Same as [`Highlighter::highlight`], but the output replaces the contents of `out` and the
+    /// ranges are sorted in `scratch`, so reusing both for many inputs allocates nothing once they
+    /// have grown large enough. Options that rewrite the ranges (clamping, merging, exclusions, the
+    /// highlight limit, ...), anchor ids, debug annotations, nesting and the coverage limit still
+    /// allocate. On error `out` is left empty.
+    pub fn highlight_into(
+        &self,
+        input: &str,
+        highlights: &[HighlightRange],
+        scratch: &mut HighlightScratch,
+        out: &mut String,
+    ) -> Result<(), HighlightingError> {
+        out.clear();
+        if input.is_empty() && highlights.is_empty() {
+            return Ok(());
+        }
+
+        let plan = self.plan(input, highlights, |_| 0.0, &mut scratch.sorted)?;
+        self.render_plan(input, &plan, &scratch.sorted, highlights, out);
+        Ok(())
+    }
+
+    /// Wraps the text between the highlights instead of the highlights themselves, e.g. for
+    /// redaction previews. `highlights` are validated exactly like in [`Highlighter::highlight`];
+    /// the wrapped gaps come from [`invert_ranges`], so none of them is empty.
+    pub fn highlight_inverted(
+        &self,
+        input: &str,
+        highlights: &[HighlightRange],
+    ) -> Result<String, HighlightingError> {
+        let (plan, prepared) = self.prepare(input, highlights)?;
+
+        // a covered input has no gaps, whatever the full cover is rendered as
+        let gaps = match plan.full_cover {
+            true => Vec::new(),
+            false => invert_ranges(input.len(), &prepared.ranges().cloned().collect::<Vec<_>>()),
+        };
+        let prepared = PreparedHighlights::from_sorted(input, &gaps)?;
+        Ok(self.render_with(&prepared, 0..input.len(), |_| Cow::Borrowed(&self.open_tag)))
+    }
+
+    /// Same as [`Highlighter::highlight`], but only the first `max_visible_chars` chars of `input`
+    /// are rendered, see [`highlight_text_truncated`]
+    pub fn highlight_truncated(
+        &self,
+        input: &str,
+        highlights: &[HighlightRange],
+        max_visible_chars: usize,
+    ) -> Result<String, HighlightingError> {
+        let (plan, prepared) = self.prepare(input, highlights)?;
+
+        let cut = visible_cut(input, max_visible_chars, |_| 1);
+        let window = 0..cut.unwrap_or(input.len());
+        let (_, tags) = self.planned_open_tags(&plan, &prepared.sorted, highlights);
+        let mut out =
+            self.render_with_tags(&prepared, window, |i| self.planned_tags(&plan, &tags, i));
+        if cut.is_some() {
+            out.push('…');
+        }
+
+        Ok(out)
+    }
+
+    /// Renders the window of `input` chosen by `options` both highlighted and plain, from the same
+    /// ranges and the same cut, so `plain` is always `highlighted` without its tags (and bidi
+    /// isolates). The window is checked like a range; highlights crossing its ends are clipped.
+    /// Like with [`Highlighter::highlight_truncated`], [`OverlapPolicy::Nest`] ranges are merged
+    /// instead of nested.
+    pub fn highlight_both<'i>(
+        &self,
+        input: &'i str,
+        highlights: &[HighlightRange],
+        options: &BothOptions,
+    ) -> Result<BothOutputs<'i>, HighlightingError> {
+        let window = options
+            .window
+            .clone()
+            .unwrap_or(HighlightRange::new(0, input.len()));
+        validate_bounds(input, core::slice::from_ref(&window))?;
+        let (plan, prepared) = self.prepare(input, highlights)?;
+
+        let shown = &input[window.lower..window.upper];
+        let cut = options
+            .max_visible_chars
+            .and_then(|max| visible_cut(shown, max, |_| 1));
+        let window = window.lower..cut.map_or(window.upper, |cut| window.lower + cut);
+
+        let (_, tags) = self.planned_open_tags(&plan, &prepared.sorted, highlights);
+        let mut highlighted = self.render_with_tags(&prepared, window.clone(), |i| {
+            self.planned_tags(&plan, &tags, i)
+        });
+        let mut plain = match self.escaping {
+            Escaping::None => Cow::Borrowed(&input[window]),
+            _ => {
+                let mut plain = String::with_capacity(window.len());
+                self.write_segment(&mut plain, &input[window])
+                    .expect("writing into a String can't fail");
+                Cow::Owned(plain)
+            }
+        };
+        if cut.is_some() {
+            highlighted.push('…');
+            plain.to_mut().push('…');
+        }
+
+        Ok(BothOutputs { highlighted, plain })
+    }
+
+    /// Same as [`Highlighter::highlight`], but a range carrying its own `(open, close)` tags is
+    /// wrapped in those instead of the configured ones. The tags are trusted markup and written
+    /// as they are, the text inside them is still escaped. The ranges are validated like with
+    /// [`OverlapPolicy::Error`], the other policies and anchor ids don't apply.
+    pub fn highlight_tagged(
+        &self,
+        input: &str,
+        highlights: &[TaggedRange],
+    ) -> Result<String, HighlightingError> {
+        self.highlight_tagged_truncated(input, highlights, usize::MAX)
+    }
+
+    /// Same as [`Highlighter::highlight_tagged`], truncated like with
+    /// [`Highlighter::highlight_truncated`]; a highlight that is cut off is closed with its own
+    /// close tag
+    pub fn highlight_tagged_truncated(
+        &self,
+        input: &str,
+        highlights: &[TaggedRange],
+        max_visible_chars: usize,
+    ) -> Result<String, HighlightingError> {
+        self.check_range_count(highlights.len())?;
+        let tags_len = highlights
+            .iter()
+            .filter(|h| self.is_rendered(&h.range))
+            .map(|h| match &h.payload {
+                Some((open, close)) => open.len().saturating_add(close.len()),
+                None => self.open_tag.len() + self.close_tag.len(),
+            })
+            .fold(0, usize::saturating_add);
+        self.check_estimated_output(input, tags_len)?;
+        let ranges = highlights
+            .iter()
+            .map(|h| h.range.clone())
+            .collect::<Vec<_>>();
+        let prepared = PreparedHighlights::new(input, &ranges)?;
+
+        let cut = visible_cut(input, max_visible_chars, |_| 1);
+        let window = 0..cut.unwrap_or(input.len());
+        let mut out = self.render_with_tags(&prepared, window, |i| match &highlights[i].payload {
+            Some((open, close)) => (Cow::Borrowed(open.as_str()), Cow::Borrowed(close.as_str())),
+            None => (
+                Cow::Borrowed(&*self.open_tag),
+                Cow::Borrowed(&*self.close_tag),
+            ),
+        });
+        if cut.is_some() {
+            out.push('…');
+        }
+
+        Ok(out)
+    }
+
+    /// Same as [`Highlighter::highlight`], but borrows `input` instead of copying it when nothing
+    /// would be highlighted or escaped
+    pub fn highlight_cow<'i>(
+        &self,
+        input: &'i str,
+        highlights: &[HighlightRange],
+    ) -> Result<Cow<'i, str>, HighlightingError> {
+        if input.is_empty() && highlights.is_empty() {
+            return Ok(Cow::Borrowed(input));
+        }
+
+        let (plan, prepared) = self.prepare(input, highlights)?;
+        let nothing_rendered = match self.renders_nested(&plan) {
+            true => !plan.nested.iter().any(|h| self.is_rendered(h)),
+            false => !prepared.ranges().any(|h| self.is_rendered(h)),
+        };
+        if nothing_rendered && self.escaping == Escaping::None {
+            return Ok(Cow::Borrowed(input));
+        }
+
+        let mut out = String::new();
+        self.render_plan(input, &plan, &prepared.sorted, highlights, &mut out);
+        Ok(Cow::Owned(out))
+    }
+
+    /// Highlights every `(input, ranges)` item on its own, results are in the same order as
+    /// `items` and one item failing doesn't affect the others; errors carry the index of their
+    /// item as [`ErrorContext::Item`]
+    pub fn highlight_many<'i>(
+        &self,
+        items: impl IntoIterator<Item = (&'i str, Vec<HighlightRange>)>,
+    ) -> Vec<Result<String, ContextError>> {
+        items
+            .into_iter()
+            .enumerate()
+            .map(|(index, (input, highlights))| self.highlight_item(index, input, &highlights))
+            .collect()
+    }
+
+    /// Same as [`Highlighter::highlight_many`], but the items are highlighted in parallel
+    #[cfg(feature = "rayon")]
+    pub fn par_highlight_many<'i, I>(&self, items: I) -> Vec<Result<String, ContextError>>
+    where
+        I: rayon::iter::IntoParallelIterator<Item = (&'i str, Vec<HighlightRange>)>,
+        I::Iter: rayon::iter::IndexedParallelIterator,
+    {
+        use rayon::iter::{IndexedParallelIterator, ParallelIterator};
+
+        items
+            .into_par_iter()
+            .enumerate()
+            .map(|(index, (input, highlights))| self.highlight_item(index, input, &highlights))
+            .collect()
+    }
+
+    /// [`Highlighter::highlight`] for the item at `index` of a batch
+    fn highlight_item(
+        &self,
+        index: usize,
+        input: &str,
+        highlights: &[HighlightRange],
+    ) -> Result<String, ContextError> {
+        self.highlight(input, highlights)
+            .map_err(|error| error.with_context(ErrorContext::Item(index)))
+    }
+
+    /// Same as [`Highlighter::highlight`], but never fails: ranges that are still invalid or
+    /// conflict with an earlier accepted range once the options are applied are skipped and
+    /// returned alongside the output, so whatever `highlight` renders is rendered alike. If the
+    /// accepted ranges exceed `max_ranges` or `max_output_bytes` all of them are skipped.
+    pub fn highlight_lossy(
+        &self,
+        input: &str,
+        highlights: &[HighlightRange],
+    ) -> (String, Vec<SkippedRange>) {
+        let (accepted, mut skipped) = self.partition_lossy(input, highlights);
+        if accepted.is_empty() {
+            return (self.plain(input), skipped);
+        }
+
+        // with nothing skipped the ranges render as given, so their indices stay the same
+        let rendered = match skipped.is_empty() {
+            true => self.highlight(input, highlights),
+            false => self.highlight(input, &accepted),
+        };
+        match rendered {
+            Ok(out) => (out, skipped),
+            // only the output limit can still fail, it rejects the accepted ranges as a whole
+            Err(reason) => {
+                skipped.extend(accepted.into_iter().map(|range| SkippedRange {
+                    range,
+                    reason: reason.clone(),
+                }));
+                skipped.sort_by_key(|skipped| (skipped.range.lower, skipped.range.upper));
+                (self.plain(input), skipped)
+            }
+        }
+    }
+
+    /// The ranges [`Highlighter::highlight_lossy`] renders, in `(lower, upper)` order, and the
+    /// ones it skips before rendering, e.g. to render the accepted ones in another format.
+    /// `highlights` are clamped, cut, deduplicated and merged first if the options say so, like
+    /// [`Highlighter::highlight`] does; more than `max_ranges` are all skipped.
+    pub fn partition_lossy(
+        &self,
+        input: &str,
+        highlights: &[HighlightRange],
+    ) -> (Vec<HighlightRange>, Vec<SkippedRange>) {
+        // too many ranges are skipped before any of them is looked at
+        if let Err(reason) = self.check_range_count(highlights.len()) {
+            let skipped = highlights.iter().map(|range| SkippedRange {
+                range: range.clone(),
+                reason: reason.clone(),
+            });
+            let mut skipped = skipped.collect::<Vec<_>>();
+            skipped.sort_unstable_by_key(|skipped| (skipped.range.lower, skipped.range.upper));
+            return (Vec::new(), skipped);
+        }
+
+        let mut highlights = self.restrict_ranges(input, highlights);
+        if self.overlap_policy == OverlapPolicy::Nest {
+            // nested ranges may overlap, and are neither deduplicated nor merged before that
+            return partition_in_bounds(input, &highlights);
+        }
+        if self.dedup_contained {
+            dedup_contained_ranges(highlights.to_mut());
+        }
+        match self.overlap_policy {
+            OverlapPolicy::Merge | OverlapPolicy::Nest => {
+                partition_valid_merged(input, &highlights)
+            }
+            OverlapPolicy::Error => {
+                let (accepted, skipped) = partition_valid(input, &highlights);
+                match self.coalesce_adjacent {
+                    true => (merge_ranges(accepted), skipped),
+                    false => (accepted, skipped),
+                }
+            }
+        }
+    }
+
+    /// `input` escaped without any highlights
+    fn plain(&self, input: &str) -> String {
+        let mut out = String::with_capacity(input.len());
+        self.write_segment(&mut out, input)
+            .expect("writing into a String can't fail");
+        out
+    }
+
+    /// Same as [`Highlighter::highlight`], but writes into `out` instead of building a `String`
+    #[cfg(feature = "std")]
+    pub fn highlight_to<W: io::Write>(
+        &self,
+        input: &str,
+        highlights: &[HighlightRange],
+        out: &mut W,
+    ) -> Result<(), HighlightError> {
+        let mut adapter = IoAdapter {
+            inner: out,
+            error: None,
+        };
+
+        match self.highlight_to_fmt(input, highlights, &mut adapter) {
+            Err(HighlightError::Fmt(_)) => Err(HighlightError::Io(
+                adapter
+                    .error
+                    .unwrap_or_else(|| io::Error::other("formatter error")),
+            )),
+            result => result,
+        }
+    }
+
+    /// Same as [`Highlighter::highlight_to`], but for a [`fmt::Write`] sink
+    pub fn highlight_to_fmt<W: fmt::Write>(
+        &self,
+        input: &str,
+        highlights: &[HighlightRange],
+        out: &mut W,
+    ) -> Result<(), HighlightError> {
+        let (plan, prepared) = self.prepare(input, highlights)?;
+
+        let (_, tags) = self.planned_open_tags(&plan, &prepared.sorted, highlights);
+        self.write_plan(input, &plan, &prepared.sorted, &tags, out)?;
+        Ok(())
+    }
+
+    /// Same as [`Highlighter::highlight`], but also reports how many highlights were dropped
+    /// because of [`HighlighterBuilder::max_highlights`] and the ids of
+    /// [`HighlighterBuilder::anchor_ids`]
+    pub fn highlight_outcome(
+        &self,
+        input: &str,
+        highlights: &[HighlightRange],
+    ) -> Result<HighlightOutcome, HighlightingError> {
+        let (plan, prepared) = self.prepare(input, highlights)?;
+
+        let mut text = String::new();
+        let (ids, flattened) =
+            self.render_plan(input, &plan, &prepared.sorted, highlights, &mut text);
+        Ok(HighlightOutcome {
+            text,
+            dropped: plan.dropped,
+            ids,
+            flattened,
+            full_cover: plan.full_cover,
+        })
+    }
+
+    /// Same as [`Highlighter::highlight`], but also reports what the
+    /// [`HighlighterBuilder::max_coverage`] gate decided and the coverage before and after it
+    pub fn highlight_gated(
+        &self,
+        input: &str,
+        highlights: &[HighlightRange],
+    ) -> Result<CoverageOutcome, HighlightingError> {
+        self.gate_coverage(input, highlights, |_| 0.0)
+    }
+
+    /// Same as [`Highlighter::highlight_gated`] for scored ranges, so
+    /// [`CoverageAction::TrimLowestScored`] drops the lowest-scored ones first; NaN scores rank
+    /// lowest
+    pub fn highlight_scored(
+        &self,
+        input: &str,
+        highlights: &[ScoredRange],
+    ) -> Result<CoverageOutcome, HighlightingError> {
+        let ranges = highlights
+            .iter()
+            .map(|h| h.range.clone())
+            .collect::<Vec<_>>();
+        self.gate_coverage(input, &ranges, |i| highlights[i].score)
+    }
+
+    /// Same as [`Highlighter::highlight`] with its [`CoverageOutcome`]
+    fn gate_coverage(
+        &self,
+        input: &str,
+        highlights: &[HighlightRange],
+        score: impl Fn(usize) -> f32,
+    ) -> Result<CoverageOutcome, HighlightingError> {
+        let mut sorted = Vec::with_capacity(highlights.len());
+        let plan = self.plan(input, highlights, score, &mut sorted)?;
+        let gate = plan.gate.unwrap_or_else(|| {
+            // a covered input may be planned without highlights
+            let original_coverage = match plan.full_cover {
+                true => 1.0,
+                false => sorted_coverage(input, &sorted),
+            };
+            CoverageGate {
+                decision: CoverageDecision::Kept,
+                original_coverage,
+                coverage: sorted_coverage(input, &sorted),
+            }
+        });
+
+        let mut text = String::new();
+        self.render_plan(input, &plan, &sorted, highlights, &mut text);
+        Ok(CoverageOutcome {
+            text,
+            decision: gate.decision,
+            original_coverage: gate.original_coverage,
+            coverage: gate.coverage,
+        })
+    }
+
+    /// Same as [`Highlighter::highlight`], but also reports where the highlighted content of
+    /// every range landed in the output, measured after tags and escaping, and what the policies
+    /// did to it. A range rendered as several highlights (cut by an exclusion, or reopened with
+    /// [`OverlapPolicy::Nest`]) is placed from the start of the first to the end of the last one.
+    pub fn highlight_placed(
+        &self,
+        input: &str,
+        highlights: &[HighlightRange],
+    ) -> Result<HighlightResult, HighlightingError> {
+        let (plan, prepared) = self.prepare(input, highlights)?;
+        if self.renders_nested(&plan) {
+            return Ok(self.highlight_placed_nested(input, highlights, &plan));
+        }
+        let (_, tags) = self.planned_open_tags(&plan, &prepared.sorted, highlights);
+
+        // output of every rendered range, by its index in the normalized ranges
+        let mut outputs = vec![None; plan.normalized.len()];
+        let markup = self.markup();
+        let renderer = WithRenderer::new(input.len(), |segment, range_idx, text| {
+            let written = match range_idx {
+                Some(i) => {
+                    let (open, close) = self.planned_tags(&plan, &tags, i);
+                    let start = text.len();
+                    let written = markup.write_highlight(text, &open, &close, segment.text);
+                    outputs[i] = Some(content_span(text, start, &open, &close));
+                    written
+                }
+                None => markup.write_text(text, segment.text),
+            };
+            written.expect("writing into a String can't fail");
+        });
+        let text = prepared.render_through(self.allow_empty_markers, |_| None, renderer);
+
+        let placements = highlights
+            .iter()
+            .enumerate()
+            .map(|(index, h)| {
+                // rendered ranges never overlap, so the ones touching `h` came from it
+                let touching = prepared
+                    .sorted
+                    .iter()
+                    .filter(|(_, r)| {
+                        if h.is_empty() {
+                            r == h
+                        } else {
+                            r.lower < h.upper && h.lower < r.upper
+                        }
+                    })
+                    .filter_map(|(i, r)| Some((r, outputs[*i].clone()?)));
+                placement(index, h, touching)
+            })
+            .collect();
+
+        Ok(HighlightResult { text, placements })
+    }
+
+    /// [`Highlighter::highlight_placed`] for [`OverlapPolicy::Nest`], which never merges ranges;
+    /// `plan` tells which ranges the coverage gate left out
+    fn highlight_placed_nested(
+        &self,
+        input: &str,
+        highlights: &[HighlightRange],
+        plan: &Plan,
+    ) -> HighlightResult {
+        // restricted one by one, so every piece knows the range it came from
+        let mut pieces = Vec::new();
+        let mut origins = Vec::new();
+        for (index, h) in highlights.iter().enumerate() {
+            if plan.left_out.binary_search(&index).is_ok() {
+                continue;
+            }
+            let restricted = self.restrict_ranges(input, core::slice::from_ref(h));
+            origins.extend(restricted.iter().map(|_| index));
+            pieces.extend(restricted.iter().cloned());
+        }
+        // the sweep only sees the rendered pieces, `indices` maps them back
+        let (indices, rendered): (Vec<_>, Vec<_>) = pieces
+            .iter()
+            .cloned()
+            .enumerate()
+            .filter(|(_, p)| self.is_rendered(p))
+            .unzip();
+
+        let mut text = String::with_capacity(input.len());
+        // output of every piece, from its first opening to its last closing
+        let mut outputs: Vec<Option<HighlightRange>> = vec![None; pieces.len()];
+        let flattened = sweep_nested(
+            input,
+            &rendered,
+            |_| None,
+            self.max_nesting_depth,
+            |event| {
+                match event {
+                    HighlightEvent::Text(segment) => self.write_segment(&mut text, segment)?,
+                    HighlightEvent::Start(i) => {
+                        text.push_str(&self.open_tag);
+                        let at = HighlightRange::new(text.len(), text.len());
+                        outputs[indices[i]].get_or_insert(at);
+                    }
+                    HighlightEvent::End(i) => {
+                        if let Some(output) = &mut outputs[indices[i]] {
+                            output.upper = text.len();
+                        }
+                        text.push_str(&self.close_tag);
+                    }
+                }
+                Ok::<(), fmt::Error>(())
+            },
+        )
+        .expect("writing into a String can't fail");
+
+        let mut placements = highlights
+            .iter()
+            .enumerate()
+            .map(|(index, h)| {
+                let own = origins.partition_point(|&o| o < index)
+                    ..origins.partition_point(|&o| o <= index);
+                let own = own.filter_map(|p| Some((&pieces[p], outputs[p].clone()?)));
+                placement(index, h, own)
+            })
+            .collect::<Vec<_>>();
+        // flattened ranges are part of the highlight they were flattened into
+        for (piece, into) in flattened {
+            let placed = &mut placements[origins[indices[piece]]];
+            if placed.output.is_none() {
+                placed.output = outputs[indices[into]].clone();
+                placed.status = PlacementStatus::Merged;
+            }
+        }
+
+        HighlightResult { text, placements }
+    }
+
+    /// Same as [`Highlighter::highlight`], but returns the pieces instead of rendering them
+    pub fn segments<'i>(
+        &self,
+        input: &'i str,
+        highlights: &[HighlightRange],
+    ) -> Result<Vec<Segment<'i>>, HighlightingError> {
+        let (_, prepared) = self.prepare(input, highlights)?;
+
+        let mut segments = Vec::with_capacity(prepared.sorted.len() * 2 + 1);
+        let walked = prepared.walk(0..input.len(), self.allow_empty_markers, |segment, _| {
+            segments.push(segment);
+            Ok::<(), Infallible>(())
+        });
+
+        match walked {
+            Ok(()) => Ok(segments),
+        }
+    }
+
+    /// [`HtmlRenderer`] with the tags and escaping of this highlighter, for
+    /// [`highlight_with_renderer`] and [`ValidatedHighlights::render`]; its policies don't apply
+    pub fn renderer(&self) -> HtmlRenderer<'_> {
+        HtmlRenderer::with_markup(self.markup())
+    }
+
+    /// Starts highlighting text that arrives in chunks, see [`StreamingHighlighter`]; only the
+    /// tags and escaping of this highlighter are used, not its policies
+    pub fn streaming(
+        &self,
+        highlights: Vec<HighlightRange>,
+    ) -> Result<StreamingHighlighter, HighlightingError> {
+        check_overlaps(&highlights)?;
+
+        let mut highlights = highlights;
+        // like the default renderer, zero-length ranges produce nothing
+        highlights.retain(|h| h.lower != h.upper);
+        sort_ranges(&mut highlights);
+
+        Ok(StreamingHighlighter {
+            highlighter: self.clone(),
+            highlights,
+            next: 0,
+            offset: 0,
+            open: false,
+        })
+    }
+
+    /// Rejects `count` ranges exceeding [`HighlighterBuilder::max_ranges`], only counting, so it's
+    /// cheap for any number of ranges
+    fn check_range_count(&self, count: usize) -> Result<(), HighlightingError> {
+        match self.max_ranges.filter(|&max| count > max) {
+            Some(max) => Err(HighlightingError::TooManyRanges { count, max }),
+            None => Ok(()),
+        }
+    }
+
+    /// Rejects an output exceeding [`HighlighterBuilder::max_output_bytes`], estimated from
+    /// `input` and a pair of the configured tags for every range of `highlights` that is rendered
+    fn check_output_size<'h>(
+        &self,
+        input: &str,
+        highlights: impl Iterator<Item = &'h HighlightRange>,
+    ) -> Result<(), HighlightingError> {
+        if self.max_output_bytes.is_none() {
+            return Ok(());
+        }
+        let rendered = highlights.filter(|h| self.is_rendered(h)).count();
+        let tags_len = self.open_tag.len() + self.close_tag.len();
+        self.check_estimated_output(input, rendered.saturating_mul(tags_len))
+    }
+
+    /// [`Highlighter::check_output_size`] with the summed length of the tags of all rendered ranges
+    fn check_estimated_output(
+        &self,
+        input: &str,
+        tags_len: usize,
+    ) -> Result<(), HighlightingError> {
+        let estimated = input.len().saturating_add(tags_len);
+        match self.max_output_bytes.filter(|&max| estimated > max) {
+            Some(max) => Err(HighlightingError::OutputTooLarge { estimated, max }),
+            None => Ok(()),
+        }
+    }
+
+    /// Applies every option to `highlights` and checks the limits, and replaces the contents of
+    /// `sorted` with the result: validated `(index, range)` pairs in `(lower, upper)` order, with
+    /// the index into [`Plan::normalized`]. All entry points render from here, so they see the same
+    /// ranges; `score` ranks the input ranges for [`CoverageAction::TrimLowestScored`].
+    fn plan<'r>(
+        &self,
+        input: &str,
+        highlights: &'r [HighlightRange],
+        score: impl Fn(usize) -> f32,
+        sorted: &mut Vec<(usize, HighlightRange)>,
+    ) -> Result<Plan<'r>, HighlightingError> {
+        self.check_range_count(highlights.len())?;
+        // even ranges the gate leaves out must not hide an error, so everything is validated first
+        let mut plan = self.normalize_sorted(input, highlights, sorted)?;
+
+        plan.gate = match self.max_coverage {
+            None => None,
+            Some((max, action)) => {
+                let original_coverage = sorted_coverage(input, sorted);
+                let decision = if original_coverage <= f64::from(max) {
+                    CoverageDecision::Kept
+                } else if action == CoverageAction::Skip {
+                    plan.left_out = (0..highlights.len()).collect();
+                    plan.normalized = Cow::Owned(Vec::new());
+                    plan.nested = Cow::Owned(Vec::new());
+                    sorted.clear();
+                    CoverageDecision::Skipped
+                } else {
+                    let units = self.trim_units(input, highlights, sorted);
+                    let kept = trim_lowest_scored(input, &units, max, score);
+                    plan.left_out = left_out_sources(highlights.len(), &units, &kept);
+                    if self.overlap_policy == OverlapPolicy::Nest {
+                        let kept = highlights
+                            .iter()
+                            .enumerate()
+                            .filter(|(i, _)| plan.left_out.binary_search(i).is_err())
+                            .map(|(_, h)| h.clone())
+                            .collect::<Vec<_>>();
+                        // nested pieces were validated one by one, so what is kept of them is valid
+                        let trimmed = self.normalize_sorted(input, &kept, sorted)?;
+                        plan.normalized = Cow::Owned(trimmed.normalized.into_owned());
+                        plan.nested = Cow::Owned(trimmed.nested.into_owned());
+                    } else {
+                        let mut position = 0;
+                        sorted.retain(|_| {
+                            position += 1;
+                            kept[position - 1]
+                        });
+                    }
+                    CoverageDecision::Trimmed {
+                        dropped: plan.left_out.len(),
+                    }
+                };
+                Some(CoverageGate {
+                    decision,
+                    original_coverage,
+                    coverage: original_coverage,
+                })
+            }
+        };
+
+        plan.dropped = self.limit_highlights(sorted);
+        if let Some(gate) = plan
+            .gate
+            .as_mut()
+            .filter(|gate| gate.decision != CoverageDecision::Kept || plan.dropped > 0)
+        {
+            gate.coverage = sorted_coverage(input, sorted);
+        }
+
+        // what is left may cover the input, which replaces the planned highlights as a whole
+        plan.full_cover = self.covers_input(input, sorted);
+        if plan.full_cover && self.full_cover != FullCoverPolicy::Wrap {
+            sorted.clear();
+            if matches!(self.full_cover, FullCoverPolicy::Tag(..)) {
+                sorted.push((0, HighlightRange::new(0, input.len())));
+            }
+            if let Some(gate) = plan.gate.as_mut() {
+                gate.coverage = sorted_coverage(input, sorted);
+            }
+        }
+        match &self.full_cover {
+            FullCoverPolicy::Tag(open, close) if plan.full_cover => {
+                self.check_estimated_output(input, open.len().saturating_add(close.len()))?
+            }
+            _ if self.renders_nested(&plan) => self.check_output_size(input, plan.nested.iter())?,
+            _ => self.check_output_size(input, sorted.iter().map(|(_, h)| h))?,
+        }
+
+        Ok(plan)
+    }
+
+    /// Whether `plan` is rendered as the nested tags of [`OverlapPolicy::Nest`] rather than from
+    /// its planned pairs, which a full cover replaces
+    fn renders_nested(&self, plan: &Plan) -> bool {
+        self.overlap_policy == OverlapPolicy::Nest
+            && !(plan.full_cover && self.full_cover != FullCoverPolicy::Wrap)
+    }
+
+    /// [`Highlighter::plan`] into a buffer of its own, for the entry points that don't take a
+    /// [`HighlightScratch`]
+    fn prepare<'i, 'r>(
+        &self,
+        input: &'i str,
+        highlights: &'r [HighlightRange],
+    ) -> Result<(Plan<'r>, PreparedHighlights<'i>), HighlightingError> {
+        let mut sorted = Vec::new();
+        let plan = self.plan(input, highlights, |_| 0.0, &mut sorted)?;
+        let prepared = PreparedHighlights {
+            input,
+            sorted: sorted.into(),
+        };
+        Ok((plan, prepared))
+    }
+
+    /// The [`Plan`] of the policies alone, with `highlights` normalized and validated into
+    /// `sorted`; with [`OverlapPolicy::Nest`] the planned ranges are merged, so they tell what the
+    /// nested ones cover
+    fn normalize_sorted<'r>(
+        &self,
+        input: &str,
+        highlights: &'r [HighlightRange],
+        sorted: &mut Vec<(usize, HighlightRange)>,
+    ) -> Result<Plan<'r>, HighlightingError> {
+        let (normalized, nested) = if self.overlap_policy == OverlapPolicy::Nest {
+            let nested = self.restrict_ranges(input, highlights);
+            validate_bounds(input, &nested)?;
+            (Cow::Owned(merge_ranges(nested.to_vec())), nested)
+        } else {
+            (self.normalize(input, highlights)?, Cow::Borrowed(&[][..]))
+        };
+        PreparedHighlights::sort_checked(input, &normalized, sorted)?;
+
+        Ok(Plan {
+            normalized,
+            nested,
+            dropped: 0,
+            gate: None,
+            left_out: Vec::new(),
+            full_cover: false,
+        })
+    }
+
+    /// What [`CoverageAction::TrimLowestScored`] keeps or leaves out as a whole, taken from the
+    /// validated plan so that no unvalidated offset is ever sliced: the planned pairs of `sorted`
+    /// in their order, or with [`OverlapPolicy::Nest`] the input ranges, whose nested pieces were
+    /// validated one by one
+    fn trim_units(
+        &self,
+        input: &str,
+        highlights: &[HighlightRange],
+        sorted: &[(usize, HighlightRange)],
+    ) -> Vec<TrimUnit> {
+        let pieces = |h| self.restrict_ranges(input, core::slice::from_ref(h));
+        if self.overlap_policy == OverlapPolicy::Nest {
+            return highlights
+                .iter()
+                .enumerate()
+                .map(|(i, h)| TrimUnit {
+                    sources: vec![i],
+                    pieces: pieces(h).into_owned(),
+                    order: (h.lower, h.upper),
+                })
+                .collect();
+        }
+
+        let mut units = sorted
+            .iter()
+            .map(|(_, h)| TrimUnit {
+                sources: Vec::new(),
+                pieces: vec![h.clone()],
+                order: (h.lower, h.upper),
+            })
+            .collect::<Vec<_>>();
+        // only offsets are compared here, every piece lies within the pair it was normalized into
+        for (i, h) in highlights.iter().enumerate() {
+            for piece in pieces(h).iter() {
+                if let Some(unit) = containing_pair(sorted, piece) {
+                    let sources = &mut units[unit].sources;
+                    if sources.last() != Some(&i) {
+                        sources.push(i);
+                    }
+                }
+            }
+        }
+        units
+    }
+
+    /// Drops the planned highlights beyond [`HighlighterBuilder::max_highlights`] from `sorted`
+    /// and returns how many merged highlights that were
+    fn limit_highlights(&self, sorted: &mut Vec<(usize, HighlightRange)>) -> usize {
+        let Some(max) = self.max_highlights else {
+            return 0;
+        };
+        if self.overlap_policy == OverlapPolicy::Nest {
+            return 0;
+        }
+
+        let (total, end) = count_merged_spans(sorted.iter().map(|(_, h)| h), max);
+        if total <= max {
+            return 0;
+        }
+        // every highlight lies within one of the merged spans, and the kept spans come first
+        sorted.retain(|(_, h)| !self.is_rendered(h) || end.is_some_and(|end| h.upper <= end));
+        total - max
+    }
+
+    /// Applies the bounds policy, the dedup option and the overlap policy
+    fn normalize<'r>(
+        &self,
+        input: &str,
+        highlights: &'r [HighlightRange],
+    ) -> Result<Cow<'r, [HighlightRange]>, HighlightingError> {
+        let mut highlights = self.restrict_ranges(input, highlights);
+        if self.dedup_contained {
+            dedup_contained_ranges(highlights.to_mut());
+        }
+
+        match self.overlap_policy {
+            OverlapPolicy::Merge | OverlapPolicy::Nest => {
+                Ok(Cow::Owned(merge_ranges(highlights.into_owned())))
+            }
+            OverlapPolicy::Error if self.coalesce_adjacent => {
+                validate_ranges(input, &highlights)?;
+
+                // validated ranges never overlap, so merging only joins the touching ones
+                Ok(Cow::Owned(merge_ranges(highlights.into_owned())))
+            }
+            OverlapPolicy::Error => Ok(highlights),
+        }
+    }
+
+    /// Applies the bounds policy, snaps to grapheme clusters and cuts out the exclusions
+    fn restrict_ranges<'r>(
+        &self,
+        input: &str,
+        highlights: &'r [HighlightRange],
+    ) -> Cow<'r, [HighlightRange]> {
+        let mut highlights = match self.bounds_policy {
+            BoundsPolicy::Strict => Cow::Borrowed(highlights),
+            BoundsPolicy::Clamp => Cow::Owned(clamp_to_input(input.len(), highlights)),
+        };
+
+        #[cfg(feature = "unicode-segmentation")]
+        if self.snap_to_graphemes {
+            highlights = Cow::Owned(snap_to_graphemes(input, &highlights));
+        }
+
+        if self.exclusions.is_empty() {
+            return highlights;
+        }
+        Cow::Owned(cut_exclusions(&highlights, &self.exclusions))
+    }
+
+    fn is_rendered(&self, highlight: &HighlightRange) -> bool {
+        self.allow_empty_markers || highlight.lower != highlight.upper
+    }
+
+    /// Whether the sorted, validated highlights leave no byte of a non-empty `input` out
+    fn covers_input(&self, input: &str, sorted: &[(usize, HighlightRange)]) -> bool {
+        let mut covered = 0;
+        for (_, h) in sorted.iter().filter(|(_, h)| !h.is_empty()) {
+            if h.lower > covered {
+                return false;
+            }
+            covered = covered.max(h.upper);
+        }
+        !input.is_empty() && covered == input.len()
+    }
+
+    /// Ids of the rendered highlights in document order and the open tags carrying them by the
+    /// index of their range, see [`HighlighterBuilder::anchor_ids`] and
+    /// [`HighlighterBuilder::debug_annotations`]; `sorted` are the prepared `(index, range)`
+    /// pairs and `sources` the ranges they were normalized from. Both are empty without anchor ids
+    /// and annotations, so nothing is allocated then. Look the tags up with
+    /// [`Highlighter::open_tag_at`].
+    pub(crate) fn anchored_open_tags(
+        &self,
+        sorted: &[(usize, HighlightRange)],
+        sources: &[HighlightRange],
+    ) -> (Vec<String>, Vec<String>) {
+        if self.anchor_id_prefix.is_none() && !self.debug_annotations {
+            return (Vec::new(), Vec::new());
+        }
+
+        // `max_highlights` may have dropped pairs, so the indices can reach past `sorted.len()`
+        let len = sorted.iter().map(|(i, _)| i + 1).max().unwrap_or(0);
+        let mut tags = vec![String::new(); len];
+        let mut ids = Vec::new();
+        for (i, range) in sorted {
+            if !self.is_rendered(range) {
+                continue;
+            }
+            let mut tag = Cow::Borrowed(&*self.open_tag);
+            if let Some(prefix) = &self.anchor_id_prefix {
+                let id = format!("{prefix}{}", ids.len());
+                tag = Cow::Owned(with_attribute(&tag, "id", &id).into_owned());
+                ids.push(id);
+            }
+            if self.debug_annotations {
+                tag = Cow::Owned(with_debug_attributes(&tag, range, sources));
+            }
+            tags[*i] = tag.into_owned();
+        }
+        (ids, tags)
+    }
+
+    /// Open tag of the range with index `i`, see [`Highlighter::anchored_open_tags`]
+    pub(crate) fn open_tag_at<'a>(&'a self, anchored: &'a [String], i: usize) -> Cow<'a, str> {
+        match anchored.get(i) {
+            Some(tag) if !tag.is_empty() => Cow::Borrowed(tag),
+            _ => Cow::Borrowed(&self.open_tag),
+        }
+    }
+
+    /// Renders `prepared.input()[window]`, `open_tag` receives the index of the range that is
+    /// being opened and is called twice per range, so it should be cheap. Unless escaping is
+    /// involved, the output is allocated with its exact final length.
+    pub(crate) fn render_with<'a>(
+        &'a self,
+        prepared: &PreparedHighlights,
+        window: Range<usize>,
+        open_tag: impl Fn(usize) -> Cow<'a, str>,
+    ) -> String {
+        self.render_with_tags(prepared, window, |i| {
+            (open_tag(i), Cow::Borrowed(&*self.close_tag))
+        })
+    }
+
+    /// Same as [`Highlighter::render_with`], with the close tag chosen per range as well
+    fn render_with_tags<'a>(
+        &'a self,
+        prepared: &PreparedHighlights,
+        window: Range<usize>,
+        tags: impl Fn(usize) -> (Cow<'a, str>, Cow<'a, str>),
+    ) -> String {
+        let tags_len = prepared
+            .in_window(&window)
+            .iter()
+            .filter(|(_, h)| self.is_rendered(h))
+            .map(|(i, _)| {
+                let (open, close) = tags(*i);
+                open.len() + close.len()
+            })
+            .sum::<usize>();
+        let mut out = String::with_capacity(window.len() + tags_len);
+        let sorted = prepared.in_window(&window);
+        self.write_sorted_with_tags(prepared.input, sorted, window, tags, &mut out)
+            .expect("writing into a String can't fail");
+
+        out
+    }
+
+    /// Writes `input[window]` with its validated `(index, range)` pairs `sorted`, in
+    /// `(lower, upper)` order, into `out`; `tags` picks the open and close tag by the index of
+    /// the range, see [`Highlighter::render_with`]
+    fn write_sorted_with_tags<'a, W: fmt::Write>(
+        &'a self,
+        input: &str,
+        sorted: &[(usize, HighlightRange)],
+        window: Range<usize>,
+        tags: impl Fn(usize) -> (Cow<'a, str>, Cow<'a, str>),
+        out: &mut W,
+    ) -> fmt::Result {
+        let sorted = sorted.iter().map(|(i, range)| (*i, range));
+        let writer = TagWriter::new(self.markup(), out, tags);
+        render_segments(
+            input,
+            window,
+            sorted,
+            self.allow_empty_markers,
+            |_| None,
+            writer,
+        )
+    }
+
+    /// Appends what [`Highlighter::highlight`] renders for `plan` to `out`, see
+    /// [`Highlighter::write_plan`]; returns the ids of [`HighlighterBuilder::anchor_ids`] and the
+    /// number of ranges flattened by [`HighlighterBuilder::max_nesting_depth`]
+    fn render_plan(
+        &self,
+        input: &str,
+        plan: &Plan,
+        sorted: &[(usize, HighlightRange)],
+        sources: &[HighlightRange],
+        out: &mut String,
+    ) -> (Vec<String>, usize) {
+        let (ids, tags) = self.planned_open_tags(plan, sorted, sources);
+        let tags_len = match self.renders_nested(plan) {
+            true => {
+                let rendered = plan.nested.iter().filter(|h| self.is_rendered(h)).count();
+                rendered * (self.open_tag.len() + self.close_tag.len())
+            }
+            false => sorted
+                .iter()
+                .filter(|(_, h)| self.is_rendered(h))
+                .map(|(i, _)| {
+                    let (open, close) = self.planned_tags(plan, &tags, *i);
+                    open.len() + close.len()
+                })
+                .sum::<usize>(),
+        };
+        out.reserve(input.len() + tags_len);
+
+        let flattened = self
+            .write_plan(input, plan, sorted, &tags, out)
+            .expect("writing into a String can't fail");
+        (ids, flattened)
+    }
+
+    /// Writes `input` as [`Highlighter::highlight`] renders `plan` and its planned pairs `sorted`,
+    /// with the open tags of [`Highlighter::planned_open_tags`]; returns the number of ranges
+    /// flattened by [`HighlighterBuilder::max_nesting_depth`]
+    fn write_plan<W: fmt::Write>(
+        &self,
+        input: &str,
+        plan: &Plan,
+        sorted: &[(usize, HighlightRange)],
+        tags: &[String],
+        out: &mut W,
+    ) -> Result<usize, fmt::Error> {
+        if self.renders_nested(plan) {
+            return self.write_nested(input, &plan.nested, out);
+        }
+
+        let tags = |i| self.planned_tags(plan, tags, i);
+        self.write_sorted_with_tags(input, sorted, 0..input.len(), tags, out)?;
+        Ok(0)
+    }
+
+    /// [`Highlighter::anchored_open_tags`] of the planned pairs `sorted`, none for the nested
+    /// tags of [`OverlapPolicy::Nest`] or a full cover replacing the highlights
+    fn planned_open_tags(
+        &self,
+        plan: &Plan,
+        sorted: &[(usize, HighlightRange)],
+        sources: &[HighlightRange],
+    ) -> (Vec<String>, Vec<String>) {
+        let replaced = plan.full_cover && self.full_cover != FullCoverPolicy::Wrap;
+        match self.overlap_policy {
+            OverlapPolicy::Nest => (Vec::new(), Vec::new()),
+            _ if replaced => (Vec::new(), Vec::new()),
+            _ => self.anchored_open_tags(sorted, sources),
+        }
+    }
+
+    /// Open and close tag of the planned pair with index `i`, given the `anchored` open tags of
+    /// [`Highlighter::planned_open_tags`]
+    fn planned_tags<'a>(
+        &'a self,
+        plan: &Plan,
+        anchored: &'a [String],
+        i: usize,
+    ) -> (Cow<'a, str>, Cow<'a, str>) {
+        match &self.full_cover {
+            FullCoverPolicy::Tag(open, close) if plan.full_cover => {
+                (Cow::Borrowed(open), Cow::Borrowed(close))
+            }
+            _ => (
+                self.open_tag_at(anchored, i),
+                Cow::Borrowed(&*self.close_tag),
+            ),
+        }
+    }
+
+    /// Writes `input` with the possibly overlapping, bounds-checked `highlights` rendered as
+    /// nested tags, see [`OverlapPolicy::Nest`] and [`sweep_nested`]; returns the number of
+    /// ranges flattened by [`HighlighterBuilder::max_nesting_depth`]
+    fn write_nested<W: fmt::Write>(
+        &self,
+        input: &str,
+        highlights: &[HighlightRange],
+        out: &mut W,
+    ) -> Result<usize, fmt::Error> {
+        let rendered = highlights
+            .iter()
+            .filter(|h| self.is_rendered(h))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let flattened = sweep_nested(
+            input,
+            &rendered,
+            |_| None,
+            self.max_nesting_depth,
+            |event| match event {
+                HighlightEvent::Text(text) => self.write_segment(out, text),
+                HighlightEvent::Start(_) => out.write_str(&self.open_tag),
+                HighlightEvent::End(_) => out.write_str(&self.close_tag),
+            },
+        )?;
+        Ok(flattened.len())
+    }
+
+    fn write_segment<W: fmt::Write>(&self, out: &mut W, segment: &str) -> fmt::Result {
+        self.escaping.write(out, segment)
+    }
+
+    fn markup(&self) -> Markup<'_> {
+        Markup {
+            open_tag: Cow::Borrowed(&self.open_tag),
+            close_tag: Cow::Borrowed(&self.close_tag),
+            escaping: self.escaping,
+            split_at_newlines: self.split_at_newlines,
+            bidi_isolate: self.bidi_isolate,
+        }
+    }
+}
+
+/// Ranges of one input with every option of a [`Highlighter`] applied, see [`Highlighter::plan`]
+struct Plan<'r> {
+    /// what the policies made of the ranges, the planned pairs index into it
+    normalized: Cow<'r, [HighlightRange]>,
+    /// with [`OverlapPolicy::Nest`] the possibly overlapping ranges to render as nested tags
+    nested: Cow<'r, [HighlightRange]>,
+    /// highlights left out because of [`HighlighterBuilder::max_highlights`]
+    dropped: usize,
+    /// what [`HighlighterBuilder::max_coverage`] decided, `None` without a limit
+    gate: Option<CoverageGate>,
+    /// indices of the input ranges the coverage gate left out, in order
+    left_out: Vec<usize>,
+    /// the highlights covered the whole input, the planned pairs are what
+    /// [`HighlighterBuilder::full_cover`] renders instead: none, or a single range over the input
+    full_cover: bool,
+}
+
+/// Decision of the coverage gate and the coverage before and after it, see [`CoverageOutcome`]
+#[derive(Debug, Clone, Copy)]
+struct CoverageGate {
+    decision: CoverageDecision,
+    original_coverage: f64,
+    coverage: f64,
+}
+
+/// Fraction of the chars of `input` covered by the disjoint `sorted` pairs, like
+/// [`HighlightStats::coverage`]
+fn sorted_coverage(input: &str, sorted: &[(usize, HighlightRange)]) -> f64 {
+    let total_chars = input.chars().count();
+    if total_chars == 0 {
+        return 0.0;
+    }
+    let covered_chars = sorted
+        .iter()
+        .map(|(_, h)| input[h.lower..h.upper].chars().count())
+        .sum::<usize>();
+    covered_chars as f64 / total_chars as f64
+}
+
+/// Ranges [`CoverageAction::TrimLowestScored`] keeps or leaves out together, see
+/// [`Highlighter::trim_units`]
+struct TrimUnit {
+    /// indices of the input ranges normalized into the unit, in order
+    sources: Vec<usize>,
+    /// the validated ranges the unit covers
+    pieces: Vec<HighlightRange>,
+    /// `(lower, upper)` of the unit, which breaks ties between equal scores
+    order: (usize, usize),
+}
+
+/// Which of the `units` [`CoverageAction::TrimLowestScored`] keeps so that at most the fraction
+/// `max` of the chars stays highlighted. A unit scores as its best-scored input range, and the
+/// lowest-scored units are left out first, of equal ones the last in document order; rather than
+/// taking them out one by one, the units are added back from the highest-scored one on with a
+/// running count of the chars they cover, so every char of the input is counted once.
+fn trim_lowest_scored(
+    input: &str,
+    units: &[TrimUnit],
+    max: f32,
+    score: impl Fn(usize) -> f32,
+) -> Vec<bool> {
+    let score = |unit: &TrimUnit| {
+        unit.sources
+            .iter()
+            .map(|&i| match score(i) {
+                score if score.is_nan() => f32::NEG_INFINITY,
+                score => score,
+            })
+            .fold(f32::NEG_INFINITY, f32::max)
+    };
+    let scores = units.iter().map(score).collect::<Vec<_>>();
+    let mut by_score = (0..units.len()).collect::<Vec<_>>();
+    by_score.sort_by(|&a, &b| {
+        scores[a]
+            .total_cmp(&scores[b])
+            .then(units[b].order.cmp(&units[a].order))
+    });
+
+    let total_chars = input.chars().count() as f64;
+    let mut covered = BTreeMap::new();
+    let mut covered_chars = 0;
+    let mut kept = vec![false; units.len()];
+    for &unit in by_score.iter().rev() {
+        let added = units[unit]
+            .pieces
+            .iter()
+            .map(|piece| cover(input, &mut covered, piece))
+            .sum::<usize>();
+        if (covered_chars + added) as f64 / total_chars > f64::from(max) {
+            break;
+        }
+        covered_chars += added;
+        kept[unit] = true;
+    }
+    kept
+}
+
+/// Indices of the input ranges, out of `len`, that only went into the `units` not `kept`, in
+/// order; a range that went into no unit was not left out by the trim
+fn left_out_sources(len: usize, units: &[TrimUnit], kept: &[bool]) -> Vec<usize> {
+    let mut left_out = vec![false; len];
+    for (unit, _) in units.iter().zip(kept).filter(|(_, kept)| !**kept) {
+        unit.sources.iter().for_each(|&i| left_out[i] = true);
+    }
+    for (unit, _) in units.iter().zip(kept).filter(|(_, kept)| **kept) {
+        unit.sources.iter().for_each(|&i| left_out[i] = false);
+    }
+    (0..len).filter(|&i| left_out[i]).collect()
+}
+
+/// Position in the sorted, validated `sorted` pairs of the one that contains `piece`
+fn containing_pair(sorted: &[(usize, HighlightRange)], piece: &HighlightRange) -> Option<usize> {
+    let end = sorted.partition_point(|(_, h)| h <= piece);
+    // the last pair starting before `piece`, or one starting with it and reaching further
+    [end.checked_sub(1), Some(end)]
+        .into_iter()
+        .flatten()
+        .find(|&at| {
+            sorted
+                .get(at)
+                .is_some_and(|(_, h)| h.lower <= piece.lower && piece.upper <= h.upper)
+        })
+}
+
+/// Adds the bounds-checked `range` to the disjoint spans of `covered`, upper ends by lower end,
+/// and returns the number of chars of `input` it covers that no span covered before; only the
+/// newly covered gaps are counted, so adding many ranges counts every char at most once
+fn cover(input: &str, covered: &mut BTreeMap<usize, usize>, range: &HighlightRange) -> usize {
+    if range.is_empty() {
+        return 0;
+    }
+
+    let mut span = range.clone();
+    // first byte that might not be covered yet
+    let mut offset = range.lower;
+    let reaching_in = covered
+        .range(..range.lower)
+        .next_back()
+        .map(|(&lower, &upper)| (lower, upper))
+        .filter(|&(_, upper)| upper >= range.lower);
+    if let Some((lower, upper)) = reaching_in {
+        covered.remove(&lower);
+        span.lower = lower;
+        span.upper = span.upper.max(upper);
+        offset = offset.max(upper);
+    }
+
+    let mut new_chars = 0;
+    while let Some((lower, upper)) = covered
+        .range(range.lower..=range.upper)
+        .next()
+        .map(|(&lower, &upper)| (lower, upper))
+    {
+        covered.remove(&lower);
+        if offset < lower {
+            new_chars += input[offset..lower].chars().count();
+        }
+        span.upper = span.upper.max(upper);
+        offset = offset.max(upper);
+    }
+    if offset < range.upper {
+        new_chars += input[offset..range.upper].chars().count();
+    }
+
+    covered.insert(span.lower, span.upper);
+    new_chars
+}
+
+/// Number of spans [`merge_ranges`] joins the sorted `highlights` into, and where the `k`th of
+/// them ends, `None` for `k = 0`; counted without merging or allocating
+fn count_merged_spans<'h>(
+    sorted: impl Iterator<Item = &'h HighlightRange>,
+    k: usize,
+) -> (usize, Option<usize>) {
+    let mut total = 0;
+    let mut end_of_kth = None;
+    let mut upper: Option<usize> = None;
+    for h in sorted.filter(|h| !h.is_empty()) {
+        match upper {
+            Some(end) if h.lower <= end => upper = Some(end.max(h.upper)),
+            _ => {
+                if total == k {
+                    end_of_kth = upper;
+                }
+                total += 1;
+                upper = Some(h.upper);
+            }
+        }
+    }
+    if total == k {
+        end_of_kth = upper;
+    }
+    (total, end_of_kth)
+}
+
+/// Byte offset in `input` after the visible chars that fit in `max` columns, `None` if all of
+/// them fit; `width` gives the columns of a char, `|_| 1` cuts after the first `max` chars.
+/// Visible chars are the chars of the input, so neither tags nor the entities escaping produces
+/// count, and an escaped char counts once. Truncating and wrapping both cut with it.
+fn visible_cut(input: &str, max: usize, width: impl Fn(char) -> usize) -> Option<usize> {
+    let mut columns = 0;
+    for (offset, c) in input.char_indices() {
+        columns += width(c);
+        if columns > max {
+            return Some(offset);
+        }
+    }
+    None
+}
+
+/// Byte range of `out` between the first `open_tag` and the last `close_tag` written since
+/// `start`; the tags are at the very ends, so the content can't be mistaken for them
+fn content_span(out: &str, start: usize, open_tag: &str, close_tag: &str) -> HighlightRange {
+    let written = &out[start..];
+    let lower = written.find(open_tag).map_or(0, |p| p + open_tag.len());
+    let upper = written
+        .rfind(close_tag)
+        .filter(|&p| p >= lower)
+        .unwrap_or(written.len());
+    HighlightRange::new(start + lower, start + upper)
+}
+
+/// [`Placement`] of `range` given the rendered ranges it ended up in, in document order, and
+/// their output
+fn placement<'r>(
+    index: usize,
+    range: &HighlightRange,
+    rendered: impl Iterator<Item = (&'r HighlightRange, HighlightRange)>,
+) -> Placement {
+    let mut output: Option<HighlightRange> = None;
+    let (mut covered, mut beyond) = (0, false);
+    for (r, out) in rendered {
+        covered += r.upper.min(range.upper) - r.lower.max(range.lower);
+        beyond |= r.lower < range.lower || r.upper > range.upper;
+        output = Some(match output {
+            Some(first) => HighlightRange::new(first.lower, out.upper),
+            None => out,
+        });
+    }
+
+    let status = match output {
+        None => PlacementStatus::Dropped,
+        Some(_) if covered < range.len() => PlacementStatus::Clipped,
+        Some(_) if beyond => PlacementStatus::Merged,
+        Some(_) => PlacementStatus::Rendered,
+    };
+    Placement {
+        index,
+        output,
+        status,
+    }
+}
+
+/// Highlights text that arrives in chunks, with ranges given as byte offsets into the text of
+/// all chunks concatenated; a highlight spanning several chunks is opened in the first and closed
+/// in the last of them
+#[derive(Debug, Clone)]
+pub struct StreamingHighlighter {
+    highlighter: Highlighter,
+    /// sorted and non-overlapping
+    highlights: Vec<HighlightRange>,
+    /// index of the first range that hasn't been closed yet
+    next: usize,
+    /// length of the text pushed so far
+    offset: usize,
+    /// whether `highlights[next]` has been opened in an earlier chunk
+    open: bool,
+}
+
+impl StreamingHighlighter {
+    /// Same as [`Highlighter::streaming`] with the default tags
+    pub fn new(highlights: Vec<HighlightRange>) -> Result<Self, HighlightingError> {
+        Highlighter::default().streaming(highlights)
+    }
+
+    /// Returns the highlighted `chunk`. Fails without consuming the chunk, if a range starts or
+    /// ends inside one of its chars.
+    pub fn push_chunk(&mut self, chunk: &str) -> Result<String, HighlightingError> {
+        let mut out = String::with_capacity(chunk.len());
+        self.write_chunk(&mut out, chunk)?;
+        Ok(out)
+    }
+
+    /// Same as [`StreamingHighlighter::push_chunk`], appending to `out`; nothing is written on
+    /// error
+    fn write_chunk(&mut self, out: &mut String, chunk: &str) -> Result<(), HighlightingError> {
+        let (start, end) = (self.offset, self.offset + chunk.len());
+        let pending = self.highlights[self.next..]
+            .iter()
+            .take_while(|h| h.lower < end)
+            .flat_map(|h| [h.lower, h.upper])
+            .filter(|offset| (start..end).contains(offset));
+        for offset in pending {
+            if !chunk.is_char_boundary(offset - start) {
+                return Err(HighlightingError::NotACharBoundary(offset));
+            }
+        }
+
+        let mut pos = start;
+        let mut write = |out: &mut String, upper: usize| {
+            self.highlighter
+                .write_segment(out, &chunk[pos - start..upper - start])
+                .expect("writing into a String can't fail");
+            pos = upper;
+        };
+        while let Some(h) = self.highlights.get(self.next) {
+            if !self.open {
+                // a range starting right at the end of the chunk is opened with the next one
+                if h.lower >= end {
+                    break;
+                }
+                write(out, h.lower);
+                out.push_str(&self.highlighter.open_tag);
+                self.open = true;
+            }
+            if h.upper > end {
+                break;
+            }
+            write(out, h.upper);
+            out.push_str(&self.highlighter.close_tag);
+            self.open = false;
+            self.next += 1;
+        }
+        write(out, end);
+
+        self.offset = end;
+        Ok(())
+    }
+
+    /// Closes a highlight that is still open and returns the ranges that didn't end within the
+    /// pushed text, including the one cut off by the close tag
+    pub fn finish(self) -> (String, Vec<SkippedRange>) {
+        let out = if self.open {
+            self.highlighter.close_tag.to_string()
+        } else {
+            String::new()
+        };
+        let input_len = self.offset;
+        let unreached = self.highlights[self.next..]
+            .iter()
+            .map(|range| SkippedRange {
+                range: range.clone(),
+                reason: HighlightingError::RangesOutOfBounds {
+                    range: range.clone(),
+                    input_len,
+                },
+            })
+            .collect();
+
+        (out, unreached)
+    }
+}
+
+/// Same as [`highlight_text`] for text split into chunks, like the leaves of a rope, with
+/// `highlights` given as byte offsets into the chunks concatenated. The chunks are read once and
+/// never joined; since the length of the text is only known after the last chunk, errors are
+/// reported in the same order as [`highlight_text`] once every chunk has been seen.
+pub fn highlight_chunks<'a, 'r>(
+    chunks: impl IntoIterator<Item = &'a str>,
+    highlights: impl IntoRanges<'r>,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    // an overlap is only reported after the bounds, so the chunks are read either way
+    let mut streaming = Highlighter::default().streaming(highlights.to_vec());
+    // every offset is checked, also those of the zero-length ranges the streaming highlighter
+    // drops, and the ones inside a char are kept to report the first in input order
+    let mut offsets = highlights
+        .iter()
+        .flat_map(|h| [h.lower, h.upper])
+        .collect::<Vec<_>>();
+    offsets.sort_unstable();
+    offsets.dedup();
+    let mut offsets = offsets.into_iter().peekable();
+    let mut inside_chars = Vec::new();
+
+    let mut out = String::new();
+    let mut input_len = 0;
+    for chunk in chunks {
+        let start = input_len;
+        input_len += chunk.len();
+        while let Some(offset) = offsets.next_if(|&offset| offset < input_len) {
+            if !chunk.is_char_boundary(offset - start) {
+                inside_chars.push(offset);
+            }
+        }
+        if !inside_chars.is_empty() {
+            continue;
+        }
+        if let Ok(highlighter) = &mut streaming
+            && let Err(err) = highlighter.write_chunk(&mut out, chunk)
+        {
+            streaming = Err(err);
+        }
+    }
+
+    if let Some(h) = highlights.iter().find(|h| h.upper > input_len) {
+        return Err(HighlightingError::RangesOutOfBounds {
+            range: h.clone(),
+            input_len,
+        });
+    }
+    let mut offsets = highlights.iter().flat_map(|h| [h.lower, h.upper]);
+    if let Some(offset) = offsets.find(|offset| inside_chars.binary_search(offset).is_ok()) {
+        return Err(HighlightingError::NotACharBoundary(offset));
+    }
+    // every range ends within the chunks, so none is left over
+    let (tail, _) = streaming?.finish();
+    out.push_str(&tail);
+    Ok(out)
+}
+
+/// Whether a [`RenderJob`] has rendered all of its input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    Done,
+    NotDone,
+}
+
+/// Resumable rendering of a large input, a bounded piece per [`RenderJob::step`], so an async
+/// task can yield between the steps without the crate being async. The output of every step is
+/// buffered until [`RenderJob::take_output`], and all of it together is byte-identical to
+/// [`highlight_text`].
+#[derive(Debug, Clone)]
+pub struct RenderJob<'a> {
+    input: &'a str,
+    streaming: StreamingHighlighter,
+    /// input bytes rendered per step, rounded down to a char boundary
+    budget: usize,
+    output: String,
+}
+
+impl<'a> RenderJob<'a> {
+    /// Validates `highlights` like [`highlight_text`] up front, so the steps can't fail.
+    /// Every step renders up to `chunk_budget_bytes` of `input`, but always at least one char.
+    pub fn new<'r>(
+        input: &'a str,
+        highlights: impl IntoRanges<'r>,
+        chunk_budget_bytes: usize,
+    ) -> Result<Self, HighlightingError> {
+        let highlights = collect_ranges(highlights);
+        validate_ranges(input, &highlights)?;
+
+        Ok(RenderJob {
+            input,
+            streaming: Highlighter::default().streaming(highlights.into_owned())?,
+            budget: chunk_budget_bytes,
+            output: String::new(),
+        })
+    }
+
+    /// Renders the next piece of the input into the output buffer
+    pub fn step(&mut self) -> StepResult {
+        let start = self.streaming.offset;
+        if start == self.input.len() {
+            return StepResult::Done;
+        }
+
+        let mut end = (start + self.budget).min(self.input.len());
+        while !self.input.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == start {
+            end = start + self.input[start..].chars().next().map_or(0, char::len_utf8);
+        }
+
+        self.streaming
+            .write_chunk(&mut self.output, &self.input[start..end])
+            .expect("ranges are validated and chunks end on char boundaries");
+        // validated, so the last highlight is closed with the last chunk
+        match end == self.input.len() {
+            true => StepResult::Done,
+            false => StepResult::NotDone,
+        }
+    }
+
+    /// Drains the output rendered since the last call
+    pub fn take_output(&mut self) -> String {
+        core::mem::take(&mut self.output)
+    }
+}
+
+/// Forwards [`fmt::Write`] into an [`io::Write`], keeping the `io::Error` that `fmt::Error` drops
+#[cfg(feature = "std")]
+struct IoAdapter<'w, W> {
+    inner: &'w mut W,
+    error: Option<io::Error>,
+}
+
+#[cfg(feature = "std")]
+impl<W: io::Write> fmt::Write for IoAdapter<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+/// Entity `c` is replaced with by HTML escaping, if any
+fn html_entity(c: char) -> Option<&'static str> {
+    match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        '"' => Some("&quot;"),
+        '\'' => Some("&#39;"),
+        _ => None,
+    }
+}
+
+fn write_html_escaped<W: fmt::Write>(out: &mut W, text: &str) -> fmt::Result {
+    let mut unescaped_from = 0;
+    for (i, c) in text.char_indices() {
+        let Some(entity) = html_entity(c) else {
+            continue;
+        };
+        out.write_str(&text[unescaped_from..i])?;
+        out.write_str(entity)?;
+        unescaped_from = i + c.len_utf8();
+    }
+
+    out.write_str(&text[unescaped_from..])
+}
+
+fn write_markdown_escaped<W: fmt::Write>(out: &mut W, text: &str, equals: bool) -> fmt::Result {
+    let mut unescaped_from = 0;
+    for (i, c) in text.char_indices() {
+        if matches!(c, '\\' | '*' | '_') || (equals && c == '=') {
+            out.write_str(&text[unescaped_from..i])?;
+            out.write_char('\\')?;
+            unescaped_from = i;
+        }
+    }
+
+    out.write_str(&text[unescaped_from..])
+}
+
+fn write_bbcode_escaped<W: fmt::Write>(out: &mut W, text: &str) -> fmt::Result {
+    let mut parts = text.split('[');
+    out.write_str(parts.next().unwrap_or_default())?;
+    for part in parts {
+        out.write_str("[\u{200B}")?;
+        out.write_str(part)?;
+    }
+
+    Ok(())
+}
+
+fn write_latex_escaped<W: fmt::Write>(out: &mut W, text: &str) -> fmt::Result {
+    let mut unescaped_from = 0;
+    for (i, c) in text.char_indices() {
+        let escaped = match c {
+            '#' => "\\#",
+            '$' => "\\$",
+            '%' => "\\%",
+            '&' => "\\&",
+            '_' => "\\_",
+            '{' => "\\{",
+            '}' => "\\}",
+            '~' => "\\textasciitilde{}",
+            '^' => "\\textasciicircum{}",
+            '\\' => "\\textbackslash{}",
+            _ => continue,
+        };
+        out.write_str(&text[unescaped_from..i])?;
+        out.write_str(escaped)?;
+        unescaped_from = i + c.len_utf8();
+    }
+
+    out.write_str(&text[unescaped_from..])
+}
+
+/// Moves ranges over `original` to the same text in its HTML-escaped form (`&` → `&amp;`, `<` →
+/// `&lt;`, `>` → `&gt;`, `"` → `&quot;`, `'` → `&#39;`, as done by
+/// [`HighlighterBuilder::escape_html`]), see [`OffsetMap`]: a range includes the whole entity of
+/// every char it covers and never splits one. Ranges reaching past `original` are dropped.
+pub fn remap_ranges_for_escaped(
+    original: &str,
+    highlights: &[HighlightRange],
+) -> Vec<HighlightRange> {
+    let mut ops = Vec::new();
+    let mut kept_from = 0;
+    for (i, c) in original.char_indices() {
+        if let Some(entity) = html_entity(c) {
+            ops.push(EditOp::Keep(i - kept_from));
+            ops.push(EditOp::Replace {
+                from_len: c.len_utf8(),
+                to_len: entity.len(),
+            });
+            kept_from = i + c.len_utf8();
+        }
+    }
+    ops.push(EditOp::Keep(original.len() - kept_from));
+    let map = OffsetMap::new(ops);
+
+    highlights
+        .iter()
+        .filter_map(|h| map.map_range(h.clone()))
+        .collect()
+}
+
+/// Same as [`highlight_text`] over `escaped`, the HTML-escaped form of `original`, with
+/// `highlights` given against `original`, see [`remap_ranges_for_escaped`]
+pub fn highlight_escaped_document(
+    escaped: &str,
+    highlights: &[HighlightRange],
+    original: &str,
+) -> Result<String, HighlightingError> {
+    validate_ranges(original, highlights)?;
+
+    let remapped = remap_ranges_for_escaped(original, highlights);
+    Highlighter::default().highlight(escaped, &remapped)
+}
+
+/// Same as [`highlight_text`], but ranges may be anchored to the end of `input`
+pub fn highlight_text_anchored<R: Into<AnchoredRange>>(
+    input: &str,
+    highlights: impl IntoIterator<Item = R>,
+) -> Result<String, HighlightingError> {
+    let highlights = resolve_ranges(input, highlights, BoundsPolicy::Strict)?;
+    highlight_text(input, highlights)
+}
+
+/// Wraps every range (byte offsets into `input`) in `<em>`/`</em>`, e.g.
+/// `highlight_text("Hello world", [0..5])` gives `<em>Hello</em> world`.
+///
+/// An empty `input` is highlighted like any other: without ranges it gives an empty string, the
+/// only range it can have is `0..0`, which is skipped unless empty markers are allowed, and any
+/// other range is [`HighlightingError::RangesOutOfBounds`]. Every renderer follows the same rules.
+pub fn highlight_text<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    Highlighter::default().highlight(input, &highlights)
+}
+
+/// Same as [`highlight_text`], but the tags are inserted into `text` itself instead of a second
+/// buffer: it grows once by the length of the tags and its bytes are moved back to front, so
+/// every byte moves at most once. On error `text` is left unchanged.
+pub fn highlight_in_place<'r>(
+    text: &mut String,
+    highlights: impl IntoRanges<'r>,
+) -> Result<(), HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    let (open, close) = ("<em>", "</em>");
+    let sorted = PreparedHighlights::new(text, &highlights)?
+        .ranges()
+        .filter(|h| !h.is_empty())
+        .cloned()
+        .collect::<Vec<_>>();
+    if sorted.is_empty() {
+        return Ok(());
+    }
+
+    let mut bytes = core::mem::take(text).into_bytes();
+    let mut read_end = bytes.len();
+    bytes.resize(read_end + sorted.len() * (open.len() + close.len()), 0);
+    let mut write_end = bytes.len();
+
+    let mut write_back = |bytes: &mut Vec<u8>, src: Range<usize>, tag: &str| {
+        let len = src.len();
+        bytes.copy_within(src, write_end - len);
+        write_end -= len;
+        bytes[write_end - tag.len()..write_end].copy_from_slice(tag.as_bytes());
+        write_end -= tag.len();
+    };
+    for h in sorted.iter().rev() {
+        write_back(&mut bytes, h.upper..read_end, close);
+        write_back(&mut bytes, h.lower..h.upper, open);
+        read_end = h.lower;
+    }
+
+    *text = String::from_utf8(bytes).expect("tags are inserted at char boundaries");
+    Ok(())
+}
+
+/// Same as [`highlight_text`], but everything outside the ranges is wrapped, see
+/// [`Highlighter::highlight_inverted`]
+pub fn highlight_text_inverted<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    Highlighter::default().highlight_inverted(input, &highlights)
+}
+
+/// Same as [`highlight_text`], but errors name `context`, e.g. the document the input came from,
+/// see [`HighlightingError::with_context`]
+pub fn highlight_text_ctx<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    context: impl Into<ErrorContext>,
+) -> Result<String, ContextError> {
+    highlight_text(input, highlights).map_err(|error| error.with_context(context))
+}
+
+/// Same as [`highlight_text`], but zero-length ranges are kept and rendered as `<em></em>`
+pub fn highlight_text_with_empty_markers<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    let highlighter = Highlighter {
+        allow_empty_markers: true,
+        ..Default::default()
+    };
+    highlighter.highlight(input, &highlights)
+}
+
+/// Same as [`highlight_text`], but wraps every highlight in `open`/`close` instead of `<em>`/`</em>`
+pub fn highlight_text_with_tags<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    open: &str,
+    close: &str,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    let highlighter = Highlighter {
+        open_tag: Arc::from(open),
+        close_tag: Arc::from(close),
+        ..Default::default()
+    };
+    highlighter.highlight(input, &highlights)
+}
+
+/// Same as [`highlight_text`], but `&`, `<`, `>`, `"` and `'` of the input are HTML-escaped, so
+/// the output is safe to inject into a page; ranges still refer to the unescaped input
+pub fn highlight_text_escaped<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    highlight_with_renderer(input, &highlights, HtmlRenderer::escaped())
+}
+
+/// Same as [`highlight_text`], but overlapping ranges are handled according to `policy`
+pub fn highlight_text_with_policy<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    policy: OverlapPolicy,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    let highlighter = Highlighter {
+        overlap_policy: policy,
+        ..Default::default()
+    };
+    highlighter.highlight(input, &highlights)
+}
+
+/// Same as [`highlight_text`], but touching ranges (e.g. 0..5 and 5..11) are rendered as one
+/// highlight instead of two adjacent ones; overlapping ranges are still rejected
+pub fn highlight_text_coalesced<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    let highlighter = Highlighter {
+        coalesce_adjacent: true,
+        ..Default::default()
+    };
+    highlighter.highlight(input, &highlights)
+}
+
+/// Same as [`highlight_text`], but every highlight is rendered as `<em class="{label}">`.
+/// Overlapping and touching ranges with the same label are merged first, see
+/// [`merge_labeled_ranges`]; ranges with different labels still must not overlap.
+pub fn highlight_text_labeled(
+    input: &str,
+    highlights: Vec<LabeledRange>,
+) -> Result<String, HighlightingError> {
+    let ranges = highlights
+        .iter()
+        .map(|h| h.range.clone())
+        .collect::<Vec<_>>();
+    validate_bounds(input, &ranges)?;
+
+    let highlights = merge_labeled_ranges(highlights)?;
+    let ranges = highlights
+        .iter()
+        .map(|h| h.range.clone())
+        .collect::<Vec<_>>();
+
+    // the tags are known up front, so the output is allocated with its exact length
+    let prepared = PreparedHighlights::from_sorted(input, &ranges)?;
+
+    let highlighter = Highlighter::default();
+    let tags = highlights
+        .iter()
+        .map(|h| labeled_open_tag(&highlighter.open_tag, &h.label))
+        .collect::<Vec<_>>();
+
+    let window = 0..input.len();
+    Ok(highlighter.render_with(&prepared, window, |i| Cow::Borrowed(&tags[i])))
+}
+
+/// Same as [`highlight_text_labeled`] for labels of any type, every label is rendered with the
+/// tags of `style(label)`. With an enum label and a `match` over it, a label without a style is a
+/// compile error instead of silently falling back to the default tags.
+pub fn highlight_text_labeled_by<L: Ord + Clone>(
+    input: &str,
+    highlights: Vec<LabeledRange<L>>,
+    style: impl Fn(&L) -> TagStyle,
+) -> Result<String, HighlightingError> {
+    let ranges = highlights
+        .iter()
+        .map(|h| h.range.clone())
+        .collect::<Vec<_>>();
+    validate_bounds(input, &ranges)?;
+
+    let highlights = merge_labeled_ranges(highlights)?;
+    let ranges = highlights
+        .iter()
+        .map(|h| h.range.clone())
+        .collect::<Vec<_>>();
+    let prepared = PreparedHighlights::from_sorted(input, &ranges)?;
+
+    let styles = highlights
+        .iter()
+        .map(|h| style(&h.label))
+        .collect::<Vec<_>>();
+    let tags = styles
+        .iter()
+        .map(|style| (style.open_tag(), style.close_tag()))
+        .collect::<Vec<_>>();
+
+    let window = 0..input.len();
+    Ok(
+        Highlighter::default().render_with_tags(&prepared, window, |i| {
+            let (open, close) = &tags[i];
+            (Cow::Borrowed(&**open), Cow::Borrowed(*close))
+        }),
+    )
+}
+
+/// `open_tag` with an HTML-escaped `{name}="{value}"` attribute before its closing `>`,
+/// composing with the attributes it already has; tags that don't end in `>` are left alone
+fn with_attribute<'a>(open_tag: &'a str, name: &str, value: &str) -> Cow<'a, str> {
+    let Some(start) = open_tag.strip_suffix('>') else {
+        return Cow::Borrowed(open_tag);
+    };
+
+    let mut tag = String::with_capacity(open_tag.len() + name.len() + value.len() + 4);
+    tag.push_str(start);
+    tag.push(' ');
+    tag.push_str(name);
+    tag.push_str("=\"");
+    write_html_escaped(&mut tag, value).expect("writing into a String can't fail");
+    tag.push_str("\">");
+    Cow::Owned(tag)
+}
+
+/// `open_tag` with `data-range="{lower}..{upper}"` and either `data-src-index` or, if several of
+/// `sources` ended up in `range`, `data-merged` with their indices, see
+/// [`HighlighterBuilder::debug_annotations`]
+fn with_debug_attributes(
+    open_tag: &str,
+    range: &HighlightRange,
+    sources: &[HighlightRange],
+) -> String {
+    let from = sources
+        .iter()
+        .enumerate()
+        .filter(|(_, source)| *source == range || ranges_overlap(source, range))
+        .map(|(j, _)| j.to_string())
+        .collect::<Vec<_>>();
+
+    let tag = with_attribute(open_tag, "data-range", &range.to_string());
+    match from.as_slice() {
+        [] => tag.into_owned(),
+        [index] => with_attribute(&tag, "data-src-index", index).into_owned(),
+        merged => with_attribute(&tag, "data-merged", &merged.join(",")).into_owned(),
+    }
+}
+
+/// Same as [`highlight_text_labeled`], but the n-th highlight in document order also gets an
+/// `id="{id_prefix}{n}"` attribute next to its class, see [`HighlighterBuilder::anchor_ids`]
+pub fn highlight_text_labeled_with_ids(
+    input: &str,
+    highlights: Vec<LabeledRange>,
+    id_prefix: &str,
+) -> Result<HighlightOutcome, HighlightingError> {
+    let ranges = highlights
+        .iter()
+        .map(|h| h.range.clone())
+        .collect::<Vec<_>>();
+    validate_bounds(input, &ranges)?;
+
+    // merging drops zero-length ranges, so every remaining range is rendered
+    let highlights = merge_labeled_ranges(highlights)?;
+    let (ranges, ids): (Vec<_>, Vec<_>) = highlights
+        .iter()
+        .enumerate()
+        .map(|(n, h)| (h.range.clone(), format!("{id_prefix}{n}")))
+        .unzip();
+    let highlighter = Highlighter::default();
+    let tags = highlights
+        .iter()
+        .zip(&ids)
+        .map(|(h, id)| {
+            let tag = labeled_open_tag(&highlighter.open_tag, &h.label);
+            with_attribute(&tag, "id", id).into_owned()
+        })
+        .collect::<Vec<_>>();
+
+    let prepared = PreparedHighlights::from_sorted(input, &ranges)?;
+    let window = 0..input.len();
+    let text = highlighter.render_with(&prepared, window, |i| Cow::Borrowed(&tags[i]));
+    Ok(HighlightOutcome {
+        text,
+        dropped: 0,
+        ids,
+        flattened: 0,
+        full_cover: highlighter.covers_input(input, &prepared.sorted),
+    })
+}
+
+/// `open_tag` with `label` as its `class`, e.g. `<em class="{label}">`
+fn labeled_open_tag(open_tag: &str, label: &str) -> String {
+    with_attribute(open_tag, "class", label).into_owned()
+}
+
+/// Same as [`highlight_text_labeled`], but overlapping ranges are allowed: the overlapping parts
+/// are rendered once with all their labels as classes, sorted and separated by spaces, e.g.
+/// `<em class="match person">`. See [`flatten_labeled_ranges`].
+pub fn highlight_text_labeled_combined(
+    input: &str,
+    highlights: Vec<LabeledRange>,
+) -> Result<String, HighlightingError> {
+    let ranges = highlights
+        .iter()
+        .map(|h| h.range.clone())
+        .collect::<Vec<_>>();
+    validate_bounds(input, &ranges)?;
+
+    let highlighter = Highlighter::default();
+    let flattened = flatten_labeled_ranges(&highlights);
+    let (ranges, tags): (Vec<_>, Vec<_>) = flattened
+        .into_iter()
+        .map(|(range, labels)| {
+            let labels = labels.into_iter().collect::<Vec<_>>();
+            let tag = labeled_open_tag(&highlighter.open_tag, &labels.join(" "));
+            (range, tag)
+        })
+        .unzip();
+
+    let prepared = PreparedHighlights::new(input, &ranges)?;
+    let window = 0..input.len();
+    Ok(highlighter.render_with(&prepared, window, |i| Cow::Borrowed(&tags[i])))
+}
+
+/// Ranges of one [`highlight_layers`] layer are wrapped in its own tags; its overlap policy only
+/// applies among its own ranges
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layer {
+    pub open_tag: String,
+    pub close_tag: String,
+    /// [`OverlapPolicy::Error`] by default, [`OverlapPolicy::Nest`] nests the ranges of the layer
+    /// like ranges of different layers
+    pub overlap_policy: OverlapPolicy,
+}
+
+impl Layer {
+    pub fn new(open_tag: &str, close_tag: &str) -> Self {
+        Layer {
+            open_tag: String::from(open_tag),
+            close_tag: String::from(close_tag),
+            overlap_policy: OverlapPolicy::default(),
+        }
+    }
+
+    pub fn with_style(style: &TagStyle) -> Self {
+        Layer::new(&style.open_tag(), style.close_tag())
+    }
+
+    pub fn overlap_policy(mut self, policy: OverlapPolicy) -> Self {
+        self.overlap_policy = policy;
+        self
+    }
+}
+
+/// Renders several independent layers of highlights at once, e.g. search terms and commented
+/// terms, each in its own tags. Ranges of different layers may overlap freely: the tags are
+/// nested like with [`OverlapPolicy::Nest`], closing and reopening the inner highlight where two
+/// only partially overlap, so they never cross. A range covering another one exactly is outside
+/// of it if its layer comes first. Zero-length ranges are dropped.
+pub fn highlight_layers(
+    input: &str,
+    layers: &[(Layer, &[HighlightRange])],
+) -> Result<String, HighlightingError> {
+    let (mut ranges, mut layer_of) = (Vec::new(), Vec::new());
+    for (i, (layer, highlights)) in layers.iter().enumerate() {
+        let highlights = match layer.overlap_policy {
+            OverlapPolicy::Error => {
+                validate_ranges(input, highlights)?;
+                Cow::Borrowed(*highlights)
+            }
+            OverlapPolicy::Merge => {
+                validate_bounds(input, highlights)?;
+                Cow::Owned(merge_ranges(highlights.to_vec()))
+            }
+            OverlapPolicy::Nest => {
+                validate_bounds(input, highlights)?;
+                Cow::Borrowed(*highlights)
+            }
+        };
+        for h in highlights.iter().filter(|h| !h.is_empty()) {
+            ranges.push(h.clone());
+            layer_of.push(i);
+        }
+    }
+
+    let tags_len = layer_of
+        .iter()
+        .map(|&i| layers[i].0.open_tag.len() + layers[i].0.close_tag.len())
+        .sum::<usize>();
+    let mut out = String::with_capacity(input.len() + tags_len);
+    let walked = sweep_nested(
+        input,
+        &ranges,
+        |_| None,
+        usize::MAX,
+        |event| {
+            out.push_str(match event {
+                HighlightEvent::Text(text) => text,
+                HighlightEvent::Start(n) => &layers[layer_of[n]].0.open_tag,
+                HighlightEvent::End(n) => &layers[layer_of[n]].0.close_tag,
+            });
+            Ok::<(), Infallible>(())
+        },
+    );
+
+    match walked {
+        Ok(_) => Ok(out),
+    }
+}
+
+/// Same as [`highlight_text_labeled`], but every label is rendered with its style from `styles`;
+/// labels without one keep the `<em class="{label}">` tag
+#[cfg(feature = "std")]
+pub fn highlight_text_labeled_styled<L: Eq + core::hash::Hash + fmt::Display>(
+    input: &str,
+    highlights: Vec<LabeledRange<L>>,
+    styles: &HashMap<L, TagStyle>,
+) -> Result<String, HighlightingError> {
+    let ranges = highlights
+        .iter()
+        .map(|h| h.range.clone())
+        .collect::<Vec<_>>();
+
+    let tags = highlights
+        .iter()
+        .map(|h| match styles.get(&h.label) {
+            Some(style) => (style.open_tag(), Cow::Borrowed(style.close_tag())),
+            None => (
+                Cow::Owned(labeled_open_tag("<em>", &h.label.to_string())),
+                Cow::Borrowed("</em>"),
+            ),
+        })
+        .collect::<Vec<_>>();
+
+    highlight_with(input, &ranges, |segment, range_idx, out| match range_idx {
+        Some(i) => {
+            let (open, close) = &tags[i];
+            out.push_str(open);
+            out.push_str(segment.text);
+            out.push_str(close);
+        }
+        None => out.push_str(segment.text),
+    })
+}
+
+/// How serious a diagnostic is, ordered so that `Error` is the greatest; see
+/// [`highlight_diagnostics_html`] and [`highlight_diagnostics_ansi`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+
+    /// `<span class="sev-error">` etc.
+    pub fn tag_style(self) -> TagStyle {
+        TagStyle::SpanClass(format!("sev-{}", self.as_str()))
+    }
+
+    /// red for errors, yellow for warnings and blue for info
+    pub fn ansi_style(self) -> AnsiStyle {
+        AnsiStyle::Color(match self {
+            Severity::Info => AnsiColor::Blue,
+            Severity::Warning => AnsiColor::Yellow,
+            Severity::Error => AnsiColor::Red,
+        })
+    }
+}
+
+/// Resolves overlapping findings by severity with [`resolve_labeled_overlaps_ord`]: an error
+/// beats a warning, which beats an info; overlapping findings of the same severity are merged
+pub fn resolve_severity_overlaps(
+    findings: Vec<(HighlightRange, Severity)>,
+) -> Vec<(HighlightRange, Severity)> {
+    let labeled = findings
+        .into_iter()
+        .map(|(range, severity)| LabeledRange::with_label(range, severity))
+        .collect();
+
+    resolve_labeled_overlaps_ord(labeled)
+        .into_iter()
+        .map(|h| (h.range, h.label))
+        .collect()
+}
+
+/// Renders every finding with the HTML tags of its [`Severity::tag_style`], unless `styles`
+/// overrides it; overlaps are resolved with [`resolve_severity_overlaps`]
+pub fn highlight_diagnostics_html(
+    input: &str,
+    findings: Vec<(HighlightRange, Severity)>,
+    styles: &BTreeMap<Severity, TagStyle>,
+) -> Result<String, HighlightingError> {
+    let resolved = resolve_severity_overlaps(findings);
+    let tags = resolved
+        .iter()
+        .map(|&(_, severity)| {
+            let style = styles
+                .get(&severity)
+                .cloned()
+                .unwrap_or_else(|| severity.tag_style());
+            (style.open_tag().into_owned(), style.close_tag().to_string())
+        })
+        .collect::<Vec<_>>();
+
+    render_findings(input, resolved, tags)
+}
+
+/// Same as [`highlight_diagnostics_html`], but with the ANSI colors of [`Severity::ansi_style`]
+/// for terminal output
+pub fn highlight_diagnostics_ansi(
+    input: &str,
+    findings: Vec<(HighlightRange, Severity)>,
+    styles: &BTreeMap<Severity, AnsiStyle>,
+) -> Result<String, HighlightingError> {
+    let resolved = resolve_severity_overlaps(findings);
+    let tags = resolved
+        .iter()
+        .map(|(_, severity)| {
+            let style = styles
+                .get(severity)
+                .copied()
+                .unwrap_or_else(|| severity.ansi_style());
+            (style.escape_sequence(), ANSI_RESET.to_string())
+        })
+        .collect::<Vec<_>>();
+
+    render_findings(input, resolved, tags)
+}
+
+/// Wraps every resolved finding in its `(open, close)` tags
+fn render_findings(
+    input: &str,
+    resolved: Vec<(HighlightRange, Severity)>,
+    tags: Vec<(String, String)>,
+) -> Result<String, HighlightingError> {
+    let ranges = resolved
+        .into_iter()
+        .map(|(range, _)| range)
+        .collect::<Vec<_>>();
+
+    highlight_with(input, &ranges, |segment, range_idx, out| match range_idx {
+        Some(i) => {
+            let (open, close) = &tags[i];
+            out.push_str(open);
+            out.push_str(segment.text);
+            out.push_str(close);
+        }
+        None => out.push_str(segment.text),
+    })
+}
+
+/// Same as [`highlight_text`], but highlights are rendered with ANSI SGR codes for terminal
+/// output; touching ranges are coalesced so there's no reset-then-set flicker between them
+pub fn highlight_text_ansi<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    style: AnsiStyle,
+) -> Result<String, HighlightingError> {
+    Ok(validate(input, highlights)?.to_ansi(style))
+}
+
+/// How [`highlight_wrap_ansi_with`] measures and wraps lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WrapOptions {
+    /// maximum number of columns per line, at least one char is put on every line
+    pub width: usize,
+    /// columns taken by a tab, every other char takes one
+    pub tab_width: usize,
+    /// see [`HighlighterBuilder::bidi_isolate`], the isolates take no columns
+    pub bidi_isolate: bool,
+}
+
+impl WrapOptions {
+    /// `width` columns with tabs taking 4 of them
+    pub fn new(width: usize) -> Self {
+        WrapOptions {
+            width,
+            tab_width: 4,
+            bidi_isolate: false,
+        }
+    }
+
+    fn char_width(&self, c: char) -> usize {
+        if c == '\t' { self.tab_width } else { 1 }
+    }
+}
+
+/// Same as [`highlight_text_ansi`], but lines are wrapped at `width` columns, see
+/// [`highlight_wrap_ansi_with`]
+pub fn highlight_wrap_ansi<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    style: AnsiStyle,
+    width: usize,
+) -> Result<String, HighlightingError> {
+    highlight_wrap_ansi_with(input, highlights, style, &WrapOptions::new(width))
+}
+
+/// Same as [`highlight_text_ansi`], but lines longer than `options.width` are wrapped, after the
+/// last whitespace that fits if there is one and in the middle of the word otherwise. Whitespace
+/// at a wrap is dropped. Every line is highlighted on its own: a highlight continuing on the next
+/// line is reset at the end of the line and set again at the start of the next one.
+pub fn highlight_wrap_ansi_with<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    style: AnsiStyle,
+    options: &WrapOptions,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    validate_ranges(input, &highlights)?;
+    let highlights = merge_ranges(highlights.into_owned());
+    let prepared = PreparedHighlights::from_sorted(input, &highlights)?;
+
+    let highlighter = Highlighter {
+        open_tag: Arc::from(style.escape_sequence()),
+        close_tag: Arc::from(ANSI_RESET),
+        bidi_isolate: options.bidi_isolate,
+        ..Default::default()
+    };
+    let mut out = String::with_capacity(input.len() + highlights.len() * 16);
+    let mut line_start = 0;
+    for (i, line) in input.split('\n').enumerate() {
+        let content = line.strip_suffix('\r').unwrap_or(line);
+        for (j, wrapped) in wrap_line(content, options).into_iter().enumerate() {
+            if i > 0 || j > 0 {
+                out.push('\n');
+            }
+            let window = line_start + wrapped.start..line_start + wrapped.end;
+            let rendered = highlighter
+                .render_with(&prepared, window, |_| Cow::Borrowed(&highlighter.open_tag));
+            out.push_str(&rendered);
+        }
+        line_start += line.len() + 1;
+    }
+
+    Ok(out)
+}
+
+/// What [`highlight_wrap_html_with`] inserts where it wraps a line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineBreak {
+    /// `<br>`
+    #[default]
+    Br,
+    /// `\n`
+    Newline,
+}
+
+/// Same as [`highlight_wrap_html_with`], wrapping with `<br>`
+pub fn highlight_wrap_html<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    max_cols: usize,
+) -> Result<String, HighlightingError> {
+    highlight_wrap_html_with(input, highlights, max_cols, LineBreak::Br)
+}
+
+/// Same as [`highlight_text_escaped`], but lines with more than `max_cols` visible chars are
+/// wrapped like in [`highlight_wrap_ansi_with`], inserting `line_break` at every wrap. Visible
+/// chars are counted in `input` like with [`Highlighter::highlight_truncated`]: tags don't count
+/// and an entity counts as the one char it escapes. A highlight spanning a wrap is closed at the
+/// end of the line and reopened on the next one, so every line has balanced tags; the line
+/// breaks of `input` are kept as they are.
+pub fn highlight_wrap_html_with<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    max_cols: usize,
+    line_break: LineBreak,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    validate_ranges(input, &highlights)?;
+    let highlights = merge_ranges(highlights.into_owned());
+    let prepared = PreparedHighlights::from_sorted(input, &highlights)?;
+
+    let highlighter = Highlighter {
+        escaping: Escaping::Html,
+        ..Default::default()
+    };
+    let options = WrapOptions {
+        tab_width: 1,
+        ..WrapOptions::new(max_cols)
+    };
+    let line_break = match line_break {
+        LineBreak::Br => "<br>",
+        LineBreak::Newline => "\n",
+    };
+    let mut out = String::with_capacity(input.len() + highlights.len() * 9);
+    let mut line_start = 0;
+    for line in input.split_inclusive('\n') {
+        let content = line
+            .strip_suffix('\n')
+            .map_or(line, |l| l.strip_suffix('\r').unwrap_or(l));
+        for (i, wrapped) in wrap_line(content, &options).into_iter().enumerate() {
+            if i > 0 {
+                out.push_str(line_break);
+            }
+            let window = line_start + wrapped.start..line_start + wrapped.end;
+            let rendered = highlighter
+                .render_with(&prepared, window, |_| Cow::Borrowed(&highlighter.open_tag));
+            out.push_str(&rendered);
+        }
+        out.push_str(&line[content.len()..]);
+        line_start += line.len();
+    }
+
+    Ok(out)
+}
+
+/// Byte ranges of the lines `line` (without line breaks) is wrapped into, see
+/// [`highlight_wrap_ansi_with`]
+fn wrap_line(line: &str, options: &WrapOptions) -> Vec<Range<usize>> {
+    let mut wrapped = Vec::new();
+    let mut start = 0;
+    loop {
+        let rest = &line[start..];
+        // at least one char is put on every line
+        let first = rest.chars().next().map_or(0, char::len_utf8);
+        let cut = visible_cut(rest, options.width, |c| options.char_width(c))
+            .map(|cut| cut.max(first))
+            .filter(|&cut| cut < rest.len());
+        let Some(cut) = cut else {
+            if !rest.is_empty() || wrapped.is_empty() {
+                wrapped.push(start..line.len());
+            }
+            return wrapped;
+        };
+
+        // wrap after the last whitespace that fits, unless the cut doesn't split a word anyway
+        let end = match rest[..cut].rfind(char::is_whitespace) {
+            Some(b) if b > 0 && !rest[cut..].starts_with(char::is_whitespace) => b,
+            _ => cut,
+        };
+        wrapped.push(start..start + rest[..end].trim_end().len());
+
+        // whitespace at a wrap is dropped, the part of the word after the break moves on
+        start += end;
+        start += line[start..].len() - line[start..].trim_start().len();
+    }
+}
+
+/// Renders `input` unchanged with a line of `^` under every line holding a highlight, like
+/// compiler diagnostics; tabs take 4 columns, see [`highlight_text_caret_with_tab_width`]
+pub fn highlight_text_caret<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+) -> Result<String, HighlightingError> {
+    highlight_text_caret_with_tab_width(input, highlights, 4)
+}
+
+/// Same as [`highlight_text_caret`], but tabs take `tab_width` columns. Every other char takes
+/// one column, wide chars (e.g. in CJK text) aren't accounted for. A range spanning line breaks
+/// is underlined on each line it covers; the line breaks themselves are never underlined, and
+/// lines get no underline if none of their chars are highlighted.
+pub fn highlight_text_caret_with_tab_width<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    tab_width: usize,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    validate_ranges(input, &highlights)?;
+    let highlights = merge_ranges(highlights.into_owned());
+
+    let mut out = String::with_capacity(input.len() * 2);
+    let mut line_start = 0;
+    for line in input.split_inclusive('\n') {
+        let content = line.trim_end_matches('\n').trim_end_matches('\r');
+        let line_break = &line[content.len()..];
+
+        let mut underline = String::new();
+        let mut underline_len = 0;
+        for (i, c) in content.char_indices() {
+            let width = if c == '\t' { tab_width } else { 1 };
+            if is_highlighted(&highlights, line_start + i) {
+                underline.extend(core::iter::repeat_n('^', width));
+                underline_len = underline.len();
+            } else {
+                underline.extend(core::iter::repeat_n(' ', width));
+            }
+        }
+        underline.truncate(underline_len);
+
+        out.push_str(line);
+        if !underline.is_empty() {
+            // the last line might not end with a break of its own
+            if line_break.is_empty() {
+                out.push('\n');
+            }
+            out.push_str(&underline);
+            out.push_str(line_break);
+        }
+        line_start += line.len();
+    }
+
+    Ok(out)
+}
+
+/// How [`render_code_snippet`] marks the highlighted spans
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnippetStyle {
+    /// a row of `^` under the highlighted chars, like [`highlight_text_caret`]
+    Caret,
+    /// the highlighted text itself is styled, like [`highlight_text_ansi`]
+    Ansi(AnsiStyle),
+}
+
+/// Source lines touched by `highlights`, plus `context_lines` lines before and after each of
+/// them, with a gutter of 1-based line numbers right-aligned to the widest one printed, in the
+/// style of compiler diagnostics:
+///
+/// ```text
+/// 2 |     let x = 1;
+/// 3 |     x + z
+///   |         ^
+/// ```
+///
+/// Lines that are too far apart to share their context form separate blocks with a `...` row
+/// between them. Tabs count as 4 columns for [`SnippetStyle::Caret`].
+pub fn render_code_snippet<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    context_lines: usize,
+    style: SnippetStyle,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    validate_ranges(input, &highlights)?;
+    let highlights = merge_ranges(highlights.into_owned());
+
+    // byte range of every line without its line break
+    let mut line_start = 0;
+    let lines = input
+        .split_inclusive('\n')
+        .map(|line| {
+            let content = line.trim_end_matches('\n').trim_end_matches('\r');
+            let range = HighlightRange::new(line_start, line_start + content.len());
+            line_start += line.len();
+            range
+        })
+        .collect::<Vec<_>>();
+    let touched = lines
+        .iter()
+        .map(|line| !clip_ranges(&highlights, line).is_empty())
+        .collect::<Vec<_>>();
+
+    let mut visible = vec![false; lines.len()];
+    for (i, _) in touched.iter().enumerate().filter(|(_, touched)| **touched) {
+        let end = i.saturating_add(context_lines).saturating_add(1);
+        let shown = i.saturating_sub(context_lines)..end.min(lines.len());
+        visible[shown].fill(true);
+    }
+    let Some(last_visible) = visible.iter().rposition(|&v| v) else {
+        return Ok(String::new());
+    };
+    let width = (last_visible + 1).to_string().len();
+
+    let mut out = String::new();
+    let mut previous = None;
+    for (i, line) in lines.iter().enumerate().filter(|(i, _)| visible[*i]) {
+        if previous.is_some_and(|previous| previous + 1 < i) {
+            out.push_str("...\n");
+        }
+        previous = Some(i);
+
+        let content = &input[line.lower..line.upper];
+        let clipped = clip_ranges(&highlights, line);
+        let rendered = match style {
+            SnippetStyle::Ansi(style) if touched[i] => {
+                Cow::Owned(highlight_text_ansi(content, &clipped, style)?)
+            }
+            _ => Cow::Borrowed(content),
+        };
+        push_gutter_line(&mut out, &(i + 1).to_string(), width, &rendered);
+
+        if style == SnippetStyle::Caret && touched[i] {
+            let mut underline = String::new();
+            let mut underline_len = 0;
+            for (offset, c) in content.char_indices() {
+                let marker = if is_highlighted(&clipped, offset) {
+                    '^'
+                } else {
+                    ' '
+                };
+                let width = if c == '\t' { 4 } else { 1 };
+                underline.extend(core::iter::repeat_n(marker, width));
+                if marker == '^' {
+                    underline_len = underline.len();
+                }
+            }
+            underline.truncate(underline_len);
+            push_gutter_line(&mut out, "", width, &underline);
+        }
+    }
+
+    Ok(out)
+}
+
+/// `{number} | {text}`, without trailing whitespace for empty `text`
+fn push_gutter_line(out: &mut String, number: &str, width: usize, text: &str) {
+    out.push_str(&format!("{number:>width$} |"));
+    if !text.is_empty() {
+        out.push(' ');
+        out.push_str(text);
+    }
+    out.push('\n');
+}
+
+/// Same as [`highlight_text`], but renders BBCode, see [`HighlighterBuilder::bbcode`]
+pub fn highlight_text_bbcode<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    tag: &str,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    let highlighter = Highlighter::builder()
+        .bbcode(tag)
+        .build()
+        .expect("BBCode tags don't conflict with the default policies");
+    highlighter.highlight(input, &highlights)
+}
+
+/// Same as [`highlight_text`], but renders LaTeX: highlights are wrapped in `\hl{…}` (from the
+/// `soul`/`soulutf8` package) and the special characters of the text are escaped, see
+/// [`highlight_text_latex_with_macro`]
+pub fn highlight_text_latex<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+) -> Result<String, HighlightingError> {
+    highlight_text_latex_with_macro(input, highlights, "hl")
+}
+
+/// Same as [`highlight_text_latex`], but highlights are wrapped in `\{macro_name}{…}`; the macro
+/// name is inserted verbatim, only the text from `input` is escaped
+pub fn highlight_text_latex_with_macro<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    macro_name: &str,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    let highlighter = Highlighter {
+        open_tag: Arc::from(format!("\\{macro_name}{{")),
+        close_tag: Arc::from("}"),
+        escaping: Escaping::Latex,
+        ..Default::default()
+    };
+    highlighter.highlight(input, &highlights)
+}
+
+/// Attribute rejected by [`PangoAttrs::attr`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PangoAttrError {
+    /// the name is empty or has characters other than ASCII letters, digits, `_` and `-`
+    InvalidName(String),
+    /// the value contains a quote, `<`, `&` or a control character
+    InvalidValue { name: String, value: String },
+}
+
+impl fmt::Display for PangoAttrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PangoAttrError::InvalidName(name) => write!(f, "invalid Pango attribute name {name:?}"),
+            PangoAttrError::InvalidValue { name, value } => {
+                write!(f, "invalid value {value:?} for Pango attribute {name}")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PangoAttrError {}
+
+/// Attributes of the `<span>` a highlight is wrapped in by [`highlight_text_pango`], in insertion
+/// order. The default is `background="yellow" foreground="black"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PangoAttrs {
+    attrs: Vec<(String, String)>,
+}
+
+impl Default for PangoAttrs {
+    fn default() -> Self {
+        PangoAttrs {
+            attrs: vec![
+                ("background".to_string(), "yellow".to_string()),
+                ("foreground".to_string(), "black".to_string()),
+            ],
+        }
+    }
+}
+
+impl PangoAttrs {
+    /// No attributes, highlights are wrapped in a bare `<span>`
+    pub fn new() -> Self {
+        PangoAttrs { attrs: Vec::new() }
+    }
+
+    /// Sets attribute `name` to `value`, replacing an earlier value. Values are checked rather
+    /// than escaped, so a stray quote can't end the attribute and the markup stays well-formed.
+    pub fn attr(mut self, name: &str, value: &str) -> Result<Self, PangoAttrError> {
+        let valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'));
+        if !valid_name {
+            return Err(PangoAttrError::InvalidName(name.to_string()));
+        }
+        if value
+            .chars()
+            .any(|c| matches!(c, '"' | '\'' | '<' | '&') || c.is_control())
+        {
+            return Err(PangoAttrError::InvalidValue {
+                name: name.to_string(),
+                value: value.to_string(),
+            });
+        }
+
+        match self.attrs.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.attrs.push((name.to_string(), value.to_string())),
+        }
+        Ok(self)
+    }
+
+    fn open_tag(&self) -> String {
+        let mut tag = String::from("<span");
+        for (name, value) in &self.attrs {
+            tag.push(' ');
+            tag.push_str(name);
+            tag.push_str("=\"");
+            tag.push_str(value);
+            tag.push('"');
+        }
+        tag.push('>');
+        tag
+    }
+
+    fn highlighter(&self) -> Highlighter {
+        Highlighter {
+            open_tag: Arc::from(self.open_tag()),
+            close_tag: Arc::from("</span>"),
+            escaping: Escaping::Html,
+            ..Default::default()
+        }
+    }
+}
+
+/// Same as [`highlight_text`], but renders Pango markup for GTK: highlights are wrapped in a
+/// `<span>` with `attrs` and the text is escaped with the XML entities Pango understands
+pub fn highlight_text_pango<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    attrs: &PangoAttrs,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    attrs.highlighter().highlight(input, &highlights)
+}
+
+/// Same as [`highlight_text_labeled`], but renders Pango markup, every label with its attributes
+/// from `attrs`; labels without an entry get [`PangoAttrs::default`]
+#[cfg(feature = "std")]
+pub fn highlight_text_pango_labeled(
+    input: &str,
+    highlights: Vec<LabeledRange>,
+    attrs: &HashMap<String, PangoAttrs>,
+) -> Result<String, HighlightingError> {
+    let ranges = highlights
+        .iter()
+        .map(|h| h.range.clone())
+        .collect::<Vec<_>>();
+    validate_bounds(input, &ranges)?;
+
+    let highlights = merge_labeled_ranges(highlights)?;
+    let ranges = highlights
+        .iter()
+        .map(|h| h.range.clone())
+        .collect::<Vec<_>>();
+    let prepared = PreparedHighlights::from_sorted(input, &ranges)?;
+
+    let fallback = PangoAttrs::default().open_tag();
+    let tags = highlights
+        .iter()
+        .map(|h| match attrs.get(&h.label) {
+            Some(attrs) => Cow::Owned(attrs.open_tag()),
+            None => Cow::Borrowed(fallback.as_str()),
+        })
+        .collect::<Vec<_>>();
+
+    let highlighter = PangoAttrs::new().highlighter();
+    let window = 0..input.len();
+    Ok(highlighter.render_with(&prepared, window, |i| Cow::Borrowed(&*tags[i])))
+}
+
+/// Same as [`highlight_text`], but renders Markdown: highlights are wrapped in `delimiter` and the
+/// text is backslash-escaped so `*` or `_` in the input can't corrupt the emphasis.
+///
+/// CommonMark doesn't render `** foo **` as emphasis, so leading and trailing whitespace of a
+/// highlight is moved outside the delimiters; an all-whitespace highlight is dropped.
+pub fn highlight_text_markdown<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    delimiter: MarkdownDelimiter,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    validate_ranges(input, &highlights)?;
+
+    let trimmed = highlights
+        .iter()
+        .map(|h| {
+            let text = &input[h.lower..h.upper];
+            let leading = text.len() - text.trim_start().len();
+            let trailing = text.len() - text.trim_end().len();
+            if leading == text.len() {
+                HighlightRange::new(h.lower, h.lower)
+            } else {
+                HighlightRange::new(h.lower + leading, h.upper - trailing)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    highlight_with_renderer(input, &trimmed, MarkdownRenderer::new(delimiter))
+}
+
+/// Same as [`highlight_text`] for a batch of documents, see [`Highlighter::highlight_many`]
+pub fn highlight_many<'i>(
+    items: impl IntoIterator<Item = (&'i str, Vec<HighlightRange>)>,
+) -> Vec<Result<String, ContextError>> {
+    Highlighter::default().highlight_many(items)
+}
+
+/// Same as [`highlight_many`], but the items are highlighted in parallel
+#[cfg(feature = "rayon")]
+pub fn par_highlight_many<'i, I>(items: I) -> Vec<Result<String, ContextError>>
+where
+    I: rayon::iter::IntoParallelIterator<Item = (&'i str, Vec<HighlightRange>)>,
+    I::Iter: rayon::iter::IndexedParallelIterator,
+{
+    Highlighter::default().par_highlight_many(items)
+}
+
+/// Highlights the fields of a document with the same [`Highlighter`], except for fields given
+/// their own tags with [`DocumentHighlighter::field_tags`]
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct DocumentHighlighter<K> {
+    highlighter: Highlighter,
+    fields: HashMap<K, Highlighter>,
+}
+
+/// Field of a document that failed to highlight, see [`DocumentHighlighter::highlight`]
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentHighlightError<K> {
+    pub field: K,
+    pub error: HighlightingError,
+}
+
+#[cfg(feature = "std")]
+impl<K: fmt::Display> fmt::Display for DocumentHighlightError<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "field {}: {}", self.field, self.error)
+    }
+}
+
+/// The error with its field as [`ErrorContext::Field`], e.g. for `?` in a function returning
+/// [`ContextError`]
+#[cfg(feature = "std")]
+impl<K: fmt::Display> From<DocumentHighlightError<K>> for ContextError {
+    fn from(error: DocumentHighlightError<K>) -> Self {
+        error.error.with_context(ErrorContext::Field {
+            doc_id: None,
+            field: error.field.to_string(),
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: fmt::Debug + fmt::Display> std::error::Error for DocumentHighlightError<K> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: Eq + core::hash::Hash> DocumentHighlighter<K> {
+    pub fn new(highlighter: Highlighter) -> Self {
+        DocumentHighlighter {
+            highlighter,
+            fields: HashMap::new(),
+        }
+    }
+
+    /// render the highlights of `field` with `open_tag` and `close_tag`, e.g. `<strong>` for
+    /// titles; everything else is configured like the shared highlighter
+    pub fn field_tags(mut self, field: K, open_tag: &str, close_tag: &str) -> Self {
+        let highlighter = Highlighter {
+            open_tag: Arc::from(open_tag),
+            close_tag: Arc::from(close_tag),
+            ..self.highlighter.clone()
+        };
+        self.fields.insert(field, highlighter);
+        self
+    }
+
+    /// Highlights every `(field, text, ranges)` on its own; fields without anything to highlight
+    /// are borrowed like in [`Highlighter::highlight_cow`]. Stops at the first field that fails.
+    pub fn highlight<'a>(
+        &self,
+        fields: impl IntoIterator<Item = (K, &'a str, Vec<HighlightRange>)>,
+    ) -> Result<HashMap<K, Cow<'a, str>>, DocumentHighlightError<K>> {
+        fields
+            .into_iter()
+            .map(|(field, text, highlights)| {
+                let highlighter = self.fields.get(&field).unwrap_or(&self.highlighter);
+                match highlighter.highlight_cow(text, &highlights) {
+                    Ok(highlighted) => Ok((field, highlighted)),
+                    Err(error) => Err(DocumentHighlightError { field, error }),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Same as [`highlight_text`] for every field of a document, see
+/// [`DocumentHighlighter::highlight`]
+#[cfg(feature = "std")]
+pub fn highlight_document<'a, K: Eq + core::hash::Hash>(
+    fields: impl IntoIterator<Item = (K, &'a str, Vec<HighlightRange>)>,
+) -> Result<HashMap<K, Cow<'a, str>>, DocumentHighlightError<K>> {
+    DocumentHighlighter::new(Highlighter::default()).highlight(fields)
+}
+
+/// Same as [`highlight_text`], but never fails; see [`Highlighter::highlight_lossy`]
+pub fn highlight_text_lossy<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+) -> (String, Vec<SkippedRange>) {
+    let highlights = collect_ranges(highlights);
+    Highlighter::default().highlight_lossy(input, &highlights)
+}
+
+/// Same as [`highlight_text`] for bytes that are mostly UTF-8: every invalid sequence is replaced
+/// with U+FFFD, as by [`String::from_utf8_lossy`], and `highlights`, given as offsets into
+/// `input`, are moved along with the text. A range covering part of an invalid sequence covers
+/// its whole replacement; an offset inside a valid multi-byte char is still an error.
+pub fn highlight_bytes(
+    input: &[u8],
+    highlights: &[HighlightRange],
+) -> Result<String, HighlightingError> {
+    if let Some(h) = highlights.iter().find(|h| h.upper > input.len()) {
+        return Err(HighlightingError::RangesOutOfBounds {
+            range: h.clone(),
+            input_len: input.len(),
+        });
+    }
+
+    let mut text = String::with_capacity(input.len());
+    let mut ops = Vec::new();
+    // `(offset in input, valid text starting there)`
+    let mut valid_spans = Vec::new();
+    let mut offset = 0;
+    for chunk in input.utf8_chunks() {
+        let (valid, invalid) = (chunk.valid(), chunk.invalid());
+        text.push_str(valid);
+        ops.push(EditOp::Keep(valid.len()));
+        if !invalid.is_empty() {
+            text.push(char::REPLACEMENT_CHARACTER);
+            ops.push(EditOp::Replace {
+                from_len: invalid.len(),
+                to_len: char::REPLACEMENT_CHARACTER.len_utf8(),
+            });
+        }
+        valid_spans.push((offset, valid));
+        offset += valid.len() + invalid.len();
+    }
+
+    let splits_char = |offset: usize| {
+        let Some(idx) = valid_spans
+            .partition_point(|&(start, _)| start <= offset)
+            .checked_sub(1)
+        else {
+            return false;
+        };
+        let (start, valid) = valid_spans[idx];
+        offset - start < valid.len() && !valid.is_char_boundary(offset - start)
+    };
+    let mut offsets = highlights.iter().flat_map(|h| [h.lower, h.upper]);
+    if let Some(offset) = offsets.find(|&offset| splits_char(offset)) {
+        return Err(HighlightingError::NotACharBoundary(offset));
+    }
+    check_overlaps(highlights)?;
+
+    let map = OffsetMap::new(ops);
+    let mapped = highlights
+        .iter()
+        .map(|h| map.map_range(h.clone()).expect("checked to be in bounds"))
+        .collect::<Vec<_>>();
+    // two ranges sharing an invalid sequence both cover its replacement
+    if let Some((first, second)) = find_overlap(&mapped) {
+        return Err(HighlightingError::OverlappingRanges {
+            first: highlights[first].clone(),
+            second: highlights[second].clone(),
+        });
+    }
+
+    Highlighter::default().highlight(&text, &mapped)
+}
+
+/// Same as [`highlight_bytes`], but fails with [`HighlightingError::InvalidUtf8`] instead of
+/// replacing invalid sequences
+pub fn highlight_bytes_strict(
+    input: &[u8],
+    highlights: &[HighlightRange],
+) -> Result<String, HighlightingError> {
+    let input = core::str::from_utf8(input)
+        .map_err(|err| HighlightingError::InvalidUtf8(err.valid_up_to()))?;
+    Highlighter::default().highlight(input, highlights)
+}
+
+/// Same as [`highlight_text`], but returns `input` borrowed instead of copying it when there is
+/// nothing to highlight (no ranges, or only zero-length ones)
+pub fn highlight_text_cow<'i, 'r>(
+    input: &'i str,
+    highlights: impl IntoRanges<'r>,
+) -> Result<Cow<'i, str>, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    Highlighter::default().highlight_cow(input, &highlights)
+}
+
+/// Same as [`highlight_text`], but streams the output into `out` instead of building a `String`
+#[cfg(feature = "std")]
+pub fn highlight_text_to<W: io::Write>(
+    input: &str,
+    highlights: &[HighlightRange],
+    out: &mut W,
+) -> Result<(), HighlightError> {
+    Highlighter::default().highlight_to(input, highlights, out)
+}
+
+/// Same as [`highlight_text_to`], but for a [`fmt::Write`] sink
+pub fn highlight_text_to_fmt<W: fmt::Write>(
+    input: &str,
+    highlights: &[HighlightRange],
+    out: &mut W,
+) -> Result<(), HighlightError> {
+    Highlighter::default().highlight_to_fmt(input, highlights, out)
+}
+
+/// Splits `input` into contiguous, non-empty segments that cover it exactly and are flagged as
+/// highlighted or not, borrowing from `input` instead of rendering a string. The segments are in
+/// input order, so concatenating their texts gives back `input`.
+pub fn highlight_segments<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+) -> Result<Vec<Segment<'_>>, HighlightingError> {
+    Ok(validate(input, highlights)?.to_segments())
+}
+
+/// Iterator returned by [`iter_segments`]
+#[derive(Debug, Clone)]
+pub struct Segments<'i> {
+    prepared: PreparedHighlights<'i>,
+    /// the sorted ranges `front..back` haven't been emitted yet
+    front: usize,
+    back: usize,
+    /// the bytes `front_offset..back_offset` haven't been emitted yet
+    front_offset: usize,
+    back_offset: usize,
+}
+
+impl<'i> Segments<'i> {
+    fn segment(&self, lower: usize, upper: usize, highlighted: bool) -> Segment<'i> {
+        Segment {
+            text: &self.prepared.input[lower..upper],
+            range: HighlightRange::new(lower, upper),
+            highlighted,
+        }
+    }
+}
+
+impl<'i> Iterator for Segments<'i> {
+    type Item = Segment<'i>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sorted = &self.prepared.sorted;
+        while self.front < self.back && sorted[self.front].1.is_empty() {
+            self.front += 1;
+        }
+        if self.front_offset >= self.back_offset {
+            return None;
+        }
+
+        let (lower, upper, highlighted) = match sorted[self.front..self.back].first() {
+            Some((_, range)) if self.front_offset < range.lower => {
+                (self.front_offset, range.lower, false)
+            }
+            Some((_, range)) => {
+                self.front += 1;
+                (range.lower, range.upper, true)
+            }
+            None => (self.front_offset, self.back_offset, false),
+        };
+        self.front_offset = upper;
+        Some(self.segment(lower, upper, highlighted))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // every remaining range yields at most itself and the gap in front of it
+        let remaining = self.back - self.front;
+        let min = usize::from(self.front_offset < self.back_offset);
+        (min, Some(2 * remaining + 1))
+    }
+}
+
+impl DoubleEndedIterator for Segments<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let sorted = &self.prepared.sorted;
+        while self.front < self.back && sorted[self.back - 1].1.is_empty() {
+            self.back -= 1;
+        }
+        if self.front_offset >= self.back_offset {
+            return None;
+        }
+
+        let (lower, upper, highlighted) = match sorted[self.front..self.back].last() {
+            Some((_, range)) if range.upper < self.back_offset => {
+                (range.upper, self.back_offset, false)
+            }
+            Some((_, range)) => {
+                self.back -= 1;
+                (range.lower, range.upper, true)
+            }
+            None => (self.front_offset, self.back_offset, false),
+        };
+        self.back_offset = lower;
+        Some(self.segment(lower, upper, highlighted))
+    }
+}
+
+impl core::iter::FusedIterator for Segments<'_> {}
+
+/// Same segments as [`highlight_segments`], but produced lazily instead of collected into a
+/// `Vec`. The ranges are validated up front, so iterating can't fail; the iterator is
+/// double-ended.
+pub fn iter_segments<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+) -> Result<Segments<'_>, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    let prepared = PreparedHighlights::new(input, &highlights)?;
+
+    Ok(Segments {
+        back: prepared.sorted.len(),
+        back_offset: input.len(),
+        prepared,
+        front: 0,
+        front_offset: 0,
+    })
+}
+
+/// Renders `input` by handing every segment of [`highlight_segments`] to `render`, together
+/// with the index of the range it came from (`None` between highlights) and the output buffer,
+/// so the markup can depend on the text or on data kept alongside the ranges. It walks the
+/// segments the same way as [`highlight_with_renderer`] and [`Highlighter`] do.
+pub fn highlight_with<'r, F>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    render: F,
+) -> Result<String, HighlightingError>
+where
+    F: FnMut(&Segment<'_>, Option<usize>, &mut String),
+{
+    let highlights = collect_ranges(highlights);
+    let prepared = PreparedHighlights::new(input, &highlights)?;
+    let renderer = WithRenderer::new(input.len(), render);
+    Ok(prepared.render_through(false, |_| None, renderer))
+}
+
+/// [`Render`] behind [`highlight_with`], handing every segment to `render` with the index of
+/// its range
+struct WithRenderer<F> {
+    out: String,
+    render: F,
+    /// input offset of the next segment
+    offset: usize,
+    /// index of the range being rendered, between `start` and `end`
+    open: Option<usize>,
+}
+
+impl<F: FnMut(&Segment<'_>, Option<usize>, &mut String)> WithRenderer<F> {
+    fn new(capacity: usize, render: F) -> Self {
+        WithRenderer {
+            out: String::with_capacity(capacity),
+            render,
+            offset: 0,
+            open: None,
+        }
+    }
+}
+
+impl<F: FnMut(&Segment<'_>, Option<usize>, &mut String)> Render for WithRenderer<F> {
+    type Output = String;
+
+    fn text(&mut self, s: &str) {
+        let segment = Segment {
+            text: s,
+            range: HighlightRange::new(self.offset, self.offset + s.len()),
+            highlighted: self.open.is_some(),
+        };
+        (self.render)(&segment, self.open, &mut self.out);
+        self.offset = segment.range.upper;
+    }
+
+    fn start(&mut self, meta: &SegmentMeta<'_>) {
+        self.open = Some(meta.index);
+    }
+
+    fn end(&mut self, _: &SegmentMeta<'_>) {
+        self.open = None;
+    }
+
+    fn finish(self) -> String {
+        self.out
+    }
+}
+
+/// Where a highlight handed to [`Render::start`] and [`Render::end`] came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentMeta<'a> {
+    /// index of the range in the slice that was passed in
+    pub index: usize,
+    /// byte offsets of the highlighted text
+    pub range: HighlightRange,
+    /// set by [`highlight_labeled_with_renderer`]
+    pub label: Option<&'a str>,
+}
+
+/// Output format for [`highlight_with_renderer`], which calls `text` for every piece of the
+/// input in order and wraps the highlighted ones in `start` and `end`. Implement it to plug in a
+/// markup the crate doesn't know about; [`HtmlRenderer`], [`AnsiRenderer`] and
+/// [`MarkdownRenderer`] are the built-in ones, and [`Highlighter`] and [`highlight_with`] walk
+/// the segments the same way.
+pub trait Render {
+    type Output;
+
+    fn text(&mut self, s: &str);
+    fn start(&mut self, meta: &SegmentMeta<'_>);
+    fn end(&mut self, meta: &SegmentMeta<'_>);
+    fn finish(self) -> Self::Output;
+}
+
+/// Tags around the highlights and escaping of the text, `<em>` without escaping by default and
+/// the tags and escaping of a highlighter with [`Highlighter::renderer`]; a labeled range gets its
+/// label as the `class` of the open tag, see [`highlight_labeled_with_renderer`]
+#[derive(Debug, Clone, Default)]
+pub struct HtmlRenderer<'h> {
+    out: String,
+    markup: Markup<'h>,
+    /// set between `start` and `end`
+    highlighted: bool,
+    /// open tag of the labeled highlight being rendered
+    labeled_tag: Option<String>,
+}
+
+impl HtmlRenderer<'_> {
+    /// Same as the default, but the text is HTML-escaped like in [`highlight_text_escaped`]
+    pub fn escaped() -> Self {
+        HtmlRenderer::with_markup(Markup {
+            escaping: Escaping::Html,
+            ..Markup::default()
+        })
+    }
+}
+
+impl<'h> HtmlRenderer<'h> {
+    fn with_markup(markup: Markup<'h>) -> Self {
+        HtmlRenderer {
+            out: String::new(),
+            markup,
+            highlighted: false,
+            labeled_tag: None,
+        }
+    }
+}
+
+impl Render for HtmlRenderer<'_> {
+    type Output = String;
+
+    fn text(&mut self, s: &str) {
+        let markup = &self.markup;
+        let written = match self.highlighted {
+            true => {
+                let open = self.labeled_tag.as_deref().unwrap_or(&markup.open_tag);
+                markup.write_highlight(&mut self.out, open, &markup.close_tag, s)
+            }
+            false => markup.write_text(&mut self.out, s),
+        };
+        written.expect("writing into a String can't fail");
+    }
+
+    fn start(&mut self, meta: &SegmentMeta<'_>) {
+        self.highlighted = true;
+        self.labeled_tag = meta
+            .label
+            .map(|label| labeled_open_tag(&self.markup.open_tag, label));
+    }
+
+    fn end(&mut self, _: &SegmentMeta<'_>) {
+        self.highlighted = false;
+        self.labeled_tag = None;
+    }
+
+    fn finish(self) -> String {
+        self.out
+    }
+}
+
+/// ANSI SGR codes, see [`highlight_text_ansi`]
+#[derive(Debug, Clone)]
+pub struct AnsiRenderer {
+    renderer: HtmlRenderer<'static>,
+}
+
+impl AnsiRenderer {
+    pub fn new(style: AnsiStyle) -> Self {
+        let markup = Markup {
+            open_tag: Cow::Owned(style.escape_sequence()),
+            close_tag: Cow::Borrowed(ANSI_RESET),
+            ..Markup::default()
+        };
+        AnsiRenderer {
+            renderer: HtmlRenderer::with_markup(markup),
+        }
+    }
+
+    /// see [`HighlighterBuilder::bidi_isolate`]
+    pub fn bidi_isolate(mut self, isolate: bool) -> Self {
+        self.renderer.markup.bidi_isolate = isolate;
+        self
+    }
+}
+
+impl Render for AnsiRenderer {
+    type Output = String;
+
+    fn text(&mut self, s: &str) {
+        self.renderer.text(s);
+    }
+
+    fn start(&mut self, meta: &SegmentMeta<'_>) {
+        // labels have no place in an escape sequence
+        let meta = SegmentMeta {
+            label: None,
+            ..meta.clone()
+        };
+        self.renderer.start(&meta);
+    }
+
+    fn end(&mut self, meta: &SegmentMeta<'_>) {
+        self.renderer.end(meta);
+    }
+
+    fn finish(self) -> String {
+        self.renderer.finish()
+    }
+}
+
+/// Markdown delimiters with backslash-escaped text, see [`highlight_text_markdown`]
+#[derive(Debug, Clone)]
+pub struct MarkdownRenderer {
+    renderer: HtmlRenderer<'static>,
+}
+
+impl MarkdownRenderer {
+    pub fn new(delimiter: MarkdownDelimiter) -> Self {
+        let markup = Markup {
+            open_tag: Cow::Borrowed(delimiter.as_str()),
+            close_tag: Cow::Borrowed(delimiter.as_str()),
+            escaping: Escaping::Markdown {
+                equals: delimiter == MarkdownDelimiter::Mark,
+            },
+            ..Markup::default()
+        };
+        MarkdownRenderer {
+            renderer: HtmlRenderer::with_markup(markup),
+        }
+    }
+}
+
+impl Render for MarkdownRenderer {
+    type Output = String;
+
+    fn text(&mut self, s: &str) {
+        self.renderer.text(s);
+    }
+
+    fn start(&mut self, meta: &SegmentMeta<'_>) {
+        // delimiters have no attributes to carry a label
+        let meta = SegmentMeta {
+            label: None,
+            ..meta.clone()
+        };
+        self.renderer.start(&meta);
+    }
+
+    fn end(&mut self, meta: &SegmentMeta<'_>) {
+        self.renderer.end(meta);
+    }
+
+    fn finish(self) -> String {
+        self.renderer.finish()
+    }
+}
+
+/// Renders `input` into `renderer`, see [`Render`]; validated like [`highlight_text`] and
+/// zero-length ranges produce nothing
+pub fn highlight_with_renderer<R: Render>(
+    input: &str,
+    highlights: &[HighlightRange],
+    renderer: R,
+) -> Result<R::Output, HighlightingError> {
+    let prepared = PreparedHighlights::new(input, highlights)?;
+    Ok(prepared.render_through(false, |_| None, renderer))
+}
+
+/// Same as [`highlight_with_renderer`], but every [`SegmentMeta`] carries the label of its range;
+/// ranges with different labels still must not overlap
+pub fn highlight_labeled_with_renderer<R: Render>(
+    input: &str,
+    highlights: &[LabeledRange],
+    renderer: R,
+) -> Result<R::Output, HighlightingError> {
+    let ranges = highlights
+        .iter()
+        .map(|h| h.range.clone())
+        .collect::<Vec<_>>();
+    let prepared = PreparedHighlights::new(input, &ranges)?;
+    let label = |i: usize| Some(highlights[i].label.as_str());
+    Ok(prepared.render_through(false, label, renderer))
+}
+
+/// The walk every renderer runs on, the built-in ones, [`highlight_with`] and [`Highlighter`]:
+/// hands the segments of `input[window]` to `renderer` in order, wrapping the highlighted ones in
+/// [`Render::start`] and [`Render::end`]. `sorted` and `keep_empty` as for [`walk_segments`],
+/// `label` gives the label of the range with an index.
+fn render_segments<'r, 'l, R: Render>(
+    input: &str,
+    window: Range<usize>,
+    sorted: impl Iterator<Item = (usize, &'r HighlightRange)>,
+    keep_empty: bool,
+    label: impl Fn(usize) -> Option<&'l str>,
+    mut renderer: R,
+) -> R::Output {
+    let walked = walk_segments(input, window, sorted, keep_empty, |segment, range_idx| {
+        match range_idx {
+            Some(index) => {
+                let meta = SegmentMeta {
+                    index,
+                    range: segment.range,
+                    label: label(index),
+                };
+                renderer.start(&meta);
+                renderer.text(segment.text);
+                renderer.end(&meta);
+            }
+            None => renderer.text(segment.text),
+        }
+        Ok::<(), Infallible>(())
+    });
+
+    match walked {
+        Ok(()) => renderer.finish(),
+    }
+}
+
+/// [`Render`] behind [`Highlighter::render_with`], writing every highlight with the tags `tags`
+/// picks by the index of its range
+struct TagWriter<'m, 'w, 'a, W, T> {
+    markup: Markup<'m>,
+    out: &'w mut W,
+    tags: T,
+    /// tags of the highlight being written, between `start` and `end`
+    open: Option<(Cow<'a, str>, Cow<'a, str>)>,
+    /// the first error of `out`, nothing is written after it
+    result: fmt::Result,
+}
+
+impl<'m, 'w, 'a, W, T> TagWriter<'m, 'w, 'a, W, T>
+where
+    W: fmt::Write,
+    T: Fn(usize) -> (Cow<'a, str>, Cow<'a, str>),
+{
+    fn new(markup: Markup<'m>, out: &'w mut W, tags: T) -> Self {
+        TagWriter {
+            markup,
+            out,
+            tags,
+            open: None,
+            result: Ok(()),
+        }
+    }
+}
+
+impl<'a, W, T> Render for TagWriter<'_, '_, 'a, W, T>
+where
+    W: fmt::Write,
+    T: Fn(usize) -> (Cow<'a, str>, Cow<'a, str>),
+{
+    type Output = fmt::Result;
+
+    fn text(&mut self, s: &str) {
+        if self.result.is_err() {
+            return;
+        }
+        self.result = match &self.open {
+            Some((open, close)) => self.markup.write_highlight(self.out, open, close, s),
+            None => self.markup.write_text(self.out, s),
+        };
+    }
+
+    fn start(&mut self, meta: &SegmentMeta<'_>) {
+        self.open = Some((self.tags)(meta.index));
+    }
+
+    fn end(&mut self, _: &SegmentMeta<'_>) {
+        self.open = None;
+    }
+
+    fn finish(self) -> fmt::Result {
+        self.result
+    }
+}
+
+/// Output format of [`highlight_text_template`]: literal text with `{text}` (the segment),
+/// `{index}` (0-based index of the highlight in document order), `{start}` and `{end}` (byte
+/// offsets of the segment) placeholders; `{{` and `}}` stand for literal braces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<TemplatePart>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TemplatePart {
+    Literal(String),
+    Text,
+    Index,
+    Start,
+    End,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self, HighlightingError> {
+        Template::parse_with(template, true)
+    }
+
+    fn parse_with(template: &str, allow_index: bool) -> Result<Self, HighlightingError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = template.char_indices().peekable();
+        while let Some((offset, c)) = rest.next() {
+            match c {
+                '{' | '}' if rest.next_if(|&(_, next)| next == c).is_some() => literal.push(c),
+                '{' => {
+                    let name_start = offset + 1;
+                    let Some(name_len) = template[name_start..].find(['{', '}']) else {
+                        return Err(HighlightingError::UnmatchedBrace(offset));
+                    };
+                    let name = &template[name_start..name_start + name_len];
+                    if !template[name_start + name_len..].starts_with('}') {
+                        return Err(HighlightingError::UnmatchedBrace(offset));
+                    }
+                    let part = match name {
+                        "text" => TemplatePart::Text,
+                        "index" if allow_index => TemplatePart::Index,
+                        "start" => TemplatePart::Start,
+                        "end" => TemplatePart::End,
+                        _ => {
+                            let name = HighlightRange::new(name_start, name_start + name_len);
+                            return Err(HighlightingError::UnknownPlaceholder(name));
+                        }
+                    };
+                    if !literal.is_empty() {
+                        parts.push(TemplatePart::Literal(core::mem::take(&mut literal)));
+                    }
+                    parts.push(part);
+                    // skip the name and the closing brace
+                    while rest.next_if(|&(i, _)| i <= name_start + name_len).is_some() {}
+                }
+                '}' => return Err(HighlightingError::UnmatchedBrace(offset)),
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(TemplatePart::Literal(literal));
+        }
+
+        Ok(Template { parts })
+    }
+
+    fn render(&self, out: &mut String, segment: &Segment<'_>, index: Option<usize>) {
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(literal) => out.push_str(literal),
+                TemplatePart::Text => out.push_str(segment.text),
+                TemplatePart::Index => {
+                    out.push_str(&index.map(|i| i.to_string()).unwrap_or_default())
+                }
+                TemplatePart::Start => out.push_str(&segment.range.lower.to_string()),
+                TemplatePart::End => out.push_str(&segment.range.upper.to_string()),
+            }
+        }
+    }
+}
+
+impl FromStr for Template {
+    type Err = HighlightingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Template::parse(s)
+    }
+}
+
+/// Same as [`highlight_text`], but every highlight is rendered through `template` instead of
+/// being wrapped in tags, e.g. `<a href="#m{index}">{text}</a>`; see [`Template`]. The text in
+/// between is passed through unchanged and nothing is escaped.
+pub fn highlight_text_template<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    template: &str,
+) -> Result<String, HighlightingError> {
+    highlight_text_templates(input, highlights, template, "{text}")
+}
+
+/// Same as [`highlight_text_template`], but the text between highlights is rendered through
+/// `plain`, which can't use `{index}`
+pub fn highlight_text_templates<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+    highlighted: &str,
+    plain: &str,
+) -> Result<String, HighlightingError> {
+    let highlighted = Template::parse(highlighted)?;
+    let plain = Template::parse_with(plain, false)?;
+
+    let mut highlights = collect_ranges(highlights).into_owned();
+    validate_ranges(input, &highlights)?;
+    sort_ranges(&mut highlights);
+
+    highlight_with(
+        input,
+        &highlights,
+        |segment, range_idx, out| match range_idx {
+            Some(_) => highlighted.render(out, segment, range_idx),
+            None => plain.render(out, segment, None),
+        },
+    )
+}
+
+/// Sweeps over the offsets where the possibly overlapping, bounds-checked `highlights` start or
+/// end while keeping a stack of the open ones, and calls `f` with the text in between and the
+/// index of every range that is opened or closed, so that the highlights nest properly: where
+/// ranges only partially overlap, the inner one is closed and reopened. Ranges sharing an offset
+/// are opened and closed in [`Boundary`] order, so the events don't depend on the order of
+/// `highlights`; zero-length ranges are opened and closed right away.
+///
+/// At most `max_depth` (at least 1) ranges are open at any offset, deeper ones are flattened into
+/// the innermost open one and get no events while they are that deep. Returns every flattened
+/// range the first time it was flattened, paired with the range it was flattened into.
+fn sweep_nested<'a, 'l, E>(
+    input: &'a str,
+    highlights: &[HighlightRange],
+    label: impl Fn(usize) -> Option<&'l str>,
+    max_depth: usize,
+    mut f: impl FnMut(HighlightEvent<'a>) -> Result<(), E>,
+) -> Result<Vec<(usize, usize)>, E> {
+    let max_depth = max_depth.max(1);
+    let mut flattened = Vec::new();
+    let mut was_flattened = vec![false; highlights.len()];
+    let mut flatten = |b: &Boundary, open: &[Boundary]| {
+        if !core::mem::replace(&mut was_flattened[b.index], true) {
+            flattened.push((b.index, open[max_depth - 1].index));
+        }
+    };
+
+    // ends are found through the open ranges, so only the starts are sorted
+    let mut starts = highlights
+        .iter()
+        .enumerate()
+        .map(|(i, h)| Boundary::start(h, label(i), i))
+        .collect::<Vec<_>>();
+    starts.sort_unstable();
+    let mut pending = starts.into_iter().peekable();
+
+    // start boundaries of the open ranges, innermost last
+    let mut open: Vec<Boundary<'l>> = Vec::new();
+    let mut offset = 0;
+    loop {
+        let next_close = open.iter().map(|b| b.other).min();
+        let next_open = pending.peek().map(|b| b.offset);
+        let Some(pos) = next_close.into_iter().chain(next_open).min() else {
+            break;
+        };
+        f(HighlightEvent::Text(&input[offset..pos]))?;
+        offset = pos;
+
+        if let Some(depth) = open.iter().position(|b| b.other == pos) {
+            // everything inside the outermost range ending here has to be closed with it,
+            // ranges that go on are reopened as if they started here
+            for b in open[depth..]
+                .iter()
+                .take(max_depth.saturating_sub(depth))
+                .rev()
+            {
+                f(HighlightEvent::End(b.index))?;
+            }
+            let mut continuing = open.split_off(depth);
+            continuing.retain(|b| b.other != pos);
+            continuing.iter_mut().for_each(|b| b.offset = pos);
+            continuing.sort_unstable();
+            for b in continuing {
+                if open.len() < max_depth {
+                    f(HighlightEvent::Start(b.index))?;
+                } else {
+                    flatten(&b, &open);
+                }
+                open.push(b);
+            }
+        }
+
+        while let Some(b) = pending.next_if(|b| b.offset == pos) {
+            if open.len() >= max_depth {
+                flatten(&b, &open);
+            } else {
+                f(HighlightEvent::Start(b.index))?;
+                if b.kind == BoundaryKind::Empty {
+                    f(HighlightEvent::End(b.index))?;
+                }
+            }
+            if b.kind != BoundaryKind::Empty {
+                open.push(b);
+            }
+        }
+    }
+
+    f(HighlightEvent::Text(&input[offset..]))?;
+    Ok(flattened)
+}
+
+/// Node of the tree returned by [`highlight_tree`], e.g. for rendering into a UI framework
+/// without going through a string
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node<'a> {
+    /// never empty
+    Text(&'a str),
+    Highlight {
+        /// set by [`highlight_tree_labeled`]
+        label: Option<&'a str>,
+        children: Vec<Node<'a>>,
+    },
+}
+
+/// `input` as a tree of text and highlight nodes; concatenating the text leaves in order gives
+/// `input` back. Overlapping ranges nest like with [`OverlapPolicy::Nest`]: a range partially
+/// overlapping another one is split into one highlight inside it and one after it. Zero-length
+/// ranges are dropped.
+pub fn highlight_tree<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+) -> Result<Vec<Node<'_>>, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    validate_bounds(input, &highlights)?;
+
+    let highlights = highlights
+        .iter()
+        .filter(|h| !h.is_empty())
+        .cloned()
+        .collect::<Vec<_>>();
+    Ok(build_tree(input, &highlights, |_| None))
+}
+
+/// Same as [`highlight_tree`], but every highlight node carries the label of its range
+pub fn highlight_tree_labeled<'a>(
+    input: &'a str,
+    highlights: &'a [LabeledRange],
+) -> Result<Vec<Node<'a>>, HighlightingError> {
+    let (ranges, labels): (Vec<_>, Vec<_>) = highlights
+        .iter()
+        .filter(|h| !h.range.is_empty())
+        .map(|h| (h.range.clone(), h.label.as_str()))
+        .unzip();
+    validate_bounds(input, &ranges)?;
+
+    Ok(build_tree(input, &ranges, |i| Some(labels[i])))
+}
+
+/// The tree of [`sweep_nested`]
+fn build_tree<'a>(
+    input: &'a str,
+    highlights: &[HighlightRange],
+    label: impl Fn(usize) -> Option<&'a str>,
+) -> Vec<Node<'a>> {
+    // children of the open highlights, the root last closed
+    let mut stack: Vec<Vec<Node<'a>>> = vec![Vec::new()];
+    let swept = sweep_nested(input, highlights, &label, usize::MAX, |event| {
+        match event {
+            HighlightEvent::Text("") => {}
+            HighlightEvent::Text(text) => stack.last_mut().unwrap().push(Node::Text(text)),
+            HighlightEvent::Start(_) => stack.push(Vec::new()),
+            HighlightEvent::End(i) => {
+                let children = stack.pop().unwrap();
+                let node = Node::Highlight {
+                    label: label(i),
+                    children,
+                };
+                stack.last_mut().unwrap().push(node);
+            }
+        }
+        Ok::<(), Infallible>(())
+    });
+
+    match swept {
+        Ok(_) => stack.pop().unwrap(),
+    }
+}
+
+/// Same as [`highlight_segments`], but every highlighted segment carries the payload of its
+/// range. There's no way to merge payloads, so overlapping ranges are always rejected.
+pub fn highlight_segments_annotated<'a, T>(
+    input: &'a str,
+    highlights: &'a [AnnotatedRange<T>],
+) -> Result<Vec<AnnotatedSegment<'a, T>>, HighlightingError> {
+    let ranges = highlights
+        .iter()
+        .map(|h| h.range.clone())
+        .collect::<Vec<_>>();
+    let prepared = PreparedHighlights::new(input, &ranges)?;
+
+    let mut segments = Vec::with_capacity(ranges.len() * 2 + 1);
+    let walked = prepared.walk(0..input.len(), false, |segment, range_idx| {
+        let payload = range_idx.map(|i| &highlights[i].payload);
+        segments.push(AnnotatedSegment { segment, payload });
+        Ok::<(), Infallible>(())
+    });
+
+    match walked {
+        Ok(()) => Ok(segments),
+    }
+}
+
+/// Piece of highlighted text, see [`highlight_events`]; `T` identifies the range a highlight
+/// came from, its index by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HighlightEvent<'a, T = usize> {
+    Text(&'a str),
+    Start(T),
+    End(T),
+}
+
+/// Iterator returned by [`highlight_events`]
+#[derive(Debug, Clone)]
+pub struct HighlightEvents<'i> {
+    prepared: PreparedHighlights<'i>,
+    /// index into the sorted ranges of the next range to open
+    next: usize,
+    /// end of the text emitted so far
+    offset: usize,
+    /// `(range index, upper)` of the open highlight
+    open: Option<(usize, usize)>,
+}
+
+impl<'i> Iterator for HighlightEvents<'i> {
+    type Item = HighlightEvent<'i>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let input = self.prepared.input;
+
+        if let Some((i, upper)) = self.open {
+            if self.offset < upper {
+                let text = &input[self.offset..upper];
+                self.offset = upper;
+                return Some(HighlightEvent::Text(text));
+            }
+            self.open = None;
+            return Some(HighlightEvent::End(i));
+        }
+
+        // zero-length ranges are dropped like in `highlight_text`
+        while let Some((_, range)) = self.prepared.sorted.get(self.next) {
+            if range.is_empty() {
+                self.next += 1;
+                continue;
+            }
+            if self.offset < range.lower {
+                let text = &input[self.offset..range.lower];
+                self.offset = range.lower;
+                return Some(HighlightEvent::Text(text));
+            }
+            let (i, range) = &self.prepared.sorted[self.next];
+            self.next += 1;
+            self.open = Some((*i, range.upper));
+            return Some(HighlightEvent::Start(*i));
+        }
+
+        if self.offset < input.len() {
+            let text = &input[self.offset..];
+            self.offset = input.len();
+            return Some(HighlightEvent::Text(text));
+        }
+        None
+    }
+}
+
+/// The highlighted `input` as a lazy stream of events, e.g. for a renderer that already
+/// consumes an event model: non-empty [`HighlightEvent::Text`] covering the input in order,
+/// with every highlight between a `Start` and an `End` carrying the index of its range.
+/// Concatenating the texts gives back `input`; writing tags for the `Start` and `End` events
+/// gives the output of [`highlight_text`].
+pub fn highlight_events<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+) -> Result<HighlightEvents<'_>, HighlightingError> {
+    Ok(validate(input, highlights)?.to_events())
+}
+
+/// Same as [`highlight_events`], but `Start` and `End` carry the payload of their range
+pub fn highlight_events_annotated<'a, T>(
+    input: &'a str,
+    highlights: &'a [AnnotatedRange<T>],
+) -> Result<impl Iterator<Item = HighlightEvent<'a, &'a T>>, HighlightingError> {
+    let events = highlight_events(input, highlights.iter().map(|h| h.range.clone()))?;
+
+    Ok(events.map(|event| match event {
+        HighlightEvent::Text(text) => HighlightEvent::Text(text),
+        HighlightEvent::Start(i) => HighlightEvent::Start(&highlights[i].payload),
+        HighlightEvent::End(i) => HighlightEvent::End(&highlights[i].payload),
+    }))
+}
+
+/// Renders only `input[window]`, e.g. the visible part of a large buffer, with `highlights`
+/// given against the whole `input`: they are validated against it, then clipped to the window
+/// like [`clip_ranges`] does, so a highlight crossing either end of the window is opened at its
+/// start or closed at its end. `window` has to be in bounds and on char boundaries.
+pub fn highlight_window(
+    input: &str,
+    window: Range<usize>,
+    highlights: &[HighlightRange],
+) -> Result<String, HighlightingError> {
+    PreparedHighlights::new(input, highlights)?.render_window(window)
+}
+
+/// Highlights a fragment of at most `max_len` chars (plus ellipses) around the first highlight,
+/// fitting as many of the following highlights as possible. The fragment is widened evenly to
+/// both sides and cut between words where possible; a `…` marks every cut. A first highlight
+/// longer than `max_len` is truncated.
+pub fn highlight_snippet(
+    input: &str,
+    highlights: &[HighlightRange],
+    max_len: usize,
+) -> Result<String, HighlightingError> {
+    validate_ranges(input, highlights)?;
+
+    let sorted = sorted_non_empty(highlights);
+    let chars = CharOffsets::new(input);
+    let window = if sorted.is_empty() {
+        let end = chars.byte_offset(max_len.min(chars.char_count()));
+        cut_between_words(input, 0..end, 0..0)
+    } else {
+        fragment_window(input, &chars, &sorted, max_len, 0)
+    };
+
+    let mut out = String::new();
+    if window.lower > 0 {
+        out.push('…');
+    }
+    out.push_str(&build_fragment(input, &sorted, window.clone()).text);
+    if window.upper < input.len() {
+        out.push('…');
+    }
+
+    Ok(out)
+}
+
+/// The window of `input` chosen by `options` rendered like [`highlight_text`] and as plain text,
+/// see [`Highlighter::highlight_both`]
+pub fn highlight_both<'i>(
+    input: &'i str,
+    highlights: &[HighlightRange],
+    options: &BothOptions,
+) -> Result<BothOutputs<'i>, HighlightingError> {
+    Highlighter::default().highlight_both(input, highlights, options)
+}
+
+/// Same as [`highlight_text`], but only the first `max_visible_chars` chars of `input` are
+/// rendered, followed by `…` if anything was cut. Tags don't count towards the limit, a highlight
+/// reaching past the cut is closed at it and later ones are dropped.
+pub fn highlight_text_truncated(
+    input: &str,
+    highlights: &[HighlightRange],
+    max_visible_chars: usize,
+) -> Result<String, HighlightingError> {
+    Highlighter::default().highlight_truncated(input, highlights, max_visible_chars)
+}
+
+/// Highlighted piece of an input, see [`fragments`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fragment {
+    /// byte range of the fragment within the input
+    pub range: HighlightRange,
+    /// the fragment rendered like [`highlight_text`], without ellipses
+    pub text: String,
+    /// number of highlights shown in the fragment, including cut off ones
+    pub highlight_count: usize,
+    /// bytes of the fragment that are highlighted
+    pub highlighted_bytes: usize,
+}
+
+/// Up to `max_fragments` non-overlapping fragments of at most `fragment_len` chars each, in
+/// document order. Every fragment is built like [`highlight_snippet`] around the first highlight
+/// that isn't shown by an earlier fragment, so highlights close to each other end up in the same
+/// fragment. Highlights cut off by the end of a fragment aren't continued in the next one.
+pub fn fragments(
+    input: &str,
+    highlights: &[HighlightRange],
+    fragment_len: usize,
+    max_fragments: usize,
+) -> Result<Vec<Fragment>, HighlightingError> {
+    validate_ranges(input, highlights)?;
+    if fragment_len == 0 {
+        return Ok(Vec::new());
+    }
+
+    let sorted = sorted_non_empty(highlights);
+    let chars = CharOffsets::new(input);
+    let mut fragments = Vec::new();
+    let mut rest = &sorted[..];
+    let mut min_start = 0;
+    while !rest.is_empty() && fragments.len() < max_fragments {
+        let window = fragment_window(input, &chars, rest, fragment_len, min_start);
+        let shown = rest.partition_point(|h| h.lower < window.upper);
+
+        fragments.push(build_fragment(input, &rest[..shown], window.clone()));
+        rest = &rest[shown..];
+        min_start = window.upper;
+    }
+
+    Ok(fragments)
+}
+
+/// Same as [`fragments`], but joined by `separator` (e.g. `" … "`)
+pub fn highlight_fragments(
+    input: &str,
+    highlights: &[HighlightRange],
+    fragment_len: usize,
+    max_fragments: usize,
+    separator: &str,
+) -> Result<String, HighlightingError> {
+    let fragments = fragments(input, highlights, fragment_len, max_fragments)?;
+    let texts = fragments
+        .iter()
+        .map(|f| f.text.as_str())
+        .collect::<Vec<_>>();
+
+    Ok(texts.join(separator))
+}
+
+/// The [`fragments`] candidate with the most highlights, then the most highlighted bytes; of
+/// equally good candidates the earliest one. `Ok(None)` if there are no highlights.
+pub fn best_fragment(
+    input: &str,
+    highlights: &[HighlightRange],
+    fragment_len: usize,
+) -> Result<Option<Fragment>, HighlightingError> {
+    best_fragment_by(input, highlights, fragment_len, |f| {
+        (f.highlight_count, f.highlighted_bytes)
+    })
+}
+
+/// Same as [`best_fragment`], but the candidate with the greatest `score` wins; ties still go to
+/// the earliest candidate. There is one candidate per highlight, built around it like
+/// [`highlight_snippet`] does.
+pub fn best_fragment_by<S: Ord>(
+    input: &str,
+    highlights: &[HighlightRange],
+    fragment_len: usize,
+    score: impl Fn(&Fragment) -> S,
+) -> Result<Option<Fragment>, HighlightingError> {
+    validate_ranges(input, highlights)?;
+
+    let sorted = sorted_non_empty(highlights);
+    let chars = CharOffsets::new(input);
+    let mut best: Option<(S, Fragment)> = None;
+    for anchor in 0..sorted.len() {
+        let window = fragment_window(input, &chars, &sorted[anchor..], fragment_len, 0);
+        let shown = ranges_in(&sorted, window.lower..window.upper);
+        let candidate = build_fragment(input, shown, window);
+
+        let candidate_score = score(&candidate);
+        // strictly greater, so the earliest of equal candidates is kept
+        if best
+            .as_ref()
+            .is_none_or(|(best_score, _)| candidate_score > *best_score)
+        {
+            best = Some((candidate_score, candidate));
+        }
+    }
+
+    Ok(best.map(|(_, fragment)| fragment))
+}
+
+fn sorted_non_empty(highlights: &[HighlightRange]) -> Vec<HighlightRange> {
+    let mut sorted = highlights
+        .iter()
+        .filter(|h| h.lower != h.upper)
+        .cloned()
+        .collect::<Vec<_>>();
+    sort_ranges(&mut sorted);
+    sorted
+}
+
+/// Byte offset of every char of an input, plus its length
+struct CharOffsets(Vec<usize>);
+
+impl CharOffsets {
+    fn new(input: &str) -> Self {
+        let offsets = input.char_indices().map(|(offset, _)| offset);
+        CharOffsets(offsets.chain(core::iter::once(input.len())).collect())
+    }
+
+    fn char_count(&self) -> usize {
+        self.0.len() - 1
+    }
+
+    /// `byte` has to be a char boundary
+    fn char_idx(&self, byte: usize) -> usize {
+        self.0.partition_point(|&offset| offset < byte)
+    }
+
+    fn byte_offset(&self, char_idx: usize) -> usize {
+        self.0[char_idx]
+    }
+}
+
+/// Byte range of at most `max_len` chars that doesn't start before byte `min_start`, around the
+/// cluster of sorted, non-empty `highlights` beginning with the first of them (which has to start
+/// at or after `min_start`)
+fn fragment_window(
+    input: &str,
+    chars: &CharOffsets,
+    highlights: &[HighlightRange],
+    max_len: usize,
+    min_start: usize,
+) -> HighlightRange {
+    let first = &highlights[0];
+    let lower = chars.char_idx(first.lower);
+    let mut upper = chars.char_idx(first.upper);
+    if upper - lower >= max_len {
+        return HighlightRange::new(first.lower, chars.byte_offset(lower + max_len));
+    }
+    for h in &highlights[1..] {
+        let next_upper = chars.char_idx(h.upper);
+        if next_upper - lower > max_len {
+            break;
+        }
+        upper = next_upper;
+    }
+
+    let char_count = chars.char_count();
+    let padding = max_len - (upper - lower);
+    let start = lower
+        .saturating_sub(padding / 2)
+        .min(char_count.saturating_sub(max_len))
+        .max(chars.char_idx(min_start));
+    let end = start.saturating_add(max_len).min(char_count);
+    let window = chars.byte_offset(start)..chars.byte_offset(end);
+    let covered = first.lower..chars.byte_offset(upper);
+
+    cut_between_words(input, window, covered)
+}
+
+/// Narrows the ends of `window` that are inside `input` so they don't cut through a word, start
+/// with the punctuation or whitespace following a cut word, or end with whitespace; without
+/// narrowing it past `covered` or down to nothing
+fn cut_between_words(input: &str, window: Range<usize>, covered: Range<usize>) -> HighlightRange {
+    let Range { mut start, mut end } = window;
+    let is_word_char = char::is_alphanumeric;
+
+    if start > 0 {
+        if inside_word(input, start, is_word_char) {
+            start += word_len_after(input, start, is_word_char);
+        }
+        start = start.min(covered.start);
+        let skipped = input[start..covered.start].trim_start_matches(|c| !is_word_char(c));
+        start = covered.start - skipped.len();
+    }
+    if end < input.len() {
+        let mut cut = end;
+        if inside_word(input, cut, is_word_char) {
+            cut -= word_len_before(input, cut, is_word_char);
+        }
+        let cut = cut.max(covered.end);
+        let cut = cut - (input[covered.end..cut].len() - input[covered.end..cut].trim_end().len());
+        if cut > start {
+            end = cut;
+        }
+    }
+
+    HighlightRange::new(start, end)
+}
+
+/// [`Fragment`] of `&input[window]` with the `highlights` clipped to it
+fn build_fragment(input: &str, highlights: &[HighlightRange], window: HighlightRange) -> Fragment {
+    let clipped = clip_ranges(highlights, &window);
+    let text = Highlighter::default()
+        .highlight(&input[window.lower..window.upper], &clipped)
+        .expect("clipped ranges stay valid");
+
+    Fragment {
+        range: window,
+        text,
+        highlight_count: clipped.len(),
+        highlighted_bytes: clipped.iter().map(|h| h.upper - h.lower).sum(),
+    }
+}
+
+/// Same as [`highlight_text`] with the byte ranges of [`word_ranges_to_byte_ranges`]
+pub fn highlight_words(
+    input: &str,
+    word_ranges: &[HighlightRange],
+) -> Result<String, HighlightingError> {
+    let byte_ranges = word_ranges_to_byte_ranges(input, word_ranges)?;
+    Highlighter::default().highlight(input, &byte_ranges)
+}
+
+/// The inverse of [`from_brackets`]: `text` with every highlight put in `[` and `]` and literal
+/// brackets and backslashes escaped, e.g. for snapshot tests
+pub fn to_marked<'r>(
+    text: &str,
+    highlights: impl IntoRanges<'r>,
+) -> Result<String, HighlightingError> {
+    to_marked_with(text, highlights, '[', ']')
+}
+
+/// The inverse of [`from_marked`], see [`to_marked`]
+pub fn to_marked_with<'r>(
+    text: &str,
+    highlights: impl IntoRanges<'r>,
+    open: char,
+    close: char,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    let prepared = PreparedHighlights::new(text, &highlights)?;
+
+    let escape = |out: &mut String, text: &str| {
+        for c in text.chars() {
+            if c == open || c == close || c == '\\' {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+    };
+    let capacity = text.len() + highlights.len() * 2;
+    let renderer = WithRenderer::new(capacity, |segment, range_idx, out| match range_idx {
+        Some(_) => {
+            out.push(open);
+            escape(out, segment.text);
+            out.push(close);
+        }
+        None => escape(out, segment.text),
+    });
+    Ok(prepared.render_through(true, |_| None, renderer))
+}
+
+/// Same as [`highlight_text`], but lower/upper of each range are char indices instead of byte offsets
+pub fn highlight_text_chars<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    validate_ranges_as(input, &highlights, IndexKind::Chars)?;
+    highlight_text(input, char_ranges_to_byte_ranges(input, &highlights)?)
+}
+
+/// Same as [`highlight_text`], but lower/upper of each range are UTF-16 code-unit indices (as
+/// reported by JavaScript or Elasticsearch) instead of byte offsets
+pub fn highlight_text_utf16<'r>(
+    input: &str,
+    highlights: impl IntoRanges<'r>,
+) -> Result<String, HighlightingError> {
+    let highlights = collect_ranges(highlights);
+    check_overlaps(&highlights)?;
+    highlight_text(input, utf16_ranges_to_byte_ranges(input, &highlights)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{search::*, test_support::*, testing::*};
+    use std::collections::{BTreeSet, HashSet};
+
+    #[test]
+    fn should_hightlight() {
+        let actual = highlight_text("Hello world", vec![HighlightRange::new(0, 5)]).unwrap();
+
+        assert_eq!("<em>Hello</em> world", actual);
+    }
+
+    #[test]
+    fn should_hightlight_multiple_ranges() {
+        let actual = highlight_text(
+            "Hello world",
+            vec![HighlightRange::new(0, 5), HighlightRange::new(6, 11)],
+        )
+        .unwrap();
+
+        assert_eq!("<em>Hello</em> <em>world</em>", actual);
+    }
+
+    #[test]
+    fn should_hightlight_multiple_ranges_when_ranges_touch() {
+        let actual = highlight_text(
+            "Hello world",
+            vec![HighlightRange::new(0, 5), HighlightRange::new(5, 11)],
+        )
+        .unwrap();
+
+        assert_eq!("<em>Hello</em><em> world</em>", actual);
+    }
+
+    #[test]
+    fn should_return_err_invalid_ranges() {
+        let actual = highlight_text(
+            "Hello world",
+            vec![HighlightRange::new(0, 5), HighlightRange::new(4, 11)],
+        );
+
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 5),
+                second: HighlightRange::new(4, 11)
+            }),
+            actual
+        );
+    }
+
+    #[test]
+    fn should_return_err_out_of_bounds() {
+        let actual = highlight_text("Hello world", vec![HighlightRange::new(0, 50)]);
+
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(0, 50),
+                input_len: 11
+            }),
+            actual
+        );
+    }
+
+    #[test]
+    fn should_hightlight_multi_byte_text_when_aligned() {
+        // "h" = 1 byte, "é" = 2 bytes, "€" = 3 bytes, "😀" = 4 bytes
+        let input = "hé€😀 wörld";
+
+        assert_eq!(
+            "<em>hé</em>€😀 wörld",
+            highlight_text(input, vec![HighlightRange::new(0, 3)]).unwrap()
+        );
+        assert_eq!(
+            "hé<em>€</em>😀 wörld",
+            highlight_text(input, vec![HighlightRange::new(3, 6)]).unwrap()
+        );
+        assert_eq!(
+            "hé€<em>😀</em> wörld",
+            highlight_text(input, vec![HighlightRange::new(6, 10)]).unwrap()
+        );
+        assert_eq!(
+            "hé€😀 <em>wörld</em>",
+            highlight_text(input, vec![HighlightRange::new(11, 17)]).unwrap()
+        );
+    }
+
+    #[test]
+    fn should_return_err_not_a_char_boundary() {
+        let input = "hé€😀 wörld";
+        let cases = [
+            // 2-byte code point "é" (1..3)
+            (HighlightRange::new(2, 5), 2),
+            (HighlightRange::new(0, 2), 2),
+            // 3-byte code point "€" (3..6)
+            (HighlightRange::new(4, 6), 4),
+            (HighlightRange::new(5, 6), 5),
+            (HighlightRange::new(0, 4), 4),
+            (HighlightRange::new(0, 5), 5),
+            // 4-byte code point "😀" (6..10)
+            (HighlightRange::new(7, 10), 7),
+            (HighlightRange::new(8, 10), 8),
+            (HighlightRange::new(9, 10), 9),
+            (HighlightRange::new(6, 7), 7),
+            (HighlightRange::new(6, 8), 8),
+            (HighlightRange::new(6, 9), 9),
+        ];
+
+        for (range, offset) in cases {
+            assert_eq!(
+                Err(HighlightingError::NotACharBoundary(offset)),
+                highlight_text(input, vec![range.clone()]),
+                "{range:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_hightlight_by_char_indices() {
+        let actual = highlight_text_chars(
+            "über cool",
+            vec![HighlightRange::new(0, 4), HighlightRange::new(5, 9)],
+        )
+        .unwrap();
+
+        assert_eq!("<em>über</em> <em>cool</em>", actual);
+    }
+
+    #[test]
+    fn should_return_err_out_of_bounds_by_char_count() {
+        // 10 bytes, but only 9 chars
+        assert_eq!(
+            Err(HighlightingError::CharRangeOutOfBounds {
+                range: HighlightRange::new(5, 10),
+                char_count: 9
+            }),
+            highlight_text_chars("über cool", vec![HighlightRange::new(5, 10)])
+        );
+        assert_eq!(
+            Err(HighlightingError::CharRangeOutOfBounds {
+                range: HighlightRange::new(5, 10),
+                char_count: 9
+            }),
+            char_range_to_byte_range("über cool", &HighlightRange::new(5, 10))
+        );
+    }
+
+    #[test]
+    fn should_return_err_overlapping_char_ranges() {
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 4),
+                second: HighlightRange::new(3, 6)
+            }),
+            highlight_text_chars(
+                "über cool",
+                vec![HighlightRange::new(0, 4), HighlightRange::new(3, 6)],
+            )
+        );
+    }
+
+    #[test]
+    fn should_hightlight_by_utf16_offsets_after_astral_char() {
+        // "😀" is 2 UTF-16 code units but 4 bytes
+        let actual = highlight_text_utf16("😀 Hello", vec![HighlightRange::new(3, 8)]).unwrap();
+
+        assert_eq!("😀 <em>Hello</em>", actual);
+    }
+
+    #[test]
+    fn should_hightlight_by_utf16_offsets_around_astral_char() {
+        let actual = highlight_text_utf16(
+            "a😀b 日本 c",
+            vec![HighlightRange::new(1, 4), HighlightRange::new(5, 7)],
+        )
+        .unwrap();
+
+        assert_eq!("a<em>😀b</em> <em>日本</em> c", actual);
+    }
+
+    #[test]
+    fn should_return_err_splits_surrogate_pair() {
+        assert_eq!(
+            Err(HighlightingError::SplitsSurrogatePair(2)),
+            highlight_text_utf16("a😀b", vec![HighlightRange::new(2, 4)])
+        );
+        assert_eq!(
+            Err(HighlightingError::SplitsSurrogatePair(2)),
+            highlight_text_utf16("a😀b", vec![HighlightRange::new(0, 2)])
+        );
+    }
+
+    #[test]
+    fn should_return_err_out_of_bounds_by_utf16_len() {
+        // 4 UTF-16 code units
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(0, 5),
+                input_len: 4
+            }),
+            highlight_text_utf16("a😀b", vec![HighlightRange::new(0, 5)])
+        );
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(5, 5),
+                input_len: 4
+            }),
+            highlight_text_utf16("a😀b", vec![HighlightRange::new(5, 5)])
+        );
+    }
+
+    #[test]
+    fn should_keep_rejecting_overlaps_with_error_policy() {
+        let actual = highlight_text_with_policy(
+            "Hello world",
+            vec![HighlightRange::new(0, 5), HighlightRange::new(3, 8)],
+            OverlapPolicy::Error,
+        );
+
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 5),
+                second: HighlightRange::new(3, 8)
+            }),
+            actual
+        );
+    }
+
+    #[test]
+    fn should_merge_overlapping_ranges() {
+        let actual = highlight_text_with_policy(
+            "Hello world",
+            vec![HighlightRange::new(0, 5), HighlightRange::new(3, 8)],
+            OverlapPolicy::Merge,
+        )
+        .unwrap();
+
+        assert_eq!("<em>Hello wo</em>rld", actual);
+    }
+
+    #[test]
+    fn should_merge_touching_ranges() {
+        let actual = highlight_text_with_policy(
+            "Hello world",
+            vec![HighlightRange::new(5, 11), HighlightRange::new(0, 5)],
+            OverlapPolicy::Merge,
+        )
+        .unwrap();
+
+        assert_eq!("<em>Hello world</em>", actual);
+    }
+
+    #[test]
+    fn should_coalesce_touching_ranges() {
+        let expected = "<em>Hello world</em>";
+
+        assert_eq!(
+            expected,
+            highlight_text_coalesced(
+                "Hello world",
+                vec![HighlightRange::new(0, 5), HighlightRange::new(5, 11)],
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            expected,
+            highlight_text_coalesced(
+                "Hello world",
+                vec![HighlightRange::new(5, 11), HighlightRange::new(0, 5)],
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn should_not_coalesce_separated_ranges() {
+        let actual = highlight_text_coalesced(
+            "Hello world",
+            vec![HighlightRange::new(6, 11), HighlightRange::new(0, 5)],
+        )
+        .unwrap();
+
+        assert_eq!("<em>Hello</em> <em>world</em>", actual);
+    }
+
+    #[test]
+    fn should_return_err_overlapping_when_coalescing() {
+        let actual = highlight_text_coalesced(
+            "Hello world",
+            vec![HighlightRange::new(0, 5), HighlightRange::new(4, 11)],
+        );
+
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 5),
+                second: HighlightRange::new(4, 11)
+            }),
+            actual
+        );
+    }
+
+    #[test]
+    fn should_hightlight_touching_ranges_regardless_of_order() {
+        let ranges = [
+            HighlightRange::new(0, 5),
+            HighlightRange::new(5, 6),
+            HighlightRange::new(6, 11),
+        ];
+        let orders = [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ];
+
+        for order in orders {
+            let shuffled = order.iter().map(|&i| ranges[i].clone()).collect::<Vec<_>>();
+
+            assert_eq!(
+                "<em>Hello</em><em> </em><em>world</em>",
+                highlight_text("Hello world", shuffled).unwrap(),
+                "{order:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_hightlight_zero_length_range_between_touching_ranges_regardless_of_order() {
+        let ranges = [
+            HighlightRange::new(0, 5),
+            HighlightRange::new(5, 5),
+            HighlightRange::new(5, 11),
+        ];
+        let orders = [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ];
+
+        for order in orders {
+            let shuffled = order.iter().map(|&i| ranges[i].clone()).collect::<Vec<_>>();
+
+            assert_eq!(
+                "<em>Hello</em><em></em><em> world</em>",
+                highlight_text_with_empty_markers("Hello world", shuffled).unwrap(),
+                "{order:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_drop_zero_length_ranges_by_default() {
+        let cases = [
+            vec![HighlightRange::new(0, 0)],
+            vec![HighlightRange::new(3, 3)],
+            vec![HighlightRange::new(11, 11)],
+            vec![HighlightRange::new(3, 3), HighlightRange::new(3, 3)],
+        ];
+
+        for ranges in cases {
+            assert_eq!(
+                "Hello world",
+                highlight_text("Hello world", ranges.clone()).unwrap(),
+                "{ranges:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_render_zero_length_ranges_as_empty_markers() {
+        let cases = [
+            (vec![HighlightRange::new(0, 0)], "<em></em>Hello world"),
+            (vec![HighlightRange::new(3, 3)], "Hel<em></em>lo world"),
+            (vec![HighlightRange::new(11, 11)], "Hello world<em></em>"),
+            (
+                vec![HighlightRange::new(3, 3), HighlightRange::new(3, 3)],
+                "Hel<em></em><em></em>lo world",
+            ),
+        ];
+
+        for (ranges, expected) in cases {
+            assert_eq!(
+                expected,
+                highlight_text_with_empty_markers("Hello world", ranges).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn should_return_err_out_of_bounds_for_zero_length_range_past_end() {
+        let actual = highlight_text("Hello world", vec![HighlightRange::new(12, 12)]);
+
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(12, 12),
+                input_len: 11
+            }),
+            actual
+        );
+    }
+
+    #[test]
+    fn should_return_err_overlapping_for_zero_length_range_inside_range() {
+        let actual = highlight_text(
+            "Hello world",
+            vec![HighlightRange::new(0, 5), HighlightRange::new(3, 3)],
+        );
+
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 5),
+                second: HighlightRange::new(3, 3)
+            }),
+            actual
+        );
+    }
+
+    #[test]
+    fn should_escape_html_outside_highlight() {
+        let actual = highlight_text_escaped("a < b & c", vec![HighlightRange::new(8, 9)]).unwrap();
+
+        assert_eq!("a &lt; b &amp; <em>c</em>", actual);
+    }
+
+    #[test]
+    fn should_escape_html_inside_highlight() {
+        let actual =
+            highlight_text_escaped(r#"say "a<b" ok"#, vec![HighlightRange::new(4, 9)]).unwrap();
+
+        assert_eq!("say <em>&quot;a&lt;b&quot;</em> ok", actual);
+    }
+
+    #[test]
+    fn should_escape_html_on_highlight_boundary() {
+        let actual = highlight_text_escaped(
+            "<tag>'x'</tag>",
+            vec![HighlightRange::new(0, 1), HighlightRange::new(4, 6)],
+        )
+        .unwrap();
+
+        assert_eq!(
+            "<em>&lt;</em>tag<em>&gt;&#39;</em>x&#39;&lt;/tag&gt;",
+            actual
+        );
+    }
+
+    #[test]
+    fn should_not_escape_html_by_default() {
+        let actual = highlight_text("a < b", vec![HighlightRange::new(4, 5)]).unwrap();
+
+        assert_eq!("a < <em>b</em>", actual);
+    }
+
+    #[test]
+    fn should_hightlight_with_custom_tags() {
+        let actual = highlight_text_with_tags(
+            "Hello world",
+            vec![HighlightRange::new(0, 5), HighlightRange::new(6, 11)],
+            r#"<span class="hit">"#,
+            "</span>",
+        )
+        .unwrap();
+
+        assert_eq!(
+            r#"<span class="hit">Hello</span> <span class="hit">world</span>"#,
+            actual
+        );
+    }
+
+    #[test]
+    fn should_hightlight_with_empty_tags() {
+        let actual =
+            highlight_text_with_tags("Hello world", vec![HighlightRange::new(0, 5)], "", "")
+                .unwrap();
+
+        assert_eq!("Hello world", actual);
+    }
+
+    #[test]
+    fn should_hightlight_with_multi_byte_tags() {
+        let actual =
+            highlight_text_with_tags("Hello world", vec![HighlightRange::new(6, 11)], "«", "»")
+                .unwrap();
+
+        assert_eq!("Hello «world»", actual);
+    }
+
+    #[test]
+    fn should_hightlight_with_tags_that_appear_in_text() {
+        let actual =
+            highlight_text_with_tags("a **b** c", vec![HighlightRange::new(2, 7)], "**", "**")
+                .unwrap();
+
+        assert_eq!("a ****b**** c", actual);
+    }
+
+    #[test]
+    fn should_hightlight_with_configured_highlighter() {
+        let highlighter = Highlighter::builder()
+            .open_tag("<mark>")
+            .close_tag("</mark>")
+            .merge_overlaps(true)
+            .escape_html(true)
+            .build()
+            .unwrap();
+
+        let actual = highlighter
+            .highlight(
+                "Fish & Chips",
+                &[HighlightRange::new(0, 4), HighlightRange::new(2, 6)],
+            )
+            .unwrap();
+
+        assert_eq!("<mark>Fish &amp;</mark> Chips", actual);
+    }
+
+    #[test]
+    fn should_hightlight_like_highlight_text_with_default_highlighter() {
+        let ranges = vec![HighlightRange::new(0, 5), HighlightRange::new(5, 11)];
+
+        assert_eq!(
+            highlight_text("Hello world", ranges.clone()),
+            Highlighter::builder()
+                .build()
+                .unwrap()
+                .highlight("Hello world", &ranges)
+        );
+    }
+
+    #[test]
+    fn should_coalesce_with_configured_highlighter() {
+        let highlighter = Highlighter::builder()
+            .coalesce_adjacent(true)
+            .build()
+            .unwrap();
+
+        let actual = highlighter
+            .highlight(
+                "Hello world",
+                &[HighlightRange::new(5, 11), HighlightRange::new(0, 5)],
+            )
+            .unwrap();
+
+        assert_eq!("<em>Hello world</em>", actual);
+    }
+
+    #[test]
+    fn should_reject_empty_markers_with_merging_at_build_time() {
+        assert_eq!(
+            Err(HighlighterConfigError::EmptyMarkersWithMerging),
+            Highlighter::builder()
+                .allow_empty_markers(true)
+                .merge_overlaps(true)
+                .build()
+                .map(|_| ())
+        );
+        assert_eq!(
+            Err(HighlighterConfigError::EmptyMarkersWithMerging),
+            Highlighter::builder()
+                .allow_empty_markers(true)
+                .coalesce_adjacent(true)
+                .build()
+                .map(|_| ())
+        );
+        assert_eq!(
+            "the maximum coverage must be a fraction from 0 to 1",
+            HighlighterConfigError::MaxCoverageOutOfRange.to_string()
+        );
+    }
+
+    #[test]
+    fn should_be_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync + Clone>() {}
+        assert_send_sync::<Highlighter>();
+    }
+
+    #[test]
+    fn should_hightlight_with_labels_as_css_classes() {
+        let actual = highlight_text_labeled(
+            "fast quick car",
+            vec![
+                LabeledRange::new(HighlightRange::new(0, 4), "query"),
+                LabeledRange::new(HighlightRange::new(5, 10), "synonym"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            r#"<em class="query">fast</em> <em class="synonym">quick</em> car"#,
+            actual
+        );
+    }
+
+    #[test]
+    fn should_escape_quotes_in_labels() {
+        let actual = highlight_text_labeled(
+            "Hello world",
+            vec![LabeledRange::new(
+                HighlightRange::new(0, 5),
+                r#"x" onclick="alert(1)"#,
+            )],
+        )
+        .unwrap();
+
+        assert_eq!(
+            r#"<em class="x&quot; onclick=&quot;alert(1)">Hello</em> world"#,
+            actual
+        );
+    }
+
+    #[test]
+    fn should_return_err_overlapping_across_labels() {
+        let actual = highlight_text_labeled(
+            "Hello world",
+            vec![
+                LabeledRange::new(HighlightRange::new(0, 5), "query"),
+                LabeledRange::new(HighlightRange::new(3, 8), "synonym"),
+            ],
+        );
+
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 5),
+                second: HighlightRange::new(3, 8)
+            }),
+            actual
+        );
+    }
+
+    #[test]
+    fn should_hightlight_with_ansi_styles() {
+        let cases = [
+            (AnsiStyle::Bold, "\x1b[1mHello\x1b[0m world"),
+            (
+                AnsiStyle::Color(AnsiColor::Red),
+                "\x1b[31mHello\x1b[0m world",
+            ),
+            (
+                AnsiStyle::Color(AnsiColor::BrightCyan),
+                "\x1b[96mHello\x1b[0m world",
+            ),
+            (AnsiStyle::Color256(208), "\x1b[38;5;208mHello\x1b[0m world"),
+            (
+                AnsiStyle::Rgb(255, 128, 0),
+                "\x1b[38;2;255;128;0mHello\x1b[0m world",
+            ),
+        ];
+
+        for (style, expected) in cases {
+            assert_eq!(
+                expected,
+                highlight_text_ansi("Hello world", vec![HighlightRange::new(0, 5)], style).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn should_not_reset_between_touching_ansi_ranges() {
+        let actual = highlight_text_ansi(
+            "Hello world",
+            vec![HighlightRange::new(5, 11), HighlightRange::new(0, 5)],
+            AnsiStyle::Bold,
+        )
+        .unwrap();
+
+        assert_eq!("\x1b[1mHello world\x1b[0m", actual);
+    }
+
+    #[test]
+    fn should_reset_between_touching_ansi_ranges_when_asked() {
+        let highlighter = Highlighter::builder()
+            .ansi_style(AnsiStyle::Bold)
+            .build()
+            .unwrap();
+
+        let actual = highlighter
+            .highlight(
+                "Hello world",
+                &[HighlightRange::new(0, 5), HighlightRange::new(5, 11)],
+            )
+            .unwrap();
+
+        assert_eq!("\x1b[1mHello\x1b[0m\x1b[1m world\x1b[0m", actual);
+    }
+
+    #[test]
+    fn should_validate_ansi_like_html() {
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(0, 50),
+                input_len: 11
+            }),
+            highlight_text_ansi(
+                "Hello world",
+                vec![HighlightRange::new(0, 50)],
+                AnsiStyle::Bold
+            )
+        );
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 5),
+                second: HighlightRange::new(4, 11)
+            }),
+            highlight_text_ansi(
+                "Hello world",
+                vec![HighlightRange::new(0, 5), HighlightRange::new(4, 11)],
+                AnsiStyle::Bold
+            )
+        );
+    }
+
+    #[test]
+    fn should_hightlight_markdown_with_each_delimiter() {
+        let cases = [
+            (MarkdownDelimiter::Strong, "**Hello** world"),
+            (MarkdownDelimiter::Emphasis, "*Hello* world"),
+            (MarkdownDelimiter::Underscore, "__Hello__ world"),
+            (MarkdownDelimiter::Mark, "==Hello== world"),
+        ];
+
+        for (delimiter, expected) in cases {
+            assert_eq!(
+                expected,
+                highlight_text_markdown("Hello world", vec![HighlightRange::new(0, 5)], delimiter)
+                    .unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn should_escape_markdown_delimiters_in_text() {
+        let actual = highlight_text_markdown(
+            "a*b_c d*e",
+            vec![HighlightRange::new(6, 9)],
+            MarkdownDelimiter::Strong,
+        )
+        .unwrap();
+
+        assert_eq!(r"a\*b\_c **d\*e**", actual);
+    }
+
+    #[test]
+    fn should_escape_equals_only_for_mark_delimiter() {
+        let input = "a = b";
+        let ranges = vec![HighlightRange::new(4, 5)];
+
+        assert_eq!(
+            "a = **b**",
+            highlight_text_markdown(input, ranges.clone(), MarkdownDelimiter::Strong).unwrap()
+        );
+        assert_eq!(
+            r"a \= ==b==",
+            highlight_text_markdown(input, ranges, MarkdownDelimiter::Mark).unwrap()
+        );
+    }
+
+    #[test]
+    fn should_move_whitespace_outside_markdown_delimiters() {
+        let actual = highlight_text_markdown(
+            "say hello  world",
+            vec![HighlightRange::new(3, 10), HighlightRange::new(10, 11)],
+            MarkdownDelimiter::Strong,
+        )
+        .unwrap();
+
+        assert_eq!("say **hello**  world", actual);
+    }
+
+    #[test]
+    fn should_split_into_segments() {
+        let actual = highlight_segments(
+            "Hello big world",
+            vec![HighlightRange::new(10, 15), HighlightRange::new(0, 5)],
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                Segment {
+                    text: "Hello",
+                    range: HighlightRange::new(0, 5),
+                    highlighted: true,
+                },
+                Segment {
+                    text: " big ",
+                    range: HighlightRange::new(5, 10),
+                    highlighted: false,
+                },
+                Segment {
+                    text: "world",
+                    range: HighlightRange::new(10, 15),
+                    highlighted: true,
+                },
+            ],
+            actual
+        );
+    }
+
+    #[test]
+    fn should_split_into_segments_that_reproduce_input() {
+        let input = "hé€😀 wörld and more";
+        let cases = [
+            vec![],
+            vec![HighlightRange::new(0, 3)],
+            vec![HighlightRange::new(3, 6), HighlightRange::new(6, 10)],
+            vec![HighlightRange::new(11, 17), HighlightRange::new(10, 10)],
+            vec![HighlightRange::new(0, input.len())],
+        ];
+
+        for ranges in cases {
+            let segments = highlight_segments(input, ranges.clone()).unwrap();
+
+            assert!(segments.iter().all(|s| !s.text.is_empty()), "{ranges:?}");
+            assert!(
+                segments
+                    .windows(2)
+                    .all(|w| w[0].range.upper() == w[1].range.lower()),
+                "{ranges:?}"
+            );
+            assert_eq!(
+                input,
+                segments.iter().map(|s| s.text).collect::<String>(),
+                "{ranges:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_return_err_invalid_ranges_for_segments() {
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 5),
+                second: HighlightRange::new(4, 11)
+            }),
+            highlight_segments(
+                "Hello world",
+                vec![HighlightRange::new(0, 5), HighlightRange::new(4, 11)],
+            )
+        );
+    }
+
+    #[test]
+    fn should_display_like_highlight_text() {
+        let cases = [
+            ("Hello world", vec![]),
+            ("", vec![]),
+            ("Hello world", vec![HighlightRange::new(0, 5)]),
+            (
+                "Hello world",
+                vec![HighlightRange::new(6, 11), HighlightRange::new(0, 5)],
+            ),
+            (
+                "Hello world",
+                vec![
+                    HighlightRange::new(5, 11),
+                    HighlightRange::new(5, 5),
+                    HighlightRange::new(0, 5),
+                ],
+            ),
+            ("hé€😀 wörld", vec![HighlightRange::new(6, 10)]),
+        ];
+
+        for (input, ranges) in cases {
+            let displayed = HighlightedText::new(input, &ranges).unwrap().to_string();
+
+            assert_eq!(highlight_text(input, ranges.clone()).unwrap(), displayed);
+        }
+    }
+
+    #[test]
+    fn should_return_err_invalid_ranges_for_display() {
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(0, 50),
+                input_len: 11
+            }),
+            HighlightedText::new("Hello world", &[HighlightRange::new(0, 50)]).map(|_| ())
+        );
+    }
+
+    /// accepts `budget` bytes, then fails every write
+    #[cfg(feature = "std")]
+    struct FailingWriter {
+        written: Vec<u8>,
+        budget: usize,
+    }
+
+    #[cfg(feature = "std")]
+    impl io::Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.written.len() + buf.len() > self.budget {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "sink closed"));
+            }
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn should_write_highlights_into_io_sink() {
+        let mut out = Vec::new();
+
+        highlight_text_to(
+            "Hello world",
+            &[HighlightRange::new(0, 5), HighlightRange::new(6, 11)],
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(b"<em>Hello</em> <em>world</em>", out.as_slice());
+    }
+
+    #[test]
+    fn should_write_highlights_into_fmt_sink() {
+        let mut out = String::from("> ");
+
+        highlight_text_to_fmt("Hello world", &[HighlightRange::new(0, 5)], &mut out).unwrap();
+
+        assert_eq!("> <em>Hello</em> world", out);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn should_propagate_io_error_from_sink() {
+        let mut out = FailingWriter {
+            written: Vec::new(),
+            budget: 8,
+        };
+
+        let actual = highlight_text_to("Hello world", &[HighlightRange::new(0, 5)], &mut out);
+
+        match actual {
+            Err(HighlightError::Io(e)) => assert_eq!(io::ErrorKind::BrokenPipe, e.kind()),
+            other => panic!("expected io error, got {other:?}"),
+        }
+        assert_eq!(b"<em>", out.written.as_slice());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn should_return_err_invalid_ranges_before_writing() {
+        let mut out = Vec::new();
+
+        let actual = highlight_text_to("Hello world", &[HighlightRange::new(0, 50)], &mut out);
+
+        assert!(matches!(
+            actual,
+            Err(HighlightError::Invalid(
+                HighlightingError::RangesOutOfBounds { .. }
+            ))
+        ));
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn should_borrow_input_without_highlights() {
+        assert!(matches!(
+            highlight_text_cow("Hello world", Vec::<HighlightRange>::new()),
+            Ok(Cow::Borrowed("Hello world"))
+        ));
+        assert!(matches!(
+            highlight_text_cow("Hello world", &[HighlightRange::new(3, 3)]),
+            Ok(Cow::Borrowed("Hello world"))
+        ));
+    }
+
+    #[test]
+    fn should_own_output_with_highlights() {
+        let actual = highlight_text_cow("Hello world", &[HighlightRange::new(0, 5)]).unwrap();
+
+        assert!(matches!(actual, Cow::Owned(_)));
+        assert_eq!("<em>Hello</em> world", actual);
+    }
+
+    #[test]
+    fn should_own_output_with_empty_markers_or_escaping() {
+        let empty_markers = Highlighter::builder()
+            .allow_empty_markers(true)
+            .build()
+            .unwrap();
+        let escaping = Highlighter::builder().escape_html(true).build().unwrap();
+
+        assert_eq!(
+            Cow::<str>::Owned(String::from("Hel<em></em>lo")),
+            empty_markers
+                .highlight_cow("Hello", &[HighlightRange::new(3, 3)])
+                .unwrap()
+        );
+        assert_eq!(
+            Cow::<str>::Owned(String::from("a &lt; b")),
+            escaping.highlight_cow("a < b", &[]).unwrap()
+        );
+    }
+
+    #[test]
+    fn should_allocate_exact_output_length() {
+        let highlighter = Highlighter::default();
+        let ranges = [HighlightRange::new(0, 5), HighlightRange::new(6, 11)];
+
+        let prepared = PreparedHighlights::new("Hello world", &ranges).unwrap();
+
+        let actual =
+            highlighter.render_with(&prepared, 0..11, |_| Cow::Borrowed(&highlighter.open_tag));
+
+        assert_eq!(actual.len(), actual.capacity());
+    }
+
+    #[test]
+    fn should_report_overlapping_ranges_in_input_order() {
+        let actual = highlight_text(
+            "Hello world",
+            vec![
+                HighlightRange::new(7, 9),
+                HighlightRange::new(0, 2),
+                HighlightRange::new(8, 11),
+                HighlightRange::new(3, 5),
+            ],
+        );
+
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(7, 9),
+                second: HighlightRange::new(8, 11),
+            }),
+            actual
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn should_display_errors() {
+        let cases: [(Box<dyn std::error::Error>, &str); 5] = [
+            (
+                Box::new(HighlightingError::OverlappingRanges {
+                    first: HighlightRange::new(0, 5),
+                    second: HighlightRange::new(4, 11),
+                }),
+                "ranges 0..5 and 4..11 overlap",
+            ),
+            (
+                Box::new(HighlightingError::RangesOutOfBounds {
+                    range: HighlightRange::new(0, 50),
+                    input_len: 11,
+                }),
+                "range 0..50 is out of bounds for input of length 11",
+            ),
+            (
+                Box::new(HighlightingError::NotACharBoundary(2)),
+                "byte offset 2 is not on a char boundary",
+            ),
+            (
+                Box::new(HighlightingError::SplitsSurrogatePair(3)),
+                "UTF-16 offset 3 splits a surrogate pair",
+            ),
+            (
+                Box::new(HighlightError::Invalid(
+                    HighlightingError::NotACharBoundary(2),
+                )),
+                "invalid highlights: byte offset 2 is not on a char boundary",
+            ),
+        ];
+
+        for (error, expected) in cases {
+            assert_eq!(expected, error.to_string());
+        }
+    }
+
+    #[test]
+    fn should_clamp_ranges_past_the_end() {
+        let highlighter = Highlighter::builder()
+            .bounds_policy(BoundsPolicy::Clamp)
+            .build()
+            .unwrap();
+
+        let actual = highlighter
+            .highlight(
+                "Hello world",
+                &[HighlightRange::new(6, 15), HighlightRange::new(12, 20)],
+            )
+            .unwrap();
+
+        assert_eq!("Hello <em>world</em>", actual);
+    }
+
+    #[test]
+    fn should_apply_empty_range_policy_after_clamping() {
+        let ranges = [HighlightRange::new(11, 15)];
+        let dropping = Highlighter::builder()
+            .bounds_policy(BoundsPolicy::Clamp)
+            .build()
+            .unwrap();
+        let keeping = Highlighter::builder()
+            .bounds_policy(BoundsPolicy::Clamp)
+            .allow_empty_markers(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            "Hello world",
+            dropping.highlight("Hello world", &ranges).unwrap()
+        );
+        assert_eq!(
+            "Hello world<em></em>",
+            keeping.highlight("Hello world", &ranges).unwrap()
+        );
+    }
+
+    #[test]
+    fn should_clamp_multi_byte_text_to_char_boundary() {
+        let highlighter = Highlighter::builder()
+            .bounds_policy(BoundsPolicy::Clamp)
+            .build()
+            .unwrap();
+
+        let actual = highlighter
+            .highlight("wörld", &[HighlightRange::new(1, 9)])
+            .unwrap();
+
+        assert_eq!("w<em>örld</em>", actual);
+    }
+
+    #[test]
+    fn should_keep_rejecting_out_of_bounds_with_strict_policy() {
+        let actual = Highlighter::default().highlight("Hello world", &[HighlightRange::new(6, 15)]);
+
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(6, 15),
+                input_len: 11,
+            }),
+            actual
+        );
+    }
+
+    #[test]
+    fn should_skip_invalid_ranges_in_lossy_mode() {
+        let (actual, skipped) = highlight_text_lossy(
+            "Hello world",
+            &[
+                HighlightRange::new(3, 8),
+                HighlightRange::new(0, 50),
+                HighlightRange::new(0, 5),
+            ],
+        );
+
+        assert_eq!("<em>Hello</em> world", actual);
+        assert_eq!(
+            vec![
+                SkippedRange {
+                    range: HighlightRange::new(0, 50),
+                    reason: HighlightingError::RangesOutOfBounds {
+                        range: HighlightRange::new(0, 50),
+                        input_len: 11,
+                    },
+                },
+                SkippedRange {
+                    range: HighlightRange::new(3, 8),
+                    reason: HighlightingError::OverlappingRanges {
+                        first: HighlightRange::new(0, 5),
+                        second: HighlightRange::new(3, 8),
+                    },
+                },
+            ],
+            skipped
+        );
+    }
+
+    #[test]
+    fn should_highlight_lossy_like_highlight_when_it_succeeds() {
+        let dedup = Highlighter::builder()
+            .dedup_contained(true)
+            .coalesce_adjacent(true)
+            .build()
+            .unwrap();
+        let ranges = [
+            HighlightRange::new(6, 7),
+            HighlightRange::new(3, 6),
+            HighlightRange::new(3, 4),
+        ];
+        assert_eq!(
+            (dedup.highlight("x ax<&\n", &ranges).unwrap(), Vec::new()),
+            dedup.highlight_lossy("x ax<&\n", &ranges)
+        );
+
+        let highlighters = [
+            Highlighter::builder(),
+            Highlighter::builder()
+                .dedup_contained(true)
+                .coalesce_adjacent(true),
+            Highlighter::builder().overlap_policy(OverlapPolicy::Merge),
+            Highlighter::builder()
+                .overlap_policy(OverlapPolicy::Merge)
+                .dedup_contained(true)
+                .bounds_policy(BoundsPolicy::Clamp),
+            Highlighter::builder().overlap_policy(OverlapPolicy::Nest),
+        ]
+        .map(|builder| builder.build().unwrap());
+        let mut generator = RangeGenerator::new(0x1055_a7e5);
+        for _ in 0..2000 {
+            let input = generator.input(10);
+            let mut ranges = generator.overlapping_ranges(&input);
+            // offsets that may fall inside a char or past the end
+            for _ in 0..generator.below(3) {
+                let lower = generator.below(input.len() + 2);
+                ranges.push(HighlightRange::new(lower, lower + generator.below(4)));
+            }
+
+            for highlighter in &highlighters {
+                if let Ok(out) = highlighter.highlight(&input, &ranges) {
+                    assert_eq!(
+                        (out, Vec::new()),
+                        highlighter.highlight_lossy(&input, &ranges),
+                        "{input:?} {ranges:?}"
+                    );
+                }
+            }
+        }
+        // after merging, neither range falls inside `é`
+        let merging = Highlighter::builder()
+            .overlap_policy(OverlapPolicy::Merge)
+            .build()
+            .unwrap();
+        let ranges = [HighlightRange::new(1, 3), HighlightRange::new(0, 2)];
+        assert_eq!(
+            ("<em>éb</em>".to_string(), Vec::new()),
+            merging.highlight_lossy("éb", &ranges)
+        );
+    }
+
+    #[test]
+    fn should_partition_clamped_ranges_like_lossy_mode() {
+        let clamping = Highlighter::builder()
+            .bounds_policy(BoundsPolicy::Clamp)
+            .build()
+            .unwrap();
+        let ranges = [
+            HighlightRange::new(6, 50),
+            HighlightRange::new(3, 8),
+            HighlightRange::new(0, 5),
+        ];
+
+        let (accepted, skipped) = clamping.partition_lossy("Hello world", &ranges);
+
+        assert_eq!(
+            vec![HighlightRange::new(0, 5), HighlightRange::new(6, 11)],
+            accepted
+        );
+        assert_eq!(
+            vec![HighlightRange::new(3, 8)],
+            skipped.into_iter().map(|s| s.range).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            clamping.highlight("Hello world", &accepted).unwrap(),
+            clamping.highlight_lossy("Hello world", &ranges).0
+        );
+    }
+
+    #[test]
+    fn should_hightlight_std_ranges() {
+        assert_eq!(
+            "<em>Hello</em> <em>world</em>",
+            highlight_text("Hello world", [0..5, 6..11]).unwrap()
+        );
+        assert_eq!(
+            "<em>Hello</em> world",
+            highlight_text("Hello world", [0..=4]).unwrap()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn should_serialize_segments_to_json() {
+        let input = r#"<b>"Hi"</b> world"#;
+        let segments = highlight_segments(input, vec![HighlightRange::new(3, 7)]).unwrap();
+
+        let json = serde_json::to_string(&segments).unwrap();
+
+        assert_eq!(
+            concat!(
+                r#"[{"text":"<b>","range":{"lower":0,"upper":3},"highlighted":false},"#,
+                r#"{"text":"\"Hi\"","range":{"lower":3,"upper":7},"highlighted":true},"#,
+                r#"{"text":"</b> world","range":{"lower":7,"upper":17},"highlighted":false}]"#
+            ),
+            json
+        );
+
+        // the frontend rebuilds the input by concatenating the texts in order
+        let parsed = serde_json::from_str::<Vec<serde_json::Value>>(&json).unwrap();
+        let rebuilt = parsed
+            .iter()
+            .map(|s| s["text"].as_str().unwrap())
+            .collect::<String>();
+        assert_eq!(input, rebuilt);
+    }
+
+    #[test]
+    fn should_render_prepared_highlights_repeatedly() {
+        let ranges = [HighlightRange::new(6, 11), HighlightRange::new(0, 5)];
+        let prepared = PreparedHighlights::new("Hello world", &ranges).unwrap();
+
+        assert_eq!("<em>Hello</em> <em>world</em>", prepared.render());
+        assert_eq!(
+            highlight_text("Hello world", ranges.clone()).unwrap(),
+            prepared.render()
+        );
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 5),
+                second: HighlightRange::new(4, 6),
+            }),
+            PreparedHighlights::new("Hello world", &[0..5, 4..6].map(HighlightRange::from))
+                .map(|p| p.render())
+        );
+    }
+
+    #[test]
+    fn should_render_window_of_prepared_highlights() {
+        let ranges = [HighlightRange::new(0, 5), HighlightRange::new(6, 11)];
+        let prepared = PreparedHighlights::new("Hello world", &ranges).unwrap();
+
+        assert_eq!(
+            Ok("<em>llo</em> <em>wor</em>".to_string()),
+            prepared.render_window(2..9)
+        );
+        // ranges only touching the window aren't rendered as empty highlights
+        assert_eq!(Ok(" ".to_string()), prepared.render_window(5..6));
+        assert_eq!(
+            Ok("<em>world</em>".to_string()),
+            prepared.render_window(6..11)
+        );
+        assert_eq!(Ok(String::new()), prepared.render_window(5..5));
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(6, 12),
+                input_len: 11
+            }),
+            prepared.render_window(6..12)
+        );
+    }
+
+    #[test]
+    fn should_report_the_region_a_delta_changed() {
+        let input = "one two three four five";
+        let ranges = [HighlightRange::new(0, 3), HighlightRange::new(8, 13)];
+        let mut prepared = PreparedHighlights::new(input, &ranges).unwrap();
+
+        // touching "three" merges it into the new highlight
+        let region = prepared
+            .apply_delta(&[HighlightRange::new(4, 8)], &[])
+            .unwrap();
+        assert_eq!(HighlightRange::new(4, 13), region.range);
+        assert_eq!("<em>two three</em>", prepared.render_region(&region));
+
+        // cutting out "two " splits it off again, "one" is left alone
+        let region = prepared
+            .apply_delta(&[], &[HighlightRange::new(4, 8)])
+            .unwrap();
+        assert_eq!(HighlightRange::new(4, 13), region.range);
+        assert_eq!("two <em>three</em>", prepared.render_region(&region));
+
+        let region = prepared
+            .apply_delta(&[HighlightRange::new(19, 23)], &[HighlightRange::new(0, 3)])
+            .unwrap();
+        assert_eq!(HighlightRange::new(0, 23), region.range);
+        assert_eq!(
+            "one two <em>three</em> four <em>five</em>",
+            prepared.render()
+        );
+
+        // nothing changes for bytes that were already highlighted
+        let region = prepared
+            .apply_delta(&[HighlightRange::new(9, 11)], &[HighlightRange::new(3, 7)])
+            .unwrap();
+        assert!(region.is_empty());
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(20, 24),
+                input_len: 23
+            }),
+            prepared.apply_delta(&[HighlightRange::new(20, 24)], &[])
+        );
+    }
+
+    #[test]
+    fn should_patch_output_like_a_full_render_after_random_deltas() {
+        let mut generator = RangeGenerator::new(0xde17_a5e7);
+
+        for _ in 0..200 {
+            let input = generator.input(24);
+            let mut expected = merge_ranges(generator.overlapping_ranges(&input));
+            let mut prepared = PreparedHighlights::new(&input, &expected).unwrap();
+
+            for _ in 0..8 {
+                let before = prepared.clone();
+                let added = generator.overlapping_ranges(&input);
+                let removed = generator.overlapping_ranges(&input);
+                let added = &added[..added.len().min(2)];
+                let removed = &removed[..removed.len().min(generator.below(2))];
+
+                let region = prepared.apply_delta(added, removed).unwrap();
+                expected = subtract_ranges(&union_ranges(&expected, added), removed);
+
+                assert_eq!(expected, prepared.ranges().cloned().collect::<Vec<_>>());
+                let (lower, upper) = (region.range.lower, region.range.upper);
+                let patched = before.render_window(0..lower).unwrap()
+                    + &prepared.render_region(&region)
+                    + &before.render_window(upper..input.len()).unwrap();
+                assert_eq!(
+                    highlight_text(&input, expected.clone()),
+                    Ok(patched),
+                    "{input:?} +{added:?} -{removed:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn should_hightlight_many_ranges() {
+        let input = "ab".repeat(100_000);
+        // reversed, so the ranges actually have to be sorted
+        let ranges = (0..100_000)
+            .rev()
+            .map(|i| HighlightRange::new(2 * i, 2 * i + 1))
+            .collect::<Vec<_>>();
+
+        let actual = highlight_text(&input, ranges.clone()).unwrap();
+
+        assert_eq!("<em>a</em>b".repeat(100_000), actual);
+        assert_eq!(
+            actual,
+            HighlightedText::new(&input, &ranges).unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn should_allocate_exact_output_length_in_public_paths() {
+        let ranges = vec![HighlightRange::new(0, 5), HighlightRange::new(6, 11)];
+        let labeled = vec![
+            LabeledRange::new(HighlightRange::new(0, 5), "greeting"),
+            LabeledRange::new(HighlightRange::new(6, 11), "planet"),
+        ];
+        let prepared = PreparedHighlights::new("Hello world", &ranges).unwrap();
+
+        let outputs = [
+            highlight_text("Hello world", ranges.clone()).unwrap(),
+            highlight_text_with_tags("Hello world", ranges.clone(), "<mark>", "</mark>").unwrap(),
+            highlight_text_labeled("Hello world", labeled).unwrap(),
+            prepared.render_window(2..11).unwrap(),
+        ];
+
+        for actual in outputs {
+            assert_eq!(actual.len(), actual.capacity(), "{actual}");
+        }
+    }
+
+    #[test]
+    fn should_hightlight_many_documents_independently() {
+        let items = vec![
+            ("Hello world", vec![HighlightRange::new(0, 5)]),
+            ("Hello", vec![HighlightRange::new(0, 50)]),
+            ("world", vec![HighlightRange::new(1, 3)]),
+        ];
+
+        let actual = highlight_many(items);
+
+        assert_eq!(
+            vec![
+                Ok("<em>Hello</em> world".to_string()),
+                Err(HighlightingError::RangesOutOfBounds {
+                    range: HighlightRange::new(0, 50),
+                    input_len: 5
+                }
+                .with_context(ErrorContext::Item(1))),
+                Ok("w<em>or</em>ld".to_string()),
+            ],
+            actual
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn should_hightlight_many_documents_in_parallel_in_order() {
+        let highlighter = Highlighter::builder()
+            .open_tag("<mark>")
+            .close_tag("</mark>")
+            .build()
+            .unwrap();
+        let inputs = (0..200).map(|i| format!("doc {i}")).collect::<Vec<_>>();
+        let items = inputs
+            .iter()
+            .map(|input| (input.as_str(), vec![HighlightRange::new(4, input.len())]))
+            .collect::<Vec<_>>();
+
+        let parallel = highlighter.par_highlight_many(items.clone());
+
+        assert_eq!(highlighter.highlight_many(items), parallel);
+        assert_eq!(Ok("doc <mark>42</mark>".to_string()), parallel[42]);
+    }
+
+    #[test]
+    fn should_hightlight_streamed_chunks() {
+        let ranges = vec![
+            HighlightRange::new(4, 6),
+            HighlightRange::new(0, 2),
+            HighlightRange::new(7, 15),
+        ];
+        let mut streaming = StreamingHighlighter::new(ranges).unwrap();
+
+        // chunk boundaries at 4 (a range start), 6 (a range end), a range spanning three chunks
+        let chunks = ["abcd", "ef", "ghij", "klm", "nopq"];
+        let actual = chunks
+            .iter()
+            .map(|chunk| streaming.push_chunk(chunk).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                "<em>ab</em>cd",
+                "<em>ef</em>",
+                "g<em>hij",
+                "klm",
+                "no</em>pq"
+            ],
+            actual
+        );
+        assert_eq!((String::new(), vec![]), streaming.finish());
+    }
+
+    #[test]
+    fn should_close_open_highlight_when_stream_finishes() {
+        let ranges = vec![HighlightRange::new(3, 8), HighlightRange::new(10, 12)];
+        let mut streaming = StreamingHighlighter::new(ranges).unwrap();
+
+        assert_eq!(Ok("abc<em>de".to_string()), streaming.push_chunk("abcde"));
+        let (out, unreached) = streaming.finish();
+
+        assert_eq!("</em>", out);
+        assert_eq!(
+            vec![HighlightRange::new(3, 8), HighlightRange::new(10, 12)],
+            unreached.into_iter().map(|s| s.range).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn should_reject_streamed_range_inside_char() {
+        let mut streaming = StreamingHighlighter::new(vec![HighlightRange::new(2, 3)]).unwrap();
+
+        assert_eq!(Ok("a".to_string()), streaming.push_chunk("a"));
+        assert_eq!(
+            Err(HighlightingError::NotACharBoundary(2)),
+            streaming.push_chunk("öb")
+        );
+        assert_eq!(Ok("x<em>y</em>b".to_string()), streaming.push_chunk("xyb"));
+        assert!(
+            StreamingHighlighter::new(vec![HighlightRange::new(0, 3), HighlightRange::new(2, 4)])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn should_hightlight_words() {
+        assert_eq!(
+            Ok("<em>Hello</em> <em>big</em> world".to_string()),
+            highlight_words("Hello big world", &[HighlightRange::new(0, 2)])
+        );
+        assert_eq!(
+            Err(HighlightingError::WordRangeOutOfBounds {
+                range: HighlightRange::new(2, 4),
+                word_count: 3
+            }),
+            highlight_words("Hello big world", &[HighlightRange::new(2, 4)])
+        );
+    }
+
+    #[test]
+    fn should_hightlight_snippet_around_first_highlight() {
+        let input = "The quick brown fox jumps over the lazy dog and runs away";
+
+        assert_eq!(
+            Ok("…<em>fox</em> <em>jumps</em> <em>over</em>…".to_string()),
+            highlight_snippet(
+                input,
+                &[
+                    HighlightRange::new(26, 30),
+                    HighlightRange::new(16, 19),
+                    HighlightRange::new(20, 25)
+                ],
+                20
+            )
+        );
+        assert_eq!(
+            Ok(format!("<em>{input}</em>")),
+            highlight_snippet(input, &[HighlightRange::new(0, input.len())], 100)
+        );
+        assert_eq!(
+            Ok("The quick…".to_string()),
+            highlight_snippet(input, &[], 12)
+        );
+    }
+
+    #[test]
+    fn should_truncate_highlight_longer_than_snippet() {
+        let input = "a verylongword b";
+
+        assert_eq!(
+            Ok("…<em>veryl</em>…".to_string()),
+            highlight_snippet(input, &[HighlightRange::new(2, 14)], 5)
+        );
+    }
+
+    #[test]
+    fn should_cluster_nearby_highlights_into_fragments() {
+        let input = "Rust is fast. Many words in between here. Rust is also safe and Rust is fun.";
+        let ranges = ranges_for_substring(input, "Rust");
+
+        let actual = fragments(input, &ranges, 40, 5).unwrap();
+
+        assert_eq!(
+            vec![
+                "<em>Rust</em> is fast. Many words in between here",
+                "<em>Rust</em> is also safe and <em>Rust</em> is fun.",
+            ],
+            actual.iter().map(|f| f.text.as_str()).collect::<Vec<_>>()
+        );
+        assert!(actual[0].range.upper() <= actual[1].range.lower());
+        assert_eq!(
+            Ok(actual[0].text.clone()),
+            highlight_fragments(input, &ranges, 40, 1, " … ")
+        );
+    }
+
+    #[test]
+    fn should_join_fragments_with_separator() {
+        let input = "alpha beta gamma delta epsilon zeta eta theta";
+        let ranges = [HighlightRange::new(0, 5), HighlightRange::new(40, 45)];
+
+        assert_eq!(
+            Ok("<em>alpha</em> beta … eta <em>theta</em>".to_string()),
+            highlight_fragments(input, &ranges, 12, 3, " … ")
+        );
+        assert_eq!(
+            Ok(String::new()),
+            highlight_fragments(input, &[], 12, 3, " … ")
+        );
+    }
+
+    #[test]
+    fn should_pick_fragment_with_most_highlights() {
+        let input =
+            "a match at the start, long filler text in the middle, match and match and match";
+        let ranges = ranges_for_substring(input, "match");
+
+        let best = best_fragment(input, &ranges, 30).unwrap().unwrap();
+
+        assert_eq!(3, best.highlight_count);
+        assert_eq!(15, best.highlighted_bytes);
+        assert_eq!(
+            "<em>match</em> and <em>match</em> and <em>match</em>",
+            best.text
+        );
+        assert_eq!(None, best_fragment(input, &[], 30).unwrap());
+    }
+
+    #[test]
+    fn should_break_fragment_score_ties_by_position() {
+        let input = "cat dog cat dog";
+        let ranges = [HighlightRange::new(0, 3), HighlightRange::new(8, 11)];
+
+        // every candidate has a single highlight of the same length
+        let best = best_fragment(input, &ranges, 5).unwrap().unwrap();
+        assert_eq!(HighlightRange::new(0, 3), best.range);
+
+        // a custom score preferring later highlights
+        let last = best_fragment_by(input, &ranges, 5, |f| f.range.lower()).unwrap();
+        assert_eq!(Some(HighlightRange::new(8, 11)), last.map(|f| f.range));
+    }
+
+    #[test]
+    fn should_truncate_inside_highlight() {
+        let ranges = [HighlightRange::new(0, 5), HighlightRange::new(6, 11)];
+
+        assert_eq!(
+            Ok("<em>Hello</em> <em>wo</em>…".to_string()),
+            highlight_text_truncated("Hello world", &ranges, 8)
+        );
+        assert_eq!(
+            Ok("<em>Hel</em>…".to_string()),
+            highlight_text_truncated("Hello world", &ranges, 3)
+        );
+    }
+
+    #[test]
+    fn should_truncate_on_tag_boundary() {
+        let ranges = [HighlightRange::new(0, 5), HighlightRange::new(6, 11)];
+
+        assert_eq!(
+            Ok("<em>Hello</em>…".to_string()),
+            highlight_text_truncated("Hello world", &ranges, 5)
+        );
+        assert_eq!(
+            Ok("<em>Hello</em> …".to_string()),
+            highlight_text_truncated("Hello world", &ranges, 6)
+        );
+        assert_eq!(
+            Ok("<em>Hello</em> <em>world</em>".to_string()),
+            highlight_text_truncated("Hello world", &ranges, 11)
+        );
+        assert_eq!(
+            Ok("<em>hö</em>…".to_string()),
+            highlight_text_truncated("höhö", &[HighlightRange::new(0, 6)], 2)
+        );
+    }
+
+    #[test]
+    fn should_highlight_both_from_the_same_window_and_cut() {
+        let input = "Hello big world";
+        let ranges = [(0, 5), (6, 9), (10, 15)].map(|(l, u)| HighlightRange::new(l, u));
+        let both = |window: Option<(usize, usize)>, max_visible_chars| {
+            let options = BothOptions {
+                window: window.map(|(lower, upper)| HighlightRange::new(lower, upper)),
+                max_visible_chars,
+            };
+            let both = highlight_both(input, &ranges, &options).unwrap();
+            (both.highlighted, both.plain)
+        };
+
+        let (highlighted, plain) = both(None, None);
+        assert_eq!("<em>Hello</em> <em>big</em> <em>world</em>", highlighted);
+        assert!(
+            matches!(plain, Cow::Borrowed("Hello big world")),
+            "{plain:?}"
+        );
+
+        let (highlighted, plain) = both(Some((3, 12)), None);
+        assert_eq!("<em>lo</em> <em>big</em> <em>wo</em>", highlighted);
+        assert!(matches!(plain, Cow::Borrowed("lo big wo")), "{plain:?}");
+
+        assert_eq!(
+            ("<em>Hello</em> <em>b</em>…".to_string(), "Hello b…".into()),
+            both(None, Some(7))
+        );
+        assert_eq!(
+            ("<em>big</em> <em>w</em>…".to_string(), "big w…".into()),
+            both(Some((6, 15)), Some(5))
+        );
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(6, 20),
+                input_len: 15
+            }),
+            highlight_both(
+                input,
+                &ranges,
+                &BothOptions {
+                    window: Some(HighlightRange::new(6, 20)),
+                    max_visible_chars: None,
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn should_strip_highlight_both_output_to_its_plain_text() {
+        let strip_tags = |html: &str| {
+            let mut text = String::new();
+            let mut in_tag = false;
+            for c in html.chars() {
+                match c {
+                    '<' => in_tag = true,
+                    '>' => in_tag = false,
+                    c if !in_tag => text.push(c),
+                    _ => {}
+                }
+            }
+            text
+        };
+        let highlighter = Highlighter::builder()
+            .escape_html(true)
+            .anchor_ids("m-")
+            .build()
+            .unwrap();
+        let mut generator = RangeGenerator::new(0x00c0_ffee);
+
+        for _ in 0..500 {
+            let input = generator.input(16);
+            let ranges = generator.valid_ranges(&input);
+            let bounds = generator.valid_ranges(&input);
+            let options = BothOptions {
+                window: bounds.first().cloned(),
+                max_visible_chars: [None, Some(generator.below(8))][generator.below(2)],
+            };
+
+            let both = highlighter
+                .highlight_both(&input, &ranges, &options)
+                .unwrap();
+
+            assert_eq!(
+                strip_tags(&both.highlighted),
+                both.plain,
+                "{input:?} {ranges:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_round_trip_through_parse_highlighted() {
+        let mut generator = RangeGenerator::new(0x9e37_79b9);
+
+        for _ in 0..300 {
+            let input = generator.input(20);
+            let ranges = generator.valid_ranges(&input);
+
+            let rendered = highlight_text(&input, ranges.clone()).unwrap();
+            let expected = ranges
+                .into_iter()
+                .filter(|r| r.lower() != r.upper())
+                .collect();
+
+            assert_eq!(
+                Ok((input.clone(), expected)),
+                parse_highlighted(&rendered, "<em>", "</em>"),
+                "{rendered}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_hightlight_everything_outside_the_ranges() {
+        assert_eq!(
+            Ok("<em>Hello </em>world<em>!</em>".to_string()),
+            highlight_text_inverted("Hello world!", vec![HighlightRange::new(6, 11)])
+        );
+        // ranges touching the start and end leave no empty wrapped segments
+        assert_eq!(
+            Ok("Hello<em> </em>world!".to_string()),
+            highlight_text_inverted("Hello world!", [0..5, 6..12])
+        );
+        assert_eq!(
+            Ok("Hello".to_string()),
+            highlight_text_inverted("Hello", vec![HighlightRange::new(0, 5)])
+        );
+        assert_eq!(
+            Ok("<em>Hello</em>".to_string()),
+            highlight_text_inverted("Hello", Vec::<HighlightRange>::new())
+        );
+    }
+
+    #[test]
+    fn should_validate_ranges_before_inverting() {
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 5),
+                second: HighlightRange::new(3, 8),
+            }),
+            highlight_text_inverted("Hello world", [0..5, 3..8])
+        );
+        assert_eq!(
+            Err(HighlightingError::NotACharBoundary(1)),
+            highlight_text_inverted("öl", vec![HighlightRange::new(1, 3)])
+        );
+
+        let dimmed = Highlighter::builder()
+            .open_tag(r#"<span class="dim">"#)
+            .close_tag("</span>")
+            .build()
+            .unwrap();
+        assert_eq!(
+            Ok(r#"<span class="dim">call </span>555-1234"#.to_string()),
+            dimmed.highlight_inverted("call 555-1234", &[HighlightRange::new(5, 13)])
+        );
+    }
+
+    #[test]
+    fn should_hightlight_contained_ranges_as_nested_tags() {
+        let input = "Hello world";
+
+        assert_eq!(
+            Ok("<em>Hello <em>world</em></em>".to_string()),
+            highlight_text_with_policy(
+                input,
+                vec![HighlightRange::new(6, 11), HighlightRange::new(0, 11)],
+                OverlapPolicy::Nest
+            )
+        );
+        assert_eq!(
+            Ok("<em><em>Hello</em></em> <em>world</em>".to_string()),
+            highlight_text_with_policy(
+                input,
+                vec![
+                    HighlightRange::new(0, 5),
+                    HighlightRange::new(0, 5),
+                    HighlightRange::new(6, 11)
+                ],
+                OverlapPolicy::Nest
+            )
+        );
+    }
+
+    #[test]
+    fn should_split_partially_overlapping_nested_ranges() {
+        assert_eq!(
+            Ok("<em>Hello<em> w</em></em><em>orld</em>".to_string()),
+            highlight_text_with_policy(
+                "Hello world",
+                vec![HighlightRange::new(0, 7), HighlightRange::new(5, 11)],
+                OverlapPolicy::Nest
+            )
+        );
+    }
+
+    #[test]
+    fn should_nest_three_ranges_sharing_a_region() {
+        let input = "abcdefghij";
+        let out = highlight_text_with_policy(
+            input,
+            vec![
+                HighlightRange::new(0, 6),
+                HighlightRange::new(2, 8),
+                HighlightRange::new(4, 10),
+            ],
+            OverlapPolicy::Nest,
+        )
+        .unwrap();
+
+        assert_eq!(
+            "<em>ab<em>cd<em>ef</em></em></em><em><em>gh</em>ij</em>",
+            out
+        );
+        assert_eq!(input, strip_highlights(&out));
+
+        // the depth at every char is the number of ranges covering it
+        let mut depth = 0;
+        let mut depths = Vec::new();
+        let mut rest = out.as_str();
+        while let Some(c) = rest.chars().next() {
+            if let Some(tail) = rest.strip_prefix("<em>") {
+                depth += 1;
+                rest = tail;
+            } else if let Some(tail) = rest.strip_prefix("</em>") {
+                depth -= 1;
+                rest = tail;
+            } else {
+                depths.push(depth);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        assert_eq!(vec![1, 1, 2, 2, 3, 3, 2, 2, 1, 1], depths);
+    }
+
+    #[test]
+    fn should_apply_bounds_policy_before_nesting() {
+        let clamping = Highlighter::builder()
+            .overlap_policy(OverlapPolicy::Nest)
+            .bounds_policy(BoundsPolicy::Clamp)
+            .build()
+            .unwrap();
+        assert_eq!(
+            Ok("He<em>l<em>lo</em></em>".to_string()),
+            clamping.highlight(
+                "Hello",
+                &[HighlightRange::new(2, 9), HighlightRange::new(3, 5)]
+            )
+        );
+
+        let strict = Highlighter::builder()
+            .overlap_policy(OverlapPolicy::Nest)
+            .build()
+            .unwrap();
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(2, 9),
+                input_len: 5,
+            }),
+            strict.highlight("Hello", &[HighlightRange::new(2, 9)])
+        );
+        assert_eq!(
+            Ok(vec![
+                Segment {
+                    text: "He",
+                    range: HighlightRange::new(0, 2),
+                    highlighted: false,
+                },
+                Segment {
+                    text: "llo",
+                    range: HighlightRange::new(2, 5),
+                    highlighted: true,
+                },
+            ]),
+            strict.segments(
+                "Hello",
+                &[HighlightRange::new(2, 5), HighlightRange::new(3, 4)]
+            )
+        );
+    }
+
+    #[test]
+    fn should_carry_payloads_into_annotated_segments() {
+        #[derive(Debug, PartialEq)]
+        struct Entity {
+            id: u32,
+            tooltip: &'static str,
+        }
+
+        let highlights = [
+            AnnotatedRange::new(
+                HighlightRange::new(6, 11),
+                Entity {
+                    id: 2,
+                    tooltip: "planet",
+                },
+            ),
+            AnnotatedRange::new(
+                HighlightRange::new(0, 5),
+                Entity {
+                    id: 1,
+                    tooltip: "greeting",
+                },
+            ),
+        ];
+        let segments = highlight_segments_annotated("Hello world!", &highlights).unwrap();
+
+        assert_eq!(
+            vec![
+                ("Hello", Some(1)),
+                (" ", None),
+                ("world", Some(2)),
+                ("!", None)
+            ],
+            segments
+                .iter()
+                .map(|s| (s.segment.text, s.payload.map(|e| e.id)))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some("planet"), segments[2].payload.map(|e| e.tooltip));
+        assert!(segments[2].segment.highlighted);
+    }
+
+    #[test]
+    fn should_reject_overlapping_annotated_ranges() {
+        let highlights = [
+            AnnotatedRange::new(HighlightRange::new(0, 5), "a"),
+            AnnotatedRange::new(HighlightRange::new(3, 8), "b"),
+        ];
+
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 5),
+                second: HighlightRange::new(3, 8),
+            }),
+            highlight_segments_annotated("Hello world", &highlights)
+        );
+    }
+
+    #[test]
+    fn should_render_segments_with_a_closure() {
+        use std::fmt::Write as _;
+
+        let input = "Hello world!";
+        let highlights = [HighlightRange::new(6, 11), HighlightRange::new(0, 5)];
+
+        let upper = highlight_with(input, &highlights, |segment, _, out| {
+            if segment.highlighted {
+                out.push_str(&segment.text.to_uppercase());
+            } else {
+                out.push_str(segment.text);
+            }
+        });
+        assert_eq!(Ok("HELLO WORLD!".to_string()), upper);
+
+        // the range index allows looking up data kept next to the ranges
+        let ids = ["planet", "greeting"];
+        let tagged = highlight_with(
+            input,
+            &highlights,
+            |segment, range_idx, out| match range_idx {
+                Some(i) => {
+                    let range = &segment.range;
+                    write!(
+                        out,
+                        r#"<b data-id="{}" data-at="{range}">{}</b>"#,
+                        ids[i], segment.text
+                    )
+                    .unwrap();
+                }
+                None => out.push_str(segment.text),
+            },
+        );
+        assert_eq!(
+            Ok(concat!(
+                r#"<b data-id="greeting" data-at="0..5">Hello</b> "#,
+                r#"<b data-id="planet" data-at="6..11">world</b>!"#
+            )
+            .to_string()),
+            tagged
+        );
+
+        let em = highlight_with(input, &highlights, |segment, _, out| {
+            if segment.highlighted {
+                write!(out, "<em>{}</em>", segment.text).unwrap();
+            } else {
+                out.push_str(segment.text);
+            }
+        });
+        assert_eq!(highlight_text(input, highlights.to_vec()), em);
+    }
+
+    #[test]
+    fn should_render_every_tag_style() {
+        let styled = |style: TagStyle| {
+            Highlighter::builder()
+                .tag_style(style)
+                .build()
+                .unwrap()
+                .highlight("Hello world", &[HighlightRange::new(6, 11)])
+                .unwrap()
+        };
+
+        assert_eq!("Hello <em>world</em>", styled(TagStyle::default()));
+        assert_eq!("Hello <strong>world</strong>", styled(TagStyle::Strong));
+        assert_eq!("Hello <mark>world</mark>", styled(TagStyle::Mark));
+        assert_eq!(
+            r#"Hello <span class="hit">world</span>"#,
+            styled(TagStyle::SpanClass("hit".to_string()))
+        );
+        assert_eq!(
+            r#"Hello <span class="a&quot;&gt;b">world</span>"#,
+            styled(TagStyle::SpanClass(r#"a">b"#.to_string()))
+        );
+        assert_eq!(
+            r#"Hello [["a"]]world[[/]]"#,
+            styled(TagStyle::Custom {
+                open: r#"[["a"]]"#.to_string(),
+                close: "[[/]]".to_string(),
+            })
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn should_render_labels_with_their_tag_style() {
+        let styles = HashMap::from([
+            ("query".to_string(), TagStyle::Mark),
+            (
+                "synonym".to_string(),
+                TagStyle::SpanClass("syn\"onym".to_string()),
+            ),
+        ]);
+
+        assert_eq!(
+            Ok(concat!(
+                r#"<mark>Hello</mark> <span class="syn&quot;onym">big</span> "#,
+                r#"<em class="spelling">world</em>"#
+            )
+            .to_string()),
+            highlight_text_labeled_styled(
+                "Hello big world",
+                vec![
+                    LabeledRange::new(HighlightRange::new(10, 15), "spelling"),
+                    LabeledRange::new(HighlightRange::new(0, 5), "query"),
+                    LabeledRange::new(HighlightRange::new(6, 9), "synonym"),
+                ],
+                &styles
+            )
+        );
+    }
+
+    #[test]
+    fn should_hightlight_text_as_latex() {
+        assert_eq!(
+            Ok(r"Hello \hl{world}".to_string()),
+            highlight_text_latex("Hello world", vec![HighlightRange::new(6, 11)])
+        );
+        assert_eq!(
+            Ok(r"Größe: \textbf{5 \%}".to_string()),
+            highlight_text_latex_with_macro(
+                "Größe: 5 %",
+                vec![HighlightRange::new(9, 12)],
+                "textbf"
+            )
+        );
+    }
+
+    #[test]
+    fn should_escape_latex_special_characters_in_all_segments() {
+        let input = r"# $ % & _ { } ~ ^ \";
+
+        assert_eq!(
+            Ok(concat!(
+                r"\# \$ \hl{\% \&} \_ \{ \} ",
+                r"\textasciitilde{} \textasciicircum{} \textbackslash{}"
+            )
+            .to_string()),
+            highlight_text_latex(input, vec![HighlightRange::new(4, 7)])
+        );
+    }
+
+    #[test]
+    fn should_hightlight_text_as_bbcode() {
+        assert_eq!(
+            Ok("[b]Hello[/b] world".to_string()),
+            highlight_text_bbcode("Hello world", vec![HighlightRange::new(0, 5)], "b")
+        );
+        // brackets in the text can't open tags
+        assert_eq!(
+            Ok("say [\u{200B}b]hi[\u{200B}/b] [mark]loud[/mark]".to_string()),
+            highlight_text_bbcode(
+                "say [b]hi[/b] loud",
+                vec![HighlightRange::new(14, 18)],
+                "mark"
+            )
+        );
+    }
+
+    #[test]
+    fn should_close_bbcode_tag_when_truncating() {
+        let bbcode = Highlighter::builder().bbcode("b").build().unwrap();
+
+        assert_eq!(
+            Ok("He[b]llo w[/b]…".to_string()),
+            bbcode.highlight_truncated("Hello world", &[HighlightRange::new(2, 9)], 7)
+        );
+        assert_eq!(
+            Ok("He[b]llo[/b]".to_string()),
+            bbcode.highlight_truncated("Hello", &[HighlightRange::new(2, 5)], 7)
+        );
+    }
+
+    #[test]
+    fn should_underline_highlights_with_carets() {
+        assert_eq!(
+            Ok("let x = 5;\n    ^   ^".to_string()),
+            highlight_text_caret(
+                "let x = 5;",
+                vec![HighlightRange::new(4, 5), HighlightRange::new(8, 9)]
+            )
+        );
+        // chars take one column no matter how many bytes they have, tabs take `tab_width`
+        assert_eq!(
+            Ok("\tgrüße welt\n  ^^^^^^".to_string()),
+            highlight_text_caret_with_tab_width("\tgrüße welt", vec![HighlightRange::new(1, 9)], 2)
+        );
+        assert_eq!(
+            Ok("\tx\n^^^^^".to_string()),
+            highlight_text_caret("\tx", vec![HighlightRange::new(0, 2)])
+        );
+    }
+
+    #[test]
+    fn should_underline_every_line_a_highlight_covers() {
+        let input = "fn main() {\r\n    foo(\n        bar);\n}\n";
+        // from `foo` to `bar`, the line of `main` has no highlight
+        let highlights = vec![HighlightRange::new(17, 33)];
+
+        assert_eq!(
+            Ok(concat!(
+                "fn main() {\r\n",
+                "    foo(\n",
+                "    ^^^^\n",
+                "        bar);\n",
+                "^^^^^^^^^^^\n",
+                "}\n"
+            )
+            .to_string()),
+            highlight_text_caret(input, highlights)
+        );
+        // a range covering nothing but a line break underlines nothing
+        assert_eq!(
+            Ok("ab\ncd".to_string()),
+            highlight_text_caret("ab\ncd", vec![HighlightRange::new(2, 3)])
+        );
+    }
+
+    #[test]
+    fn should_close_and_reopen_highlights_at_newlines() {
+        let splitting = Highlighter::builder()
+            .split_at_newlines(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            Ok("first <em>line</em>\n<em>second</em> line".to_string()),
+            splitting.highlight("first line\nsecond line", &[HighlightRange::new(6, 17)])
+        );
+        // CRLF is one break, the tags go around both bytes
+        assert_eq!(
+            Ok("a<em>b</em>\r\n<em>c</em>d".to_string()),
+            splitting.highlight("ab\r\ncd", &[HighlightRange::new(1, 5)])
+        );
+        // no empty tags after a highlight ending at a break or for empty lines
+        assert_eq!(
+            Ok("<em>ab</em>\n\n<em>c</em>d".to_string()),
+            splitting.highlight(
+                "ab\n\ncd",
+                &[HighlightRange::new(0, 3), HighlightRange::new(3, 5)]
+            )
+        );
+        assert_eq!(
+            Ok("<em>ab\ncd</em>".to_string()),
+            Highlighter::default().highlight("ab\ncd", &[HighlightRange::new(0, 5)])
+        );
+    }
+
+    #[test]
+    fn should_wrap_ansi_highlight_spanning_the_wrap_point() {
+        assert_eq!(
+            Ok(concat!(
+                "hel\x1b[1mlo\x1b[0m\n",
+                "\x1b[1mwon\x1b[0mderful\n",
+                "world"
+            )
+            .to_string()),
+            highlight_wrap_ansi(
+                "hello wonderful world",
+                vec![HighlightRange::new(3, 9)],
+                AnsiStyle::Bold,
+                12
+            )
+        );
+        // hard line breaks are kept, every line is wrapped on its own
+        assert_eq!(
+            Ok("ab\n\x1b[31mcd\x1b[0m\n\x1b[31mef\x1b[0m\ngh".to_string()),
+            highlight_wrap_ansi(
+                "ab\ncd ef gh",
+                vec![HighlightRange::new(3, 8)],
+                AnsiStyle::Color(AnsiColor::Red),
+                4
+            )
+        );
+    }
+
+    #[test]
+    fn should_wrap_line_that_is_one_long_highlight() {
+        assert_eq!(
+            Ok("\x1b[1mabcd\x1b[0m\n\x1b[1mefgh\x1b[0m\n\x1b[1mij\x1b[0m".to_string()),
+            highlight_wrap_ansi(
+                "abcdefghij",
+                vec![HighlightRange::new(0, 10)],
+                AnsiStyle::Bold,
+                4
+            )
+        );
+        // tabs take `tab_width` columns
+        let options = WrapOptions {
+            width: 5,
+            tab_width: 4,
+            bidi_isolate: false,
+        };
+        assert_eq!(
+            Ok("\ta\nb".to_string()),
+            highlight_wrap_ansi_with(
+                "\tab",
+                Vec::<HighlightRange>::new(),
+                AnsiStyle::Bold,
+                &options
+            )
+        );
+    }
+
+    #[test]
+    fn should_hightlight_window_with_clipped_ranges() {
+        let input = "one two three four";
+        let highlights = [HighlightRange::new(4, 7), HighlightRange::new(14, 18)];
+
+        assert_eq!(
+            Ok("<em>two</em> three".to_string()),
+            highlight_window(input, 4..13, &highlights)
+        );
+        assert_eq!(
+            Ok("<em>o</em> three <em>fo</em>".to_string()),
+            highlight_window(input, 6..16, &highlights)
+        );
+        assert_eq!(
+            clip_ranges(&highlights, &HighlightRange::new(6, 16)),
+            vec![HighlightRange::new(0, 1), HighlightRange::new(8, 10)]
+        );
+    }
+
+    #[test]
+    fn should_reject_window_off_char_boundary() {
+        let input = "grüße";
+
+        assert_eq!(
+            Err(HighlightingError::NotACharBoundary(3)),
+            highlight_window(input, 3..7, &[HighlightRange::new(0, 2)])
+        );
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(2, 9),
+                input_len: 7,
+            }),
+            highlight_window(input, 2..9, &[])
+        );
+        // ranges are checked against the whole input, even outside the window
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(5, 8),
+                input_len: 7,
+            }),
+            highlight_window(input, 0..2, &[HighlightRange::new(5, 8)])
+        );
+    }
+
+    #[test]
+    fn should_remap_ranges_to_escaped_text() {
+        let original = r#"a<b & "c""#;
+        // `a&lt;b &amp; &quot;c&quot;`
+        assert_eq!(
+            vec![
+                HighlightRange::new(0, 1),
+                HighlightRange::new(1, 5),
+                HighlightRange::new(7, 12),
+                HighlightRange::new(13, 26),
+            ],
+            remap_ranges_for_escaped(
+                original,
+                &[
+                    HighlightRange::new(0, 1),
+                    HighlightRange::new(1, 2),
+                    HighlightRange::new(4, 5),
+                    HighlightRange::new(6, 9),
+                ]
+            )
+        );
+        assert_eq!(
+            vec![HighlightRange::new(0, 8)],
+            remap_ranges_for_escaped("ö'x", &[HighlightRange::new(0, 4)])
+        );
+    }
+
+    #[test]
+    fn should_hightlight_pre_escaped_document() {
+        let original = r#"Tom & Jerry <3 "cheese""#;
+        let mut escaped = String::new();
+        write_html_escaped(&mut escaped, original).unwrap();
+
+        assert_eq!(
+            Ok(concat!(
+                "Tom <em>&amp; Jerry</em> &lt;3 ",
+                "<em>&quot;cheese&quot;</em>"
+            )
+            .to_string()),
+            highlight_escaped_document(
+                &escaped,
+                &[HighlightRange::new(4, 11), HighlightRange::new(15, 23)],
+                original
+            )
+        );
+        assert_eq!(
+            Ok("<em>&lt;</em>&gt;".to_string()),
+            highlight_escaped_document("&lt;&gt;", &[HighlightRange::new(0, 1)], "<>")
+        );
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(0, 3),
+                input_len: 2,
+            }),
+            highlight_escaped_document("&lt;&gt;", &[HighlightRange::new(0, 3)], "<>")
+        );
+    }
+
+    #[test]
+    fn should_accept_borrowed_ranges_and_iterators() {
+        let input = "Hello world";
+        let cached = vec![HighlightRange::new(0, 5), HighlightRange::new(6, 11)];
+        let expected = Ok("<em>Hello</em> <em>world</em>".to_string());
+
+        assert_eq!(expected, highlight_text(input, &cached));
+        assert_eq!(expected, highlight_text(input, &cached[..]));
+        assert_eq!(expected, highlight_text(input, cached.iter()));
+        assert_eq!(expected, highlight_text_with_empty_markers(input, &cached));
+        assert_eq!(expected, highlight_text(input, [0..5, 6..11]));
+        assert_eq!(
+            Ok("<em>Hello world</em>".to_string()),
+            highlight_text_coalesced(input, [0..5, 5..11])
+        );
+        assert_eq!(
+            Ok(2),
+            highlight_segments(input, &cached[..1]).map(|segments| segments.len())
+        );
+        assert_eq!(
+            expected,
+            highlight_text(input, cached.iter().map(|h| h.lower..h.upper))
+        );
+        assert_eq!(expected, highlight_text(input, cached.iter().peekable()));
+        assert_eq!(
+            expected,
+            highlight_text(input, cached.iter().take_while(|h| h.lower < 11))
+        );
+        assert_eq!(
+            expected,
+            highlight_text(input, cached.iter().collect::<BTreeSet<_>>())
+        );
+        assert_eq!(
+            expected,
+            highlight_text(input, cached.iter().cloned().collect::<HashSet<_>>())
+        );
+        assert_eq!(expected, highlight_text(input, &[0..=4, 6..=10]));
+        assert_eq!(
+            expected,
+            highlight_text(input, HighlightSet::from_iter(cached.clone()))
+        );
+        assert_eq!(
+            (expected.clone().unwrap(), Vec::new()),
+            highlight_text_lossy(input, cached.iter().peekable())
+        );
+        assert_eq!(
+            expected,
+            highlight_with(input, [0..5, 6..11], |segment, range, out| match range {
+                Some(_) => out.push_str(&format!("<em>{}</em>", segment.text)),
+                None => out.push_str(segment.text),
+            })
+        );
+
+        // owned ranges are used in place, only the output is allocated
+        let owned = cached.clone();
+        let (_, in_place) = count_allocations(|| highlight_text(input, owned));
+        let (_, direct) = count_allocations(|| Highlighter::default().highlight(input, &cached));
+        assert_eq!(direct, in_place);
+
+        // the cached ranges are still there to be rendered again
+        assert_eq!(expected, highlight_text(input, cached));
+    }
+
+    #[test]
+    fn should_accept_zero_length_range_at_end_of_input() {
+        let input = "Hello world";
+
+        assert_eq!(
+            Ok("Hello world<em></em>".to_string()),
+            highlight_text_with_empty_markers(input, [HighlightRange::new(11, 11)])
+        );
+        // whether the marker shows up is up to the empty-range policy, not validation
+        assert_eq!(
+            Ok("Hello world".to_string()),
+            highlight_text(input, [HighlightRange::new(11, 11)])
+        );
+        assert_eq!(
+            Ok("Hello <em>world</em>".to_string()),
+            highlight_text(input, [HighlightRange::new(6, 11)])
+        );
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(11, 12),
+                input_len: 11,
+            }),
+            highlight_text(input, [HighlightRange::new(11, 12)])
+        );
+    }
+
+    #[test]
+    fn should_convert_between_inclusive_and_exclusive_bounds() {
+        // a single char
+        assert_eq!(
+            Ok(HighlightRange::new(3, 4)),
+            HighlightRange::new_inclusive(3, 3)
+        );
+        assert_eq!(Some((3, 3)), HighlightRange::new(3, 4).to_inclusive());
+        // ending at the last byte of "Hello"
+        let last = HighlightRange::new_inclusive(0, 4).unwrap();
+        assert_eq!(
+            Ok("<em>Hello</em>".to_string()),
+            highlight_text("Hello", [last.clone()])
+        );
+        assert_eq!(Some((0, 4)), last.to_inclusive());
+
+        assert_eq!(None, HighlightRange::new(2, 2).to_inclusive());
+        assert_eq!(
+            Ok(HighlightRange::new(usize::MAX - 1, usize::MAX)),
+            HighlightRange::new_inclusive(usize::MAX - 1, usize::MAX - 1)
+        );
+        assert_eq!(
+            Err(RangeConstructionError::EndOverflow),
+            HighlightRange::new_inclusive(0, usize::MAX)
+        );
+        assert_eq!(
+            Err(RangeConstructionError::Reversed { lower: 4, upper: 2 }),
+            HighlightRange::new_inclusive(4, 2)
+        );
+    }
+
+    #[test]
+    fn should_dedup_triplicate_ranges_before_validation() {
+        let highlighter = Highlighter::builder()
+            .dedup_contained(true)
+            .build()
+            .unwrap();
+        let ranges = vec![
+            HighlightRange::new(5, 7),
+            HighlightRange::new(5, 7),
+            HighlightRange::new(5, 7),
+        ];
+
+        assert_eq!(
+            Ok("This <em>is</em> a sample text".to_string()),
+            highlighter.highlight("This is a sample text", &ranges)
+        );
+    }
+
+    #[test]
+    fn should_still_reject_partial_overlaps_after_dedup() {
+        let highlighter = Highlighter::builder()
+            .dedup_contained(true)
+            .build()
+            .unwrap();
+        let ranges = vec![
+            HighlightRange::new(0, 4),
+            HighlightRange::new(0, 4),
+            HighlightRange::new(2, 7),
+        ];
+
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 4),
+                second: HighlightRange::new(2, 7),
+            }),
+            highlighter.highlight("This is a sample text", &ranges)
+        );
+    }
+
+    #[test]
+    fn should_render_only_the_first_highlights() {
+        let highlighter = Highlighter::builder().max_highlights(2).build().unwrap();
+        let ranges = vec![
+            HighlightRange::new(10, 11),
+            HighlightRange::new(0, 1),
+            HighlightRange::new(4, 5),
+            HighlightRange::new(2, 3),
+        ];
+
+        assert_eq!(
+            Ok(HighlightOutcome {
+                text: "<em>a</em> <em>b</em> c d e f".to_string(),
+                dropped: 2,
+                ids: vec![],
+                flattened: 0,
+                full_cover: false,
+            }),
+            highlighter.highlight_outcome("a b c d e f", &ranges)
+        );
+    }
+
+    #[test]
+    fn should_count_merged_ranges_once_against_the_limit() {
+        let highlighter = Highlighter::builder()
+            .merge_overlaps(true)
+            .max_highlights(1)
+            .build()
+            .unwrap();
+        let mut ranges = (0..10)
+            .map(|i| HighlightRange::new(i, i + 3))
+            .collect::<Vec<_>>();
+        ranges.push(HighlightRange::new(14, 15));
+
+        assert_eq!(
+            Ok(HighlightOutcome {
+                text: "<em>abcdefghijkl</em>mno".to_string(),
+                dropped: 1,
+                ids: vec![],
+                flattened: 0,
+                full_cover: false,
+            }),
+            highlighter.highlight_outcome("abcdefghijklmno", &ranges)
+        );
+    }
+
+    #[test]
+    fn should_drop_as_many_highlights_as_take_first_k() {
+        let input = "abcdefghijklmnopqrstuvwxyz0123";
+        let ranges = [(0, 2), (2, 5), (8, 10), (10, 12), (15, 20), (25, 30)]
+            .map(|(lower, upper)| HighlightRange::new(lower, upper));
+        let highlighter = Highlighter::builder().max_highlights(2).build().unwrap();
+
+        let outcome = highlighter.highlight_outcome(input, &ranges).unwrap();
+
+        let taken = take_first_k(ranges.to_vec(), 2);
+        assert_eq!(taken.dropped(), outcome.dropped);
+        // touching highlights count once but keep their own tags
+        assert_eq!(
+            "<em>ab</em><em>cde</em>fgh<em>ij</em><em>kl</em>mnopqrstuvwxyz0123",
+            outcome.text
+        );
+    }
+
+    #[test]
+    fn should_count_merged_spans_like_take_first_k() {
+        let mut generator = RangeGenerator::new(0x7a4e_51c3);
+
+        for _ in 0..500 {
+            let input = generator.input(16);
+            let mut ranges = generator.overlapping_ranges(&input);
+            sort_ranges(&mut ranges);
+            let k = generator.below(4);
+
+            let taken = take_first_k(ranges.clone(), k);
+            let (total, end) = count_merged_spans(ranges.iter(), k);
+            assert_eq!(taken.total, total, "{ranges:?}");
+            if taken.dropped() > 0 {
+                assert_eq!(
+                    taken.ranges.last().map(|span| span.upper),
+                    end,
+                    "{ranges:?}"
+                );
+            }
+        }
+
+        // nothing but the validation allocates when nothing is dropped
+        let input = "abcdefghijklmnop";
+        let highlighter = Highlighter::builder().max_highlights(8).build().unwrap();
+        let ranges = (0..8)
+            .map(|i| HighlightRange::new(2 * i, 2 * i + 1))
+            .collect::<Vec<_>>();
+        let mut sorted = Vec::with_capacity(ranges.len());
+        let (result, allocations) =
+            count_allocations(|| highlighter.plan(input, &ranges, |_| 0.0, &mut sorted));
+        let plan = result.unwrap();
+        assert!(matches!(plan.normalized, Cow::Borrowed(_)));
+        assert_eq!(0, plan.dropped);
+        assert_eq!(0, allocations);
+    }
+
+    #[test]
+    fn should_detect_full_cover_after_merging() {
+        let touching = [(0, 5), (5, 6), (6, 11)].map(|(l, u)| HighlightRange::new(l, u));
+        let with = |policy| Highlighter::builder().full_cover(policy).build().unwrap();
+
+        let wrapped = with(FullCoverPolicy::Wrap)
+            .highlight_outcome("Hello world", &touching)
+            .unwrap();
+        assert_eq!("<em>Hello</em><em> </em><em>world</em>", wrapped.text);
+        assert!(wrapped.full_cover);
+
+        let skipped = with(FullCoverPolicy::Skip)
+            .highlight_outcome("Hello world", &touching)
+            .unwrap();
+        assert_eq!("Hello world", skipped.text);
+        assert!(skipped.full_cover);
+        assert!(matches!(
+            with(FullCoverPolicy::Skip).highlight_cow("Hello world", &touching),
+            Ok(Cow::Borrowed("Hello world"))
+        ));
+
+        let tagged = with(FullCoverPolicy::Tag("<mark>".into(), "</mark>".into()));
+        assert_eq!(
+            Ok("<mark>Hello world</mark>".to_string()),
+            tagged.highlight("Hello world", &touching)
+        );
+
+        // overlapping ranges only add up to the whole input once merged
+        let merging = Highlighter::builder()
+            .merge_overlaps(true)
+            .escape_html(true)
+            .full_cover(FullCoverPolicy::Skip)
+            .build()
+            .unwrap();
+        assert_eq!(
+            Ok("a &lt; b".to_string()),
+            merging.highlight(
+                "a < b",
+                &[HighlightRange::new(0, 3), HighlightRange::new(1, 5)]
+            )
+        );
+    }
+
+    #[test]
+    fn should_honor_full_cover_in_every_entry_point() {
+        let input = "Hello world";
+        let touching = [HighlightRange::new(0, 5), HighlightRange::new(5, 11)];
+        let tag = FullCoverPolicy::Tag("<mark>".into(), "</mark>".into());
+        let tagged = Highlighter::builder()
+            .full_cover(tag.clone())
+            .anchor_ids("m")
+            .build()
+            .unwrap();
+
+        let mut out = String::new();
+        tagged.highlight_to_fmt(input, &touching, &mut out).unwrap();
+        assert_eq!("<mark>Hello world</mark>", out);
+        assert_eq!(
+            Ok("<mark>Hello</mark>…".to_string()),
+            tagged.highlight_truncated(input, &touching, 5)
+        );
+        let placed = tagged.highlight_placed(input, &touching).unwrap();
+        assert_eq!("<mark>Hello world</mark>", placed.text);
+        assert_eq!(
+            vec![PlacementStatus::Merged; 2],
+            placed
+                .placements
+                .iter()
+                .map(|p| p.status)
+                .collect::<Vec<_>>()
+        );
+        let segments = tagged.segments(input, &touching).unwrap();
+        assert_eq!(1, segments.len());
+        assert!(segments[0].highlighted);
+        let both = tagged
+            .highlight_both(input, &touching, &BothOptions::default())
+            .unwrap();
+        assert_eq!(
+            ("<mark>Hello world</mark>", input),
+            (&*both.highlighted, &*both.plain)
+        );
+        let outcome = tagged.highlight_outcome(input, &touching).unwrap();
+        assert!(outcome.ids.is_empty() && outcome.full_cover);
+
+        let skipping = Highlighter::builder()
+            .full_cover(FullCoverPolicy::Skip)
+            .overlap_policy(OverlapPolicy::Nest)
+            .build()
+            .unwrap();
+        let nested = [HighlightRange::new(0, 11), HighlightRange::new(0, 5)];
+        assert!(matches!(
+            skipping.highlight_cow(input, &nested),
+            Ok(Cow::Borrowed("Hello world"))
+        ));
+        let placed = skipping.highlight_placed(input, &nested).unwrap();
+        assert_eq!(input, placed.text);
+        assert!(
+            placed
+                .placements
+                .iter()
+                .all(|p| p.status == PlacementStatus::Dropped)
+        );
+        let segments = skipping.segments(input, &nested).unwrap();
+        assert_eq!(
+            vec![false],
+            segments.iter().map(|s| s.highlighted).collect::<Vec<_>>()
+        );
+        let nesting = Highlighter::builder()
+            .full_cover(tag)
+            .overlap_policy(OverlapPolicy::Nest)
+            .build()
+            .unwrap();
+        assert_eq!(
+            Ok("<mark>Hello world</mark>".to_string()),
+            nesting.highlight(input, &nested)
+        );
+    }
+
+    #[test]
+    fn should_wrap_partial_cover_regardless_of_full_cover_policy() {
+        let ranges = [HighlightRange::new(0, 5), HighlightRange::new(6, 11)];
+        let skipping = Highlighter::builder()
+            .full_cover(FullCoverPolicy::Skip)
+            .build()
+            .unwrap();
+
+        let outcome = skipping.highlight_outcome("Hello world", &ranges).unwrap();
+
+        assert_eq!("<em>Hello</em> <em>world</em>", outcome.text);
+        assert!(!outcome.full_cover);
+        assert_eq!(
+            Ok("<em>Hello world</em>".to_string()),
+            highlight_text("Hello world", [HighlightRange::new(0, 11)])
+        );
+        assert_eq!(
+            Ok(String::new()),
+            skipping.highlight("", &[HighlightRange::new(0, 0)])
+        );
+    }
+
+    #[test]
+    fn should_render_no_highlights_with_a_limit_of_zero() {
+        let highlighter = Highlighter::builder().max_highlights(0).build().unwrap();
+
+        assert_eq!(
+            Ok("a b".to_string()),
+            highlighter.highlight("a b", &[HighlightRange::new(0, 1)])
+        );
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(0, 9),
+                input_len: 3,
+            }),
+            highlighter.highlight("a b", &[HighlightRange::new(0, 9)])
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn should_highlight_every_field_of_a_document() {
+        let highlighter = DocumentHighlighter::new(Highlighter::default()).field_tags(
+            "title",
+            "<strong>",
+            "</strong>",
+        );
+
+        let highlighted = highlighter
+            .highlight([
+                ("title", "Rust in Action", vec![HighlightRange::new(0, 4)]),
+                ("body", "Rust is fast", vec![HighlightRange::new(0, 4)]),
+                ("tags", "programming", vec![]),
+            ])
+            .unwrap();
+
+        assert_eq!("<strong>Rust</strong> in Action", highlighted["title"]);
+        assert_eq!("<em>Rust</em> is fast", highlighted["body"]);
+        assert!(matches!(highlighted["tags"], Cow::Borrowed("programming")));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn should_name_the_field_that_failed() {
+        let result = highlight_document([
+            ("title", "Rust", vec![HighlightRange::new(0, 4)]),
+            ("body", "Rust", vec![HighlightRange::new(0, 9)]),
+        ]);
+
+        let error = result.unwrap_err();
+        assert_eq!("body", error.field);
+        assert_eq!(
+            "field body: range 0..9 is out of bounds for input of length 4",
+            error.to_string()
+        );
+
+        let error = ContextError::from(error);
+        assert_eq!(
+            ErrorContext::Field {
+                doc_id: None,
+                field: "body".to_string()
+            },
+            error.context
+        );
+        assert_eq!(
+            "field body: range 0..9 is out of bounds for input of length 4",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn should_name_the_context_of_a_failed_highlight() {
+        let overlapping = [HighlightRange::new(0, 5), HighlightRange::new(3, 8)];
+
+        let error = highlight_text_ctx("Hello world", overlapping.clone(), ("doc-17", "title"))
+            .unwrap_err();
+
+        assert_eq!(
+            "document doc-17, field title: ranges 0..5 and 3..8 overlap",
+            error.to_string()
+        );
+        assert_eq!(
+            highlight_text("Hello world", overlapping).unwrap_err(),
+            error.error
+        );
+        assert_eq!(
+            Ok("<em>Hello</em> world".to_string()),
+            highlight_text_ctx("Hello world", [HighlightRange::new(0, 5)], "doc-17")
+        );
+        assert_eq!(
+            "item 1: range 0..9 is out of bounds for input of length 2",
+            highlight_many([("ab", vec![]), ("cd", vec![HighlightRange::new(0, 9)])])[1]
+                .as_ref()
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn should_never_highlight_inside_configured_exclusions() {
+        let highlighter = Highlighter::builder()
+            .exclusions(vec![HighlightRange::new(4, 7)])
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            Ok("<em>x = </em>`x`<em> = 1</em>".to_string()),
+            highlighter.highlight("x = `x` = 1", &[HighlightRange::new(0, 11)])
+        );
+    }
+
+    #[test]
+    fn should_hightlight_overlaps_with_combined_labels() {
+        let labeled = vec![
+            LabeledRange::new(HighlightRange::new(0, 11), "person"),
+            LabeledRange::new(HighlightRange::new(5, 15), "match"),
+        ];
+
+        assert_eq!(
+            Ok(concat!(
+                r#"<em class="person">Alan </em><em class="match person">Turing</em>"#,
+                r#"<em class="match"> was</em> here"#
+            )
+            .to_string()),
+            highlight_text_labeled_combined("Alan Turing was here", labeled)
+        );
+    }
+
+    #[test]
+    fn should_overlay_layers_with_their_own_tags() {
+        let input = "Alan Turing was here";
+        let search = Layer::new("<mark>", "</mark>");
+        let comment = Layer::with_style(&TagStyle::SpanClass("comment".into()));
+        let layers = |a: &[HighlightRange], b: &[HighlightRange]| {
+            highlight_layers(input, &[(search.clone(), a), (comment.clone(), b)])
+        };
+
+        // B inside A
+        assert_eq!(
+            Ok(r#"<mark>Alan <span class="comment">Turing</span></mark> was here"#.to_string()),
+            layers(&[HighlightRange::new(0, 11)], &[HighlightRange::new(5, 11)])
+        );
+        // A inside B
+        assert_eq!(
+            Ok(r#"<span class="comment">Alan <mark>Turing</mark> was</span> here"#.to_string()),
+            layers(&[HighlightRange::new(5, 11)], &[HighlightRange::new(0, 15)])
+        );
+        // partially overlapping, the one starting later is split
+        assert_eq!(
+            Ok(concat!(
+                r#"<mark>Alan <span class="comment">Turing</span></mark>"#,
+                r#"<span class="comment"> was</span> here"#
+            )
+            .to_string()),
+            layers(&[HighlightRange::new(0, 11)], &[HighlightRange::new(5, 15)])
+        );
+        // disjoint
+        assert_eq!(
+            Ok(r#"<mark>Alan</mark> Turing <span class="comment">was</span> here"#.to_string()),
+            layers(&[HighlightRange::new(0, 4)], &[HighlightRange::new(12, 15)])
+        );
+        // the same range, the first layer outside
+        assert_eq!(
+            Ok(r#"Alan <mark><span class="comment">Turing</span></mark> was here"#.to_string()),
+            layers(&[HighlightRange::new(5, 11)], &[HighlightRange::new(5, 11)])
+        );
+    }
+
+    #[test]
+    fn should_apply_overlap_policy_within_each_layer() {
+        let input = "Alan Turing was here";
+        let overlapping = [HighlightRange::new(0, 11), HighlightRange::new(5, 15)];
+        let other = [HighlightRange::new(12, 20)];
+
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 11),
+                second: HighlightRange::new(5, 15),
+            }),
+            highlight_layers(
+                input,
+                &[
+                    (Layer::new("<b>", "</b>"), &overlapping),
+                    (Layer::new("<i>", "</i>"), &other)
+                ]
+            )
+        );
+        assert_eq!(
+            Ok("<b>Alan Turing <i>was</i></b><i> here</i>".to_string()),
+            highlight_layers(
+                input,
+                &[
+                    (
+                        Layer::new("<b>", "</b>").overlap_policy(OverlapPolicy::Merge),
+                        &overlapping
+                    ),
+                    (Layer::new("<i>", "</i>"), &other),
+                ]
+            )
+        );
+        assert_eq!(
+            Ok("<b>Alan <b>Turing</b></b><b> was</b> here".to_string()),
+            highlight_layers(
+                input,
+                &[(
+                    Layer::new("<b>", "</b>").overlap_policy(OverlapPolicy::Nest),
+                    &overlapping
+                )]
+            )
+        );
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(12, 21),
+                input_len: 20,
+            }),
+            highlight_layers(
+                input,
+                &[
+                    (Layer::new("<b>", "</b>"), &overlapping[..1]),
+                    (Layer::new("<i>", "</i>"), &[HighlightRange::new(12, 21)]),
+                ]
+            )
+        );
+        assert_eq!(Ok(input.to_string()), highlight_layers(input, &[]));
+    }
+
+    #[test]
+    fn should_reject_too_many_ranges_before_sorting_them() {
+        let input = "Hello world";
+        let hostile = vec![HighlightRange::new(0, 1); 1_000_000];
+        let limited = Highlighter::builder().max_ranges(1000).build().unwrap();
+
+        let (result, allocations) = count_allocations(|| limited.highlight(input, &hostile));
+        let error = result.unwrap_err();
+        assert_eq!(
+            HighlightingError::TooManyRanges {
+                count: 1_000_000,
+                max: 1000
+            },
+            error
+        );
+        assert_eq!("1000000 ranges exceed the limit of 1000", error.to_string());
+        // rejected before anything is copied, sorted or rendered
+        assert_eq!(0, allocations);
+
+        // the lossy variant skips all ranges instead
+        let long = "a".repeat(1001);
+        let distinct = (0..1001)
+            .map(|i| HighlightRange::new(i, i + 1))
+            .collect::<Vec<_>>();
+        let (out, skipped) = limited.highlight_lossy(&long, &distinct);
+        assert_eq!(long, out);
+        assert_eq!(1001, skipped.len());
+        let ((out, skipped), allocations) =
+            count_allocations(|| limited.highlight_lossy(input, &hostile));
+        assert_eq!(input, out);
+        assert_eq!(1_000_000, skipped.len());
+        // only the output and the list of skipped ranges
+        assert_eq!(2, allocations);
+        assert_eq!(
+            Ok("<em>H</em>ello world".to_string()),
+            limited.highlight(input, &hostile[..1])
+        );
+
+        let nesting = Highlighter::builder()
+            .overlap_policy(OverlapPolicy::Nest)
+            .max_ranges(1000)
+            .build()
+            .unwrap();
+        let mut out = String::new();
+        let (result, allocations) =
+            count_allocations(|| nesting.highlight_to_fmt(input, &hostile, &mut out));
+        assert!(
+            matches!(
+                result,
+                Err(HighlightError::Invalid(
+                    HighlightingError::TooManyRanges { .. }
+                ))
+            ),
+            "{result:?}"
+        );
+        assert_eq!(0, allocations);
+    }
+
+    #[test]
+    fn should_reject_a_large_output_before_rendering_it() {
+        let input = "Hello world";
+        let ranges = [HighlightRange::new(0, 5), HighlightRange::new(6, 11)];
+        // 11 bytes of input and 2 * 9 bytes of tags
+        let limited = Highlighter::builder().max_output_bytes(28).build().unwrap();
+
+        let (result, allocations) = count_allocations(|| limited.highlight(input, &ranges));
+        let error = result.unwrap_err();
+        assert_eq!(
+            HighlightingError::OutputTooLarge {
+                estimated: 29,
+                max: 28
+            },
+            error
+        );
+        assert_eq!(
+            "estimated output of 29 bytes exceeds the limit of 28 bytes",
+            error.to_string()
+        );
+        // nothing but the buffer the ranges are sorted in
+        assert_eq!(1, allocations);
+        assert_eq!(
+            Ok("<em>Hello</em> world".to_string()),
+            limited.highlight(input, &ranges[..1])
+        );
+        // zero-length ranges aren't rendered and merged ones are counted once
+        let empty = [HighlightRange::new(0, 5), HighlightRange::new(8, 8)];
+        assert_eq!(
+            Ok("<em>Hello</em> world".to_string()),
+            limited.highlight(input, &empty)
+        );
+        let merging = Highlighter::builder()
+            .merge_overlaps(true)
+            .max_output_bytes(28)
+            .build()
+            .unwrap();
+        let overlapping = [HighlightRange::new(0, 5), HighlightRange::new(2, 8)];
+        assert_eq!(
+            Ok("<em>Hello wo</em>rld".to_string()),
+            merging.highlight(input, &overlapping)
+        );
+
+        // custom tags count with their own length
+        let huge = "<b>".repeat(1000);
+        let tagged = [TaggedRange::new(
+            HighlightRange::new(0, 5),
+            Some((huge, "</b>".to_string())),
+        )];
+        let (result, allocations) = count_allocations(|| limited.highlight_tagged(input, &tagged));
+        assert_eq!(
+            Err(HighlightingError::OutputTooLarge {
+                estimated: 3015,
+                max: 28
+            }),
+            result
+        );
+        assert_eq!(0, allocations);
+    }
+
+    #[cfg(feature = "unicode-segmentation")]
+    #[test]
+    fn should_merge_ranges_colliding_after_snapping() {
+        let flag = "\u{1F1E9}\u{1F1EA}";
+        let highlighter = Highlighter::builder()
+            .snap_to_graphemes(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            Ok("<em>\u{1F1E9}\u{1F1EA}</em>".to_string()),
+            highlighter.highlight(
+                flag,
+                &[HighlightRange::new(0, 4), HighlightRange::new(4, 8)]
+            )
+        );
+        // only overlaps the snapping created are merged, the others are still rejected
+        assert!(matches!(
+            highlighter.highlight(
+                "Hello world",
+                &[HighlightRange::new(0, 5), HighlightRange::new(3, 8)]
+            ),
+            Err(HighlightingError::OverlappingRanges { .. })
+        ));
+        let merging = Highlighter::builder()
+            .snap_to_graphemes(true)
+            .merge_overlaps(true)
+            .build()
+            .unwrap();
+        assert_eq!(
+            Ok("<em>Hello wo</em>rld".to_string()),
+            merging.highlight(
+                "Hello world",
+                &[HighlightRange::new(0, 5), HighlightRange::new(3, 8)]
+            )
+        );
+    }
+
+    #[test]
+    fn should_rebuild_highlight_text_from_events() {
+        let input = "This is a sample text";
+        let ranges = vec![
+            HighlightRange::new(10, 16),
+            HighlightRange::new(4, 4),
+            HighlightRange::new(0, 4),
+            HighlightRange::new(5, 7),
+        ];
+
+        let mut rebuilt = String::new();
+        let mut depth = 0;
+        for event in highlight_events(input, &ranges).unwrap() {
+            match event {
+                HighlightEvent::Text(text) => {
+                    assert!(!text.is_empty());
+                    rebuilt.push_str(text);
+                }
+                HighlightEvent::Start(_) => {
+                    depth += 1;
+                    rebuilt.push_str("<em>");
+                }
+                HighlightEvent::End(_) => {
+                    depth -= 1;
+                    rebuilt.push_str("</em>");
+                }
+            }
+            assert!((0..=1).contains(&depth));
+        }
+
+        assert_eq!(0, depth);
+        assert_eq!(highlight_text(input, ranges).unwrap(), rebuilt);
+    }
+
+    #[test]
+    fn should_emit_range_indices_with_start_and_end() {
+        let events = highlight_events(
+            "ab cd",
+            [HighlightRange::new(3, 5), HighlightRange::new(0, 2)],
+        )
+        .unwrap()
+        .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                HighlightEvent::Start(1),
+                HighlightEvent::Text("ab"),
+                HighlightEvent::End(1),
+                HighlightEvent::Text(" "),
+                HighlightEvent::Start(0),
+                HighlightEvent::Text("cd"),
+                HighlightEvent::End(0),
+            ],
+            events
+        );
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 2),
+                second: HighlightRange::new(1, 3),
+            }),
+            highlight_events(
+                "ab cd",
+                [HighlightRange::new(0, 2), HighlightRange::new(1, 3)]
+            )
+            .map(|events| events.count())
+        );
+    }
+
+    #[test]
+    fn should_attach_payloads_to_annotated_events() {
+        let ranges = vec![AnnotatedRange::new(HighlightRange::new(0, 2), "greeting")];
+
+        let events = highlight_events_annotated("hi!", &ranges)
+            .unwrap()
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                HighlightEvent::Start(&"greeting"),
+                HighlightEvent::Text("hi"),
+                HighlightEvent::End(&"greeting"),
+                HighlightEvent::Text("!"),
+            ],
+            events
+        );
+    }
+
+    #[test]
+    fn should_iterate_the_same_segments_as_highlight_segments() {
+        let input = "This is a sample text";
+        let ranges = vec![
+            HighlightRange::new(10, 16),
+            HighlightRange::new(4, 4),
+            HighlightRange::new(0, 4),
+            HighlightRange::new(5, 7),
+            HighlightRange::new(21, 21),
+        ];
+        let expected = highlight_segments(input, &ranges).unwrap();
+
+        let forward = iter_segments(input, &ranges).unwrap().collect::<Vec<_>>();
+        let mut backward = iter_segments(input, &ranges)
+            .unwrap()
+            .rev()
+            .collect::<Vec<_>>();
+        backward.reverse();
+
+        assert_eq!(expected, forward);
+        assert_eq!(expected, backward);
+        assert!(
+            forward
+                .iter()
+                .all(|s| &input[s.range.lower()..s.range.upper()] == s.text)
+        );
+    }
+
+    #[test]
+    fn should_iterate_segments_from_both_ends() {
+        let mut segments = iter_segments("ab cd ef", [HighlightRange::new(3, 5)]).unwrap();
+        assert_eq!((1, Some(3)), segments.size_hint());
+
+        assert_eq!(Some("ab "), segments.next().map(|s| s.text));
+        assert_eq!(Some(" ef"), segments.next_back().map(|s| s.text));
+        assert_eq!(Some("cd"), segments.next_back().map(|s| s.text));
+        assert_eq!(None, segments.next());
+        assert_eq!(None, segments.next_back());
+        assert_eq!((0, Some(1)), segments.size_hint());
+
+        assert_eq!(
+            0,
+            iter_segments("", Vec::<HighlightRange>::new())
+                .unwrap()
+                .count()
+        );
+    }
+
+    #[test]
+    fn should_hightlight_in_place_like_highlight_text() {
+        let cases: [(&str, &[HighlightRange]); 5] = [
+            (
+                "This is a sample text",
+                &[HighlightRange::new(5, 7), HighlightRange::new(10, 16)],
+            ),
+            // touching ranges and a range ending at the end of the input
+            (
+                "abcdef",
+                &[
+                    HighlightRange::new(4, 6),
+                    HighlightRange::new(0, 2),
+                    HighlightRange::new(2, 4),
+                ],
+            ),
+            // multi-byte chars and zero-length ranges
+            (
+                "über größe",
+                &[
+                    HighlightRange::new(0, 2),
+                    HighlightRange::new(6, 6),
+                    HighlightRange::new(7, 12),
+                ],
+            ),
+            ("", &[HighlightRange::new(0, 0)]),
+            ("no highlights", &[]),
+        ];
+
+        for (input, highlights) in cases {
+            let mut text = input.to_string();
+            highlight_in_place(&mut text, highlights).unwrap();
+
+            assert_eq!(highlight_text(input, highlights).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn should_leave_the_text_unchanged_when_highlighting_in_place_fails() {
+        let mut text = "Hello world".to_string();
+
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 5),
+                second: HighlightRange::new(3, 8),
+            }),
+            highlight_in_place(
+                &mut text,
+                &[HighlightRange::new(0, 5), HighlightRange::new(3, 8)]
+            )
+        );
+        assert_eq!("Hello world", text);
+    }
+
+    #[test]
+    fn should_hightlight_a_mix_of_absolute_and_anchored_ranges() {
+        assert_eq!(
+            Ok("<em>Total</em>: 12,50 <em>€</em>".to_string()),
+            highlight_text_anchored(
+                "Total: 12,50 €",
+                [
+                    AnchoredRange::from(0..5),
+                    AnchoredRange::from_end_chars(1, 0),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn should_render_diagnostics_by_severity_in_html_and_ansi() {
+        let input = "port = 80a # todo";
+        let findings = vec![
+            (HighlightRange::new(13, 17), Severity::Info),
+            (HighlightRange::new(0, 10), Severity::Warning),
+            (HighlightRange::new(7, 10), Severity::Error),
+        ];
+
+        assert_eq!(
+            Ok(concat!(
+                r#"<span class="sev-warning">port = </span><span class="sev-error">80a</span>"#,
+                r#" # <span class="sev-info">todo</span>"#
+            )
+            .to_string()),
+            highlight_diagnostics_html(input, findings.clone(), &BTreeMap::new())
+        );
+        assert_eq!(
+            Ok("\x1b[33mport = \x1b[0m\x1b[31m80a\x1b[0m # \x1b[34mtodo\x1b[0m".to_string()),
+            highlight_diagnostics_ansi(input, findings, &BTreeMap::new())
+        );
+    }
+
+    #[test]
+    fn should_override_severity_styles() {
+        let findings = vec![(HighlightRange::new(0, 4), Severity::Error)];
+
+        let html = BTreeMap::from([(Severity::Error, TagStyle::Strong)]);
+        assert_eq!(
+            Ok("<strong>port</strong> = 80a".to_string()),
+            highlight_diagnostics_html("port = 80a", findings.clone(), &html)
+        );
+
+        let ansi = BTreeMap::from([(Severity::Error, AnsiStyle::Bold)]);
+        assert_eq!(
+            Ok("\x1b[1mport\x1b[0m = 80a".to_string()),
+            highlight_diagnostics_ansi("port = 80a", findings, &ansi)
+        );
+    }
+
+    #[test]
+    fn should_render_a_code_snippet_with_context_and_carets() {
+        let input = "fn main() {\n    let x = 1;\n    x + z\n}\n";
+        let z = input.find('z').unwrap();
+
+        assert_eq!(
+            Ok(concat!(
+                "2 |     let x = 1;\n",
+                "3 |     x + z\n",
+                "  |         ^\n",
+                "4 | }\n",
+            )
+            .to_string()),
+            render_code_snippet(
+                input,
+                vec![HighlightRange::new(z, z + 1)],
+                1,
+                SnippetStyle::Caret
+            )
+        );
+    }
+
+    #[test]
+    fn should_not_overflow_with_unbounded_context_or_fragment_length() {
+        let input = "fn main() {\n    let x = 1;\n    x + z\n}\n";
+        let z = input.find('z').unwrap();
+        let z = [HighlightRange::new(z, z + 1)];
+
+        assert_eq!(
+            render_code_snippet(input, z.clone(), 4, SnippetStyle::Caret),
+            render_code_snippet(input, z.clone(), usize::MAX, SnippetStyle::Caret)
+        );
+        assert_eq!(
+            highlight_text(input, z.clone()),
+            highlight_snippet(input, &z, usize::MAX)
+        );
+        assert_eq!(
+            1,
+            fragments(input, &z, usize::MAX, usize::MAX).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn should_mark_multi_line_spans_and_several_spans_per_line() {
+        let input = "let a = b + c;\nlet d = (\n  e);\n";
+
+        assert_eq!(
+            Ok(concat!(
+                "1 | let a = b + c;\n",
+                "  |         ^   ^\n",
+                "2 | let d = (\n",
+                "  |         ^\n",
+                "3 |   e);\n",
+                "  | ^^^^^\n",
+            )
+            .to_string()),
+            render_code_snippet(input, [8..9, 12..13, 23..30], 0, SnippetStyle::Caret)
+        );
+    }
+
+    #[test]
+    fn should_split_far_apart_lines_into_blocks() {
+        let input = (1..=12).map(|i| format!("line {i}\n")).collect::<String>();
+        let first = input.find("line 2").unwrap();
+        let last = input.find("line 11").unwrap();
+
+        assert_eq!(
+            Ok(concat!(
+                " 1 | line 1\n",
+                " 2 | \x1b[1mline\x1b[0m 2\n",
+                " 3 | line 3\n",
+                "...\n",
+                "10 | line 10\n",
+                "11 | line \x1b[1m11\x1b[0m\n",
+                "12 | line 12\n",
+            )
+            .to_string()),
+            render_code_snippet(
+                &input,
+                [first..first + 4, last + 5..last + 7],
+                1,
+                SnippetStyle::Ansi(AnsiStyle::Bold)
+            )
+        );
+    }
+
+    #[test]
+    fn should_merge_touching_ranges_with_the_same_label_when_rendering() {
+        assert_eq!(
+            Ok(r#"<em class="person">Ada Love</em><em class="location">lace</em>"#.to_string()),
+            highlight_text_labeled(
+                "Ada Lovelace",
+                vec![
+                    LabeledRange::new(HighlightRange::new(4, 8), "person"),
+                    LabeledRange::new(HighlightRange::new(0, 3), "person"),
+                    LabeledRange::new(HighlightRange::new(3, 4), "person"),
+                    LabeledRange::new(HighlightRange::new(8, 12), "location"),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn should_render_highlights_through_a_template() {
+        assert_eq!(
+            Ok(r##"This <a href="#m0">is</a> a <a href="#m1">sample</a> text"##.to_string()),
+            highlight_text_template(
+                "This is a sample text",
+                vec![HighlightRange::new(10, 16), HighlightRange::new(5, 7)],
+                r##"<a href="#m{index}">{text}</a>"##
+            )
+        );
+        assert_eq!(
+            Ok("[This]{0..4} is".to_string()),
+            highlight_text_template(
+                "This is",
+                vec![HighlightRange::new(0, 4)],
+                "[{text}]{{{start}..{end}}}"
+            )
+        );
+    }
+
+    #[test]
+    fn should_render_plain_text_through_a_second_template() {
+        assert_eq!(
+            Ok("(Hello )[world]".to_string()),
+            highlight_text_templates(
+                "Hello world",
+                vec![HighlightRange::new(6, 11)],
+                "[{text}]",
+                "({text})"
+            )
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_templates() {
+        let highlights = vec![HighlightRange::new(0, 5)];
+
+        assert_eq!(
+            Err(HighlightingError::UnknownPlaceholder(HighlightRange::new(
+                4, 7
+            ))),
+            highlight_text_template("Hello", highlights.clone(), "<b>{txt}</b>")
+        );
+        assert_eq!(
+            Err(HighlightingError::UnmatchedBrace(3)),
+            highlight_text_template("Hello", highlights.clone(), "<b>{text</b>")
+        );
+        assert_eq!(
+            Err(HighlightingError::UnmatchedBrace(7)),
+            highlight_text_template("Hello", highlights.clone(), "{text} }")
+        );
+        assert_eq!(
+            Err(HighlightingError::UnknownPlaceholder(HighlightRange::new(
+                1, 6
+            ))),
+            highlight_text_templates("Hello", highlights, "{text}", "{index}")
+        );
+    }
+
+    #[test]
+    fn should_give_highlights_anchor_ids_after_merging() {
+        let highlighter = Highlighter::builder()
+            .merge_overlaps(true)
+            .anchor_ids("hl-")
+            .build()
+            .unwrap();
+        let ranges = vec![
+            HighlightRange::new(8, 11),
+            HighlightRange::new(0, 3),
+            HighlightRange::new(2, 5),
+        ];
+
+        assert_eq!(
+            Ok(HighlightOutcome {
+                text: r#"<em id="hl-0">abcde</em>fgh<em id="hl-1">ijk</em>"#.to_string(),
+                dropped: 0,
+                ids: vec!["hl-0".to_string(), "hl-1".to_string()],
+                flattened: 0,
+                full_cover: false,
+            }),
+            highlighter.highlight_outcome("abcdefghijk", &ranges)
+        );
+        assert_eq!(
+            Ok(r#"<em id="hl-0">abcde</em>fgh<em id="hl-1">ijk</em>"#.to_string()),
+            highlighter.highlight("abcdefghijk", &ranges)
+        );
+    }
+
+    #[test]
+    fn should_give_anchor_ids_and_annotations_to_the_highlights_max_highlights_keeps() {
+        let ranges = [HighlightRange::new(6, 11), HighlightRange::new(0, 5)];
+        let anchored = Highlighter::builder()
+            .max_highlights(1)
+            .anchor_ids("m")
+            .build()
+            .unwrap();
+        let annotated = Highlighter::builder()
+            .max_highlights(1)
+            .debug_annotations(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            Ok(r#"<em id="m0">hello</em> world"#.to_string()),
+            anchored.highlight("hello world", &ranges)
+        );
+        assert_eq!(
+            Ok(r#"<em data-range="0..5" data-src-index="1">hello</em> world"#.to_string()),
+            annotated.highlight("hello world", &ranges)
+        );
+    }
+
+    #[test]
+    fn should_compose_anchor_ids_with_classes() {
+        let outcome = highlight_text_labeled_with_ids(
+            "Ada in London",
+            vec![
+                LabeledRange::new(HighlightRange::new(7, 13), "location"),
+                LabeledRange::new(HighlightRange::new(0, 3), "person"),
+            ],
+            "m",
+        )
+        .unwrap();
+
+        assert_eq!(
+            r#"<em class="person" id="m0">Ada</em> in <em class="location" id="m1">London</em>"#,
+            outcome.text
+        );
+        assert_eq!(vec!["m0", "m1"], outcome.ids);
+    }
+
+    #[test]
+    fn should_drive_a_custom_renderer() {
+        /// Typst markup, with the range offsets in a label
+        #[derive(Default)]
+        struct Typst(String);
+
+        impl Render for Typst {
+            type Output = String;
+
+            fn text(&mut self, s: &str) {
+                self.0.push_str(s);
+            }
+
+            fn start(&mut self, _: &SegmentMeta<'_>) {
+                self.0.push_str("#highlight[");
+            }
+
+            fn end(&mut self, meta: &SegmentMeta<'_>) {
+                let label = meta.label.unwrap_or("hl");
+                self.0
+                    .push_str(&format!("]<{label}-{}-{}>", meta.index, meta.range));
+            }
+
+            fn finish(self) -> String {
+                self.0
+            }
+        }
+
+        let highlights = vec![HighlightRange::new(10, 16), HighlightRange::new(5, 7)];
+        assert_eq!(
+            Ok("This #highlight[is]<hl-1-5..7> a #highlight[sample]<hl-0-10..16> text".to_string()),
+            highlight_with_renderer("This is a sample text", &highlights, Typst::default())
+        );
+
+        let labeled = vec![LabeledRange::new(HighlightRange::new(0, 4), "noun")];
+        assert_eq!(
+            Ok("#highlight[This]<noun-0-0..4> is".to_string()),
+            highlight_labeled_with_renderer("This is", &labeled, Typst::default())
+        );
+    }
+
+    #[test]
+    fn should_render_built_in_formats_through_the_renderer() {
+        let highlights = vec![HighlightRange::new(0, 5)];
+
+        assert_eq!(
+            Ok("<em>a &lt; b</em>!".to_string()),
+            highlight_with_renderer("a < b!", &highlights, HtmlRenderer::escaped())
+        );
+        assert_eq!(
+            Ok("\x1b[1ma < b\x1b[0m!".to_string()),
+            highlight_with_renderer("a < b!", &highlights, AnsiRenderer::new(AnsiStyle::Bold))
+        );
+        assert_eq!(
+            Ok("==a \\= b==!".to_string()),
+            highlight_with_renderer(
+                "a = b!",
+                &highlights,
+                MarkdownRenderer::new(MarkdownDelimiter::Mark)
+            )
+        );
+    }
+
+    #[test]
+    fn should_render_with_the_tags_and_escaping_of_a_highlighter() {
+        let highlighter = Highlighter::builder()
+            .tag_style(TagStyle::Mark)
+            .escape_html(true)
+            .split_at_newlines(true)
+            .build()
+            .unwrap();
+        let highlights = vec![HighlightRange::new(0, 5)];
+
+        assert_eq!(
+            Ok("<mark>a &lt;</mark>\n<mark>b</mark>!".to_string()),
+            highlight_with_renderer("a <\nb!", &highlights, highlighter.renderer())
+        );
+        assert_eq!(
+            highlighter.highlight("a <\nb!", &highlights),
+            highlight_with_renderer("a <\nb!", &highlights, highlighter.renderer())
+        );
+
+        let labeled = vec![LabeledRange::new(HighlightRange::new(0, 1), "var")];
+        assert_eq!(
+            Ok("<mark class=\"var\">a</mark> &lt;".to_string()),
+            highlight_labeled_with_renderer("a <", &labeled, highlighter.renderer())
+        );
+    }
+
+    #[test]
+    fn should_isolate_highlighted_rtl_text() {
+        let input = "say שלום now";
+        let highlighter = Highlighter::builder()
+            .open_tag("[")
+            .close_tag("]")
+            .bidi_isolate(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            Ok("say [\u{2068}שלום\u{2069}] now".to_string()),
+            highlighter.highlight(input, &[HighlightRange::new(4, 12)])
+        );
+        assert_eq!(
+            "say [\u{2068}שלום\u{2069}] now".as_bytes(),
+            b"say [\xe2\x81\xa8\xd7\xa9\xd7\x9c\xd7\x95\xd7\x9d\xe2\x81\xa9] now"
+        );
+        // only the 6 chars of the input count
+        assert_eq!(
+            Ok("say [\u{2068}ש\u{2069}]…".to_string()),
+            highlighter.highlight_truncated(input, &[HighlightRange::new(4, 12)], 5)
+        );
+    }
+
+    #[test]
+    fn should_isolate_rtl_text_in_ansi_output() {
+        let input = "say שלום now";
+        let renderer = AnsiRenderer::new(AnsiStyle::Bold).bidi_isolate(true);
+
+        assert_eq!(
+            Ok("say \x1b[1m\u{2068}שלום\u{2069}\x1b[0m now".to_string()),
+            highlight_with_renderer(input, &[HighlightRange::new(4, 12)], renderer)
+        );
+
+        let options = WrapOptions {
+            bidi_isolate: true,
+            ..WrapOptions::new(8)
+        };
+        assert_eq!(
+            Ok("say \x1b[1m\u{2068}שלום\u{2069}\x1b[0m\nnow".to_string()),
+            highlight_wrap_ansi_with(
+                input,
+                vec![HighlightRange::new(4, 12)],
+                AnsiStyle::Bold,
+                &options
+            )
+        );
+    }
+
+    #[test]
+    fn should_build_a_tree_of_highlights() {
+        assert_eq!(
+            Ok(vec![
+                Node::Text("Hello "),
+                Node::Highlight {
+                    label: None,
+                    children: vec![Node::Text("world")],
+                },
+            ]),
+            highlight_tree("Hello world", vec![HighlightRange::new(6, 11)])
+        );
+        assert_eq!(
+            Ok(vec![
+                Node::Highlight {
+                    label: None,
+                    children: vec![
+                        Node::Text("ab"),
+                        Node::Highlight {
+                            label: None,
+                            children: vec![Node::Text("cd")],
+                        },
+                    ],
+                },
+                Node::Highlight {
+                    label: None,
+                    children: vec![Node::Text("e")],
+                },
+                Node::Text("f")
+            ]),
+            highlight_tree(
+                "abcdef",
+                vec![HighlightRange::new(0, 4), HighlightRange::new(2, 5)]
+            )
+        );
+    }
+
+    #[test]
+    fn should_reconstruct_the_input_from_the_tree() {
+        fn concat(nodes: &[Node<'_>], out: &mut String) {
+            for node in nodes {
+                match node {
+                    Node::Text(text) => out.push_str(text),
+                    Node::Highlight { children, .. } => concat(children, out),
+                }
+            }
+        }
+
+        let input = "The quick brown fox jumps";
+        let labeled = vec![
+            LabeledRange::new(HighlightRange::new(4, 15), "phrase"),
+            LabeledRange::new(HighlightRange::new(10, 19), "animal"),
+            LabeledRange::new(HighlightRange::new(4, 9), "word"),
+            LabeledRange::new(HighlightRange::new(20, 20), "marker"),
+        ];
+        let tree = highlight_tree_labeled(input, &labeled).unwrap();
+
+        let mut text = String::new();
+        concat(&tree, &mut text);
+        assert_eq!(input, text);
+        assert_eq!(
+            Node::Highlight {
+                label: Some("phrase"),
+                children: vec![
+                    Node::Highlight {
+                        label: Some("word"),
+                        children: vec![Node::Text("quick")],
+                    },
+                    Node::Text(" "),
+                    Node::Highlight {
+                        label: Some("animal"),
+                        children: vec![Node::Text("brown")],
+                    },
+                ],
+            },
+            tree[1]
+        );
+    }
+
+    #[test]
+    fn should_not_allocate_with_warm_highlight_scratch() {
+        let highlighter = Highlighter::builder().escape_html(true).build().unwrap();
+        let mut scratch = HighlightScratch::default();
+        let mut out = String::new();
+        let snippets = [
+            (
+                "the <quick> brown fox",
+                vec![HighlightRange::new(4, 11), HighlightRange::new(18, 21)],
+            ),
+            (
+                "jumps over",
+                vec![HighlightRange::new(6, 10), HighlightRange::new(0, 5)],
+            ),
+            ("the lazy dog & cat", vec![]),
+        ];
+        // warms up the scratch and the output
+        for (input, ranges) in &snippets {
+            highlighter
+                .highlight_into(input, ranges, &mut scratch, &mut out)
+                .unwrap();
+        }
+
+        for _ in 0..1000 {
+            for (input, ranges) in &snippets {
+                let (result, allocations) = count_allocations(|| {
+                    highlighter.highlight_into(input, ranges, &mut scratch, &mut out)
+                });
+
+                assert_eq!(Ok(()), result);
+                assert_eq!(0, allocations);
+                assert_eq!(highlighter.highlight(input, ranges).as_ref(), Ok(&out));
+            }
+        }
+    }
+
+    #[test]
+    fn should_render_empty_input_the_same_in_every_renderer() {
+        type Renderer = fn(&[HighlightRange]) -> Result<String, HighlightingError>;
+        let renderers: [(&str, Renderer); 19] = [
+            ("text", |h| highlight_text("", h.to_vec())),
+            ("escaped", |h| highlight_text_escaped("", h.to_vec())),
+            ("inverted", |h| highlight_text_inverted("", h.to_vec())),
+            ("ansi", |h| {
+                highlight_text_ansi("", h.to_vec(), AnsiStyle::Bold)
+            }),
+            ("bbcode", |h| highlight_text_bbcode("", h.to_vec(), "b")),
+            ("latex", |h| highlight_text_latex("", h.to_vec())),
+            ("markdown", |h| {
+                highlight_text_markdown("", h.to_vec(), MarkdownDelimiter::Strong)
+            }),
+            ("pango", |h| {
+                highlight_text_pango("", h.to_vec(), &PangoAttrs::new())
+            }),
+            ("caret", |h| highlight_text_caret("", h.to_vec())),
+            ("wrap html", |h| highlight_wrap_html("", h.to_vec(), 10)),
+            ("wrap ansi", |h| {
+                highlight_wrap_ansi("", h.to_vec(), AnsiStyle::Bold, 10)
+            }),
+            ("bytes", |h| highlight_bytes(b"", h)),
+            ("labeled", |h| {
+                let labeled = h.iter().map(|h| LabeledRange::new(h.clone(), "a"));
+                highlight_text_labeled("", labeled.collect())
+            }),
+            ("validated", |h| {
+                validate("", h.to_vec()).map(|v| v.to_html())
+            }),
+            ("segments", |h| {
+                let segments = highlight_segments("", h.to_vec())?;
+                Ok(segments.into_iter().map(|s| s.text).collect())
+            }),
+            ("cow", |h| highlight_text_cow("", h).map(Cow::into_owned)),
+            ("anchored", |h| {
+                let builder = Highlighter::builder().anchor_ids("m-");
+                builder.build().unwrap().highlight("", h)
+            }),
+            ("nested", |h| {
+                let builder = Highlighter::builder().overlap_policy(OverlapPolicy::Nest);
+                builder.build().unwrap().highlight("", h)
+            }),
+            ("coverage", |h| {
+                let builder = Highlighter::builder().max_coverage(0.5, CoverageAction::Skip);
+                builder.build().unwrap().highlight("", h)
+            }),
+        ];
+
+        for (name, render) in renderers {
+            assert_eq!(Ok(String::new()), render(&[]), "{name}");
+            assert_eq!(
+                Ok(String::new()),
+                render(&[HighlightRange::new(0, 0)]),
+                "{name}"
+            );
+            for range in [HighlightRange::new(0, 1), HighlightRange::new(1, 1)] {
+                assert_eq!(
+                    Err(HighlightingError::RangesOutOfBounds {
+                        range: range.clone(),
+                        input_len: 0
+                    }),
+                    render(&[range]),
+                    "{name}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn should_render_zero_length_range_over_empty_input_as_empty_marker() {
+        let markers = Highlighter::builder().allow_empty_markers(true);
+        let empty = [HighlightRange::new(0, 0)];
+
+        assert_eq!(
+            Ok("<em></em>".to_string()),
+            highlight_text_with_empty_markers("", empty.clone())
+        );
+        assert_eq!(
+            Ok("<em id=\"m-0\"></em>".to_string()),
+            markers
+                .clone()
+                .anchor_ids("m-")
+                .build()
+                .unwrap()
+                .highlight("", &empty)
+        );
+        assert_eq!(
+            Ok(vec![Segment {
+                text: "",
+                range: HighlightRange::new(0, 0),
+                highlighted: true
+            }]),
+            markers.clone().build().unwrap().segments("", &empty)
+        );
+        // clamped into the empty input, any range turns into an empty marker
+        let clamping = markers.bounds_policy(BoundsPolicy::Clamp).build().unwrap();
+        assert_eq!(
+            Ok("<em></em>".to_string()),
+            clamping.highlight("", &[HighlightRange::new(0, 4)])
+        );
+    }
+
+    #[test]
+    fn should_borrow_empty_input_without_allocating() {
+        let highlighter = Highlighter::builder()
+            .escape_html(true)
+            .anchor_ids("m-")
+            .exclusions([HighlightRange::new(0, 3)])
+            .max_highlights(2)
+            .build()
+            .unwrap();
+        let mut scratch = HighlightScratch::default();
+        let mut out = String::new();
+
+        let (cow, allocations) = count_allocations(|| highlighter.highlight_cow("", &[]));
+        assert!(matches!(cow, Ok(Cow::Borrowed(""))), "{cow:?}");
+        assert_eq!(0, allocations);
+
+        let (result, allocations) =
+            count_allocations(|| highlighter.highlight_into("", &[], &mut scratch, &mut out));
+        assert_eq!(Ok(()), result);
+        assert_eq!("", out);
+        assert_eq!(0, allocations);
+    }
+
+    #[test]
+    fn should_name_the_offsets_of_ranges_rejected_for_empty_input() {
+        assert_eq!(
+            "range 0..1 is out of bounds for input of length 0",
+            highlight_text("", [HighlightRange::new(0, 1)])
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            "range 3..3 is out of bounds for input of length 0",
+            highlight_text_with_empty_markers("", [HighlightRange::new(3, 3)])
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn should_highlight_into_like_highlight() {
+        let highlighters = [
+            Highlighter::default(),
+            Highlighter::builder().anchor_ids("m-").build().unwrap(),
+            Highlighter::builder()
+                .overlap_policy(OverlapPolicy::Nest)
+                .build()
+                .unwrap(),
+            Highlighter::builder()
+                .overlap_policy(OverlapPolicy::Merge)
+                .build()
+                .unwrap(),
+        ];
+        let input = "overlapping ranges";
+        let ranges = [
+            HighlightRange::new(12, 18),
+            HighlightRange::new(0, 4),
+            HighlightRange::new(2, 7),
+        ];
+        let mut scratch = HighlightScratch::default();
+        let mut out = "stale".to_string();
+
+        for highlighter in highlighters {
+            let result = highlighter.highlight_into(input, &ranges, &mut scratch, &mut out);
+
+            assert_eq!(
+                highlighter.highlight(input, &ranges),
+                result.map(|()| out.clone())
+            );
+        }
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 4),
+                second: HighlightRange::new(2, 7),
+            }),
+            Highlighter::default().highlight_into(input, &ranges, &mut scratch, &mut out)
+        );
+        assert_eq!("", out);
+    }
+
+    #[test]
+    fn should_highlight_chunks_like_the_concatenated_text() {
+        let chunks = ["The qu", "ick br", "own", " fo", "x"];
+        let ranges = [
+            HighlightRange::new(4, 9),
+            HighlightRange::new(10, 15),
+            HighlightRange::new(16, 19),
+            HighlightRange::new(3, 3),
+        ];
+
+        assert_eq!(
+            highlight_text(&chunks.concat(), ranges.clone()),
+            highlight_chunks(chunks, &ranges)
+        );
+        assert_eq!(
+            Ok("The <em>quick</em> <em>brown</em> <em>fox</em>".to_string()),
+            highlight_chunks(chunks, &ranges)
+        );
+        assert_eq!(
+            Ok(String::new()),
+            highlight_chunks([], Vec::<HighlightRange>::new())
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_chunk_ranges_in_the_order_of_highlight_text() {
+        let chunks = ["a", "öb", "c"];
+        for ranges in [
+            vec![HighlightRange::new(2, 3)],
+            vec![HighlightRange::new(2, 2)],
+            vec![HighlightRange::new(0, 3), HighlightRange::new(1, 4)],
+            vec![HighlightRange::new(0, 3), HighlightRange::new(1, 6)],
+            vec![HighlightRange::new(2, 3), HighlightRange::new(4, 6)],
+        ] {
+            let expected = highlight_text(&chunks.concat(), ranges.clone());
+            assert!(expected.is_err());
+            assert_eq!(expected, highlight_chunks(chunks, &ranges), "{ranges:?}");
+        }
+
+        // the bounds are checked before the overlaps, the offsets in input order
+        let input = "xéé éa&";
+        let ranges = [
+            HighlightRange::new(1, 7),
+            HighlightRange::new(2, 7),
+            HighlightRange::new(1, 6),
+        ];
+        for at in (0..=input.len()).filter(|&at| input.is_char_boundary(at)) {
+            let (head, tail) = input.split_at(at);
+            assert_eq!(
+                Err(HighlightingError::NotACharBoundary(7)),
+                highlight_chunks([head, tail], &ranges),
+                "{at}"
+            );
+        }
+        let mut generator = RangeGenerator::new(0xc4_0a7e);
+        for _ in 0..500 {
+            let input = generator.input(12);
+            let (mut ranges, _) = generator.invalid_ranges(&input);
+            ranges.extend(generator.overlapping_ranges(&input));
+            let chars = input.chars().map(String::from).collect::<Vec<_>>();
+            assert_eq!(
+                highlight_text(&input, ranges.clone()),
+                highlight_chunks(chars.iter().map(String::as_str), &ranges),
+                "{input:?} {ranges:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_highlight_bytes_around_invalid_utf8() {
+        let highlight = |input: &[u8], range: Range<usize>| highlight_bytes(input, &[range.into()]);
+
+        // before, inside and after the highlight
+        assert_eq!(
+            Ok("\u{fffd}a<em>bc</em>".to_string()),
+            highlight(b"\xffabc", 2..4)
+        );
+        assert_eq!(
+            Ok("<em>a\u{fffd}b</em>c".to_string()),
+            highlight(b"a\xffbc", 0..3)
+        );
+        assert_eq!(
+            Ok("<em>ab</em>\u{fffd}c".to_string()),
+            highlight(b"ab\xffc", 0..2)
+        );
+        // a truncated 3-byte sequence is replaced once, partially covering it covers the whole
+        assert_eq!(
+            Ok("a<em>\u{fffd}b</em>".to_string()),
+            highlight(b"a\xe2\x82b", 2..4)
+        );
+        assert_eq!(
+            Ok("<em>ä</em>\u{fffd}".to_string()),
+            highlight(b"\xc3\xa4\xff", 0..2)
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_byte_ranges() {
+        assert_eq!(
+            Err(HighlightingError::NotACharBoundary(2)),
+            highlight_bytes("aöb\u{ff}".as_bytes(), &[HighlightRange::new(2, 3)])
+        );
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(1, 4),
+                input_len: 3
+            }),
+            highlight_bytes(b"a\xffb", &[HighlightRange::new(1, 4)])
+        );
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 2),
+                second: HighlightRange::new(2, 4),
+            }),
+            highlight_bytes(
+                b"a\xe2\x82b",
+                &[HighlightRange::new(0, 2), HighlightRange::new(2, 4)]
+            )
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_utf8_in_strict_mode() {
+        assert_eq!(
+            Err(HighlightingError::InvalidUtf8(2)),
+            highlight_bytes_strict(b"ab\xffc", &[HighlightRange::new(0, 1)])
+        );
+        assert_eq!(
+            Ok("<em>ab</em>c".to_string()),
+            highlight_bytes_strict(b"abc", &[HighlightRange::new(0, 2)])
+        );
+    }
+
+    #[test]
+    fn should_order_coincident_boundaries_for_nesting() {
+        let ranges = [
+            (HighlightRange::new(2, 4), "b"),
+            (HighlightRange::new(0, 4), "a"),
+            (HighlightRange::new(4, 4), "b"),
+            (HighlightRange::new(4, 6), "a"),
+            (HighlightRange::new(4, 4), "a"),
+            (HighlightRange::new(4, 8), "a"),
+            (HighlightRange::new(2, 4), "a"),
+        ];
+        let mut boundaries = ranges
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (range, label))| Boundary::of(range, Some(label), i))
+            .filter(|b| b.offset == 4)
+            .collect::<Vec<_>>();
+        boundaries.sort_unstable();
+
+        // innermost end first, then the longest start, then zero-length ranges by label
+        assert_eq!(
+            vec![
+                (BoundaryKind::End, 0),
+                (BoundaryKind::End, 6),
+                (BoundaryKind::End, 1),
+                (BoundaryKind::Start, 5),
+                (BoundaryKind::Start, 3),
+                (BoundaryKind::Empty, 4),
+                (BoundaryKind::Empty, 2),
+            ],
+            boundaries
+                .iter()
+                .map(|b| (b.kind, b.index))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn should_render_the_same_for_any_order_of_coincident_ranges() {
+        // small linear congruential generator, so the cases are random-ish but reproducible
+        let mut seed = 0x51f1_5e3d_u64;
+        let mut next = |bound: u64| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            ((seed >> 33) % bound) as usize
+        };
+
+        let input = "abcdefgh";
+        let nest = |builder: HighlighterBuilder| {
+            builder
+                .overlap_policy(OverlapPolicy::Nest)
+                .allow_empty_markers(true)
+                .build()
+                .unwrap()
+        };
+        let html = nest(Highlighter::builder());
+        let ansi = nest(Highlighter::builder().ansi_style(AnsiStyle::Bold));
+
+        for _ in 0..300 {
+            // few distinct offsets, so many boundaries coincide
+            let labeled = (0..next(7))
+                .map(|_| {
+                    let (a, b) = (next(4) * 2, next(4) * 2);
+                    let range = HighlightRange::new(a.min(b), a.max(b));
+                    LabeledRange::new(range, ["x", "y"][next(2)])
+                })
+                .collect::<Vec<_>>();
+            let mut shuffled = labeled.clone();
+            for i in (1..shuffled.len()).rev() {
+                shuffled.swap(i, next(i as u64 + 1));
+            }
+
+            let ranges = |labeled: &[LabeledRange]| {
+                labeled.iter().map(|h| h.range.clone()).collect::<Vec<_>>()
+            };
+            let events = |labeled: &[LabeledRange]| {
+                let mut events = Vec::new();
+                let ranges = ranges(labeled);
+                let label = |i: usize| Some(labeled[i].label.as_str());
+                sweep_nested(input, &ranges, label, usize::MAX, |event| {
+                    events.push(match event {
+                        HighlightEvent::Text(text) => HighlightEvent::Text(text),
+                        HighlightEvent::Start(i) => HighlightEvent::Start(labeled[i].clone()),
+                        HighlightEvent::End(i) => HighlightEvent::End(labeled[i].clone()),
+                    });
+                    Ok::<(), Infallible>(())
+                })
+                .unwrap();
+                events
+            };
+
+            assert_eq!(
+                html.highlight(input, &ranges(&labeled)),
+                html.highlight(input, &ranges(&shuffled))
+            );
+            assert_eq!(
+                ansi.highlight(input, &ranges(&labeled)),
+                ansi.highlight(input, &ranges(&shuffled))
+            );
+            assert_eq!(events(&labeled), events(&shuffled), "{labeled:?}");
+            assert_eq!(
+                highlight_tree_labeled(input, &labeled),
+                highlight_tree_labeled(input, &shuffled)
+            );
+            assert_eq!(
+                flatten_labeled_ranges(&labeled),
+                flatten_labeled_ranges(&shuffled)
+            );
+        }
+    }
+
+    #[test]
+    fn should_use_tag_overrides_only_for_their_range() {
+        let input = "see a & b <here> too";
+        let link = ("<a href=\"#src\">".to_string(), "</a>".to_string());
+        let highlights = [
+            TaggedRange::new(HighlightRange::new(0, 3), None),
+            TaggedRange::new(HighlightRange::new(4, 9), Some(link)),
+            TaggedRange::new(HighlightRange::new(10, 16), None),
+        ];
+        let highlighter = Highlighter::builder().escape_html(true).build().unwrap();
+
+        assert_eq!(
+            Ok("<em>see</em> <a href=\"#src\">a &amp; b</a> <em>&lt;here&gt;</em> too".to_string()),
+            highlighter.highlight_tagged(input, &highlights)
+        );
+        // cut inside the overridden range, which is closed with its own tag
+        assert_eq!(
+            Ok("<em>see</em> <a href=\"#src\">a &amp;</a>…".to_string()),
+            highlighter.highlight_tagged_truncated(input, &highlights, 7)
+        );
+    }
+
+    #[test]
+    fn should_place_ranges_in_escaped_output_with_custom_tags() {
+        let input = "if a<b && c then";
+        let highlighter = Highlighter::builder()
+            .open_tag("<mark class=\"hit\">")
+            .close_tag("</mark>")
+            .escape_html(true)
+            .build()
+            .unwrap();
+        let ranges = [HighlightRange::new(3, 6), HighlightRange::new(7, 9)];
+
+        let result = highlighter.highlight_placed(input, &ranges).unwrap();
+
+        assert_eq!(
+            highlighter.highlight(input, &ranges),
+            Ok(result.text.clone())
+        );
+        let placed = result
+            .placements
+            .iter()
+            .map(|p| {
+                let output = p.output.clone().unwrap();
+                (p.status, &result.text[output.lower()..output.upper()])
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (PlacementStatus::Rendered, "a&lt;b"),
+                (PlacementStatus::Rendered, "&amp;&amp;")
+            ],
+            placed
+        );
+    }
+
+    #[test]
+    fn should_place_merged_clipped_and_dropped_ranges() {
+        let input = "abcdefghij";
+        let highlighter = Highlighter::builder()
+            .overlap_policy(OverlapPolicy::Merge)
+            .bounds_policy(BoundsPolicy::Clamp)
+            .build()
+            .unwrap();
+        let ranges = [
+            HighlightRange::new(0, 3),
+            HighlightRange::new(2, 5),
+            HighlightRange::new(6, 6),
+            HighlightRange::new(8, 15),
+            HighlightRange::new(20, 22),
+        ];
+
+        let result = highlighter.highlight_placed(input, &ranges).unwrap();
+
+        assert_eq!("<em>abcde</em>fgh<em>ij</em>", result.text);
+        let merged = Some(HighlightRange::new(4, 9));
+        assert_eq!(
+            vec![
+                (merged.clone(), PlacementStatus::Merged),
+                (merged, PlacementStatus::Merged),
+                (None, PlacementStatus::Dropped),
+                (Some(HighlightRange::new(21, 23)), PlacementStatus::Clipped),
+                (None, PlacementStatus::Dropped),
+            ],
+            result
+                .placements
+                .into_iter()
+                .map(|p| (p.output, p.status))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn should_place_nested_and_limited_ranges() {
+        let input = "abcdefghij";
+        let nest = Highlighter::builder()
+            .overlap_policy(OverlapPolicy::Nest)
+            .build()
+            .unwrap();
+        let ranges = [HighlightRange::new(0, 6), HighlightRange::new(3, 9)];
+
+        let result = nest.highlight_placed(input, &ranges).unwrap();
+
+        assert_eq!(nest.highlight(input, &ranges), Ok(result.text.clone()));
+        let outputs = result
+            .placements
+            .iter()
+            .map(|p| {
+                let output = p.output.clone().unwrap();
+                &result.text[output.lower()..output.upper()]
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["abc<em>def</em>", "def</em></em><em>ghi"], outputs);
+
+        let limited = Highlighter::builder().max_highlights(1).build().unwrap();
+        let statuses = limited
+            .highlight_placed(
+                input,
+                &[HighlightRange::new(5, 6), HighlightRange::new(0, 2)],
+            )
+            .unwrap()
+            .placements
+            .into_iter()
+            .map(|p| p.status)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![PlacementStatus::Dropped, PlacementStatus::Rendered],
+            statuses
+        );
+    }
+
+    #[test]
+    fn should_wrap_highlighted_html_counting_visible_chars() {
+        let input = "a <b> & c highlighted words here";
+
+        assert_eq!(
+            Ok("a <em>&lt;b&gt; &amp; c</em><br><em>highlighted</em><br>words here".to_string()),
+            highlight_wrap_html(input, [HighlightRange::new(2, 21)], 12)
+        );
+        assert_eq!(
+            Ok("a &lt;b&gt; &amp;\n<em>c</em>\n<em>highlig</em>\nhted\nwords\nhere".to_string()),
+            highlight_wrap_html_with(
+                input,
+                [HighlightRange::new(8, 9), HighlightRange::new(10, 17)],
+                7,
+                LineBreak::Newline
+            )
+        );
+    }
+
+    #[test]
+    fn should_cut_visible_chars_alike_when_truncating_and_wrapping() {
+        let input = "a<b&c de";
+        let escaping = Highlighter::builder().escape_html(true).build().unwrap();
+
+        assert_eq!(Some(5), visible_cut(input, 5, |_| 1));
+        assert_eq!(
+            Some(2),
+            visible_cut("\tab", 5, |c| if c == '\t' { 4 } else { 1 })
+        );
+        assert_eq!(None, visible_cut(input, 8, |_| 1));
+        assert_eq!(
+            Ok("<em>a</em>&lt;b&amp;c…".to_string()),
+            escaping.highlight_truncated(input, &[HighlightRange::new(0, 1)], 5)
+        );
+        assert_eq!(
+            Ok("<em>a</em>&lt;b&amp;c<br>de".to_string()),
+            highlight_wrap_html(input, [HighlightRange::new(0, 1)], 5)
+        );
+    }
+
+    #[test]
+    fn should_keep_line_breaks_when_wrapping_html() {
+        assert_eq!(
+            Ok("<em>one</em><br><em>two</em>\r\nthree\n".to_string()),
+            highlight_wrap_html("one two\r\nthree\n", [HighlightRange::new(0, 7)], 5)
+        );
+    }
+
+    #[test]
+    fn should_highlight_text_as_pango_markup() {
+        assert_eq!(
+            Ok(
+                "This <span background=\"yellow\" foreground=\"black\">is</span> a sample"
+                    .to_string()
+            ),
+            highlight_text_pango(
+                "This is a sample",
+                [HighlightRange::new(5, 7)],
+                &PangoAttrs::default()
+            )
+        );
+
+        let attrs = PangoAttrs::new()
+            .attr("background", "#ffff00")
+            .and_then(|attrs| attrs.attr("weight", "bold"))
+            .unwrap();
+        assert_eq!(
+            Ok(
+                "a &amp; <span background=\"#ffff00\" weight=\"bold\">&lt;b&gt;</span> &#39;c&#39;"
+                    .to_string()
+            ),
+            highlight_text_pango("a & <b> 'c'", [HighlightRange::new(4, 7)], &attrs)
+        );
+    }
+
+    #[test]
+    fn should_reject_pango_attributes_that_break_the_markup() {
+        assert_eq!(
+            Err(PangoAttrError::InvalidValue {
+                name: "foreground".to_string(),
+                value: "red\" weight=\"bold".to_string()
+            }),
+            PangoAttrs::new().attr("foreground", "red\" weight=\"bold")
+        );
+        assert_eq!(
+            Err(PangoAttrError::InvalidName("x><b".to_string())),
+            PangoAttrs::new().attr("x><b", "1")
+        );
+        assert_eq!(
+            PangoAttrs::new().attr("foreground", "blue").unwrap(),
+            PangoAttrs::new()
+                .attr("foreground", "red")
+                .and_then(|attrs| attrs.attr("foreground", "blue"))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn should_highlight_labeled_ranges_as_pango_markup() {
+        let attrs = HashMap::from([(
+            "person".to_string(),
+            PangoAttrs::new().attr("underline", "single").unwrap(),
+        )]);
+        let highlights = vec![
+            LabeledRange::new(HighlightRange::new(0, 3), "person"),
+            LabeledRange::new(HighlightRange::new(6, 9), "place"),
+        ];
+
+        assert_eq!(
+            Ok("<span underline=\"single\">Bob</span> &amp; <span background=\"yellow\" foreground=\"black\">Rio</span>".to_string()),
+            highlight_text_pango_labeled("Bob & Rio", highlights, &attrs)
+        );
+    }
+
+    #[test]
+    fn should_render_typed_labels_with_their_styles() {
+        let highlights = vec![
+            LabeledRange::with_label(HighlightRange::new(0, 4), MatchKind::Exact),
+            LabeledRange::with_label(HighlightRange::new(2, 6), MatchKind::Exact),
+            LabeledRange::with_label(HighlightRange::new(7, 11), MatchKind::Synonym),
+        ];
+
+        assert_eq!(
+            Ok("<strong>runs r</strong>u<em>jogs</em>".to_string()),
+            highlight_text_labeled_by("runs rujogs", highlights, |kind| match kind {
+                MatchKind::Exact => TagStyle::Strong,
+                MatchKind::Stemmed => TagStyle::Mark,
+                MatchKind::Synonym => TagStyle::Em,
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn should_style_typed_labels_from_a_map() {
+        let styles = HashMap::from([(MatchKind::Exact, TagStyle::Mark)]);
+        let highlights = vec![
+            LabeledRange::with_label(HighlightRange::new(0, 3), MatchKind::Exact),
+            LabeledRange::with_label(HighlightRange::new(4, 7), MatchKind::Stemmed),
+        ];
+
+        assert_eq!(
+            Ok("<mark>run</mark> <em class=\"stemmed\">ran</em>".to_string()),
+            highlight_text_labeled_styled("run ran", highlights, &styles)
+        );
+    }
+
+    #[test]
+    fn should_skip_highlighting_above_max_coverage() {
+        let highlighter = Highlighter::builder()
+            .max_coverage(0.4, CoverageAction::Skip)
+            .build()
+            .unwrap();
+        let highlights = [
+            HighlightRange::new(0, 1),
+            HighlightRange::new(2, 4),
+            HighlightRange::new(5, 8),
+        ];
+
+        assert_eq!(
+            Ok(CoverageOutcome {
+                text: "a an the cat".to_string(),
+                decision: CoverageDecision::Skipped,
+                original_coverage: 0.5,
+                coverage: 0.0,
+            }),
+            highlighter.highlight_gated("a an the cat", &highlights)
+        );
+        assert_eq!(
+            Ok("a an the cat".to_string()),
+            highlighter.highlight("a an the cat", &highlights)
+        );
+        assert_eq!(
+            Ok("<em>a</em> an the cat".to_string()),
+            highlighter.highlight("a an the cat", &highlights[..1])
+        );
+        // skipped ranges are still validated
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(5, 20),
+                input_len: 12
+            }),
+            highlighter.highlight("a an the cat", &[HighlightRange::new(5, 20)])
+        );
+    }
+
+    #[test]
+    fn should_trim_lowest_scored_ranges_above_max_coverage() {
+        let highlighter = Highlighter::builder()
+            .max_coverage(0.5, CoverageAction::TrimLowestScored)
+            .build()
+            .unwrap();
+        let scored = [
+            ScoredRange::new(HighlightRange::new(0, 4), 1.0),
+            ScoredRange::new(HighlightRange::new(5, 9), 0.5),
+            ScoredRange::new(HighlightRange::new(10, 12), 2.0),
+        ];
+
+        assert_eq!(
+            Ok(CoverageOutcome {
+                text: "<em>aaaa</em> bbbb <em>cc</em>".to_string(),
+                decision: CoverageDecision::Trimmed { dropped: 1 },
+                original_coverage: 10.0 / 12.0,
+                coverage: 0.5,
+            }),
+            highlighter.highlight_scored("aaaa bbbb cc", &scored)
+        );
+        // unscored ranges are dropped from the last one
+        assert_eq!(
+            Ok("<em>ab</em> <em>cd</em> ef".to_string()),
+            highlighter.highlight(
+                "ab cd ef",
+                &[
+                    HighlightRange::new(0, 2),
+                    HighlightRange::new(3, 5),
+                    HighlightRange::new(6, 8)
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn should_reject_max_coverage_outside_zero_to_one() {
+        for max in [f32::NAN, -0.1, 1.5] {
+            assert_eq!(
+                Err(HighlighterConfigError::MaxCoverageOutOfRange),
+                Highlighter::builder()
+                    .max_coverage(max, CoverageAction::Skip)
+                    .build()
+                    .map(|_| ()),
+                "{max}"
+            );
+        }
+        for max in [0.0, 1.0] {
+            let builder = Highlighter::builder().max_coverage(max, CoverageAction::Skip);
+            assert!(builder.build().is_ok(), "{max}");
+        }
+    }
+
+    #[test]
+    fn should_gate_coverage_in_every_entry_point() {
+        let input = "a an the cat";
+        let highlights = [HighlightRange::new(0, 1), HighlightRange::new(2, 4)];
+        let builder = || {
+            Highlighter::builder()
+                .max_coverage(0.2, CoverageAction::TrimLowestScored)
+                .anchor_ids("m")
+        };
+        let highlighter = builder().build().unwrap();
+        let trimmed = "<em id=\"m0\">a</em> an the cat";
+
+        assert_eq!(
+            Ok(trimmed.to_string()),
+            highlighter.highlight(input, &highlights)
+        );
+        let mut out = String::new();
+        highlighter
+            .highlight_to_fmt(input, &highlights, &mut out)
+            .unwrap();
+        assert_eq!(trimmed, out);
+        assert_eq!(
+            Ok(trimmed.to_string()),
+            highlighter.highlight_truncated(input, &highlights, 100)
+        );
+        let outcome = highlighter.highlight_outcome(input, &highlights).unwrap();
+        assert_eq!((trimmed, 1), (outcome.text.as_str(), outcome.ids.len()));
+        let placed = highlighter.highlight_placed(input, &highlights).unwrap();
+        assert_eq!(trimmed, placed.text);
+        assert_eq!(PlacementStatus::Dropped, placed.placements[1].status);
+        let segments = highlighter.segments(input, &highlights).unwrap();
+        assert_eq!(1, segments.iter().filter(|s| s.highlighted).count());
+        let both = highlighter
+            .highlight_both(input, &highlights, &BothOptions::default())
+            .unwrap();
+        assert_eq!(trimmed, both.highlighted);
+
+        let nesting = builder()
+            .overlap_policy(OverlapPolicy::Nest)
+            .build()
+            .unwrap();
+        let nested = [HighlightRange::new(0, 1), HighlightRange::new(0, 4)];
+        assert_eq!(
+            Ok("<em>a</em> an the cat".to_string()),
+            nesting.highlight(input, &nested)
+        );
+        let placed = nesting.highlight_placed(input, &nested).unwrap();
+        assert_eq!("<em>a</em> an the cat", placed.text);
+        assert_eq!(PlacementStatus::Dropped, placed.placements[1].status);
+    }
+
+    #[test]
+    fn should_trim_like_dropping_ranges_one_by_one() {
+        let mut generator = RangeGenerator::new(0x51c3_7a4e);
+        let highlighter = Highlighter::builder()
+            .merge_overlaps(true)
+            .max_coverage(0.3, CoverageAction::TrimLowestScored)
+            .build()
+            .unwrap();
+
+        for _ in 0..300 {
+            let input = generator.input(24);
+            let ranges = generator.overlapping_ranges(&input);
+            let scored = ranges
+                .iter()
+                .map(|h| ScoredRange::new(h.clone(), generator.below(3) as f32))
+                .collect::<Vec<_>>();
+            let outcome = highlighter.highlight_scored(&input, &scored).unwrap();
+
+            // drop the lowest-scored merged span, the last of equally scored ones, until it fits;
+            // a span scores as the best range merged into it
+            let within = |span: &HighlightRange, h: &ScoredRange| {
+                span.lower <= h.range.lower && h.range.upper <= span.upper
+            };
+            let mut kept = merge_ranges(ranges.clone())
+                .into_iter()
+                .map(|span| {
+                    let score = scored
+                        .iter()
+                        .filter(|h| within(&span, h))
+                        .map(|h| h.score)
+                        .fold(f32::NEG_INFINITY, f32::max);
+                    (span, score)
+                })
+                .collect::<Vec<_>>();
+            let spans = kept.clone();
+            let coverage = |kept: &[(HighlightRange, f32)]| {
+                let ranges = kept.iter().map(|(h, _)| h.clone()).collect::<Vec<_>>();
+                highlight_stats(&input, &ranges).unwrap().coverage()
+            };
+            while coverage(&kept) > 0.3 {
+                let lowest = (0..kept.len())
+                    .rev()
+                    .min_by(|&a, &b| kept[a].1.total_cmp(&kept[b].1))
+                    .unwrap();
+                kept.remove(lowest);
+            }
+            let dropped = scored
+                .iter()
+                .filter(|h| spans.iter().any(|(span, _)| within(span, h)))
+                .filter(|h| !kept.iter().any(|(span, _)| within(span, h)))
+                .count();
+            if dropped > 0 {
+                assert_eq!(CoverageDecision::Trimmed { dropped }, outcome.decision);
+            }
+            assert_eq!(coverage(&kept), outcome.coverage, "{input:?} {scored:?}");
+        }
+    }
+
+    #[test]
+    fn should_trim_the_normalized_ranges() {
+        let merging = Highlighter::builder()
+            .overlap_policy(OverlapPolicy::Merge)
+            .max_coverage(0.5, CoverageAction::TrimLowestScored)
+            .build()
+            .unwrap();
+        // neither range falls on char boundaries before they are merged
+        let ranges = [HighlightRange::new(1, 3), HighlightRange::new(0, 2)];
+        assert_eq!(Ok("éb".to_string()), merging.highlight("éb", &ranges));
+        assert_eq!(
+            Ok("<em>éb</em> cd".to_string()),
+            merging.highlight("éb cd", &ranges)
+        );
+
+        // equal scores are dropped from the last in document order, whatever the input order
+        let highlighter = Highlighter::builder()
+            .max_coverage(0.5, CoverageAction::TrimLowestScored)
+            .build()
+            .unwrap();
+        let ranges = [
+            HighlightRange::new(6, 8),
+            HighlightRange::new(0, 2),
+            HighlightRange::new(3, 5),
+        ];
+        for order in [[0, 1, 2], [1, 2, 0], [2, 0, 1], [2, 1, 0]] {
+            let permuted = order.map(|i| ScoredRange::new(ranges[i].clone(), 1.0));
+            let outcome = highlighter.highlight_scored("ab cd ef", &permuted).unwrap();
+            assert_eq!("<em>ab</em> <em>cd</em> ef", outcome.text, "{order:?}");
+            assert_eq!(CoverageDecision::Trimmed { dropped: 1 }, outcome.decision);
+        }
+    }
+
+    #[test]
+    fn should_count_coverage_in_chars() {
+        let highlighter = Highlighter::builder()
+            .max_coverage(0.7, CoverageAction::Skip)
+            .build()
+            .unwrap();
+
+        // 3 of 5 chars, but 6 of 8 bytes
+        let outcome = highlighter
+            .highlight_gated("äää b", &[HighlightRange::new(0, 6)])
+            .unwrap();
+        assert_eq!(CoverageDecision::Kept, outcome.decision);
+        assert_eq!(0.6, outcome.coverage);
+        assert_eq!("<em>äää</em> b", outcome.text);
+    }
+
+    #[test]
+    fn should_flatten_ranges_nested_deeper_than_max_depth() {
+        let highlighter = Highlighter::builder()
+            .overlap_policy(OverlapPolicy::Nest)
+            .max_nesting_depth(2)
+            .build()
+            .unwrap();
+        let concentric = (0..5)
+            .map(|i| HighlightRange::new(i, 10 - i))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            Ok(HighlightOutcome {
+                text: "<em>0<em>12345678</em>9</em>".to_string(),
+                dropped: 0,
+                ids: vec![],
+                flattened: 3,
+                // the outermost range covers the input
+                full_cover: true,
+            }),
+            highlighter.highlight_outcome("0123456789", &concentric)
+        );
+    }
+
+    #[test]
+    fn should_reopen_flattened_ranges_once_shallow_enough() {
+        let highlighter = Highlighter::builder()
+            .overlap_policy(OverlapPolicy::Nest)
+            .max_nesting_depth(2)
+            .build()
+            .unwrap();
+        let highlights = [
+            HighlightRange::new(0, 4),
+            HighlightRange::new(1, 3),
+            HighlightRange::new(2, 8),
+        ];
+
+        // 2..8 is flattened into 1..3, then opened inside 0..4 and on its own after it
+        assert_eq!(
+            Ok("<em>a<em>bc</em><em>d</em></em><em>efgh</em>".to_string()),
+            highlighter.highlight("abcdefgh", &highlights)
+        );
+        let placed = highlighter
+            .highlight_placed(
+                "abcdefgh",
+                &[
+                    HighlightRange::new(0, 6),
+                    HighlightRange::new(1, 5),
+                    HighlightRange::new(2, 4),
+                ],
+            )
+            .unwrap();
+        assert_eq!("<em>a<em>bcde</em>f</em>gh", placed.text);
+        assert_eq!(PlacementStatus::Merged, placed.placements[2].status);
+        assert_eq!(placed.placements[1].output, placed.placements[2].output);
+    }
+
+    #[test]
+    fn should_annotate_open_tags_with_their_source_ranges() {
+        let highlighter = Highlighter::builder()
+            .overlap_policy(OverlapPolicy::Merge)
+            .debug_annotations(true)
+            .build()
+            .unwrap();
+        let highlights = [
+            HighlightRange::new(0, 5),
+            HighlightRange::new(10, 15),
+            HighlightRange::new(3, 9),
+        ];
+
+        assert_eq!(
+            Ok(concat!(
+                r#"<em data-range="0..9" data-merged="0,2">the quick</em> "#,
+                r#"<em data-range="10..15" data-src-index="1">brown</em> fox"#
+            )
+            .to_string()),
+            highlighter.highlight("the quick brown fox", &highlights)
+        );
+        // production output is unchanged without the option
+        assert_eq!(
+            Ok("<em>the quick</em> <em>brown</em> fox".to_string()),
+            Highlighter::builder()
+                .overlap_policy(OverlapPolicy::Merge)
+                .build()
+                .unwrap()
+                .highlight("the quick brown fox", &highlights)
+        );
+    }
+
+    #[test]
+    fn should_compose_debug_annotations_with_custom_tags_and_escaping() {
+        let highlighter = Highlighter::builder()
+            .tag_style(TagStyle::SpanClass("hl".to_string()))
+            .escape_html(true)
+            .anchor_ids("m")
+            .debug_annotations(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            Ok(r#"a &lt; <span class="hl" id="m0" data-range="4..7" data-src-index="0">b&amp;c</span>"#
+                .to_string()),
+            highlighter.highlight("a < b&c", &[HighlightRange::new(4, 7)])
+        );
+    }
+
+    #[test]
+    fn should_render_job_in_steps_identical_to_one_shot() {
+        let input = "Grüße, wörld! 日本語 text. ".repeat(5000);
+        let highlights = input
+            .char_indices()
+            .filter(|(i, _)| i % 7 == 0)
+            .step_by(3)
+            .map(|(i, c)| HighlightRange::new(i, i + c.len_utf8()))
+            .chain([HighlightRange::new(input.len() - 6, input.len())])
+            .collect::<Vec<_>>();
+        let expected = highlight_text(&input, highlights.clone()).unwrap();
+
+        for budget in [0, 1, 5, 4096] {
+            let mut job = RenderJob::new(&input, highlights.clone(), budget).unwrap();
+            let mut drained = String::new();
+            let mut steps = 0;
+            while job.step() == StepResult::NotDone {
+                drained.push_str(&job.take_output());
+                steps += 1;
+            }
+            drained.push_str(&job.take_output());
+
+            assert_eq!(expected, drained);
+            assert!(steps + 1 >= input.len().div_ceil(4096));
+            assert_eq!(StepResult::Done, job.step());
+            assert_eq!("", job.take_output());
+        }
+    }
+
+    #[test]
+    fn should_validate_render_job_up_front() {
+        assert_eq!(
+            Err(HighlightingError::NotACharBoundary(1)),
+            RenderJob::new("ä", [HighlightRange::new(1, 2)], 8).map(|_| ())
+        );
+
+        let mut job = RenderJob::new("", Vec::<HighlightRange>::new(), 8).unwrap();
+        assert_eq!(StepResult::Done, job.step());
+        assert_eq!("", job.take_output());
+    }
+
+    #[test]
+    fn should_split_input_into_highlighted_pieces_and_gaps() {
+        let input = "ab cd€ef gh";
+        let ranges = [
+            HighlightRange::new(8, 10),
+            HighlightRange::new(3, 8),
+            HighlightRange::new(0, 0),
+        ];
+        let split = split_at_ranges(input, &ranges).unwrap();
+
+        fn texts<'a>(segments: &[Segment<'a>]) -> Vec<&'a str> {
+            segments.iter().map(|s| s.text).collect()
+        }
+        assert_eq!(vec!["cd€", "ef"], texts(&split.highlighted));
+        assert_eq!(vec!["ab ", " gh"], texts(&split.gaps));
+        assert_eq!(HighlightRange::new(8, 10), split.highlighted[1].range);
+        assert_eq!(
+            input,
+            split.interleaved().map(|s| s.text).collect::<String>()
+        );
+        assert_eq!(
+            highlight_segments(input, ranges.clone()).unwrap(),
+            split.interleaved().cloned().collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            Err(HighlightingError::NotACharBoundary(7)),
+            split_at_ranges(input, &[HighlightRange::new(7, 8)])
+        );
+    }
+
+    #[test]
+    fn should_pin_output_fingerprints_of_this_output_version() {
+        // changing one of these means cached output is invalidated, bump OUTPUT_VERSION if the
+        // rendered bytes changed on purpose
+        assert_eq!(1, OUTPUT_VERSION);
+        assert_eq!(
+            0x80ae_63c0_717e_eb60,
+            output_fingerprint(&Highlighter::default())
+        );
+        let marked = Highlighter::builder()
+            .tag_style(TagStyle::Mark)
+            .escape_html(true)
+            .build()
+            .unwrap();
+        assert_eq!(0x0ef5_2e7a_8ae4_cd27, output_fingerprint(&marked));
+        let merging = Highlighter::builder()
+            .overlap_policy(OverlapPolicy::Merge)
+            .max_highlights(3)
+            .build()
+            .unwrap();
+        assert_eq!(0xb2ab_0a88_2aac_23e3, output_fingerprint(&merging));
+    }
+
+    #[test]
+    fn should_change_output_fingerprint_with_every_output_option() {
+        let configs = [
+            Highlighter::builder(),
+            Highlighter::builder().open_tag("<b>"),
+            Highlighter::builder().close_tag("</b>"),
+            // same tags in total, split differently
+            Highlighter::builder().open_tag("<em></em").close_tag(">"),
+            Highlighter::builder().overlap_policy(OverlapPolicy::Merge),
+            Highlighter::builder().overlap_policy(OverlapPolicy::Nest),
+            Highlighter::builder().bounds_policy(BoundsPolicy::Clamp),
+            Highlighter::builder().coalesce_adjacent(true),
+            Highlighter::builder().allow_empty_markers(true),
+            Highlighter::builder().escape_html(true),
+            Highlighter::builder().bbcode("b"),
+            Highlighter::builder().split_at_newlines(true),
+            Highlighter::builder().dedup_contained(true),
+            Highlighter::builder().max_highlights(0),
+            Highlighter::builder().max_highlights(1),
+            Highlighter::builder().max_coverage(0.5, CoverageAction::Skip),
+            Highlighter::builder().max_coverage(0.5, CoverageAction::TrimLowestScored),
+            Highlighter::builder().max_coverage(0.25, CoverageAction::Skip),
+            Highlighter::builder().max_nesting_depth(2),
+            Highlighter::builder().exclusions([HighlightRange::new(0, 3)]),
+            Highlighter::builder().exclusions([HighlightRange::new(0, 4)]),
+            Highlighter::builder().anchor_ids(""),
+            Highlighter::builder().anchor_ids("hit-"),
+            Highlighter::builder().bidi_isolate(true),
+            Highlighter::builder().debug_annotations(true),
+            Highlighter::builder().full_cover(FullCoverPolicy::Skip),
+            Highlighter::builder().full_cover(FullCoverPolicy::Tag("<b>".into(), "</b>".into())),
+        ];
+
+        let fingerprints = configs
+            .into_iter()
+            .map(|builder| output_fingerprint(&builder.build().unwrap()))
+            .collect::<Vec<_>>();
+
+        for (i, fingerprint) in fingerprints.iter().enumerate() {
+            assert!(!fingerprints[..i].contains(fingerprint), "config {i}");
+        }
+        // equal configs, however they were built
+        assert_eq!(
+            fingerprints[0],
+            output_fingerprint(
+                &Highlighter::builder()
+                    .tag_style(TagStyle::Em)
+                    .build()
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            fingerprints[19],
+            output_fingerprint(
+                &Highlighter::builder()
+                    .exclusions([HighlightRange::new(1, 3), HighlightRange::new(0, 2)])
+                    .build()
+                    .unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn should_report_the_capabilities_of_this_build() {
+        let capabilities = capabilities();
+
+        assert_eq!(env!("CARGO_PKG_VERSION"), capabilities.version);
+        assert_eq!(OUTPUT_VERSION, capabilities.output_version);
+        assert_eq!(cfg!(feature = "std"), capabilities.has_feature("std"));
+        assert_eq!(cfg!(feature = "regex"), capabilities.has_feature("regex"));
+        assert_eq!(cfg!(feature = "serde"), capabilities.has_feature("serde"));
+        assert!(!capabilities.has_feature("gzip"));
+        assert_eq!(3, capabilities.overlap_policies.len());
+        assert!(capabilities.bounds_policies.contains(&BoundsPolicy::Clamp));
+        assert!(capabilities.renderers.contains(&"html"));
+    }
+}
@@ -0,0 +1,1048 @@
+//! Finding the ranges to highlight: substring, keyword and regex matches, [`CompiledQuery`] and
+//! diffs
+
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::cmp::Reverse;
+
+use crate::{range::*, render::*, validate::*};
+
+/// A range for every occurrence of `needle` in `haystack`. Occurrences don't overlap: searching
+/// continues after the end of the leftmost match, so `"aa"` is found once in `"aaa"`. An empty
+/// `needle` has no occurrences.
+pub fn ranges_for_substring(haystack: &str, needle: &str) -> Vec<HighlightRange> {
+    if needle.is_empty() {
+        return Vec::new();
+    }
+
+    haystack
+        .match_indices(needle)
+        .map(|(lower, m)| HighlightRange::new(lower, lower + m.len()))
+        .collect()
+}
+
+/// Same as [`highlight_text`] with the ranges of [`ranges_for_substring`]
+pub fn highlight_matches(haystack: &str, needle: &str) -> String {
+    let ranges = ranges_for_substring(haystack, needle);
+    Highlighter::default()
+        .highlight(haystack, &ranges)
+        .expect("matches are disjoint and on char boundaries")
+}
+
+/// Ranges of the occurrences of any of the `needles` in `haystack`, found in a single scan.
+/// Occurrences don't overlap: the leftmost match wins, and of several needles matching at the same
+/// offset the longest one (`"catalog"` over `"cat"`). Empty needles are ignored.
+pub fn ranges_for_keywords(haystack: &str, needles: &[&str]) -> Vec<HighlightRange> {
+    keyword_matches(haystack, needles)
+        .into_iter()
+        .map(|(range, _)| range)
+        .collect()
+}
+
+/// Same as [`ranges_for_keywords`], but every range is labeled with the needle that produced it,
+/// see [`highlight_text_labeled`]
+pub fn labeled_ranges_for_keywords(haystack: &str, needles: &[&str]) -> Vec<LabeledRange> {
+    keyword_matches(haystack, needles)
+        .into_iter()
+        .map(|(range, needle)| LabeledRange::new(range, needles[needle]))
+        .collect()
+}
+
+/// Matches of [`ranges_for_keywords`], each with the index of its needle
+fn keyword_matches(haystack: &str, needles: &[&str]) -> Vec<(HighlightRange, usize)> {
+    let mut by_length = (0..needles.len())
+        .filter(|&i| !needles[i].is_empty())
+        .collect::<Vec<_>>();
+    by_length.sort_by_key(|&i| Reverse(needles[i].len()));
+
+    let mut matches = Vec::new();
+    let mut pos = 0;
+    while let Some(c) = haystack[pos..].chars().next() {
+        let rest = &haystack[pos..];
+        match by_length.iter().find(|&&i| rest.starts_with(needles[i])) {
+            Some(&i) => {
+                let upper = pos + needles[i].len();
+                matches.push((HighlightRange::new(pos, upper), i));
+                pos = upper;
+            }
+            None => pos += c.len_utf8(),
+        }
+    }
+
+    matches
+}
+
+/// A range for every match of `re` in `haystack`; empty matches are skipped since they don't
+/// highlight anything
+#[cfg(feature = "regex")]
+pub fn ranges_for_regex(haystack: &str, re: &regex::Regex) -> Vec<HighlightRange> {
+    re.find_iter(haystack)
+        .filter(|m| !m.is_empty())
+        .map(|m| HighlightRange::from(m.range()))
+        .collect()
+}
+
+/// Same as [`ranges_for_regex`], but only the part captured by group `group_idx` of each match is
+/// highlighted; matches where the group didn't participate are skipped
+#[cfg(feature = "regex")]
+pub fn ranges_for_regex_group(
+    haystack: &str,
+    re: &regex::Regex,
+    group_idx: usize,
+) -> Vec<HighlightRange> {
+    re.captures_iter(haystack)
+        .filter_map(|captures| captures.get(group_idx))
+        .filter(|m| !m.is_empty())
+        .map(|m| HighlightRange::from(m.range()))
+        .collect()
+}
+
+/// Same as [`highlight_text`] with the ranges of [`ranges_for_regex`]
+#[cfg(feature = "regex")]
+pub fn highlight_regex(haystack: &str, re: &regex::Regex) -> String {
+    let ranges = ranges_for_regex(haystack, re);
+    Highlighter::default()
+        .highlight(haystack, &ranges)
+        .expect("regex matches are disjoint and on char boundaries")
+}
+
+/// Same as [`ranges_for_substring`], but ignoring case; ranges are byte offsets into the original
+/// `haystack` even where lowercasing changes the length of a char (e.g. `'ẞ'` to `'ß'`). A match
+/// has to cover whole chars of `haystack`, matching only part of a char's lowercase form (like
+/// the `i` of `'İ'`, which lowercases to `"i\u{307}"`) doesn't count.
+pub fn ranges_for_substring_ci(haystack: &str, needle: &str) -> Vec<HighlightRange> {
+    ranges_for_substring_ci_with(haystack, needle, CaseFold::Simple)
+}
+
+/// Same as [`ranges_for_substring_ci`], but lowercasing with the rules of `case_fold`, e.g.
+/// [`CaseFold::Turkic`] so `"istanbul"` matches the two byte `'İ'` of `"İstanbul"` as a whole
+pub fn ranges_for_substring_ci_with(
+    haystack: &str,
+    needle: &str,
+    case_fold: CaseFold,
+) -> Vec<HighlightRange> {
+    ranges_for_folded(haystack, needle, |c, folded| {
+        folded.extend(case_fold.lowercase(c))
+    })
+}
+
+/// Rules for lowercasing chars when matching case-insensitively
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaseFold {
+    /// the default Unicode lowercase mapping of [`char::to_lowercase`]
+    #[default]
+    Simple,
+    /// Turkish and Azerbaijani rules: `'I'` lowercases to the dotless `'ı'` and `'İ'` to a plain
+    /// `'i'`, everything else like [`CaseFold::Simple`]
+    Turkic,
+}
+
+impl CaseFold {
+    /// The lowercase form of `c`, one or more chars
+    fn lowercase(self, c: char) -> impl Iterator<Item = char> {
+        let special = match (self, c) {
+            (CaseFold::Turkic, 'I') => Some('ı'),
+            (CaseFold::Turkic, 'İ') => Some('i'),
+            _ => None,
+        };
+        let simple = special.is_none().then(|| c.to_lowercase());
+        special.into_iter().chain(simple.into_iter().flatten())
+    }
+}
+
+/// Same as [`ranges_for_substring_ci`], but diacritics are ignored as well, so `"uber"` matches
+/// both the precomposed and the decomposed `"Über"`. A match always covers the diacritics that
+/// follow its last char in `haystack`.
+#[cfg(feature = "unicode-normalization")]
+pub fn ranges_for_substring_folded(haystack: &str, needle: &str) -> Vec<HighlightRange> {
+    use unicode_normalization::char::{decompose_canonical, is_combining_mark};
+
+    ranges_for_folded(haystack, needle, |c, folded| {
+        decompose_canonical(c, |d| {
+            if !is_combining_mark(d) {
+                folded.extend(d.to_lowercase());
+            }
+        })
+    })
+}
+
+/// Finds `needle` in `haystack` after both went through `fold`, which appends the folded form of
+/// a char to the string. Matches are only reported if they start and end with whole chars of
+/// `haystack`, chars folded to nothing belong to the match before them.
+fn ranges_for_folded(
+    haystack: &str,
+    needle: &str,
+    fold: impl Fn(char, &mut String),
+) -> Vec<HighlightRange> {
+    let mut folded_needle = String::with_capacity(needle.len());
+    needle.chars().for_each(|c| fold(c, &mut folded_needle));
+    if folded_needle.is_empty() {
+        return Vec::new();
+    }
+
+    // (offset in `folded`, offset in `haystack`) of every char that isn't folded to nothing, plus
+    // both ends; the offsets in `folded` are strictly increasing
+    let mut boundaries = Vec::with_capacity(haystack.len() + 1);
+    let mut folded = String::with_capacity(haystack.len());
+    for (offset, c) in haystack.char_indices() {
+        let folded_offset = folded.len();
+        fold(c, &mut folded);
+        if folded.len() > folded_offset {
+            boundaries.push((folded_offset, offset));
+        }
+    }
+    boundaries.push((folded.len(), haystack.len()));
+    let original = |folded_offset: usize| {
+        boundaries
+            .binary_search_by_key(&folded_offset, |&(folded, _)| folded)
+            .ok()
+            .map(|i| boundaries[i].1)
+    };
+
+    let mut ranges = Vec::new();
+    let mut pos = 0;
+    while let Some(found) = folded[pos..].find(&folded_needle) {
+        let (lower, upper) = (pos + found, pos + found + folded_needle.len());
+        match (original(lower), original(upper)) {
+            (Some(original_lower), Some(original_upper)) => {
+                ranges.push(HighlightRange::new(original_lower, original_upper));
+                pos = upper;
+            }
+            // not aligned with the chars of `haystack`, retry from the next char
+            _ => pos = lower + folded[lower..].chars().next().map_or(1, char::len_utf8),
+        }
+    }
+
+    ranges
+}
+
+/// Approximate occurrences of `needle` in `haystack`: every range whose text is at most
+/// `max_edits` insertions, deletions or substitutions of chars away from `needle`, so `"recieve"`
+/// finds `"receive"` with one edit. The score is the negated edit distance, so [`select_top_k`]
+/// prefers the closest matches. Of overlapping candidates only the one with the fewest edits is
+/// kept, the leftmost and then longest on a tie, so `"😀ö"` rather than `"😀"` for `"😀ü"`.
+/// Chars are compared exactly and an empty `needle` has no matches.
+pub fn ranges_for_fuzzy(haystack: &str, needle: &str, max_edits: u32) -> Vec<ScoredRange> {
+    let needle = needle.chars().collect::<Vec<_>>();
+    if needle.is_empty() {
+        return Vec::new();
+    }
+    let max_edits = max_edits as usize;
+
+    // Sellers' dynamic programming, one column per char of `haystack`: `distances[i]` is the
+    // fewest edits turning `needle[..i]` into a text ending at the current offset, and
+    // `starts[i]` the offset that text starts at
+    let mut distances = (0..=needle.len()).collect::<Vec<_>>();
+    let mut starts = vec![0; needle.len() + 1];
+    let mut candidates = Vec::new();
+    for (offset, c) in haystack.char_indices() {
+        let end = offset + c.len_utf8();
+        let (mut diagonal, mut diagonal_start) = (distances[0], starts[0]);
+        starts[0] = end;
+        for (i, &n) in needle.iter().enumerate() {
+            let substituted = (diagonal + usize::from(n != c), diagonal_start);
+            let skipped_char = (distances[i + 1] + 1, starts[i + 1]);
+            let skipped_needle = (distances[i] + 1, starts[i]);
+            (diagonal, diagonal_start) = (distances[i + 1], starts[i + 1]);
+            (distances[i + 1], starts[i + 1]) =
+                [skipped_char, skipped_needle]
+                    .into_iter()
+                    .fold(substituted, |best, other| match other.0 < best.0 {
+                        true => other,
+                        false => best,
+                    });
+        }
+        let (distance, start) = (distances[needle.len()], starts[needle.len()]);
+        if distance <= max_edits && start < end {
+            candidates.push((distance, start, Reverse(end)));
+        }
+    }
+
+    // fewest edits first, then leftmost, then longest; the selected ranges don't overlap, so
+    // the one starting last before a candidate ends is the only one it can overlap
+    candidates.sort_unstable();
+    let mut selected = alloc::collections::BTreeMap::<usize, (usize, usize)>::new();
+    for (distance, lower, Reverse(upper)) in candidates {
+        let before = selected.range(..upper).next_back();
+        if before.is_none_or(|(_, &(before_upper, _))| before_upper <= lower) {
+            selected.insert(lower, (upper, distance));
+        }
+    }
+
+    selected
+        .into_iter()
+        .map(|(lower, (upper, distance))| {
+            ScoredRange::new(HighlightRange::new(lower, upper), -(distance as f32))
+        })
+        .collect()
+}
+
+/// How [`CompiledQuery`] and [`ranges_for_keywords_with`] match their terms
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MatchOptions {
+    /// compare lowercased chars; a match has to cover whole chars of the document, like in
+    /// [`ranges_for_substring_ci`]
+    pub case_insensitive: bool,
+    /// how chars are lowercased if `case_insensitive` is set
+    pub case_fold: CaseFold,
+    /// only accept matches at word boundaries, see [`at_word_boundaries`]
+    pub whole_words: bool,
+    /// any run of whitespace in a term matches any run of whitespace in the document, line breaks
+    /// included, so `"hello world"` matches `"hello\n  world"`; the match covers the whole run.
+    /// Whitespace at the start and end of a term is trimmed off before matching, so matches
+    /// never start or end with whitespace.
+    pub collapse_whitespace: bool,
+}
+
+/// Whether `haystack[lower..upper]` is delimited like a whole word: if its first char is a word
+/// char, the char before it mustn't be one, and likewise for its last char and the char after.
+/// Word chars are alphanumeric (in any script) or `_`, so `"cat"` is a whole word in `"cat-like"`
+/// but not in `"cat_food"`, and `"C++"` is one in `"C++11"` since its last char isn't a word char.
+/// Offsets out of bounds or inside a char are never at word boundaries.
+pub fn at_word_boundaries(haystack: &str, lower: usize, upper: usize) -> bool {
+    let is_word_char = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let (Some(before), Some(matched), Some(after)) = (
+        haystack.get(..lower),
+        haystack.get(lower..upper),
+        haystack.get(upper..),
+    ) else {
+        return false;
+    };
+    let open_before =
+        !is_word_char(matched.chars().next()) || !is_word_char(before.chars().next_back());
+    let open_after =
+        !is_word_char(matched.chars().next_back()) || !is_word_char(after.chars().next());
+    open_before && open_after
+}
+
+/// Same as [`ranges_for_keywords_with`] for a single needle
+pub fn ranges_for_substring_with(
+    haystack: &str,
+    needle: &str,
+    options: MatchOptions,
+) -> Vec<HighlightRange> {
+    ranges_for_keywords_with(haystack, &[needle], options)
+}
+
+/// Same as [`ranges_for_keywords`], but matching with `options`; a match that is rejected as not
+/// being a whole word doesn't hide a shorter needle or a later match overlapping it
+pub fn ranges_for_keywords_with(
+    haystack: &str,
+    needles: &[&str],
+    options: MatchOptions,
+) -> Vec<HighlightRange> {
+    CompiledQuery::new(needles, options).find_ranges(haystack)
+}
+
+/// A set of search terms prepared once for highlighting many documents, e.g. the keywords of a
+/// query applied to every search result. Matches like [`ranges_for_keywords`]: the leftmost match
+/// wins, and of several terms matching at the same offset the longest one.
+#[derive(Debug, Clone)]
+pub struct CompiledQuery {
+    /// terms as compared against the document (lowercased if case-insensitive), longest first,
+    /// without empty ones
+    terms: Vec<String>,
+    /// sorted first chars of `terms`, a document char can only start a match if it's in here
+    first_chars: Vec<char>,
+    options: MatchOptions,
+    highlighter: Highlighter,
+}
+
+/// Buffers reused by [`CompiledQuery::highlight_with_scratch`] across documents
+#[derive(Debug, Clone, Default)]
+pub struct Scratch {
+    ranges: Vec<HighlightRange>,
+}
+
+impl CompiledQuery {
+    pub fn new<S: AsRef<str>>(terms: impl IntoIterator<Item = S>, options: MatchOptions) -> Self {
+        CompiledQuery::with_highlighter(terms, options, Highlighter::default())
+    }
+
+    /// Same as [`CompiledQuery::new`], but matches are rendered with the tags and escaping of
+    /// `highlighter`; its policies don't apply since the matches never overlap
+    pub fn with_highlighter<S: AsRef<str>>(
+        terms: impl IntoIterator<Item = S>,
+        options: MatchOptions,
+        highlighter: Highlighter,
+    ) -> Self {
+        let mut terms = terms
+            .into_iter()
+            .map(|term| match options.case_insensitive {
+                true => term
+                    .as_ref()
+                    .chars()
+                    .flat_map(|c| options.case_fold.lowercase(c))
+                    .collect(),
+                false => term.as_ref().to_string(),
+            })
+            .map(|term: String| match options.collapse_whitespace {
+                true => term.split_whitespace().collect::<Vec<_>>().join(" "),
+                false => term,
+            })
+            .filter(|term| !term.is_empty())
+            .collect::<Vec<_>>();
+        // equal terms have to be adjacent for `dedup`, not only terms of equal length
+        terms.sort_by(|a, b| (Reverse(a.len()), a).cmp(&(Reverse(b.len()), b)));
+        terms.dedup();
+
+        let mut first_chars = terms
+            .iter()
+            .filter_map(|term| term.chars().next())
+            .collect::<Vec<_>>();
+        first_chars.sort_unstable();
+        first_chars.dedup();
+
+        CompiledQuery {
+            terms,
+            first_chars,
+            options,
+            highlighter,
+        }
+    }
+
+    pub fn find_ranges(&self, doc: &str) -> Vec<HighlightRange> {
+        let mut ranges = Vec::new();
+        self.find_ranges_into(doc, &mut ranges);
+        ranges
+    }
+
+    /// Same as [`CompiledQuery::find_ranges`], but the ranges replace the contents of `ranges`
+    pub fn find_ranges_into(&self, doc: &str, ranges: &mut Vec<HighlightRange>) {
+        ranges.clear();
+
+        let mut pos = 0;
+        while let Some(c) = doc[pos..].chars().next() {
+            let first = match self.options.case_insensitive {
+                true => self.options.case_fold.lowercase(c).next().unwrap_or(c),
+                false => c,
+            };
+            let found = self.first_chars.binary_search(&first).ok().and_then(|_| {
+                self.terms
+                    .iter()
+                    .find_map(|term| self.match_at(doc, pos, term))
+            });
+            match found {
+                Some(upper) => {
+                    ranges.push(HighlightRange::new(pos, upper));
+                    pos = upper;
+                }
+                None => pos += c.len_utf8(),
+            }
+        }
+    }
+
+    pub fn highlight(&self, doc: &str) -> Result<String, HighlightingError> {
+        self.highlight_with_scratch(doc, &mut Scratch::default())
+    }
+
+    /// Same as [`CompiledQuery::highlight`], but the buffers in `scratch` are reused, so a warm
+    /// `scratch` leaves only the output and the prepared ranges to allocate per document
+    pub fn highlight_with_scratch(
+        &self,
+        doc: &str,
+        scratch: &mut Scratch,
+    ) -> Result<String, HighlightingError> {
+        self.find_ranges_into(doc, &mut scratch.ranges);
+
+        // matches are found left to right and never overlap
+        let prepared = PreparedHighlights::from_sorted(doc, &scratch.ranges)?;
+        let window = 0..doc.len();
+        let highlighter = &self.highlighter;
+        let (_, tags) = highlighter.anchored_open_tags(&prepared.sorted, &scratch.ranges);
+        Ok(highlighter.render_with(&prepared, window, |i| highlighter.open_tag_at(&tags, i)))
+    }
+
+    /// End of the match of `term` starting at `pos`, if any
+    fn match_at(&self, doc: &str, pos: usize, term: &str) -> Option<usize> {
+        let rest = &doc[pos..];
+        let options = &self.options;
+        let upper = match (options.collapse_whitespace, options.case_insensitive) {
+            (true, _) => self.match_collapsed(rest, term).map(|len| pos + len),
+            (false, false) => rest.starts_with(term).then(|| pos + term.len()),
+            (false, true) => {
+                let mut term_chars = term.chars();
+                rest.char_indices().find_map(|(offset, c)| {
+                    // `None` ends the search: a mismatch or the term ending inside `c`
+                    let matches =
+                        (self.options.case_fold.lowercase(c)).all(|l| term_chars.next() == Some(l));
+                    match (matches, term_chars.as_str().is_empty()) {
+                        (true, true) => Some(Some(pos + offset + c.len_utf8())),
+                        (true, false) => None,
+                        (false, _) => Some(None),
+                    }
+                })?
+            }
+        }?;
+
+        (!self.options.whole_words || at_word_boundaries(doc, pos, upper)).then_some(upper)
+    }
+
+    /// Length of the match of `term`, whose whitespace runs are collapsed into single spaces, at
+    /// the start of `rest` with [`MatchOptions::collapse_whitespace`]; a space of `term` consumes
+    /// a whole whitespace run of `rest`
+    fn match_collapsed(&self, rest: &str, term: &str) -> Option<usize> {
+        let mut term_chars = term.chars();
+        let mut chars = rest.char_indices().peekable();
+        while !term_chars.as_str().is_empty() {
+            let (_, c) = chars.next()?;
+            let matches = match c.is_whitespace() {
+                true => {
+                    while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+                    term_chars.next() == Some(' ')
+                }
+                false if self.options.case_insensitive => {
+                    (self.options.case_fold.lowercase(c)).all(|l| term_chars.next() == Some(l))
+                }
+                false => term_chars.next() == Some(c),
+            };
+            if !matches {
+                return None;
+            }
+        }
+
+        Some(chars.peek().map_or(rest.len(), |&(offset, _)| offset))
+    }
+}
+
+/// Ranges over `new` covering the chars that were inserted or changed compared to `old`, see
+/// [`diff_ranges`]
+pub fn ranges_for_diff(old: &str, new: &str) -> Vec<HighlightRange> {
+    diff_ranges(old, new).1
+}
+
+/// Char-level diff of `old` and `new` as `(deleted, inserted)`: ranges over `old` covering the
+/// chars missing from `new`, and ranges over `new` covering the chars missing from `old`.
+/// Consecutive changed chars form a single range. Based on the longest common subsequence, which
+/// takes `O(old.len() * new.len())` time and memory, so this is meant for short texts like form
+/// fields rather than whole documents.
+pub fn diff_ranges(old: &str, new: &str) -> (Vec<HighlightRange>, Vec<HighlightRange>) {
+    let old_chars = old.char_indices().collect::<Vec<_>>();
+    let new_chars = new.char_indices().collect::<Vec<_>>();
+    let (n, m) = (old_chars.len(), new_chars.len());
+
+    // lcs[i * (m + 1) + j] is the length of the longest common subsequence of the suffixes
+    // `old_chars[i..]` and `new_chars[j..]`
+    let width = m + 1;
+    let mut lcs = vec![0u32; (n + 1) * width];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i * width + j] = if old_chars[i].1 == new_chars[j].1 {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let push = |ranges: &mut Vec<HighlightRange>, (offset, c): (usize, char)| {
+        let upper = offset + c.len_utf8();
+        match ranges.last_mut() {
+            Some(last) if last.upper == offset => last.upper = upper,
+            _ => ranges.push(HighlightRange::new(offset, upper)),
+        }
+    };
+    let (mut deleted, mut inserted) = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_chars[i].1 == new_chars[j].1 {
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+            push(&mut deleted, old_chars[i]);
+            i += 1;
+        } else {
+            push(&mut inserted, new_chars[j]);
+            j += 1;
+        }
+    }
+
+    (deleted, inserted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_support::*, testing::*};
+
+    #[test]
+    fn should_find_misspelled_occurrences_scored_by_edit_distance() {
+        fn fuzzy<'h>(haystack: &'h str, needle: &str, max_edits: u32) -> Vec<(&'h str, f32)> {
+            ranges_for_fuzzy(haystack, needle, max_edits)
+                .into_iter()
+                .map(|s| (&haystack[s.range.lower..s.range.upper], s.score))
+                .collect()
+        }
+
+        // swapped chars are two substitutions
+        assert_eq!(vec![("receive", -2.0)], fuzzy("I receive it", "recieve", 2));
+        assert!(fuzzy("I receive it", "recieve", 1).is_empty());
+        assert_eq!(
+            vec![("quik", -1.0), ("quick", 0.0)],
+            fuzzy("a quikc fox, a quick fox", "quick", 1)
+        );
+        assert_eq!(vec![("😀ö", -1.0)], fuzzy("a😀öb", "😀ü", 1));
+        assert!(fuzzy("grösse", "größe", 1).is_empty());
+        assert!(fuzzy("anything", "", 2).is_empty());
+    }
+
+    #[test]
+    fn should_keep_the_closest_then_leftmost_of_overlapping_fuzzy_matches() {
+        // "abc" is exact, "bc" and "abcd" one edit away
+        assert_eq!(
+            vec![ScoredRange::new(HighlightRange::new(1, 4), 0.0)],
+            ranges_for_fuzzy("xabcd", "abc", 1)
+        );
+        assert_eq!(
+            vec![
+                ScoredRange::new(HighlightRange::new(0, 2), 0.0),
+                ScoredRange::new(HighlightRange::new(2, 4), 0.0),
+            ],
+            ranges_for_fuzzy("aaaa", "aa", 1)
+        );
+        // "a", "ab" and "ba" are all one edit away
+        assert_eq!(
+            vec![ScoredRange::new(HighlightRange::new(0, 2), -1.0)],
+            ranges_for_fuzzy("aba", "aa", 1)
+        );
+        assert_eq!(
+            vec![HighlightRange::new(6, 11)],
+            select_top_k(ranges_for_fuzzy("quikc quick quack", "quick", 1), 1)
+        );
+    }
+
+    #[test]
+    fn should_find_fuzzy_matches_on_char_boundaries_of_generated_inputs() {
+        let mut generator = RangeGenerator::new(0x00f0_220f);
+
+        for _ in 0..300 {
+            let haystack = generator.input(24);
+            let needle = generator.input(4);
+            let matches = ranges_for_fuzzy(&haystack, &needle, 2);
+
+            let ranges = matches.iter().map(|s| s.range.clone()).collect::<Vec<_>>();
+            assert_eq!(
+                Ok(()),
+                validate_ranges(&haystack, &ranges),
+                "{haystack:?} {needle:?}"
+            );
+            assert!(matches.iter().all(|s| (-2.0..=0.0).contains(&s.score)));
+            if !needle.is_empty() {
+                for exact in ranges_for_substring(&haystack, &needle) {
+                    assert!(
+                        ranges
+                            .iter()
+                            .any(|r| r.lower < exact.upper && exact.lower < r.upper)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn should_find_all_substring_occurrences() {
+        assert_eq!(
+            vec![HighlightRange::new(0, 2), HighlightRange::new(6, 8)],
+            ranges_for_substring("ab cd ab", "ab")
+        );
+        // leftmost-first, non-overlapping
+        assert_eq!(
+            vec![HighlightRange::new(0, 2)],
+            ranges_for_substring("aaa", "aa")
+        );
+        assert_eq!(
+            Vec::<HighlightRange>::new(),
+            ranges_for_substring("aaa", "")
+        );
+        assert_eq!(
+            "<em>bö</em>se <em>bö</em>",
+            highlight_matches("böse bö", "bö")
+        );
+    }
+
+    #[test]
+    fn should_find_substring_occurrences_ignoring_case() {
+        assert_eq!(
+            vec![HighlightRange::new(0, 4), HighlightRange::new(22, 26)],
+            ranges_for_substring_ci("Rust is great, I love RUST", "rust")
+        );
+        // 'ẞ' is three bytes, its lowercase 'ß' only two
+        assert_eq!(
+            vec![HighlightRange::new(0, 8), HighlightRange::new(9, 16)],
+            ranges_for_substring_ci("STRAẞE straße", "Straße")
+        );
+        // 'İ' lowercases to "i\u{307}", so a plain "i" only matches half of it
+        assert_eq!(
+            vec![HighlightRange::new(5, 6)],
+            ranges_for_substring_ci("İst ist", "i")
+        );
+        assert_eq!(
+            vec![HighlightRange::new(0, 3)],
+            ranges_for_substring_ci("İst ist", "i\u{307}s")
+        );
+    }
+
+    #[test]
+    fn should_match_dotted_and_dotless_i_with_turkic_case_folding() {
+        let turkic =
+            |haystack, needle| ranges_for_substring_ci_with(haystack, needle, CaseFold::Turkic);
+
+        // 'İ' is two bytes but lowercases to a single 'i'
+        assert_eq!(
+            vec![HighlightRange::new(0, 9)],
+            turkic("İstanbul", "istanbul")
+        );
+        assert_eq!(
+            vec![HighlightRange::new(3, 12)],
+            turkic("in İstanbul", "İSTANBUL")
+        );
+        assert_eq!(vec![HighlightRange::new(1, 4)], turkic("kİMİ", "im"));
+        // 'I' lowercases to the two byte dotless 'ı'
+        assert_eq!(vec![HighlightRange::new(0, 7)], turkic("ılgın", "ILGIN"));
+        assert_eq!(vec![HighlightRange::new(0, 5)], turkic("ILGIN", "ılgın"));
+        assert_eq!(vec![HighlightRange::new(4, 7)], turkic("ılgıN", "ın"));
+        // the dotted and the dotless i don't match each other
+        assert_eq!(Vec::<HighlightRange>::new(), turkic("ILGIN", "ilgin"));
+        assert_eq!(Vec::<HighlightRange>::new(), turkic("İstanbul", "ıstanbul"));
+        assert_eq!(
+            Vec::<HighlightRange>::new(),
+            ranges_for_substring_ci("İstanbul", "istanbul")
+        );
+    }
+
+    #[test]
+    fn should_forward_case_fold_from_compiled_query() {
+        let options = MatchOptions {
+            case_insensitive: true,
+            case_fold: CaseFold::Turkic,
+            ..Default::default()
+        };
+
+        let query = CompiledQuery::new(["istanbul", "ILIK"], options);
+        assert_eq!(
+            Ok("<em>İstanbul</em>, <em>ılık</em>, ilik".to_string()),
+            query.highlight("İstanbul, ılık, ilik")
+        );
+        let query = CompiledQuery::new(
+            ["istanbul"],
+            MatchOptions {
+                case_fold: CaseFold::Simple,
+                ..options
+            },
+        );
+        assert_eq!(Ok("İstanbul".to_string()), query.highlight("İstanbul"));
+    }
+
+    #[test]
+    fn should_find_all_keywords_longest_first() {
+        let haystack = "the cat read the catalog";
+
+        assert_eq!(
+            vec![
+                HighlightRange::new(0, 3),
+                HighlightRange::new(4, 7),
+                HighlightRange::new(13, 16),
+                HighlightRange::new(17, 24),
+            ],
+            ranges_for_keywords(haystack, &["cat", "the", "catalog", ""])
+        );
+        // leftmost wins over longest when occurrences overlap in the text
+        assert_eq!(
+            vec![HighlightRange::new(0, 2)],
+            ranges_for_keywords("abcd", &["bcd", "ab"])
+        );
+    }
+
+    #[test]
+    fn should_label_keyword_ranges_with_their_needle() {
+        let ranges = labeled_ranges_for_keywords("cat or dog", &["dog", "cat"]);
+
+        assert_eq!(
+            Ok(r#"<em class="cat">cat</em> or <em class="dog">dog</em>"#.to_string()),
+            highlight_text_labeled("cat or dog", ranges)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn should_find_regex_matches() {
+        let re = regex::Regex::new(r"\d+\.\d+\.\d+\.\d+|x*").unwrap();
+
+        assert_eq!(
+            vec![HighlightRange::new(5, 13)],
+            ranges_for_regex("from 10.0.0.1", &re)
+        );
+        assert_eq!(
+            "from <em>10.0.0.1</em> to <em>10.0.0.2</em>",
+            highlight_regex("from 10.0.0.1 to 10.0.0.2", &re)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn should_find_regex_group_matches() {
+        let re = regex::Regex::new(r"ERROR\s+(\w+)|WARN(-\w+)?").unwrap();
+        let haystack = "ERROR disk WARN ERROR  net";
+
+        assert_eq!(
+            vec![HighlightRange::new(6, 10), HighlightRange::new(23, 26)],
+            ranges_for_regex_group(haystack, &re, 1)
+        );
+        assert_eq!(
+            Vec::<HighlightRange>::new(),
+            ranges_for_regex_group(haystack, &re, 2)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "unicode-normalization")]
+    fn should_find_substring_occurrences_ignoring_diacritics() {
+        // precomposed "Ü" (two bytes) and decomposed "U\u{308}" (three bytes)
+        let haystack = "Über, U\u{308}ber, uber";
+
+        assert_eq!(
+            vec![
+                HighlightRange::new(0, 5),
+                HighlightRange::new(7, 13),
+                HighlightRange::new(15, 19),
+            ],
+            ranges_for_substring_folded(haystack, "uber")
+        );
+        assert_eq!(
+            ranges_for_substring_folded(haystack, "uber"),
+            ranges_for_substring_folded(haystack, "über")
+        );
+        // the trailing combining accent belongs to the highlighted "e"
+        assert_eq!(
+            vec![HighlightRange::new(0, 6)],
+            ranges_for_substring_folded("cafe\u{301}", "CAFE")
+        );
+    }
+
+    #[test]
+    fn should_find_changed_ranges_in_new_text() {
+        assert_eq!(
+            vec![HighlightRange::new(6, 12), HighlightRange::new(17, 18)],
+            ranges_for_diff("Hello world", "Hello brave world!")
+        );
+        assert_eq!(
+            Ok("Hello <em>brave </em>world<em>!</em>".to_string()),
+            highlight_text(
+                "Hello brave world!",
+                ranges_for_diff("Hello world", "Hello brave world!")
+            )
+        );
+        assert_eq!(
+            Vec::<HighlightRange>::new(),
+            ranges_for_diff("same", "same")
+        );
+    }
+
+    #[test]
+    fn should_find_deleted_and_inserted_ranges() {
+        // "ö" is two bytes
+        let (deleted, inserted) = diff_ranges("grün böse", "grau böse");
+
+        assert_eq!(vec![HighlightRange::new(2, 5)], deleted);
+        assert_eq!(vec![HighlightRange::new(2, 4)], inserted);
+        assert_eq!(
+            (vec![HighlightRange::new(0, 3)], vec![]),
+            diff_ranges("abc", "")
+        );
+        assert_eq!(
+            (vec![], vec![HighlightRange::new(0, 3)]),
+            diff_ranges("", "abc")
+        );
+    }
+
+    #[test]
+    fn should_find_compiled_query_terms() {
+        let query = CompiledQuery::new(["cat", "catalog", ""], MatchOptions::default());
+
+        assert_eq!(
+            vec![HighlightRange::new(0, 7), HighlightRange::new(12, 15)],
+            query.find_ranges("catalog for cats")
+        );
+        assert_eq!(
+            Ok("<em>catalog</em> for <em>cat</em>s".to_string()),
+            query.highlight("catalog for cats")
+        );
+
+        let repeated = CompiledQuery::new(["ab", "cd", "ab"], MatchOptions::default());
+        assert_eq!(["ab", "cd"], repeated.terms.as_slice());
+    }
+
+    #[test]
+    fn should_match_compiled_query_terms_ignoring_case_and_on_whole_words() {
+        let query = CompiledQuery::new(
+            ["STRASSE", "öl"],
+            MatchOptions {
+                case_insensitive: true,
+                whole_words: true,
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            Ok("<em>Strasse</em> und <em>Öl</em>, Erdöl, <em>ÖL</em>".to_string()),
+            query.highlight("Strasse und Öl, Erdöl, ÖL")
+        );
+        // the lowercase form of 'İ' is two chars, matching only the first one doesn't count
+        let query = CompiledQuery::new(
+            ["i"],
+            MatchOptions {
+                case_insensitive: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(vec![HighlightRange::new(3, 4)], query.find_ranges("İ i"));
+    }
+
+    #[test]
+    fn should_allocate_less_per_document_with_a_compiled_query() {
+        let needles = ["quick", "fox", "dog", "lazy"];
+        let query = CompiledQuery::new(needles, MatchOptions::default());
+        let mut scratch = Scratch::default();
+        let docs = [
+            "the quick brown fox jumps over the lazy dog",
+            "a lazy fox, a quick dog, a fox again",
+        ];
+        // warms up the scratch buffers
+        for doc in docs {
+            query.highlight_with_scratch(doc, &mut scratch).unwrap();
+        }
+
+        for doc in docs {
+            let (naive, naive_allocations) = count_allocations(|| {
+                highlight_text(doc, ranges_for_keywords(doc, &needles)).unwrap()
+            });
+            let (compiled, compiled_allocations) =
+                count_allocations(|| query.highlight_with_scratch(doc, &mut scratch).unwrap());
+
+            assert_eq!(naive, compiled);
+            assert!(
+                compiled_allocations < naive_allocations,
+                "{compiled_allocations} vs {naive_allocations}"
+            );
+            assert!(compiled_allocations <= 2, "{compiled_allocations}");
+        }
+    }
+
+    #[test]
+    fn should_match_whole_words_only() {
+        let whole_words = MatchOptions {
+            whole_words: true,
+            ..MatchOptions::default()
+        };
+        let haystack = "cat-like cat concatenate cat_food tomcat cat";
+        let found = |needle| {
+            ranges_for_substring_with(haystack, needle, whole_words)
+                .into_iter()
+                .map(|r| r.lower())
+                .collect::<Vec<_>>()
+        };
+
+        // hyphens delimit words, underscores don't
+        assert_eq!(vec![0, 9, 41], found("cat"));
+        assert_eq!(vec![25], found("cat_food"));
+        assert_eq!(vec![4], found("like"));
+        assert_eq!(
+            Vec::<HighlightRange>::new(),
+            ranges_for_substring_with("schön", "sch", whole_words)
+        );
+    }
+
+    #[test]
+    fn should_match_any_whitespace_run_with_collapsed_whitespace() {
+        let collapsed = MatchOptions {
+            collapse_whitespace: true,
+            ..MatchOptions::default()
+        };
+        fn found<'h>(haystack: &'h str, needle: &str) -> Vec<&'h str> {
+            let collapsed = MatchOptions {
+                collapse_whitespace: true,
+                ..MatchOptions::default()
+            };
+            ranges_for_substring_with(haystack, needle, collapsed)
+                .into_iter()
+                .map(|r| &haystack[r.lower()..r.upper()])
+                .collect()
+        }
+
+        // across a line break, covering the real run
+        assert_eq!(
+            vec!["hello\n  world"],
+            found("say hello\n  world", "hello world")
+        );
+        assert_eq!(
+            vec!["hello world", "hello\t\r\nworld"],
+            found("hello world, hello\t\r\nworld", "hello   world")
+        );
+        // right after a whitespace run
+        assert_eq!(vec!["a\u{a0}b"], found("  \n\u{3000}a\u{a0}b", "a b"));
+        // trailing and leading whitespace of the needle is trimmed
+        assert_eq!(
+            vec!["hello world"],
+            found("hello world !", " hello world\n")
+        );
+        assert!(found("hello world", " \n ").is_empty());
+        // a space in the needle needs whitespace in the haystack
+        assert!(found("helloworld", "hello world").is_empty());
+        assert!(found("hello world", "helloworld").is_empty());
+        assert_eq!(
+            "<em>Straße\n Nord</em>",
+            CompiledQuery::new(
+                ["strasse nord", "STRAẞE NORD"],
+                MatchOptions {
+                    case_insensitive: true,
+                    ..collapsed
+                }
+            )
+            .highlight("Straße\n Nord")
+            .unwrap()
+        );
+        assert_eq!(
+            vec![HighlightRange::new(0, 9), HighlightRange::new(14, 22)],
+            ranges_for_keywords_with(
+                "New  York and new\nyork",
+                &["new york", "York"],
+                MatchOptions {
+                    whole_words: true,
+                    case_insensitive: true,
+                    ..collapsed
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn should_only_require_boundaries_next_to_word_chars_of_the_needle() {
+        let whole_words = MatchOptions {
+            whole_words: true,
+            case_insensitive: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            vec![HighlightRange::new(0, 3), HighlightRange::new(8, 11)],
+            ranges_for_substring_with("C++ and c++11 and xC++", "C++", whole_words)
+        );
+        assert_eq!(
+            vec![HighlightRange::new(0, 3), HighlightRange::new(14, 21)],
+            ranges_for_keywords_with("cat catalogue catalog", &["cat", "catalog"], whole_words)
+        );
+        // the shorter needle still matches where the longer one isn't a whole word
+        assert_eq!(
+            vec![HighlightRange::new(0, 3)],
+            ranges_for_keywords_with("foo-barx", &["foo-bar", "foo"], whole_words)
+        );
+        assert!(at_word_boundaries("(x)", 0, 1));
+        assert!(!at_word_boundaries("ab", 0, 1));
+        assert!(!at_word_boundaries("ab", 1, 3));
+        assert!(!at_word_boundaries("ab", 2, 1));
+        assert!(!at_word_boundaries("ö", 0, 1));
+    }
+}
@@ -0,0 +1,403 @@
+//! Generators and invariant checks for testing code that produces ranges, behind the `testing`
+//! feature. `RangeGenerator` is deterministic for a seed, so a failing case can be replayed; with
+//! the `proptest` feature the same inputs and ranges come as shrinking proptest strategies.
+
+use alloc::{string::String, vec::Vec};
+use core::iter;
+
+#[cfg(feature = "proptest")]
+use proptest::{collection, prelude::*, sample};
+
+use crate::{range::*, render::*, validate::*};
+
+/// Chars of the generated inputs: multi-byte ones and markup that has to be escaped, but never
+/// the `<em>` markers themselves
+const INPUT_CHARS: [char; 8] = ['a', 'b', ' ', 'ö', '€', '😀', '<', '&'];
+
+/// Reproducible source of inputs and ranges over them, a small linear congruential generator
+#[derive(Debug, Clone)]
+pub struct RangeGenerator {
+    state: u64,
+}
+
+impl RangeGenerator {
+    pub fn new(seed: u64) -> Self {
+        RangeGenerator { state: seed }
+    }
+
+    /// A number below `bound`, or 0 if `bound` is 0
+    pub fn below(&mut self, bound: usize) -> usize {
+        self.state = self
+            .state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1);
+        match bound {
+            0 => 0,
+            _ => ((self.state >> 33) % bound as u64) as usize,
+        }
+    }
+
+    /// Up to `max_chars` chars, mixing multi-byte chars, spaces, `<` and `&`
+    pub fn input(&mut self, max_chars: usize) -> String {
+        let len = self.below(max_chars + 1);
+        (0..len)
+            .map(|_| INPUT_CHARS[self.below(INPUT_CHARS.len())])
+            .collect()
+    }
+
+    /// Ranges that [`highlight_text`] accepts for `input`: sorted, in bounds, on char boundaries
+    /// and not overlapping, though some of them touch or are zero-length
+    pub fn valid_ranges(&mut self, input: &str) -> Vec<HighlightRange> {
+        let picks = char_boundaries(input).map(|_| self.below(4)).collect();
+        ranges_from_picks(char_boundaries(input), picks)
+    }
+
+    /// Up to 8 ranges in bounds and on char boundaries of `input`, in any order and possibly
+    /// overlapping, like the raw matches of several searches
+    pub fn overlapping_ranges(&mut self, input: &str) -> Vec<HighlightRange> {
+        let bounds = char_boundaries(input).collect::<Vec<_>>();
+        (0..self.below(9))
+            .map(|_| {
+                let lower = bounds[self.below(bounds.len())];
+                HighlightRange::new(lower, bounds[self.below(bounds.len())])
+            })
+            .collect()
+    }
+
+    /// Ranges that [`highlight_text`] rejects for `input`, with the error it reports: an
+    /// overlap, an offset inside a char or a range past the end
+    pub fn invalid_ranges(&mut self, input: &str) -> (Vec<HighlightRange>, HighlightingError) {
+        let ranges = self.valid_ranges(input);
+        let kind = self.below(3);
+        let picks = [
+            self.below(usize::MAX),
+            self.below(3),
+            self.below(usize::MAX),
+        ];
+        add_invalid_range(input.len(), &inside_chars(input), ranges, kind, picks)
+    }
+}
+
+/// Offsets of the chars of `input` and its end
+fn char_boundaries(input: &str) -> impl Iterator<Item = usize> + '_ {
+    input
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain(iter::once(input.len()))
+}
+
+/// Offsets right after the first byte of every multi-byte char of `input`
+fn inside_chars(input: &str) -> Vec<usize> {
+    input
+        .char_indices()
+        .filter(|(_, c)| c.len_utf8() > 1)
+        .map(|(offset, _)| offset + 1)
+        .collect()
+}
+
+/// Sorted ranges between `boundaries` that don't overlap: `picks[n] % 4` uses the n-th boundary
+/// 0, 0, 1 or 2 times, so consecutive pairs of them touch or are empty
+fn ranges_from_picks(
+    boundaries: impl Iterator<Item = usize>,
+    picks: Vec<usize>,
+) -> Vec<HighlightRange> {
+    let mut bounds = Vec::new();
+    for (offset, pick) in boundaries.zip(picks) {
+        bounds.extend(iter::repeat_n(offset, [0, 0, 1, 2][pick % 4]));
+    }
+    bounds.truncate(bounds.len() / 2 * 2);
+
+    bounds
+        .chunks(2)
+        .map(|pair| HighlightRange::new(pair[0], pair[1]))
+        .collect()
+}
+
+/// `ranges`, valid for an input of `input_len` bytes with multi-byte chars at `inside_chars`, with
+/// one range added that [`highlight_text`] rejects, and the error: an overlap, an offset inside a
+/// char or, by `kind` or if neither is possible, a range past the end. `picks` choose the range
+/// and offsets, each is reduced to the number of choices.
+fn add_invalid_range(
+    input_len: usize,
+    inside_chars: &[usize],
+    mut ranges: Vec<HighlightRange>,
+    kind: usize,
+    picks: [usize; 3],
+) -> (Vec<HighlightRange>, HighlightingError) {
+    let non_empty = (0..ranges.len())
+        .filter(|&i| !ranges[i].is_empty())
+        .collect::<Vec<_>>();
+
+    match kind % 3 {
+        0 if !non_empty.is_empty() => {
+            let first = ranges[non_empty[picks[0] % non_empty.len()]].clone();
+            ranges.push(first.clone());
+            let second = first.clone();
+            (
+                ranges,
+                HighlightingError::OverlappingRanges { first, second },
+            )
+        }
+        1 if !inside_chars.is_empty() => {
+            let offset = inside_chars[picks[0] % inside_chars.len()];
+            ranges.push(HighlightRange::new(offset, input_len));
+            (ranges, HighlightingError::NotACharBoundary(offset))
+        }
+        _ => {
+            let range =
+                HighlightRange::new(picks[0] % (input_len + 1), input_len + 1 + picks[1] % 3);
+            let at = picks[2] % (ranges.len() + 1);
+            ranges.insert(at, range.clone());
+            (
+                ranges,
+                HighlightingError::RangesOutOfBounds { range, input_len },
+            )
+        }
+    }
+}
+
+/// Inputs of up to `max_chars` chars like [`RangeGenerator::input`], shrinking towards shorter
+/// ones made of `a`
+#[cfg(feature = "proptest")]
+pub fn inputs(max_chars: usize) -> impl Strategy<Value = String> {
+    collection::vec(sample::select(&INPUT_CHARS[..]), 0..=max_chars)
+        .prop_map(|chars| chars.into_iter().collect())
+}
+
+/// Ranges that [`highlight_text`] accepts for any input of `input_len` single-byte chars, like
+/// [`RangeGenerator::valid_ranges`]; they shrink towards fewer ranges. Use [`valid_ranges_for`]
+/// for inputs with multi-byte chars.
+#[cfg(feature = "proptest")]
+pub fn valid_ranges(input_len: usize) -> impl Strategy<Value = Vec<HighlightRange>> {
+    collection::vec(0..4usize, input_len + 1)
+        .prop_map(move |picks| ranges_from_picks(0..=input_len, picks))
+}
+
+/// Ranges that [`highlight_text`] accepts for `input`, on its char boundaries
+#[cfg(feature = "proptest")]
+pub fn valid_ranges_for(input: &str) -> impl Strategy<Value = Vec<HighlightRange>> + use<> {
+    let boundaries = char_boundaries(input).collect::<Vec<_>>();
+    collection::vec(0..4usize, boundaries.len())
+        .prop_map(move |picks| ranges_from_picks(boundaries.iter().copied(), picks))
+}
+
+/// Ranges that [`highlight_text`] rejects for any input of `input_len` single-byte chars, tagged
+/// with the error it reports, like [`RangeGenerator::invalid_ranges`]: an overlap or a range past
+/// the end
+#[cfg(feature = "proptest")]
+pub fn invalid_ranges(
+    input_len: usize,
+) -> impl Strategy<Value = (Vec<HighlightRange>, HighlightingError)> {
+    (valid_ranges(input_len), 0..3usize, any::<[usize; 3]>()).prop_map(
+        move |(ranges, kind, picks)| add_invalid_range(input_len, &[], ranges, kind, picks),
+    )
+}
+
+/// Ranges that [`highlight_text`] rejects for `input`, tagged with the error it reports; an
+/// offset inside a char is one of the ways they are invalid
+#[cfg(feature = "proptest")]
+pub fn invalid_ranges_for(
+    input: &str,
+) -> impl Strategy<Value = (Vec<HighlightRange>, HighlightingError)> + use<> {
+    let input_len = input.len();
+    let inside_chars = inside_chars(input);
+    (valid_ranges_for(input), 0..3usize, any::<[usize; 3]>()).prop_map(
+        move |(ranges, kind, picks)| {
+            add_invalid_range(input_len, &inside_chars, ranges, kind, picks)
+        },
+    )
+}
+
+/// An input of [`inputs`] together with ranges of [`valid_ranges_for`] over it
+#[cfg(feature = "proptest")]
+pub fn inputs_with_valid_ranges(
+    max_chars: usize,
+) -> impl Strategy<Value = (String, Vec<HighlightRange>)> {
+    inputs(max_chars).prop_flat_map(|input| {
+        let ranges = valid_ranges_for(&input);
+        (Just(input), ranges)
+    })
+}
+
+/// An input of [`inputs`] together with ranges of [`invalid_ranges_for`] over it
+#[cfg(feature = "proptest")]
+pub fn inputs_with_invalid_ranges(
+    max_chars: usize,
+) -> impl Strategy<Value = (String, (Vec<HighlightRange>, HighlightingError))> {
+    inputs(max_chars).prop_flat_map(|input| {
+        let ranges = invalid_ranges_for(&input);
+        (Just(input), ranges)
+    })
+}
+
+/// Panics unless `ranges` are in the canonical form of [`merge_ranges`]: sorted, none of them
+/// empty and each one ending before the next one starts
+pub fn assert_canonical(ranges: &[HighlightRange]) {
+    for range in ranges {
+        assert!(!range.is_empty(), "empty range {range} in {ranges:?}");
+    }
+    for pair in ranges.windows(2) {
+        assert!(
+            pair[0].upper < pair[1].lower,
+            "{} and {} overlap, touch or are out of order in {ranges:?}",
+            pair[0],
+            pair[1]
+        );
+    }
+}
+
+/// Panics unless `segments` split `input` into consecutive pieces: the first one starts at 0,
+/// every other one where the one before it ends, the last one ends at the end of `input`, and
+/// each text is the part of `input` at its range
+pub fn assert_covers_input(segments: &[Segment], input: &str) {
+    let mut offset = 0;
+    for segment in segments {
+        assert_eq!(
+            offset, segment.range.lower,
+            "gap or overlap before {segment:?}"
+        );
+        assert_eq!(
+            input.get(segment.range.lower..segment.range.upper),
+            Some(segment.text),
+            "text of {segment:?}"
+        );
+        offset = segment.range.upper;
+    }
+    assert_eq!(input.len(), offset, "segments end before the input does");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_generate_ranges_highlight_text_accepts() {
+        let mut generator = RangeGenerator::new(0x1f2e_3d4c);
+
+        for _ in 0..500 {
+            let input = generator.input(16);
+            let ranges = generator.valid_ranges(&input);
+
+            assert_eq!(
+                Ok(()),
+                validate_ranges(&input, &ranges),
+                "{input:?} {ranges:?}"
+            );
+            assert!(ranges.is_sorted(), "{ranges:?}");
+        }
+    }
+
+    #[test]
+    fn should_generate_ranges_with_the_error_highlight_text_reports() {
+        let mut generator = RangeGenerator::new(0x5a5a_0f0f);
+
+        for _ in 0..500 {
+            let input = generator.input(16);
+            let (ranges, error) = generator.invalid_ranges(&input);
+
+            assert_eq!(
+                Err(error),
+                highlight_text(&input, ranges.clone()),
+                "{input:?} {ranges:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn should_concat_segments_to_the_input() {
+        let mut generator = RangeGenerator::new(0x0bad_cafe);
+
+        for _ in 0..500 {
+            let input = generator.input(16);
+            let ranges = generator.valid_ranges(&input);
+
+            let segments = highlight_segments(&input, ranges).unwrap();
+
+            assert_covers_input(&segments, &input);
+            assert_eq!(input, segments.iter().map(|s| s.text).collect::<String>());
+        }
+    }
+
+    #[test]
+    fn should_merge_generated_overlaps_into_canonical_ranges() {
+        let mut generator = RangeGenerator::new(0xfeed_f00d);
+
+        for _ in 0..500 {
+            let input = generator.input(16);
+            let ranges = generator.overlapping_ranges(&input);
+
+            let merged = merge_ranges(ranges.clone());
+
+            assert_canonical(&merged);
+            assert_eq!(Ok(()), validate_ranges(&input, &merged), "{ranges:?}");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "0..3 and 3..5 overlap, touch or are out of order")]
+    fn should_reject_touching_ranges_as_not_canonical() {
+        assert_canonical(&[HighlightRange::new(0, 3), HighlightRange::new(3, 5)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "gap or overlap before")]
+    fn should_reject_segments_leaving_a_gap() {
+        let input = "Hello world";
+        let mut segments = highlight_segments(input, [HighlightRange::new(0, 5)]).unwrap();
+        segments.remove(0);
+
+        assert_covers_input(&segments, input);
+    }
+
+    #[test]
+    fn should_pick_zero_below_zero() {
+        let mut generator = RangeGenerator::new(7);
+
+        assert_eq!(0, generator.below(0));
+        assert_eq!(String::new(), generator.input(0));
+    }
+
+    #[cfg(feature = "proptest")]
+    proptest! {
+        #[test]
+        fn should_strip_rendered_tags_back_to_the_input(
+            (input, ranges) in inputs_with_valid_ranges(16)
+        ) {
+            let rendered = highlight_text(&input, ranges).unwrap();
+
+            prop_assert_eq!(input, rendered.replace("<em>", "").replace("</em>", ""));
+        }
+
+        #[test]
+        fn should_concat_strategy_segments_to_the_input(
+            (input, ranges) in inputs_with_valid_ranges(16)
+        ) {
+            let segments = highlight_segments(&input, ranges).unwrap();
+
+            assert_covers_input(&segments, &input);
+            prop_assert_eq!(
+                input.clone(),
+                segments.iter().map(|s| s.text).collect::<String>()
+            );
+        }
+
+        #[test]
+        fn should_reject_strategy_ranges_with_their_error(
+            (input, (ranges, error)) in inputs_with_invalid_ranges(16)
+        ) {
+            prop_assert_eq!(Err(error), highlight_text(&input, ranges));
+        }
+
+        #[test]
+        fn should_generate_ranges_for_an_input_length(
+            ranges in valid_ranges(12),
+            (invalid, error) in invalid_ranges(12)
+        ) {
+            let input = "a".repeat(12);
+
+            prop_assert_eq!(Ok(()), validate_ranges(&input, &ranges));
+            prop_assert!(ranges.is_sorted());
+            prop_assert_eq!(Err(error), highlight_text(&input, invalid));
+        }
+    }
+}
@@ -0,0 +1,1620 @@
+//! Checking ranges against an input: the errors, the overlap and bounds policies, and the
+//! policies resolving overlaps between labeled or scored ranges
+
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{cmp::Reverse, fmt};
+
+use crate::range::*;
+#[cfg(doc)]
+use crate::render::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HighlightingError {
+    /// `first` comes before `second` in the ranges that were passed in
+    OverlappingRanges {
+        first: HighlightRange,
+        second: HighlightRange,
+    },
+    /// `input_len` is measured in the same unit as the range (bytes, UTF-16 code units, ...); char
+    /// ranges are reported as [`HighlightingError::CharRangeOutOfBounds`] instead
+    RangesOutOfBounds {
+        range: HighlightRange,
+        input_len: usize,
+    },
+    /// byte offset that falls inside a multi-byte code point
+    NotACharBoundary(usize),
+    /// UTF-16 offset that falls between the two halves of a surrogate pair
+    SplitsSurrogatePair(usize),
+    /// range of char indices reaching past the last char, see [`IndexKind::Chars`]
+    CharRangeOutOfBounds {
+        range: HighlightRange,
+        char_count: usize,
+    },
+    /// [`AnchoredRange`] measured from the end that starts before the start of the input;
+    /// `input_len` is counted in `kind`
+    AnchoredRangeUnderflow {
+        len: usize,
+        end_gap: usize,
+        input_len: usize,
+        kind: IndexKind,
+    },
+    /// range of word indices reaching past the last word, see [`word_ranges_to_byte_ranges`]
+    WordRangeOutOfBounds {
+        range: HighlightRange,
+        word_count: usize,
+    },
+    /// moving `range` by `delta` would put it below zero or past `usize::MAX`, see
+    /// [`shift_ranges`]
+    ShiftOutOfRange { range: HighlightRange, delta: isize },
+    /// 1-based line that doesn't exist, see [`line_col_to_byte_ranges`]
+    LineOutOfBounds { line: u32, line_count: usize },
+    /// 1-based column past the end of its line, `line_len` is counted in chars
+    ColumnOutOfBounds {
+        line: u32,
+        column: u32,
+        line_len: usize,
+    },
+    /// byte range of the name in a template's `{name}` that isn't one of its placeholders, see
+    /// [`Template`]
+    UnknownPlaceholder(HighlightRange),
+    /// byte offset of a `{` or `}` in a template that is neither doubled nor part of a
+    /// placeholder
+    UnmatchedBrace(usize),
+    /// byte offset of the first byte that isn't valid UTF-8, see [`highlight_bytes_strict`]
+    InvalidUtf8(usize),
+    /// [`split_long_ranges`] was asked for pieces of at most 0 chars
+    ZeroMaxLen,
+    /// byte offset of a range boundary inside the entity or tag at `markup`, see
+    /// [`ValidationMode::HtmlAware`]
+    SplitsMarkup {
+        offset: usize,
+        markup: HighlightRange,
+        kind: MarkupKind,
+    },
+    /// more ranges were passed in than [`HighlighterBuilder::max_ranges`] allows
+    TooManyRanges { count: usize, max: usize },
+    /// the output was estimated at `estimated` bytes, more than
+    /// [`HighlighterBuilder::max_output_bytes`] allows
+    OutputTooLarge { estimated: usize, max: usize },
+}
+
+impl HighlightingError {
+    /// Names where `self` happened, e.g. the document it was produced for
+    pub fn with_context(self, context: impl Into<ErrorContext>) -> ContextError {
+        ContextError {
+            context: context.into(),
+            error: self,
+        }
+    }
+}
+
+/// A [`HighlightingError`] and where it happened, shown before the error in its message. The
+/// context is kept out of [`HighlightingError`] itself, which has to stay usable in `const`
+/// contexts like [`validate_ranges_const`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ContextError {
+    pub context: ErrorContext,
+    pub error: HighlightingError,
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.context, self.error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ContextError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Where a [`HighlightingError`] happened, see [`HighlightingError::with_context`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorContext {
+    /// free-form, e.g. a document id
+    Label(String),
+    /// field of a document, `doc_id` is `None` when the field is all that's known
+    Field {
+        doc_id: Option<String>,
+        field: String,
+    },
+    /// index of the item in a batch, see [`Highlighter::highlight_many`]
+    Item(usize),
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorContext::Label(label) => f.write_str(label),
+            ErrorContext::Field {
+                doc_id: Some(doc_id),
+                field,
+            } => write!(f, "document {doc_id}, field {field}"),
+            ErrorContext::Field {
+                doc_id: None,
+                field,
+            } => write!(f, "field {field}"),
+            ErrorContext::Item(index) => write!(f, "item {index}"),
+        }
+    }
+}
+
+impl From<&str> for ErrorContext {
+    fn from(label: &str) -> Self {
+        ErrorContext::Label(label.to_string())
+    }
+}
+
+impl From<String> for ErrorContext {
+    fn from(label: String) -> Self {
+        ErrorContext::Label(label)
+    }
+}
+
+/// `(doc_id, field)`
+impl<D: Into<String>, F: Into<String>> From<(D, F)> for ErrorContext {
+    fn from((doc_id, field): (D, F)) -> Self {
+        ErrorContext::Field {
+            doc_id: Some(doc_id.into()),
+            field: field.into(),
+        }
+    }
+}
+
+impl fmt::Display for HighlightingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HighlightingError::OverlappingRanges { first, second } => {
+                write!(f, "ranges {first} and {second} overlap")
+            }
+            HighlightingError::RangesOutOfBounds { range, input_len } => write!(
+                f,
+                "range {range} is out of bounds for input of length {input_len}"
+            ),
+            HighlightingError::NotACharBoundary(offset) => {
+                write!(f, "byte offset {offset} is not on a char boundary")
+            }
+            HighlightingError::SplitsSurrogatePair(offset) => {
+                write!(f, "UTF-16 offset {offset} splits a surrogate pair")
+            }
+            HighlightingError::CharRangeOutOfBounds { range, char_count } => write!(
+                f,
+                "char range {range} is out of bounds for input with {char_count} chars"
+            ),
+            HighlightingError::AnchoredRangeUnderflow {
+                len,
+                end_gap,
+                input_len,
+                kind,
+            } => {
+                let unit = match kind {
+                    IndexKind::Bytes => "bytes",
+                    IndexKind::Chars => "chars",
+                };
+                write!(
+                    f,
+                    "the last {len} {unit} before the final {end_gap} don't fit into input of {input_len} {unit}"
+                )
+            }
+            HighlightingError::WordRangeOutOfBounds { range, word_count } => write!(
+                f,
+                "word range {range} is out of bounds for input with {word_count} words"
+            ),
+            HighlightingError::ShiftOutOfRange { range, delta } => {
+                write!(
+                    f,
+                    "shifting range {range} by {delta} leaves the usize range"
+                )
+            }
+            HighlightingError::LineOutOfBounds { line, line_count } => write!(
+                f,
+                "line {line} is out of bounds for input with {line_count} lines"
+            ),
+            HighlightingError::ColumnOutOfBounds {
+                line,
+                column,
+                line_len,
+            } => write!(
+                f,
+                "column {column} is out of bounds for line {line} of length {line_len}"
+            ),
+            HighlightingError::UnknownPlaceholder(range) => {
+                write!(f, "unknown placeholder at bytes {range} of template")
+            }
+            HighlightingError::UnmatchedBrace(offset) => {
+                write!(f, "unmatched brace at byte {offset} of template")
+            }
+            HighlightingError::InvalidUtf8(offset) => {
+                write!(f, "invalid UTF-8 at byte {offset}")
+            }
+            HighlightingError::ZeroMaxLen => f.write_str("ranges can't be split into empty pieces"),
+            HighlightingError::SplitsMarkup {
+                offset,
+                markup,
+                kind,
+            } => {
+                let kind = match kind {
+                    MarkupKind::Entity => "entity",
+                    MarkupKind::Tag => "tag",
+                };
+                write!(f, "byte offset {offset} splits the HTML {kind} at {markup}")
+            }
+            HighlightingError::TooManyRanges { count, max } => {
+                write!(f, "{count} ranges exceed the limit of {max}")
+            }
+            HighlightingError::OutputTooLarge { estimated, max } => write!(
+                f,
+                "estimated output of {estimated} bytes exceeds the limit of {max} bytes"
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for HighlightingError {}
+
+/// How [`highlight_text_with_policy`] and [`Highlighter`] treat ranges that overlap each other
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverlapPolicy {
+    /// reject with [`HighlightingError::OverlappingRanges`]
+    #[default]
+    Error,
+    /// coalesce overlapping and touching ranges into a single highlight
+    Merge,
+    /// give every range its own tags, nesting a range inside the ones it overlaps; where ranges
+    /// only partially overlap the inner one is closed and reopened so that the tags never cross.
+    /// Only affects rendering into text, [`Highlighter::segments`] and the other flat views treat
+    /// it like [`OverlapPolicy::Merge`].
+    Nest,
+}
+
+/// How a [`Highlighter`] treats ranges that reach past the end of the input
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BoundsPolicy {
+    /// reject with [`HighlightingError::RangesOutOfBounds`]
+    #[default]
+    Strict,
+    /// cut ranges off at the end of the input and drop ranges that start past it; ranges that
+    /// end up empty are treated like any other zero-length range
+    Clamp,
+}
+
+/// Same checks as [`highlight_text`] performs, usable in const context to reject a bad static
+/// table at compile time: `const _: () = assert!(validate_ranges_const(TEXT, &RANGES).is_ok());`.
+/// Overlaps are found by comparing every pair, which is fine for the small tables this is
+/// meant for, and the first overlapping pair by index is reported.
+pub const fn validate_ranges_const(
+    input: &str,
+    highlights: &[HighlightRange],
+) -> Result<(), HighlightingError> {
+    let bytes = input.as_bytes();
+    let mut i = 0;
+    while i < highlights.len() {
+        let h = &highlights[i];
+        if h.upper > bytes.len() {
+            return Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(h.lower, h.upper),
+                input_len: bytes.len(),
+            });
+        }
+        i += 1;
+    }
+
+    let mut i = 0;
+    while i < highlights.len() {
+        let h = &highlights[i];
+        // continuation bytes of UTF-8 are 0b10xx_xxxx
+        if h.lower < bytes.len() && bytes[h.lower] & 0xC0 == 0x80 {
+            return Err(HighlightingError::NotACharBoundary(h.lower));
+        }
+        if h.upper < bytes.len() && bytes[h.upper] & 0xC0 == 0x80 {
+            return Err(HighlightingError::NotACharBoundary(h.upper));
+        }
+        i += 1;
+    }
+
+    let mut i = 0;
+    while i < highlights.len() {
+        let mut j = i + 1;
+        while j < highlights.len() {
+            let (a, b) = (&highlights[i], &highlights[j]);
+            if a.lower < b.upper && b.lower < a.upper {
+                return Err(HighlightingError::OverlappingRanges {
+                    first: HighlightRange::new(a.lower, a.upper),
+                    second: HighlightRange::new(b.lower, b.upper),
+                });
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+
+    Ok(())
+}
+
+/// What the offsets of a range count, see [`validate_ranges_as`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IndexKind {
+    /// byte offsets, which must lie on char boundaries
+    #[default]
+    Bytes,
+    /// char indices, bounded by the number of chars instead of bytes
+    Chars,
+}
+
+/// Checks that `highlights` are in bounds and don't overlap, with offsets counted in `kind`;
+/// out-of-bounds char ranges are reported as [`HighlightingError::CharRangeOutOfBounds`]
+pub fn validate_ranges_as(
+    input: &str,
+    highlights: &[HighlightRange],
+    kind: IndexKind,
+) -> Result<(), HighlightingError> {
+    match kind {
+        IndexKind::Bytes => validate_ranges(input, highlights),
+        IndexKind::Chars => {
+            let char_count = input.chars().count();
+            if let Some(h) = highlights.iter().find(|h| h.upper > char_count) {
+                return Err(HighlightingError::CharRangeOutOfBounds {
+                    range: h.clone(),
+                    char_count,
+                });
+            }
+            check_overlaps(highlights)
+        }
+    }
+}
+
+/// What [`validate_ranges_in`] checks the range boundaries against, besides the bounds, char
+/// boundaries and overlaps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValidationMode {
+    /// the input is plain text, anything goes
+    #[default]
+    Text,
+    /// the input is already HTML, no boundary may fall inside an entity like `&amp;` or a tag
+    /// like `<a href="...">`, see [`snap_out_of_markup`] for moving them out instead
+    HtmlAware,
+}
+
+/// What a range boundary split, see [`HighlightingError::SplitsMarkup`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MarkupKind {
+    /// `&amp;`, `&#39;`, ...
+    Entity,
+    /// `<b>`, `</a>`, `<!-- ... -->`, ...
+    Tag,
+}
+
+/// Same as [`validate_ranges_as`] with byte offsets, plus the boundary checks of `mode`; the
+/// first boundary splitting markup is reported in the order of `highlights`
+pub fn validate_ranges_in(
+    input: &str,
+    highlights: &[HighlightRange],
+    mode: ValidationMode,
+) -> Result<(), HighlightingError> {
+    validate_ranges(input, highlights)?;
+    if mode == ValidationMode::Text {
+        return Ok(());
+    }
+
+    let spans = markup_spans(input);
+    let offsets = highlights.iter().flat_map(|h| [h.lower, h.upper]);
+    match offsets
+        .filter_map(|offset| markup_around(&spans, offset).map(|span| (offset, span)))
+        .next()
+    {
+        Some((offset, (markup, kind))) => Err(HighlightingError::SplitsMarkup {
+            offset,
+            markup: markup.clone(),
+            kind: *kind,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Entities and tags of `input` in order, found by a simple scan rather than an HTML parser: a
+/// `<` only starts a tag when followed by a letter, `/`, `!` or `?`, and a `>` in a quoted
+/// attribute value doesn't end it; an entity is `&`, at most 32 letters, digits or `#` and `;`.
+/// Anything left unterminated is text.
+pub(crate) fn markup_spans(input: &str) -> Vec<(HighlightRange, MarkupKind)> {
+    let bytes = input.as_bytes();
+    // a tag can't end after the last `>`, so an unterminated `<` doesn't rescan the whole rest of
+    // the input for every `<` coming after it
+    let tags_until = bytes
+        .iter()
+        .rposition(|&b| b == b'>')
+        .map_or(0, |last| last + 1);
+    let mut spans = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let end = match bytes[offset] {
+            b'<' if offset < tags_until => {
+                tag_end(&bytes[..tags_until], offset).map(|end| (end, MarkupKind::Tag))
+            }
+            b'&' => entity_end(bytes, offset).map(|end| (end, MarkupKind::Entity)),
+            _ => None,
+        };
+        match end {
+            Some((end, kind)) => {
+                spans.push((HighlightRange::new(offset, end), kind));
+                offset = end;
+            }
+            None => offset += 1,
+        }
+    }
+    spans
+}
+
+/// End of the tag starting with the `<` at `start`
+fn tag_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let next = *bytes.get(start + 1)?;
+    if !(next.is_ascii_alphabetic() || matches!(next, b'/' | b'!' | b'?')) {
+        return None;
+    }
+
+    let mut quote = None;
+    for (offset, &b) in bytes.iter().enumerate().skip(start + 1) {
+        match quote {
+            Some(q) if b == q => quote = None,
+            Some(_) => {}
+            None if b == b'"' || b == b'\'' => quote = Some(b),
+            None if b == b'>' => return Some(offset + 1),
+            None => {}
+        }
+    }
+    None
+}
+
+/// End of the entity starting with the `&` at `start`
+fn entity_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let name = &bytes[start + 1..];
+    let len = name
+        .iter()
+        .take(33)
+        .position(|&b| !(b.is_ascii_alphanumeric() || b == b'#'))?;
+    (len > 0 && name[len] == b';').then_some(start + len + 2)
+}
+
+/// The span of sorted, disjoint `spans` that `offset` lies strictly inside of
+pub(crate) fn markup_around(
+    spans: &[(HighlightRange, MarkupKind)],
+    offset: usize,
+) -> Option<&(HighlightRange, MarkupKind)> {
+    let span = spans.get(spans.partition_point(|(span, _)| span.upper <= offset))?;
+    (span.0.lower < offset).then_some(span)
+}
+
+/// Reports the first issue [`validate_ranges_all`] would, without collecting the others
+pub(crate) fn validate_ranges(
+    input: &str,
+    highlights: &[HighlightRange],
+) -> Result<(), HighlightingError> {
+    validate_bounds(input, highlights)?;
+    check_overlaps(highlights)
+}
+
+/// A single problem found by [`validate_ranges_all`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    /// index of the offending range in the validated slice
+    pub index: usize,
+    /// for overlaps, index of the other range (always greater than `index`)
+    pub conflicting_index: Option<usize>,
+    pub error: HighlightingError,
+}
+
+/// Every problem found by [`validate_ranges_all`]: out-of-bounds ranges first, then char
+/// boundary violations, then overlapping pairs, each group in index order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+/// Same checks as the highlighting functions perform, but collects every problem instead of
+/// stopping at the first one; each overlapping pair is reported once
+pub fn validate_ranges_all(
+    input: &str,
+    highlights: &[HighlightRange],
+) -> Result<(), ValidationReport> {
+    let input_len = input.len();
+    let mut issues = Vec::new();
+
+    for (index, h) in highlights.iter().enumerate() {
+        // lower <= upper, so this also allows a zero-length range at exactly `input_len`
+        if h.upper > input_len {
+            issues.push(ValidationIssue {
+                index,
+                conflicting_index: None,
+                error: HighlightingError::RangesOutOfBounds {
+                    range: h.clone(),
+                    input_len,
+                },
+            });
+        }
+    }
+
+    for (index, h) in highlights.iter().enumerate() {
+        if h.upper > input_len {
+            continue;
+        }
+        for offset in [h.lower, h.upper] {
+            if !input.is_char_boundary(offset) {
+                issues.push(ValidationIssue {
+                    index,
+                    conflicting_index: None,
+                    error: HighlightingError::NotACharBoundary(offset),
+                });
+            }
+        }
+    }
+
+    for (first, second) in overlapping_pairs(highlights) {
+        issues.push(ValidationIssue {
+            index: first,
+            conflicting_index: Some(second),
+            error: HighlightingError::OverlappingRanges {
+                first: highlights[first].clone(),
+                second: highlights[second].clone(),
+            },
+        });
+    }
+
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(ValidationReport { issues })
+    }
+}
+
+/// Bounds and char boundary checks of [`validate_ranges`], without looking for overlaps
+pub(crate) fn validate_bounds(
+    input: &str,
+    highlights: &[HighlightRange],
+) -> Result<(), HighlightingError> {
+    let input_len = input.len();
+    if let Some(h) = highlights.iter().find(|h| h.upper > input_len) {
+        return Err(HighlightingError::RangesOutOfBounds {
+            range: h.clone(),
+            input_len,
+        });
+    }
+
+    let mut offsets = highlights.iter().flat_map(|h| [h.lower, h.upper]);
+    match offsets.find(|&offset| !input.is_char_boundary(offset)) {
+        Some(offset) => Err(HighlightingError::NotACharBoundary(offset)),
+        None => Ok(()),
+    }
+}
+
+/// Whether ranges given in `(lower, upper)` order contain an overlap, in a single pass keeping the
+/// furthest end seen so far, so a long range is caught overlapping any of the ranges after it
+pub(crate) fn sorted_ranges_overlap<'r>(
+    sorted: impl IntoIterator<Item = &'r HighlightRange>,
+) -> bool {
+    let mut furthest: Option<usize> = None;
+    for range in sorted {
+        if furthest.is_some_and(|upper| upper > range.lower) {
+            return true;
+        }
+        furthest = Some(furthest.map_or(range.upper, |upper| upper.max(range.upper)));
+    }
+
+    false
+}
+
+/// Whether `a` and `b` overlap: the one ordered first ends after the other starts, so a
+/// zero-length range overlaps a range it lies strictly inside of
+pub(crate) fn ranges_overlap(a: &HighlightRange, b: &HighlightRange) -> bool {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    first.upper > second.lower
+}
+
+/// Indices `(first, second)` into `highlights` of the overlapping pair the highlighting functions
+/// report with [`HighlightingError::OverlappingRanges`]: `first` is the lowest index of a range
+/// overlapping any other, `second` the lowest index of a range overlapping it. Found with an
+/// interval sweep, so the result doesn't depend on how ranges sharing a bound are ordered.
+pub fn find_overlap(highlights: &[HighlightRange]) -> Option<(usize, usize)> {
+    let mut sorted = (0..highlights.len()).collect::<Vec<_>>();
+    sorted.sort_by_key(|&i| &highlights[i]);
+
+    // a range overlaps an earlier one iff the furthest end before it lies after its start, and a
+    // later one iff the next one starts before it ends
+    let mut furthest: Option<usize> = None;
+    let mut first: Option<usize> = None;
+    for (pos, &i) in sorted.iter().enumerate() {
+        let h = &highlights[i];
+        let overlaps_earlier = furthest.is_some_and(|upper| upper > h.lower);
+        let overlaps_later = sorted
+            .get(pos + 1)
+            .is_some_and(|&next| h.upper > highlights[next].lower);
+        if overlaps_earlier || overlaps_later {
+            first = Some(first.map_or(i, |first| first.min(i)));
+        }
+        furthest = Some(furthest.map_or(h.upper, |upper| upper.max(h.upper)));
+    }
+
+    let first = first?;
+    let second = (0..highlights.len())
+        .find(|&j| j != first && ranges_overlap(&highlights[first], &highlights[j]))?;
+    Some((first, second))
+}
+
+/// Overlaps are reported with the ranges as they were passed in, so they're unit-agnostic
+pub(crate) fn check_overlaps(highlights: &[HighlightRange]) -> Result<(), HighlightingError> {
+    match find_overlap(highlights) {
+        Some((first, second)) => Err(HighlightingError::OverlappingRanges {
+            first: highlights[first].clone(),
+            second: highlights[second].clone(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Index pairs `(first, second)` with `first < second` of all overlapping ranges, sorted
+pub(crate) fn overlapping_pairs(highlights: &[HighlightRange]) -> Vec<(usize, usize)> {
+    let mut sorted = (0..highlights.len()).collect::<Vec<_>>();
+    sorted.sort_by_key(|&i| &highlights[i]);
+
+    // sweep by lower bound, keeping the ranges that haven't ended yet
+    let mut active: Vec<usize> = Vec::new();
+    let mut pairs = Vec::new();
+    for i in sorted {
+        let current = &highlights[i];
+        active.retain(|&a| highlights[a].upper > current.lower);
+        pairs.extend(active.iter().map(|&a| (a.min(i), a.max(i))));
+        active.push(i);
+    }
+
+    pairs.sort_unstable();
+    pairs
+}
+
+/// A range dropped by [`highlight_text_lossy`] and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedRange {
+    pub range: HighlightRange,
+    pub reason: HighlightingError,
+}
+
+/// Splits into ranges that are valid on their own and don't conflict with each other, and the
+/// skipped rest. Ranges are processed in `(lower, upper)` order and of two overlapping ranges
+/// the first one is kept.
+pub(crate) fn partition_valid(
+    input: &str,
+    highlights: &[HighlightRange],
+) -> (Vec<HighlightRange>, Vec<SkippedRange>) {
+    let mut sorted = highlights.to_vec();
+    sort_ranges(&mut sorted);
+
+    let mut accepted: Vec<HighlightRange> = Vec::with_capacity(sorted.len());
+    let mut skipped = Vec::new();
+    for range in sorted {
+        if let Err(reason) = validate_bounds(input, core::slice::from_ref(&range)) {
+            skipped.push(SkippedRange { range, reason });
+            continue;
+        }
+        match accepted.last() {
+            Some(last) if range.lower < last.upper => {
+                let reason = HighlightingError::OverlappingRanges {
+                    first: last.clone(),
+                    second: range.clone(),
+                };
+                skipped.push(SkippedRange { range, reason });
+            }
+            _ => accepted.push(range),
+        }
+    }
+
+    (accepted, skipped)
+}
+
+/// Same as [`partition_valid`] for ranges that may overlap, like the ones of
+/// [`OverlapPolicy::Nest`]: each range only has to be valid on its own.
+pub(crate) fn partition_in_bounds(
+    input: &str,
+    highlights: &[HighlightRange],
+) -> (Vec<HighlightRange>, Vec<SkippedRange>) {
+    let mut sorted = highlights.to_vec();
+    sort_ranges(&mut sorted);
+
+    let mut accepted = Vec::with_capacity(sorted.len());
+    let mut skipped = Vec::new();
+    for range in sorted {
+        match validate_bounds(input, core::slice::from_ref(&range)) {
+            Ok(()) => accepted.push(range),
+            Err(reason) => skipped.push(SkippedRange { range, reason }),
+        }
+    }
+
+    (accepted, skipped)
+}
+
+/// Same as [`partition_valid`] for ranges that are merged like [`merge_ranges`] does, e.g. with
+/// [`OverlapPolicy::Merge`]: the valid merged spans, and the ranges reaching past the input or
+/// merged into a span that doesn't fall on char boundaries. Zero-length ranges are dropped by the
+/// merge rather than skipped.
+pub(crate) fn partition_valid_merged(
+    input: &str,
+    highlights: &[HighlightRange],
+) -> (Vec<HighlightRange>, Vec<SkippedRange>) {
+    let mut sorted = highlights.to_vec();
+    sort_ranges(&mut sorted);
+
+    let mut skipped = Vec::new();
+    // every span with the ranges merged into it
+    let mut spans: Vec<(HighlightRange, Vec<HighlightRange>)> = Vec::new();
+    for range in sorted.into_iter().filter(|r| r.lower != r.upper) {
+        // spans only grow, so a range past the end can't be part of a valid one
+        if range.upper > input.len() {
+            let reason = HighlightingError::RangesOutOfBounds {
+                range: range.clone(),
+                input_len: input.len(),
+            };
+            skipped.push(SkippedRange { range, reason });
+            continue;
+        }
+        match spans.last_mut() {
+            Some((span, sources)) if range.lower <= span.upper => {
+                span.upper = span.upper.max(range.upper);
+                sources.push(range);
+            }
+            _ => spans.push((range.clone(), vec![range])),
+        }
+    }
+
+    let mut accepted = Vec::with_capacity(spans.len());
+    for (span, sources) in spans {
+        match validate_bounds(input, core::slice::from_ref(&span)) {
+            Ok(()) => accepted.push(span),
+            Err(reason) => skipped.extend(sources.into_iter().map(|range| SkippedRange {
+                range,
+                reason: reason.clone(),
+            })),
+        }
+    }
+    skipped.sort_by_key(|skipped| (skipped.range.lower, skipped.range.upper));
+
+    (accepted, skipped)
+}
+
+/// See [`BoundsPolicy::Clamp`]
+pub(crate) fn clamp_to_input(
+    input_len: usize,
+    highlights: &[HighlightRange],
+) -> Vec<HighlightRange> {
+    highlights
+        .iter()
+        .filter(|h| h.lower <= input_len)
+        .map(|h| HighlightRange::new(h.lower, h.upper.min(input_len)))
+        .collect()
+}
+
+/// Resolves overlaps between labeled ranges so they can be passed to [`highlight_text_labeled`]:
+/// labels listed earlier in `priority` win over later ones, unlisted labels lose against all
+/// listed ones; see [`resolve_labeled_overlaps_by`]
+pub fn resolve_labeled_overlaps(
+    highlights: Vec<LabeledRange>,
+    priority: &[&str],
+) -> Vec<LabeledRange> {
+    resolve_labeled_overlaps_by(highlights, |label| {
+        priority.iter().position(|&p| p == label).map(Reverse)
+    })
+}
+
+/// Same as [`resolve_labeled_overlaps_by`] with the labels' own order as priority: the greater
+/// label wins, e.g. the last variant of an enum deriving `Ord`
+pub fn resolve_labeled_overlaps_ord<L: Ord + Clone>(
+    highlights: Vec<LabeledRange<L>>,
+) -> Vec<LabeledRange<L>> {
+    resolve_labeled_overlaps_by(highlights, L::clone)
+}
+
+/// Resolves overlaps between labeled ranges, the label with the higher `priority` gets the
+/// contested bytes and the losing range is trimmed or split around them. Overlapping ranges of
+/// equal priority are merged into one range labeled like the first of them in `(lower, upper,
+/// label)` order. The result is sorted, non-overlapping and has no zero-length ranges.
+pub fn resolve_labeled_overlaps_by<L: Ord + Clone, P: Ord>(
+    highlights: Vec<LabeledRange<L>>,
+    priority: impl Fn(&L) -> P,
+) -> Vec<LabeledRange<L>> {
+    let mut ranked = highlights
+        .into_iter()
+        .filter(|h| h.range.lower != h.range.upper)
+        .map(|h| (priority(&h.label), h))
+        .collect::<Vec<_>>();
+    ranked.sort_by(|(p, a), (q, b)| {
+        q.cmp(p)
+            .then_with(|| a.range.cmp(&b.range))
+            .then_with(|| a.label.cmp(&b.label))
+    });
+
+    // ranges of equal priority can't take bytes from each other, so they are merged first
+    let mut merged: Vec<(P, LabeledRange<L>)> = Vec::with_capacity(ranked.len());
+    for (p, h) in ranked {
+        match merged.last_mut() {
+            Some((q, last)) if *q == p && h.range.lower < last.range.upper => {
+                last.range.upper = last.range.upper.max(h.range.upper);
+            }
+            _ => merged.push((p, h)),
+        }
+    }
+
+    // highest priority first, each range only keeps the bytes no earlier one has claimed
+    let mut claimed = HighlightSet::new();
+    let mut resolved = Vec::with_capacity(merged.len());
+    for (_, h) in merged {
+        for piece in subtract_ranges(core::slice::from_ref(&h.range), claimed.as_slice()) {
+            resolved.push(LabeledRange::with_label(piece, h.label.clone()));
+        }
+        claimed.insert(h.range);
+    }
+
+    resolved.sort_by(|a, b| a.range.cmp(&b.range));
+    resolved
+}
+
+/// A [`HighlightRange`] with a relevance score, see [`select_top_k`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoredRange {
+    pub range: HighlightRange,
+    pub score: f32,
+}
+
+impl ScoredRange {
+    pub fn new(range: HighlightRange, score: f32) -> Self {
+        ScoredRange { range, score }
+    }
+}
+
+/// Picks up to `k` of the highest-scoring ranges that don't overlap each other, in document
+/// order. Candidates are considered from the highest score down and skipped if they overlap an
+/// already selected range; equal scores go to the earlier range, and NaN scores rank lowest.
+pub fn select_top_k(highlights: Vec<ScoredRange>, k: usize) -> Vec<HighlightRange> {
+    let mut candidates = highlights;
+    candidates.sort_by(|a, b| {
+        let score = |s: &ScoredRange| match s.score.is_nan() {
+            true => f32::NEG_INFINITY,
+            false => s.score,
+        };
+        score(b)
+            .total_cmp(&score(a))
+            .then_with(|| a.range.cmp(&b.range))
+    });
+
+    let mut selected: Vec<HighlightRange> = Vec::with_capacity(k.min(candidates.len()));
+    for candidate in candidates {
+        if selected.len() == k {
+            break;
+        }
+        let overlaps = selected
+            .iter()
+            .any(|s| s.lower < candidate.range.upper && candidate.range.lower < s.upper);
+        if !overlaps && !selected.contains(&candidate.range) {
+            selected.push(candidate.range);
+        }
+    }
+
+    sort_ranges(&mut selected);
+    selected
+}
+
+/// Output of [`take_first_k`] and [`take_last_k`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TakenRanges {
+    /// the kept ranges, merged and in document order
+    pub ranges: Vec<HighlightRange>,
+    /// number of ranges after merging, kept or not, e.g. the 17 of "3 of 17 matches shown"
+    pub total: usize,
+}
+
+impl TakenRanges {
+    /// number of merged ranges left out
+    pub fn dropped(&self) -> usize {
+        self.total - self.ranges.len()
+    }
+}
+
+/// Keeps the `k` earliest ranges of `highlights` after merging them with [`merge_ranges`], so
+/// overlapping or touching ranges count once; the total is counted after merging as well
+pub fn take_first_k(highlights: Vec<HighlightRange>, k: usize) -> TakenRanges {
+    let mut ranges = merge_ranges(highlights);
+    let total = ranges.len();
+    ranges.truncate(k);
+    TakenRanges { ranges, total }
+}
+
+/// Same as [`take_first_k`], but keeps the `k` latest ranges
+pub fn take_last_k(highlights: Vec<HighlightRange>, k: usize) -> TakenRanges {
+    let mut ranges = merge_ranges(highlights);
+    let total = ranges.len();
+    let ranges = ranges.split_off(total.saturating_sub(k));
+    TakenRanges { ranges, total }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{render::*, test_support::*};
+
+    #[test]
+    fn should_report_all_validation_problems() {
+        let input = "hé llo world";
+        let ranges = [
+            HighlightRange::new(0, 5),
+            HighlightRange::new(2, 4),
+            HighlightRange::new(4, 20),
+            HighlightRange::new(3, 9),
+            HighlightRange::new(10, 12),
+            HighlightRange::new(15, 30),
+        ];
+
+        let report = validate_ranges_all(input, &ranges).unwrap_err();
+
+        assert_eq!(
+            vec![
+                ValidationIssue {
+                    index: 2,
+                    conflicting_index: None,
+                    error: HighlightingError::RangesOutOfBounds {
+                        range: HighlightRange::new(4, 20),
+                        input_len: 13,
+                    },
+                },
+                ValidationIssue {
+                    index: 5,
+                    conflicting_index: None,
+                    error: HighlightingError::RangesOutOfBounds {
+                        range: HighlightRange::new(15, 30),
+                        input_len: 13,
+                    },
+                },
+                ValidationIssue {
+                    index: 1,
+                    conflicting_index: None,
+                    error: HighlightingError::NotACharBoundary(2),
+                },
+                ValidationIssue {
+                    index: 0,
+                    conflicting_index: Some(1),
+                    error: HighlightingError::OverlappingRanges {
+                        first: HighlightRange::new(0, 5),
+                        second: HighlightRange::new(2, 4),
+                    },
+                },
+                ValidationIssue {
+                    index: 0,
+                    conflicting_index: Some(2),
+                    error: HighlightingError::OverlappingRanges {
+                        first: HighlightRange::new(0, 5),
+                        second: HighlightRange::new(4, 20),
+                    },
+                },
+                ValidationIssue {
+                    index: 0,
+                    conflicting_index: Some(3),
+                    error: HighlightingError::OverlappingRanges {
+                        first: HighlightRange::new(0, 5),
+                        second: HighlightRange::new(3, 9),
+                    },
+                },
+                ValidationIssue {
+                    index: 1,
+                    conflicting_index: Some(3),
+                    error: HighlightingError::OverlappingRanges {
+                        first: HighlightRange::new(2, 4),
+                        second: HighlightRange::new(3, 9),
+                    },
+                },
+                ValidationIssue {
+                    index: 2,
+                    conflicting_index: Some(3),
+                    error: HighlightingError::OverlappingRanges {
+                        first: HighlightRange::new(4, 20),
+                        second: HighlightRange::new(3, 9),
+                    },
+                },
+                ValidationIssue {
+                    index: 2,
+                    conflicting_index: Some(4),
+                    error: HighlightingError::OverlappingRanges {
+                        first: HighlightRange::new(4, 20),
+                        second: HighlightRange::new(10, 12),
+                    },
+                },
+                ValidationIssue {
+                    index: 2,
+                    conflicting_index: Some(5),
+                    error: HighlightingError::OverlappingRanges {
+                        first: HighlightRange::new(4, 20),
+                        second: HighlightRange::new(15, 30),
+                    },
+                },
+            ],
+            report.issues
+        );
+    }
+
+    #[test]
+    fn should_pass_validation_of_valid_ranges() {
+        assert_eq!(
+            Ok(()),
+            validate_ranges_all(
+                "Hello world",
+                &[
+                    HighlightRange::new(6, 11),
+                    HighlightRange::new(0, 5),
+                    HighlightRange::new(5, 5),
+                ],
+            )
+        );
+    }
+
+    #[test]
+    fn should_report_first_problem_when_validating_single_error() {
+        let ranges = [HighlightRange::new(0, 5), HighlightRange::new(4, 11)];
+        let report = validate_ranges_all("Hello world", &ranges).unwrap_err();
+
+        assert_eq!(
+            Err(report.issues[0].error.clone()),
+            validate_ranges("Hello world", &ranges)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn should_round_trip_error_through_json() {
+        let error = HighlightingError::OverlappingRanges {
+            first: HighlightRange::new(0, 5),
+            second: HighlightRange::new(4, 11),
+        };
+
+        let json = serde_json::to_string(&error).unwrap();
+
+        assert_eq!(
+            r#"{"OverlappingRanges":{"first":{"lower":0,"upper":5},"second":{"lower":4,"upper":11}}}"#,
+            json
+        );
+        assert_eq!(error, serde_json::from_str(&json).unwrap());
+    }
+
+    #[test]
+    fn should_give_contested_bytes_to_the_higher_priority_label() {
+        let labeled =
+            |lower, upper, label| LabeledRange::new(HighlightRange::new(lower, upper), label);
+        let priority = ["query", "synonym", "spelling"];
+
+        // the synonym is split around the query it contains, the spelling is trimmed
+        let resolved = resolve_labeled_overlaps(
+            vec![
+                labeled(0, 11, "synonym"),
+                labeled(3, 6, "query"),
+                labeled(9, 14, "spelling"),
+            ],
+            &priority,
+        );
+        assert_eq!(
+            vec![
+                labeled(0, 3, "synonym"),
+                labeled(3, 6, "query"),
+                labeled(6, 11, "synonym"),
+                labeled(11, 14, "spelling"),
+            ],
+            resolved
+        );
+        assert_eq!(
+            Ok(concat!(
+                r#"<em class="synonym">abc</em><em class="query">def</em>"#,
+                r#"<em class="synonym">ghijk</em><em class="spelling">lmn</em>"#
+            )
+            .to_string()),
+            highlight_text_labeled("abcdefghijklmn", resolved)
+        );
+
+        // unlisted labels lose against listed ones, empty ranges are dropped
+        assert_eq!(
+            vec![labeled(0, 4, "query"), labeled(4, 6, "other")],
+            resolve_labeled_overlaps(
+                vec![
+                    labeled(2, 6, "other"),
+                    labeled(0, 4, "query"),
+                    labeled(5, 5, "query")
+                ],
+                &priority
+            )
+        );
+    }
+
+    #[test]
+    fn should_merge_overlapping_ranges_of_equal_priority() {
+        let labeled =
+            |lower, upper, label| LabeledRange::new(HighlightRange::new(lower, upper), label);
+
+        assert_eq!(
+            vec![labeled(0, 8, "query")],
+            resolve_labeled_overlaps(
+                vec![labeled(3, 8, "query"), labeled(0, 5, "query")],
+                &["query"]
+            )
+        );
+        // same priority, different labels: the earliest range names the merged one
+        assert_eq!(
+            vec![labeled(0, 10, "b")],
+            resolve_labeled_overlaps_by(
+                vec![labeled(3, 8, "a"), labeled(0, 5, "b"), labeled(6, 10, "a")],
+                |label| label.len()
+            )
+        );
+        // touching ranges don't compete and stay separate
+        assert_eq!(
+            vec![labeled(0, 3, "a"), labeled(3, 6, "b")],
+            resolve_labeled_overlaps_by(vec![labeled(3, 6, "b"), labeled(0, 3, "a")], |_| 0)
+        );
+    }
+
+    #[test]
+    fn should_select_the_highest_scoring_disjoint_ranges() {
+        let ranges = vec![
+            ScoredRange::new(HighlightRange::new(0, 4), 0.5),
+            ScoredRange::new(HighlightRange::new(2, 8), 0.9),
+            ScoredRange::new(HighlightRange::new(10, 12), 0.7),
+            ScoredRange::new(HighlightRange::new(14, 16), 0.1),
+            ScoredRange::new(HighlightRange::new(20, 22), f32::NAN),
+        ];
+
+        assert_eq!(
+            vec![HighlightRange::new(2, 8), HighlightRange::new(10, 12)],
+            select_top_k(ranges.clone(), 2)
+        );
+        assert_eq!(
+            vec![
+                HighlightRange::new(2, 8),
+                HighlightRange::new(10, 12),
+                HighlightRange::new(14, 16),
+                HighlightRange::new(20, 22),
+            ],
+            select_top_k(ranges.clone(), 10)
+        );
+        assert_eq!(Vec::<HighlightRange>::new(), select_top_k(ranges, 0));
+    }
+
+    #[test]
+    fn should_break_score_ties_by_position() {
+        let ranges = vec![
+            ScoredRange::new(HighlightRange::new(6, 10), 1.0),
+            ScoredRange::new(HighlightRange::new(4, 8), 1.0),
+            ScoredRange::new(HighlightRange::new(4, 8), 1.0),
+            ScoredRange::new(HighlightRange::new(0, 2), 1.0),
+        ];
+
+        assert_eq!(
+            vec![HighlightRange::new(0, 2), HighlightRange::new(4, 8)],
+            select_top_k(ranges, 3)
+        );
+    }
+
+    #[test]
+    fn should_stamp_errors_with_their_context() {
+        let error = HighlightingError::NotACharBoundary(2);
+
+        assert_eq!(
+            "doc-17: byte offset 2 is not on a char boundary",
+            error.clone().with_context("doc-17").to_string()
+        );
+        assert_eq!(
+            "document doc-17, field title: byte offset 2 is not on a char boundary",
+            error.clone().with_context(("doc-17", "title")).to_string()
+        );
+        assert_eq!(
+            ContextError {
+                context: ErrorContext::Item(3),
+                error
+            },
+            HighlightingError::NotACharBoundary(2).with_context(ErrorContext::Item(3))
+        );
+    }
+
+    #[test]
+    fn should_scan_unterminated_tags_in_linear_time() {
+        // quadratic if every `<` looked for a `>` up to the end of the input
+        let unterminated = "<a".repeat(200_000);
+        assert_eq!(
+            Vec::<(HighlightRange, MarkupKind)>::new(),
+            markup_spans(&unterminated)
+        );
+
+        let closed_at_the_end = unterminated.clone() + ">";
+        assert_eq!(
+            vec![(HighlightRange::new(0, 400_001), MarkupKind::Tag)],
+            markup_spans(&closed_at_the_end)
+        );
+        assert_eq!(
+            vec![(HighlightRange::new(1, 4), MarkupKind::Tag)],
+            markup_spans("><b>  <i")
+        );
+    }
+
+    #[test]
+    fn should_reject_boundaries_inside_html_markup() {
+        // the tag is 0..15, the entities 20..25 and 32..36 and the closing tag 46..50
+        let html = r#"<p class="a>b">Fish &amp; chips &lt; 3 < 4 & 5</p>"#;
+        let splits = |offset, markup: (usize, usize), kind| {
+            Err(HighlightingError::SplitsMarkup {
+                offset,
+                markup: HighlightRange::new(markup.0, markup.1),
+                kind,
+            })
+        };
+
+        // a bare `<` or `&` is text
+        let around_markup = [(15, 19), (20, 25), (26, 31), (39, 46)]
+            .map(|(lower, upper)| HighlightRange::new(lower, upper));
+        assert_eq!(
+            Ok(()),
+            validate_ranges_in(html, &around_markup, ValidationMode::HtmlAware)
+        );
+        assert_eq!(
+            splits(22, (20, 25), MarkupKind::Entity),
+            validate_ranges_in(
+                html,
+                &[HighlightRange::new(22, 31)],
+                ValidationMode::HtmlAware
+            )
+        );
+        // the `>` in the attribute value doesn't end the tag
+        assert_eq!(
+            splits(13, (0, 15), MarkupKind::Tag),
+            validate_ranges_in(
+                html,
+                &[HighlightRange::new(13, 19)],
+                ValidationMode::HtmlAware
+            )
+        );
+        assert_eq!(
+            splits(48, (46, 50), MarkupKind::Tag),
+            validate_ranges_in(
+                html,
+                &[HighlightRange::new(39, 48)],
+                ValidationMode::HtmlAware
+            )
+        );
+        assert_eq!(
+            Ok(()),
+            validate_ranges_in(html, &[HighlightRange::new(22, 48)], ValidationMode::Text)
+        );
+        assert_eq!(
+            Err(HighlightingError::RangesOutOfBounds {
+                range: HighlightRange::new(22, 60),
+                input_len: 50
+            }),
+            validate_ranges_in(
+                html,
+                &[HighlightRange::new(22, 60)],
+                ValidationMode::HtmlAware
+            )
+        );
+        assert_eq!(
+            "byte offset 22 splits the HTML entity at 20..25",
+            splits(22, (20, 25), MarkupKind::Entity)
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    /// 10 ranges that merge into the 4 spans `0..5`, `8..12`, `15..20` and `25..30`
+    fn ten_ranges_in_four_spans() -> Vec<HighlightRange> {
+        vec![
+            HighlightRange::new(25, 30),
+            HighlightRange::new(1, 3),
+            HighlightRange::new(16, 18),
+            HighlightRange::new(0, 2),
+            HighlightRange::new(9, 12),
+            HighlightRange::new(3, 5),
+            HighlightRange::new(15, 16),
+            HighlightRange::new(2, 4),
+            HighlightRange::new(8, 10),
+            HighlightRange::new(17, 20),
+        ]
+    }
+
+    #[test]
+    fn should_take_the_first_k_merged_ranges() {
+        let taken = take_first_k(ten_ranges_in_four_spans(), 3);
+
+        assert_eq!(
+            TakenRanges {
+                ranges: vec![
+                    HighlightRange::new(0, 5),
+                    HighlightRange::new(8, 12),
+                    HighlightRange::new(15, 20),
+                ],
+                total: 4,
+            },
+            taken
+        );
+        assert_eq!(1, taken.dropped());
+        assert_eq!(0, take_first_k(ten_ranges_in_four_spans(), 4).dropped());
+        assert_eq!(
+            TakenRanges {
+                ranges: vec![],
+                total: 4
+            },
+            take_first_k(ten_ranges_in_four_spans(), 0)
+        );
+    }
+
+    #[test]
+    fn should_take_the_last_k_merged_ranges() {
+        let taken = take_last_k(ten_ranges_in_four_spans(), 3);
+
+        assert_eq!(
+            TakenRanges {
+                ranges: vec![
+                    HighlightRange::new(8, 12),
+                    HighlightRange::new(15, 20),
+                    HighlightRange::new(25, 30),
+                ],
+                total: 4,
+            },
+            taken
+        );
+        assert_eq!(1, taken.dropped());
+        assert_eq!(
+            merge_ranges(ten_ranges_in_four_spans()),
+            take_last_k(ten_ranges_in_four_spans(), 10).ranges
+        );
+    }
+
+    #[test]
+    fn should_validate_the_same_ranges_differently_per_index_kind() {
+        // 5 chars, 6 bytes
+        let input = "héllo";
+
+        let past_last_char = [HighlightRange::new(0, 6)];
+        assert_eq!(
+            Ok(()),
+            validate_ranges_as(input, &past_last_char, IndexKind::Bytes)
+        );
+        assert_eq!(
+            Err(HighlightingError::CharRangeOutOfBounds {
+                range: HighlightRange::new(0, 6),
+                char_count: 5,
+            }),
+            validate_ranges_as(input, &past_last_char, IndexKind::Chars)
+        );
+
+        // splits "é" as bytes, but is the second char
+        let inside_e = [HighlightRange::new(1, 2)];
+        assert_eq!(
+            Err(HighlightingError::NotACharBoundary(2)),
+            validate_ranges_as(input, &inside_e, IndexKind::Bytes)
+        );
+        assert_eq!(
+            Ok(()),
+            validate_ranges_as(input, &inside_e, IndexKind::Chars)
+        );
+    }
+
+    #[test]
+    fn should_name_the_index_kind_in_out_of_bounds_errors() {
+        assert_eq!(
+            "char range 0..6 is out of bounds for input with 5 chars",
+            validate_ranges_as("héllo", &[HighlightRange::new(0, 6)], IndexKind::Chars)
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            "range 0..7 is out of bounds for input of length 6",
+            validate_ranges_as("héllo", &[HighlightRange::new(0, 7)], IndexKind::Bytes)
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    const LEGAL_TEXT: &str = "Terms apply. Void where prohibited.";
+    const LEGAL_RANGES: [HighlightRange; 3] = [
+        HighlightRange::new(0, 5),
+        HighlightRange::new(18, 13),
+        HighlightRange::new(24, 34),
+    ];
+    const _: () = assert!(validate_ranges_const(LEGAL_TEXT, &LEGAL_RANGES).is_ok());
+    const _: () = assert!(LEGAL_RANGES[1].len() == 5 && !LEGAL_RANGES[1].is_empty());
+
+    #[test]
+    fn should_hightlight_a_const_table() {
+        assert_eq!(
+            Ok("<em>Terms</em> apply. <em>Void </em>where <em>prohibited</em>.".to_string()),
+            highlight_text(LEGAL_TEXT, &LEGAL_RANGES)
+        );
+    }
+
+    #[test]
+    fn should_validate_ranges_in_const_context_like_at_runtime() {
+        let cases: [&[HighlightRange]; 4] = [
+            &[HighlightRange::new(0, 99)],
+            &[HighlightRange::new(1, 2)],
+            &[
+                HighlightRange::new(0, 3),
+                HighlightRange::new(5, 6),
+                HighlightRange::new(2, 4),
+            ],
+            &[HighlightRange::new(0, 3), HighlightRange::new(3, 3)],
+        ];
+
+        for highlights in cases {
+            assert_eq!(
+                validate_ranges("über", highlights),
+                validate_ranges_const("über", highlights)
+            );
+        }
+    }
+
+    #[test]
+    fn should_catch_overlaps_of_ranges_sharing_a_lower_bound() {
+        let ranges = [
+            HighlightRange::new(0, 2),
+            HighlightRange::new(0, 5),
+            HighlightRange::new(0, 3),
+        ];
+        for order in [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ] {
+            let ordered = order.map(|i| ranges[i].clone());
+
+            assert_eq!(Some((0, 1)), find_overlap(&ordered), "{ordered:?}");
+            assert_eq!(
+                Err(HighlightingError::OverlappingRanges {
+                    first: ordered[0].clone(),
+                    second: ordered[1].clone(),
+                }),
+                highlight_text("abcdef", ordered.clone())
+            );
+            let report = validate_ranges_all("abcdef", &ordered).unwrap_err();
+            assert_eq!(3, report.issues.len());
+        }
+    }
+
+    #[test]
+    fn should_catch_a_range_containing_non_adjacent_ones() {
+        // sorted, 0..10 is only next to 2..3, but contains 5..6 as well
+        let ranges = [
+            HighlightRange::new(5, 6),
+            HighlightRange::new(2, 3),
+            HighlightRange::new(0, 10),
+        ];
+
+        assert_eq!(Some((0, 2)), find_overlap(&ranges));
+        assert_eq!(Some((0, 1)), find_overlap(&ranges[1..]));
+        assert_eq!(None, find_overlap(&ranges[..2]));
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(5, 6),
+                second: HighlightRange::new(0, 10),
+            }),
+            highlight_text("abcdefghij", ranges.clone())
+        );
+    }
+
+    #[test]
+    fn should_find_the_same_overlap_as_comparing_all_pairs() {
+        // small linear congruential generator, so the cases are random-ish but reproducible
+        let mut seed = 0x7f4a_7c15_u64;
+        let mut next = |bound: u64| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            ((seed >> 33) % bound) as usize
+        };
+
+        for _ in 0..500 {
+            let ranges = (0..next(7))
+                .map(|_| {
+                    let (a, b) = (next(8), next(8));
+                    HighlightRange::new(a.min(b), a.max(b))
+                })
+                .collect::<Vec<_>>();
+            let expected = (0..ranges.len())
+                .flat_map(|i| (i + 1..ranges.len()).map(move |j| (i, j)))
+                .find(|&(i, j)| ranges_overlap(&ranges[i], &ranges[j]));
+
+            assert_eq!(expected, find_overlap(&ranges), "{ranges:?}");
+            assert_eq!(expected, overlapping_pairs(&ranges).first().copied());
+        }
+    }
+
+    #[test]
+    fn should_resolve_typed_label_overlaps_by_their_order() {
+        let highlights = vec![
+            LabeledRange::with_label(HighlightRange::new(0, 6), MatchKind::Synonym),
+            LabeledRange::with_label(HighlightRange::new(2, 4), MatchKind::Exact),
+            LabeledRange::with_label(HighlightRange::new(3, 8), MatchKind::Stemmed),
+        ];
+
+        assert_eq!(
+            vec![
+                LabeledRange::with_label(HighlightRange::new(0, 2), MatchKind::Synonym),
+                LabeledRange::with_label(HighlightRange::new(2, 4), MatchKind::Exact),
+                LabeledRange::with_label(HighlightRange::new(4, 8), MatchKind::Stemmed),
+            ],
+            resolve_labeled_overlaps_ord(highlights)
+        );
+    }
+
+    #[test]
+    fn should_render_validated_highlights_to_every_format() {
+        let input = "a <b> c";
+        let highlights = [HighlightRange::new(4, 5), HighlightRange::new(0, 1)];
+        let validated = validate(input, highlights.clone()).unwrap();
+
+        assert_eq!(
+            highlight_text(input, highlights.clone()),
+            Ok(validated.to_html())
+        );
+        assert_eq!(
+            highlight_text_escaped(input, highlights.clone()),
+            Ok(validated.to_html_escaped())
+        );
+        assert_eq!(
+            highlight_text_ansi(input, highlights.clone(), AnsiStyle::Bold),
+            Ok(validated.to_ansi(AnsiStyle::Bold))
+        );
+        assert_eq!(
+            highlight_segments(input, highlights.clone()),
+            Ok(validated.to_segments())
+        );
+        assert_eq!(
+            highlight_events(input, highlights.clone())
+                .unwrap()
+                .collect::<Vec<_>>(),
+            validated.to_events().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![&HighlightRange::new(0, 1), &HighlightRange::new(4, 5)],
+            validated.ranges().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn should_share_validated_highlights_across_threads() {
+        let input = "shared input";
+        let validated = validate(input, [HighlightRange::new(0, 6)]).unwrap();
+
+        let (html, ansi) = std::thread::scope(|scope| {
+            let for_html = validated.clone();
+            let html = scope.spawn(move || for_html.to_html());
+            let ansi = scope.spawn(|| validated.to_ansi(AnsiStyle::Color256(3)));
+            (html.join().unwrap(), ansi.join().unwrap())
+        });
+
+        assert_eq!("<em>shared</em> input", html);
+        assert_eq!(
+            highlight_text_ansi(input, [HighlightRange::new(0, 6)], AnsiStyle::Color256(3)),
+            Ok(ansi)
+        );
+        assert_eq!(
+            Err(HighlightingError::OverlappingRanges {
+                first: HighlightRange::new(0, 4),
+                second: HighlightRange::new(2, 6)
+            }),
+            validate(
+                input,
+                [HighlightRange::new(0, 4), HighlightRange::new(2, 6)]
+            )
+            .map(|_| ())
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use super::*;
+use wasm_bindgen::prelude::*;
+
+/// [`Segment`] with UTF-16 offsets, serialized for JavaScript
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct JsSegment<'a> {
+    pub text: &'a str,
+    pub lower: usize,
+    pub upper: usize,
+    pub highlighted: bool,
+}
+
+/// Same as [`highlight_text_utf16`], errors become JavaScript `Error`s with the message of
+/// the [`HighlightingError`]
+#[wasm_bindgen]
+pub fn highlight(text: &str, ranges: &[u32]) -> Result<String, JsValue> {
+    highlight_pairs(text, ranges).map_err(js_error)
+}
+
+/// Same as [`highlight_segments`] with UTF-16 offsets, returns an array of
+/// `{text, lower, upper, highlighted}` objects
+#[wasm_bindgen(js_name = highlightSegments)]
+pub fn highlight_segments_js(text: &str, ranges: &[u32]) -> Result<JsValue, JsValue> {
+    let segments = segments_utf16(text, ranges).map_err(js_error)?;
+    serde_wasm_bindgen::to_value(&segments).map_err(JsValue::from)
+}
+
+fn js_error(message: String) -> JsValue {
+    JsError::new(&message).into()
+}
+
+/// Everything below the JavaScript boundary of [`highlight`]
+pub(crate) fn highlight_pairs(text: &str, ranges: &[u32]) -> Result<String, String> {
+    let highlights = ranges_from_pairs(ranges)?;
+    highlight_text_utf16(text, highlights).map_err(|e| e.to_string())
+}
+
+/// Everything below the JavaScript boundary of [`highlight_segments_js`]
+pub(crate) fn segments_utf16<'a>(
+    text: &'a str,
+    ranges: &[u32],
+) -> Result<Vec<JsSegment<'a>>, String> {
+    let highlights = ranges_from_pairs(ranges)?;
+    check_overlaps(&highlights).map_err(|e| e.to_string())?;
+    let highlights = utf16_ranges_to_byte_ranges(text, &highlights).map_err(|e| e.to_string())?;
+    let segments = highlight_segments(text, highlights).map_err(|e| e.to_string())?;
+
+    // segments cover the text in order, so their UTF-16 offsets are a running sum
+    let mut offset = 0;
+    Ok(segments
+        .into_iter()
+        .map(|segment| {
+            let lower = offset;
+            offset += segment.text.encode_utf16().count();
+            JsSegment {
+                text: segment.text,
+                lower,
+                upper: offset,
+                highlighted: segment.highlighted,
+            }
+        })
+        .collect())
+}
+
+fn ranges_from_pairs(ranges: &[u32]) -> Result<Vec<HighlightRange>, String> {
+    if !ranges.len().is_multiple_of(2) {
+        return Err(format!(
+            "expected lower/upper pairs, got {} offsets",
+            ranges.len()
+        ));
+    }
+    Ok(ranges
+        .chunks_exact(2)
+        .map(|pair| HighlightRange::new(pair[0] as usize, pair[1] as usize))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_highlight_flat_utf16_pairs_for_wasm() {
+        // "😀" is two UTF-16 code units and four bytes
+        assert_eq!(
+            Ok("😀 <em>Hello</em>".to_string()),
+            highlight_pairs("😀 Hello", &[3, 8])
+        );
+        assert_eq!(
+            Err("UTF-16 offset 1 splits a surrogate pair".to_string()),
+            highlight_pairs("😀 Hello", &[1, 3])
+        );
+        assert_eq!(
+            Err("ranges 0..5 and 3..8 overlap".to_string()),
+            highlight_pairs("Hello world", &[0, 5, 3, 8])
+        );
+        assert_eq!(
+            Err("expected lower/upper pairs, got 3 offsets".to_string()),
+            highlight_pairs("Hello world", &[0, 5, 6])
+        );
+    }
+
+    #[test]
+    fn should_build_utf16_segments_for_wasm() {
+        let segments = segments_utf16("😀 Hello!", &[3, 8]).unwrap();
+
+        assert_eq!(
+            vec![
+                JsSegment {
+                    text: "😀 ",
+                    lower: 0,
+                    upper: 3,
+                    highlighted: false,
+                },
+                JsSegment {
+                    text: "Hello",
+                    lower: 3,
+                    upper: 8,
+                    highlighted: true,
+                },
+                JsSegment {
+                    text: "!",
+                    lower: 8,
+                    upper: 9,
+                    highlighted: false,
+                },
+            ],
+            segments
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_highlight"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("binary is built for integration tests");
+    // the binary may exit on bad arguments before reading stdin, closing the pipe
+    let _ = child.stdin.take().unwrap().write_all(stdin.as_bytes());
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> &str {
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+#[test]
+fn should_hightlight_stdin_with_ranges_as_html() {
+    let output = run(&["--ranges", "5-7,11..17"], "This is a <sample> text");
+
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        "This <em>is</em> a &lt;<em>sample</em>&gt; text",
+        stdout(&output)
+    );
+}
+
+#[test]
+fn should_hightlight_pattern_and_regex_matches() {
+    let pattern = run(&["--pattern", "ab", "--output", "markdown"], "ab cab");
+    assert_eq!("**ab** c**ab**", stdout(&pattern));
+
+    let regex = run(&["--regex", r"\d+", "--output", "ansi"], "a1 b22");
+    assert_eq!("a\x1b[1m1\x1b[0m b\x1b[1m22\x1b[0m", stdout(&regex));
+}
+
+#[test]
+fn should_print_segments_as_json() {
+    let output = run(&["--ranges", "0-2", "--output", "segments-json"], "hi!");
+
+    let json: serde_json::Value = serde_json::from_str(stdout(&output)).unwrap();
+    assert_eq!(
+        serde_json::json!([
+            {"text": "hi", "range": {"lower": 0, "upper": 2}, "highlighted": true},
+            {"text": "!", "range": {"lower": 2, "upper": 3}, "highlighted": false},
+        ]),
+        json
+    );
+}
+
+#[test]
+fn should_exit_with_code_1_on_invalid_ranges() {
+    let output = run(&["--ranges", "0-5,3-8"], "Hello world");
+
+    assert_eq!(Some(1), output.status.code());
+    assert!(output.stdout.is_empty());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("ranges 0..5 and 3..8 overlap"),
+        "{output:?}"
+    );
+}
+
+#[test]
+fn should_skip_and_clamp_ranges_when_lenient() {
+    let output = run(&["--ranges", "0-5,3-8,6-50", "--lenient"], "Hello world");
+
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("<em>Hello</em> <em>world</em>", stdout(&output));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("skipped range 3..8"));
+}
+
+#[test]
+fn should_exit_with_code_2_on_bad_arguments() {
+    assert_eq!(Some(2), run(&[], "text").status.code());
+    assert_eq!(
+        Some(2),
+        run(&["--ranges", "0-1", "--output", "pdf"], "text")
+            .status
+            .code()
+    );
+    assert_eq!(
+        Some(2),
+        run(&["--ranges", "0-1", "--pattern", "t"], "text")
+            .status
+            .code()
+    );
+}
+
+#[test]
+fn should_exit_with_code_3_on_io_errors() {
+    let output = run(&["--ranges", "0-1", "does/not/exist.txt"], "");
+
+    assert_eq!(Some(3), output.status.code());
+}
@@ -0,0 +1,87 @@
+use highlight_ranges_kata::prelude::*;
+use highlight_ranges_kata::{range, render, search, validate};
+
+#[test]
+fn should_highlight_text_through_the_crate_root() {
+    use highlight_ranges_kata::{HighlightRange, highlight_text};
+
+    assert_eq!(
+        Ok("This <em>is</em> a <em>sample</em> text".to_string()),
+        highlight_text(
+            "This is a sample text",
+            [HighlightRange::new(5, 7), HighlightRange::new(10, 16)]
+        )
+    );
+}
+
+#[test]
+fn should_run_search_validate_and_render_through_the_modules() {
+    let doc = "The <quick> fox met the lazy dog & the quick cat";
+
+    let found = search::ranges_for_keywords(doc, &["quick", "dog"]);
+    let given = range::parse_ranges("24-28").unwrap();
+    let ranges = range::union_ranges(&found, &given);
+    validate::validate_ranges_all(doc, &ranges).unwrap();
+
+    let highlighter = render::Highlighter::builder()
+        .tag_style(render::TagStyle::Mark)
+        .escape_html(true)
+        .build()
+        .unwrap();
+    assert_eq!(
+        Ok(
+            "The &lt;<mark>quick</mark>&gt; fox met the <mark>lazy</mark> <mark>dog</mark> &amp; \
+            the <mark>quick</mark> cat"
+                .to_string()
+        ),
+        highlighter.highlight(doc, &ranges)
+    );
+}
+
+#[test]
+fn should_cover_the_common_case_with_the_prelude() {
+    let doc = "Rust is great, I love RUST";
+    let query = CompiledQuery::new(
+        ["rust"],
+        MatchOptions {
+            case_insensitive: true,
+            ..Default::default()
+        },
+    );
+    let ranges = query.find_ranges(doc);
+
+    let validated = validate(doc, ranges.iter().cloned()).unwrap();
+    assert_eq!(
+        "<em>Rust</em> is great, I love <em>RUST</em>",
+        validated.to_html()
+    );
+    let highlighted = highlight_segments(doc, ranges)
+        .unwrap()
+        .into_iter()
+        .filter(|segment| segment.highlighted)
+        .map(|segment| segment.text)
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["Rust", "RUST"], highlighted);
+}
+
+#[test]
+fn should_report_rejected_ranges_through_public_errors() {
+    let ranges = parse_ranges("0-5,3-8").unwrap();
+
+    assert_eq!(
+        Err(HighlightingError::OverlappingRanges {
+            first: HighlightRange::new(0, 5),
+            second: HighlightRange::new(3, 8),
+        }),
+        highlight_text("Hello world", ranges.clone())
+    );
+
+    let merging = Highlighter::builder()
+        .overlap_policy(OverlapPolicy::Merge)
+        .build()
+        .unwrap();
+    assert_eq!(
+        Ok("<em>Hello wo</em>rld".to_string()),
+        merging.highlight("Hello world", &ranges)
+    );
+}